@@ -4,7 +4,7 @@ use web_sys::{console};
 use wasm_bindgen::JsValue;
 use http4r_core::handler::Handler;
 use http4r_core::headers::Headers;
-use http4r_core::http_message::{body_string, HttpVersion, Method, one_pt_one, Request, Response};
+use http4r_core::http_message::{body_string, HttpVersion, Method, one_pt_one, Request, Response, Trailers};
 use http4r_core::http_message::Body::BodyString;
 use http4r_core::uri::Uri;
 use std::time::Instant;
@@ -58,10 +58,18 @@ impl JSResponse {
 pub fn serve(req: JSRequest) -> JSResponse {
     panic::set_hook(Box::new(console_error_panic_hook::hook));
     let mut app = ExampleApp::new(LoggingHttpHandler::new(ConsoleLogger {}, WasmClock {}, Router {}));
+    let method = match Method::from(&req.method) {
+        Ok(method) => method,
+        Err(e) => return JSResponse {
+            body: e.to_string(),
+            headers: "Content-Type: text/plain".to_string(),
+            status: 400,
+        },
+    };
     let request = Request {
         headers: Headers::js_headers_from_string(&req.headers),
-        trailers: Headers::empty(),
-        method: Method::from(&req.method),
+        trailers: Trailers::empty(),
+        method,
         uri: Uri::parse(&req.uri),
         body: BodyString(req.body.as_str()),
         version: one_pt_one(),
@@ -73,7 +81,7 @@ pub fn serve(req: JSRequest) -> JSResponse {
     };
     app.handle(request, |res| {
         response = JSResponse {
-            body: body_string(res.body),
+            body: body_string(res.body).unwrap(),
             status: res.status.value(),
             headers: Headers::js_headers_to_string(&res.headers.vec),
         }
@@ -21,5 +21,5 @@ use http4r_core::server::Server;
 
 fn main(){
     let mut server = Server::new(8080);
-    server.start(|| { Ok(Router {}) }, true);
+    server.start(|| { Ok(Router {}) }, true).unwrap();
 }
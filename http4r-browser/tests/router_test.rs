@@ -14,12 +14,12 @@ mod tests {
 
         router.handle(Request::get(Uri::parse("/some/path"), Headers::empty()), |res| {
             assert_eq!(res.status, OK);
-            assert_eq!(body_string(res.body), "GET");
+            assert_eq!(body_string(res.body).unwrap(), "GET");
         });
 
         router.handle(Request::post(Uri::parse("/some/path"), Headers::empty(), Body::empty()), |res| {
             assert_eq!(res.status, OK);
-            assert_eq!(body_string(res.body), "POST");
+            assert_eq!(body_string(res.body).unwrap(), "POST");
         })
 
     }
@@ -0,0 +1,89 @@
+/*
+ Compares the event loop with a thread per connection, on a busy server that also has idle keep-alive connections.
+ With a thread per connection, each idle connection holds on to a thread until its keep-alive timeout,
+ whereas the event loop only hands a connection to a thread once the head of a request has arrived.
+
+    cargo run --release --example event_loop
+ */
+use std::io::Write;
+use std::net::TcpStream;
+use std::thread;
+use std::time::{Duration, Instant};
+use http4r_core::handler::Handler;
+use http4r_core::headers::Headers;
+use http4r_core::http_message::{body_string, read_message_from_wire, Request, Response, WireState};
+use http4r_core::http_message::Body::BodyString;
+use http4r_core::server::{Server, ServerMode, ServerOptions};
+
+const THREADS: usize = 8;
+const CLIENTS: usize = 8;
+const REQUESTS_PER_CLIENT: usize = 2000;
+
+struct HelloHandler {}
+
+impl Handler for HelloHandler {
+    fn handle<F>(&mut self, _req: Request, fun: F) -> () where F: FnOnce(Response) -> () + Sized {
+        fun(Response::ok(Headers::empty(), BodyString("hello")))
+    }
+}
+
+fn main() {
+    let mut modes = vec!(("thread per connection", ServerMode::ThreadPerConnection));
+    #[cfg(unix)]
+    modes.push(("event loop", ServerMode::EventLoop));
+
+    println!("{} threads, {} clients making {} requests each over keep-alive connections", THREADS, CLIENTS, REQUESTS_PER_CLIENT);
+    for idle in [0, THREADS * 2] {
+        for (name, mode) in &modes {
+            let elapsed = run(mode.clone(), idle);
+            let requests = (CLIENTS * REQUESTS_PER_CLIENT) as f64;
+            println!("{:<22} {:>3} idle connections: {:>8.0} requests/s, took {:?}", name, idle, requests / elapsed.as_secs_f64(), elapsed);
+        }
+    }
+}
+
+fn run(mode: ServerMode, idle: usize) -> Duration {
+    let mut server = Server::with_options(0, ServerOptions {
+        mode,
+        threadpool_size: THREADS,
+        keep_alive_timeout: Duration::from_secs(2),
+        max_requests_per_connection: usize::MAX,
+        ..ServerOptions::default()
+    });
+    let handle = server.start(|| Ok(HelloHandler {}), true).unwrap();
+    let port = server.port;
+
+    // each has had a request, and now waits for its next one that never comes
+    let idle: Vec<TcpStream> = (0..idle).map(|_| {
+        let stream = TcpStream::connect(format!("127.0.0.1:{}", port)).unwrap();
+        get(&stream);
+        stream
+    }).collect();
+
+    let started = Instant::now();
+    let clients: Vec<_> = (0..CLIENTS).map(|_| thread::spawn(move || {
+        let stream = TcpStream::connect(format!("127.0.0.1:{}", port)).unwrap();
+        for _ in 0..REQUESTS_PER_CLIENT {
+            get(&stream);
+        }
+    })).collect();
+    for client in clients {
+        client.join().unwrap();
+    }
+    let elapsed = started.elapsed();
+
+    drop(idle);
+    handle.stop(Duration::from_secs(5));
+    elapsed
+}
+
+fn get(mut stream: &TcpStream) {
+    stream.write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+    let mut reader = [0; 4096];
+    let mut wire = WireState::new();
+    let (mut start_line_writer, mut headers_writer, mut trailers_writer) = (Vec::with_capacity(1024), Vec::with_capacity(1024), Vec::with_capacity(1024));
+    let (mut chunks_writer, mut compress_writer) = (Vec::with_capacity(1024), Vec::with_capacity(1024));
+    let response = read_message_from_wire(stream.try_clone().unwrap(), &mut reader, &mut wire, false, &mut start_line_writer, &mut headers_writer, &mut chunks_writer, &mut compress_writer, &mut trailers_writer)
+        .unwrap().to_res();
+    assert_eq!("hello", body_string(response.body).unwrap());
+}
@@ -1,21 +1,229 @@
-use std::net::TcpStream;
+use std::cell::{Cell, RefCell};
+use std::cmp::min;
+use std::fmt;
+use std::io::{ErrorKind, Read, Write};
+use std::net::ToSocketAddrs;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
 
+use crate::connection::Connection;
 use crate::handler::Handler;
 use crate::headers::Headers;
 use crate::http_message;
-use crate::http_message::{HttpMessage, read_message_from_wire, Request, RequestOptions, Response, with_content_length, write_message_to_wire};
-use crate::http_message::Body::{BodyString};
+use crate::http_message::{expects_continue, HttpMessage, MessageError, MessageLimits, read_message_from_wire, Request, RequestOptions, Response, Trailers, WireState, with_content_length, write_message_to_wire};
+use crate::http_message::Body::{BodyBytes, BodyStream, BodyString};
+use crate::http_message::Method::HEAD;
+use crate::http_message::Status::{Continue, SwitchingProtocols};
+use crate::http2;
+use crate::http2::Http2Options;
+use crate::socket::{SocketAddress, Stream};
+use crate::websocket;
+use crate::websocket::{WebSocket, WebSocketOptions};
+#[cfg(feature = "tls")]
+use crate::tls::ClientTls;
 
 impl Client {
     pub fn new(base_uri: &str, port: u16, options: Option<ClientOptions>) -> Client {
         Client {
             base_uri: base_uri.to_string(),
             port,
-            options: options.unwrap_or(ClientOptions {
-                headers_size: 16384,
-                trailers_size: 16384,
-            }),
-            err: "".to_string()
+            address: None,
+            options: options.unwrap_or_default(),
+            err: "".to_string(),
+            error: None,
+        }
+    }
+
+    /// Connects to an address rather than looking up a name, eg [::1]:8080 or a unix socket.
+    pub fn connect_to(address: SocketAddress, options: Option<ClientOptions>) -> Client {
+        Client {
+            base_uri: address.to_string(),
+            port: address.port(),
+            address: Some(address),
+            options: options.unwrap_or_default(),
+            err: "".to_string(),
+            error: None,
+        }
+    }
+
+    // an ipv6 address needs brackets to tell it apart from the port
+    fn authority(&self) -> String {
+        match &self.address {
+            Some(address) => address.host(),
+            None if self.base_uri.contains(':') && !self.base_uri.starts_with('[') => format!("[{}]:{}", self.base_uri, self.port),
+            None => format!("{}:{}", self.base_uri, self.port),
+        }
+    }
+
+    fn connect(&self, uri: &str, started: Instant) -> Result<Stream, ClientError> {
+        let timeout = match self.options.total_timeout {
+            Some(total) => min(self.options.connect_timeout, total.saturating_sub(started.elapsed())),
+            None => self.options.connect_timeout,
+        };
+        let addresses = match &self.address {
+            Some(address) => vec!(address.clone()),
+            None => uri.to_socket_addrs().map_err(|e| ClientError::CouldNotConnect(e.to_string()))?
+                .map(SocketAddress::from).collect(),
+        };
+        let mut error = ClientError::CouldNotConnect(format!("Could not find an address for {}", uri));
+        // try each address the name resolves to, eg the ipv6 one and then the ipv4 one
+        for address in addresses {
+            error = match Stream::connect(&address, timeout) {
+                Ok(stream) => return self.secure(stream, timeout),
+                Err(e) if e.kind() == ErrorKind::TimedOut || e.kind() == ErrorKind::WouldBlock =>
+                    ClientError::ConnectTimeout(format!("Timed out connecting to {} after {}ms", uri, timeout.as_millis())),
+                Err(e) => ClientError::CouldNotConnect(e.to_string()),
+            };
+        }
+        Err(error)
+    }
+
+    #[cfg(feature = "tls")]
+    fn secure(&self, stream: Stream, timeout: Duration) -> Result<Stream, ClientError> {
+        match &self.options.tls {
+            Some(tls) => tls.connect(stream, self.host().as_str(), timeout)
+                .map_err(|e| ClientError::Tls(format!("TLS handshake with {} failed: {}", self.authority(), e))),
+            None => Ok(stream),
+        }
+    }
+
+    #[cfg(not(feature = "tls"))]
+    fn secure(&self, stream: Stream, _timeout: Duration) -> Result<Stream, ClientError> {
+        Ok(stream)
+    }
+
+    // the name the certificate has to be for, which is the authority without the port
+    #[cfg(feature = "tls")]
+    fn host(&self) -> String {
+        match &self.address {
+            Some(SocketAddress::Ip(address)) => address.ip().to_string(),
+            Some(address) => address.host(),
+            None => self.base_uri.clone(),
+        }
+    }
+
+    // over TLS it is whatever we agreed on over ALPN, otherwise only if we were told the server speaks it
+    #[cfg(feature = "tls")]
+    fn speaks_http2(&self, stream: &Stream) -> Option<(Http2Options, &'static str)> {
+        match &self.options.tls {
+            Some(_) if stream.alpn_protocol() == Some(b"h2".to_vec()) => Some((self.options.http2.clone().unwrap_or_default(), "https")),
+            Some(_) => None,
+            None => self.options.http2.clone().map(|http2| (http2, "http")),
+        }
+    }
+
+    #[cfg(not(feature = "tls"))]
+    fn speaks_http2(&self, _stream: &Stream) -> Option<(Http2Options, &'static str)> {
+        self.options.http2.clone().map(|http2| (http2, "http"))
+    }
+
+    /// Opens a WebSocket to the uri of the request, by asking the server to switch its connection over from HTTP/1.1.
+    /// The headers of the request go along with the handshake, eg a Cookie or an Origin.
+    pub fn websocket(&mut self, req: Request, options: Option<WebSocketOptions>) -> Result<WebSocket, ClientError> {
+        let options = options.unwrap_or_default();
+        let uri = self.authority();
+        let started = Instant::now();
+        let mut stream = self.connect(uri.as_str(), started)?;
+        // a prior knowledge client still speaks HTTP/1.1 first when it asks, but ALPN leaves no choice
+        if self.speaks_http2(&stream).map(|(_, scheme)| scheme == "https").unwrap_or(false) {
+            return Err(ClientError::WebSocket("The server agreed on HTTP/2, which WebSockets are not opened over".to_string()));
+        }
+        let key = websocket::new_key();
+        let mut headers = req.headers
+            .replace(("Upgrade", "websocket"))
+            .replace(("Connection", "Upgrade"))
+            .replace(("Sec-WebSocket-Key", key.as_str()))
+            .replace(("Sec-WebSocket-Version", "13"));
+        if !headers.has("Host") {
+            headers = headers.add(("Host", uri.as_str()));
+        }
+        if !options.protocols.is_empty() {
+            headers = headers.replace(("Sec-WebSocket-Protocol", options.protocols.join(", ").as_str()));
+        }
+        if let Err(e) = write_message_to_wire(&mut stream, HttpMessage::Request(Request::get(req.uri, headers)), RequestOptions::default()) {
+            return Err(ClientError::BadResponse(MessageError::ConnectionClosed(e.to_string())));
+        }
+
+        let reader: &mut [u8] = &mut [0; 4096];
+        let mut wire = WireState::with_limits(MessageLimits {
+            max_headers: self.options.max_headers,
+            max_body_size: self.options.max_body_size,
+            max_decompressed_size: self.options.max_decompressed_size,
+            head_timeout: self.options.total_timeout.map(|total| total.saturating_sub(started.elapsed())),
+            read_timeout: Some(self.options.read_timeout),
+        });
+        let mut chunks_writer = Vec::new();
+        let mut compress_writer = Vec::new();
+        let mut start_line_writer = Vec::with_capacity(self.options.status_line_size);
+        let mut headers_writer = Vec::with_capacity(self.options.headers_size);
+        let mut trailers_writer = Vec::with_capacity(self.options.trailers_size);
+        // the response borrows what it was read into, which we want back to see what came after it
+        let (status, headers) = {
+            let result = read_message_from_wire(
+                stream.try_clone().unwrap(),
+                reader,
+                &mut wire,
+                false,
+                &mut start_line_writer,
+                &mut headers_writer,
+                &mut chunks_writer,
+                &mut compress_writer,
+                &mut trailers_writer
+            );
+            match result {
+                Ok(HttpMessage::Response(res)) => (res.status, Headers::from_headers(&res.headers)),
+                Ok(HttpMessage::Request(_)) => return Err(ClientError::WebSocket("The server sent a request rather than a response".to_string())),
+                Err(MessageError::Timeout(_)) => {
+                    let timeout = self.options.read_timeout;
+                    return Err(ClientError::ReadTimeout(format!("Timed out after {}ms waiting for the handshake", timeout.as_millis())));
+                }
+                Err(e) => return Err(ClientError::BadResponse(e)),
+            }
+        };
+        let protocol = websocket_agreed(&status, &headers, key.as_str(), &options.protocols)?;
+        // the server may have sent its first messages straight after the handshake
+        let leftover = if wire.has_leftover_bytes() { reader[wire.up_to_in_reader..wire.read_bytes_from_stream].to_vec() } else { vec!() };
+        let _ = stream.set_read_timeout(options.read_timeout);
+        Ok(WebSocket::new(stream, leftover, true, options, protocol))
+    }
+
+    // we could not get a response, so the handler gets a bad request instead and the reason why is kept
+    fn fail<F>(&mut self, error: ClientError, fun: F) where F: FnOnce(Response) + Sized {
+        self.err = error.to_string();
+        self.error = Some(error);
+        fun(Response::bad_request(Headers::empty(), BodyString(self.err.as_str())))
+    }
+
+    // only worth waiting for the server to say continue if the body is big enough
+    fn should_expect_continue(&self, req: &Request) -> bool {
+        let length = match req.body {
+            BodyString(str) => Some(str.len()),
+            BodyBytes(bytes) => Some(bytes.len()),
+            BodyStream(_) => req.headers.content_length_header().and_then(|length| length.ok()),
+        };
+        let big_enough = length.map(|length| length > self.options.expect_continue_threshold).unwrap_or(false);
+        // compressed bodies are written in one go, so we could not wait in between the head and the body
+        let compressed = req.headers.has("Content-Encoding");
+        !compressed && !req.headers.has("Transfer-Encoding") && (big_enough || expects_continue(&req.headers))
+    }
+
+    fn expect_continue<'a>(&self, req: Request<'a>, stream: &WithDeadline, read_ahead: &Rc<RefCell<Vec<u8>>>) -> Request<'a> {
+        let (headers, body): (Headers, Box<dyn Read + 'a>) = match req.body {
+            BodyString(str) => (req.headers.ensure(("Content-Length", str.len().to_string().as_str())), Box::new(str.as_bytes())),
+            BodyBytes(bytes) => (req.headers.ensure(("Content-Length", bytes.len().to_string().as_str())), Box::new(bytes)),
+            BodyStream(reader) => (req.headers, reader),
+        };
+        Request {
+            headers: headers.replace(("Expect", "100-continue")),
+            body: BodyStream(Box::new(WaitForContinue {
+                stream: stream.try_clone().unwrap(),
+                timeout: self.options.expect_continue_timeout,
+                read_ahead: read_ahead.clone(),
+                body,
+                waited: false,
+                rejected: false,
+            })),
+            ..req
         }
     }
 }
@@ -23,52 +231,420 @@ impl Client {
 impl Handler for Client {
     fn handle<F>(self: &mut Client, req: Request, fun: F) -> ()
         where F: FnOnce(Response) -> () + Sized {
-        let uri = format!("{}:{}", self.base_uri, self.port);
-        let mut stream = TcpStream::connect(uri.clone()).unwrap();
-
-        let with_host_header = req.with_header(("Host", uri.as_str()));
-        write_message_to_wire(&mut stream, HttpMessage::Request(with_host_header), RequestOptions::default());
-
-        let mut reader: &mut [u8] = &mut [0; 4096];
-        let mut chunks_writer = Vec::with_capacity(1048576);
-        let mut compress_writer = Vec::with_capacity(1048576);
-        let mut start_line_writer = Vec::with_capacity(16384);
-        let mut headers_writer = Vec::with_capacity(16384);
-        let mut trailers_writer = Vec::with_capacity(16384);
-
-        let result = read_message_from_wire(
-            stream.try_clone().unwrap(),
-            &mut reader,
-            &mut start_line_writer,
-            &mut headers_writer,
-            &mut chunks_writer,
-            &mut compress_writer,
-            &mut trailers_writer
-        );
-
-        let response = match result {
-            Ok(http_message::HttpMessage::Response(res)) => res,
-            Err(e) => {
-                self.err = e.to_string();
-                Response::bad_request(Headers::empty(), BodyString(self.err.as_str()))
-            },
-            _ => Response::bad_request(Headers::empty(), BodyString("will happen if server replies with invalid response"))
-        };
+        let uri = self.authority();
+        let mut req = req.with_header(("Host", uri.as_str()));
+        let mut retries_left = self.options.retries;
+        let started = Instant::now();
+        self.error = None;
+
+        'attempts: loop {
+            let stream = match self.connect(uri.as_str(), started) {
+                Ok(stream) => stream,
+                Err(error) => return self.fail(error, fun),
+            };
+            if let Some((http2, scheme)) = self.speaks_http2(&stream) {
+                let authority = req.headers.get("Host").unwrap_or(uri.clone());
+                return match http2::send_request(stream, req, authority.as_str(), scheme, &self.options, &http2, started) {
+                    Ok(response) => fun(response),
+                    Err(error) => self.fail(error, fun),
+                };
+            }
+            // from here on every read and write, of the body too, only gets what is left of the total timeout
+            let mut stream = WithDeadline::new(stream, self.options.total_timeout.map(|total| started + total));
+            let retry = if retries_left > 0 { replayable(&req) } else { None };
+            let is_head = req.method == HEAD;
+            let read_ahead = Rc::new(RefCell::new(vec!()));
+
+            let request = if self.should_expect_continue(&req) {
+                self.expect_continue(req, &stream, &read_ahead)
+            } else {
+                req
+            };
+            let options = RequestOptions {
+                max_compressed_content_length: self.options.max_compressed_content_length,
+                ..RequestOptions::default()
+            };
+            // the server may have responded before it stopped reading, eg that the body is too big, or else reading says why
+            let _ = write_message_to_wire(&mut stream, HttpMessage::Request(request), options);
+
+            let reader: &mut [u8] = &mut [0; 4096];
+            let mut wire = WireState::with_limits(MessageLimits {
+                max_headers: self.options.max_headers,
+                max_body_size: self.options.max_body_size,
+                max_decompressed_size: self.options.max_decompressed_size,
+                // the total timeout started before we connected, so the head only gets what is left of it
+                head_timeout: self.options.total_timeout.map(|total| total.saturating_sub(started.elapsed())),
+                read_timeout: Some(self.options.read_timeout),
+            });
+            let mut chunks_writer = Vec::with_capacity(1048576);
+            let mut compress_writer = Vec::with_capacity(1048576);
+            let mut start_line_writer = Vec::with_capacity(self.options.status_line_size);
+            let mut headers_writer = Vec::with_capacity(self.options.headers_size);
+            let mut trailers_writer = Vec::with_capacity(self.options.trailers_size);
+
+            loop {
+                chunks_writer.clear();
+                compress_writer.clear();
+                start_line_writer.clear();
+                headers_writer.clear();
+                trailers_writer.clear();
+
+                let result = read_message_from_wire(
+                    ReadAhead::new(&read_ahead, &stream),
+                    reader,
+                    &mut wire,
+                    is_head,
+                    &mut start_line_writer,
+                    &mut headers_writer,
+                    &mut chunks_writer,
+                    &mut compress_writer,
+                    &mut trailers_writer
+                );
 
-        fun(response)
+                let response = match result {
+                    // the server said continue after we had given up waiting and sent the body anyway
+                    Ok(http_message::HttpMessage::Response(res)) if res.status == Continue => continue,
+                    Ok(http_message::HttpMessage::Response(res)) => res,
+                    // the connection went away before we got a response, so try again if it is safe to
+                    Err(MessageError::ConnectionClosed(_)) | Err(MessageError::IoError(_)) if retry.is_some() => {
+                        retries_left -= 1;
+                        req = retry.unwrap();
+                        continue 'attempts;
+                    }
+                    Err(MessageError::Timeout(_)) if self.options.total_timeout.map(|total| started.elapsed() >= total).unwrap_or(false) => {
+                        let total = self.options.total_timeout.unwrap();
+                        return self.fail(ClientError::TotalTimeout(format!("Timed out after {}ms waiting for the response", total.as_millis())), fun);
+                    }
+                    Err(MessageError::Timeout(_)) => {
+                        let timeout = self.options.read_timeout;
+                        return self.fail(ClientError::ReadTimeout(format!("Timed out after {}ms reading the response", timeout.as_millis())), fun);
+                    }
+                    Err(e) => return self.fail(ClientError::BadResponse(e), fun),
+                    _ => Response::bad_request(Headers::empty(), BodyString("will happen if server replies with invalid response"))
+                };
+
+                return fun(response);
+            }
+        }
     }
 }
 
+/*
+ https://www.rfc-editor.org/rfc/rfc6455#section-4.1
+ If the status code received from the server is not 101, the client handles the response per HTTP
+ procedures. If the response lacks a |Sec-WebSocket-Accept| header field or the |Sec-WebSocket-Accept|
+ contains a value other than the base64-encoded SHA-1 of the concatenation of the |Sec-WebSocket-Key|
+ (as a string, not base64-decoded) with the string "258EAFA5-E914-47DA-95CA-C5AB0DC85B11",
+ the client MUST _Fail the WebSocket Connection_.
+ */
+fn websocket_agreed(status: &http_message::Status, headers: &Headers, key: &str, protocols: &[String]) -> Result<Option<String>, ClientError> {
+    let fail = |reason: String| Err(ClientError::WebSocket(reason));
+    if *status != SwitchingProtocols {
+        return fail(format!("The server responded {} {} rather than switching protocols", status.value(), status.to_string()));
+    }
+    if !websocket::has_token(headers, "Upgrade", "websocket") || !websocket::has_token(headers, "Connection", "upgrade") {
+        return fail("The server did not switch the connection over to websocket".to_string());
+    }
+    if headers.get("Sec-WebSocket-Accept") != Some(websocket::accept_key(key)) {
+        return fail("The server did not accept our Sec-WebSocket-Key".to_string());
+    }
+    // we never ask for any extensions
+    if headers.has("Sec-WebSocket-Extensions") {
+        return fail("The server agreed on extensions we did not ask for".to_string());
+    }
+    match headers.get("Sec-WebSocket-Protocol") {
+        Some(protocol) if !protocols.contains(&protocol) => fail(format!("The server agreed on subprotocol {} that we did not offer", protocol)),
+        protocol => Ok(protocol),
+    }
+}
+
+/*
+ https://www.rfc-editor.org/rfc/rfc9110#section-9.2.2
+ A client SHOULD NOT automatically retry a request with a non-idempotent method unless it has
+ some means to know that the request semantics are actually idempotent.
+ We can only send the request again if we still have all of its body.
+ */
+fn replayable<'a>(req: &Request<'a>) -> Option<Request<'a>> {
+    let body = match req.body {
+        BodyString(str) => BodyString(str),
+        BodyBytes(bytes) => BodyBytes(bytes),
+        BodyStream(_) => return None,
+    };
+    if !req.method.is_idempotent() {
+        return None;
+    }
+    Some(Request {
+        headers: Headers::from_headers(&req.headers),
+        body,
+        uri: req.uri,
+        method: req.method.clone(),
+        version: req.version.clone(),
+        trailers: Trailers::from(req.trailers.get()),
+    })
+}
+
 pub struct Client {
     pub base_uri: String,
     pub port: u16,
+    // if we were given an address then we do not look up the base uri
+    address: Option<SocketAddress>,
     options: ClientOptions,
     pub err: String,
+    // why the last request did not get a response
+    pub error: Option<ClientError>,
+}
+
+#[derive(Clone, Debug)]
+pub enum ClientError {
+    CouldNotConnect(String),
+    ConnectTimeout(String),
+    ReadTimeout(String),
+    TotalTimeout(String),
+    BadResponse(MessageError),
+    // eg the certificate is not one we trust
+    #[cfg(feature = "tls")]
+    Tls(String),
+    // the server broke the rules of HTTP/2, or reset the stream
+    Http2(String),
+    // the server would not switch the connection over to a WebSocket
+    WebSocket(String),
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, format: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ClientError::CouldNotConnect(msg)
+            | ClientError::ConnectTimeout(msg)
+            | ClientError::ReadTimeout(msg)
+            | ClientError::TotalTimeout(msg) => format.write_str(msg),
+            #[cfg(feature = "tls")]
+            ClientError::Tls(msg) => format.write_str(msg),
+            ClientError::Http2(msg) => format.write_str(msg),
+            ClientError::WebSocket(msg) => format.write_str(msg),
+            ClientError::BadResponse(e) => format.write_str(&e.to_string()),
+        }
+    }
 }
 
 pub struct ClientOptions {
-    headers_size: usize,
-    trailers_size: usize,
+    pub status_line_size: usize,
+    pub headers_size: usize,
+    pub max_headers: usize,
+    pub max_body_size: usize,
+    pub trailers_size: usize,
+    pub max_decompressed_size: usize,
+    pub expect_continue_threshold: usize,
+    pub expect_continue_timeout: Duration,
+    pub max_compressed_content_length: usize,
+    // how many times to send an idempotent request again if the connection closes before we get a response
+    pub retries: usize,
+    pub connect_timeout: Duration,
+    // how long to wait for any one read of the response, including its body
+    pub read_timeout: Duration,
+    // how long from connecting until we have read all of the response, including writing the request,
+    // so a handler that is slow to read the body uses it up too
+    pub total_timeout: Option<Duration>,
+    // call https rather than http
+    #[cfg(feature = "tls")]
+    pub tls: Option<ClientTls>,
+    // speak HTTP/2 without asking first, as we know the server does, or these options if we agree on h2 over ALPN
+    pub http2: Option<Http2Options>,
+}
+
+impl Default for ClientOptions {
+    fn default() -> ClientOptions {
+        ClientOptions {
+            status_line_size: 16384,
+            headers_size: 16384,
+            max_headers: 100,
+            // we asked for the response, so take however much of it there is
+            max_body_size: usize::MAX,
+            trailers_size: 16384,
+            // but a small compressed body can still decompress into an enormous one
+            max_decompressed_size: 1073741824,
+            expect_continue_threshold: 1048576,
+            expect_continue_timeout: Duration::from_secs(1),
+            max_compressed_content_length: 1048576,
+            retries: 1,
+            connect_timeout: Duration::from_secs(10),
+            read_timeout: Duration::from_secs(30),
+            total_timeout: None,
+            #[cfg(feature = "tls")]
+            tls: None,
+            http2: None,
+        }
+    }
+}
+
+/// Holds back the body until the server says to continue, or gives up waiting and sends it anyway.
+/// If the server responds with a final status instead, eg 417 or 413, then the body is never sent.
+struct WaitForContinue<'a> {
+    stream: WithDeadline,
+    timeout: Duration,
+    // the start of a final response, that we read to see it was not a 100, for the response to be read from
+    read_ahead: Rc<RefCell<Vec<u8>>>,
+    body: Box<dyn Read + 'a>,
+    waited: bool,
+    rejected: bool,
+}
+
+impl<'a> WaitForContinue<'a> {
+    fn server_says_continue(&mut self) -> bool {
+        // the timeout is for the whole wait, however slowly the status line arrives
+        let deadline = Instant::now() + self.timeout;
+        let mut status_line = [0; 12];
+        let mut read = 0;
+        let timed_out = loop {
+            if read == status_line.len() {
+                break false;
+            }
+            let left = deadline.saturating_duration_since(Instant::now());
+            if left.is_zero() {
+                break true;
+            }
+            let _ = self.stream.set_read_timeout(Some(left));
+            match self.stream.read(&mut status_line[read..]) {
+                // the server went away, so sending the body is going to fail anyway
+                Ok(0) => break false,
+                Ok(more) => read += more,
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(_) => break true,
+            }
+        };
+        let says_continue = if read == status_line.len() && status_line.ends_with(b" 100") {
+            self.skip_interim_response(deadline);
+            true
+        } else {
+            self.read_ahead.borrow_mut().extend_from_slice(&status_line[..read]);
+            // timed out, so the server may not know about 100-continue
+            timed_out
+        };
+        let _ = self.stream.set_read_timeout(None);
+        says_continue
+    }
+
+    fn skip_interim_response(&mut self, deadline: Instant) {
+        let mut last_four = [0; 4];
+        let mut octet = [0; 1];
+        while &last_four != b"\r\n\r\n" {
+            let left = deadline.saturating_duration_since(Instant::now());
+            if left.is_zero() || self.stream.set_read_timeout(Some(left)).is_err() || self.stream.read(&mut octet).unwrap_or(0) != 1 {
+                return;
+            }
+            last_four.rotate_left(1);
+            last_four[3] = octet[0];
+        }
+    }
+}
+
+/// The connection a response is read from, after whatever was read ahead of it while waiting for continue.
+struct ReadAhead {
+    read_ahead: Rc<RefCell<Vec<u8>>>,
+    stream: WithDeadline,
+}
+
+impl ReadAhead {
+    fn new(read_ahead: &Rc<RefCell<Vec<u8>>>, stream: &WithDeadline) -> ReadAhead {
+        ReadAhead { read_ahead: read_ahead.clone(), stream: stream.try_clone().unwrap() }
+    }
+}
+
+impl Read for ReadAhead {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let mut read_ahead = self.read_ahead.borrow_mut();
+        if read_ahead.is_empty() {
+            return self.stream.read(buf);
+        }
+        let read = min(buf.len(), read_ahead.len());
+        buf[..read].copy_from_slice(&read_ahead[..read]);
+        read_ahead.drain(..read);
+        Ok(read)
+    }
+}
+
+impl Write for ReadAhead {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.stream.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+impl Connection for ReadAhead {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> std::io::Result<()> {
+        self.stream.set_read_timeout(timeout)
+    }
+}
+
+/// A connection that gives up at the deadline of the total timeout, whatever it was doing,
+/// so any one read waits for no longer than its read timeout or what is left, whichever is less.
+pub(crate) struct WithDeadline {
+    stream: Stream,
+    deadline: Option<Instant>,
+    // the read timeout we were asked for, which only holds until the deadline is nearer
+    read_timeout: Cell<Option<Duration>>,
+}
+
+impl WithDeadline {
+    pub(crate) fn new(stream: Stream, deadline: Option<Instant>) -> WithDeadline {
+        WithDeadline { stream, deadline, read_timeout: Cell::new(None) }
+    }
+
+    fn try_clone(&self) -> std::io::Result<WithDeadline> {
+        Ok(WithDeadline { stream: self.stream.try_clone()?, deadline: self.deadline, read_timeout: self.read_timeout.clone() })
+    }
+
+    fn left(&self) -> std::io::Result<Option<Duration>> {
+        match self.deadline.map(|deadline| deadline.saturating_duration_since(Instant::now())) {
+            Some(left) if left.is_zero() => Err(std::io::Error::new(ErrorKind::TimedOut, "Timed out at the total timeout")),
+            left => Ok(left),
+        }
+    }
+}
+
+impl Read for WithDeadline {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if let Some(left) = self.left()? {
+            let timeout = self.read_timeout.get().map(|timeout| min(timeout, left)).unwrap_or(left);
+            self.stream.set_read_timeout(Some(timeout))?;
+        }
+        self.stream.read(buf)
+    }
+}
+
+impl Write for WithDeadline {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if let Some(left) = self.left()? {
+            self.stream.set_write_timeout(Some(left))?;
+        }
+        self.stream.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+impl Connection for WithDeadline {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> std::io::Result<()> {
+        self.read_timeout.set(timeout);
+        self.stream.set_read_timeout(timeout)
+    }
+}
+
+impl<'a> Read for WaitForContinue<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if !self.waited {
+            self.waited = true;
+            self.rejected = !self.server_says_continue();
+        }
+        if self.rejected {
+            return Ok(0);
+        }
+        self.body.read(buf)
+    }
 }
 
 pub struct WithContentLength<H> where H: Handler {
@@ -3,6 +3,8 @@ use crate::http_message::CompressionAlgorithm;
 use flate2::bufread::{DeflateEncoder, GzEncoder};
 use flate2::{Compression};
 use flate2::read::{GzDecoder, DeflateDecoder};
+use flate2::read;
+use flate2::write;
 
 pub struct Codex {}
 impl Codex {
@@ -40,6 +42,47 @@ impl Codex {
             }
             CompressionAlgorithm::NONE => panic!("Cannot decode with no compression algorithmw")
         }
+    }
+
+    /// Compresses the reader as it is read, so that a body can be compressed while it streams.
+    pub fn encode_reader<'a, R: Read + 'a>(reader: R, compression: &CompressionAlgorithm) -> Box<dyn Read + 'a> {
+        match compression {
+            CompressionAlgorithm::GZIP => Box::new(read::GzEncoder::new(reader, Compression::fast())),
+            CompressionAlgorithm::DEFLATE => Box::new(read::DeflateEncoder::new(reader, Compression::fast())),
+            CompressionAlgorithm::BROTLI => Box::new(brotli::CompressorReader::new(reader, 4096, 5, 22)),
+            CompressionAlgorithm::NONE => Box::new(reader)
+        }
+    }
 
+    /// Decompresses the reader as it is read, so that a body can be decompressed while it streams.
+    pub fn decode_reader<'a, R: Read + 'a>(reader: R, compression: &CompressionAlgorithm) -> Box<dyn Read + 'a> {
+        match compression {
+            CompressionAlgorithm::GZIP => Box::new(read::GzDecoder::new(reader)),
+            CompressionAlgorithm::DEFLATE => Box::new(read::DeflateDecoder::new(reader)),
+            CompressionAlgorithm::BROTLI => Box::new(brotli::Decompressor::new(reader, 4096)),
+            CompressionAlgorithm::NONE => Box::new(reader)
+        }
+    }
+
+    /// Compresses whatever is written before passing it on to the writer.
+    /// The compressed stream is only finished once the returned writer is dropped.
+    pub fn encode_writer<'a, W: Write + 'a>(writer: W, compression: &CompressionAlgorithm) -> Box<dyn Write + 'a> {
+        match compression {
+            CompressionAlgorithm::GZIP => Box::new(write::GzEncoder::new(writer, Compression::fast())),
+            CompressionAlgorithm::DEFLATE => Box::new(write::DeflateEncoder::new(writer, Compression::fast())),
+            CompressionAlgorithm::BROTLI => Box::new(brotli::CompressorWriter::new(writer, 4096, 5, 22)),
+            CompressionAlgorithm::NONE => Box::new(writer)
+        }
+    }
+
+    /// Decompresses whatever is written before passing it on to the writer.
+    /// Anything still buffered is only passed on once the returned writer is dropped.
+    pub fn decode_writer<'a, W: Write + 'a>(writer: W, compression: &CompressionAlgorithm) -> Box<dyn Write + 'a> {
+        match compression {
+            CompressionAlgorithm::GZIP => Box::new(write::GzDecoder::new(writer)),
+            CompressionAlgorithm::DEFLATE => Box::new(write::DeflateDecoder::new(writer)),
+            CompressionAlgorithm::BROTLI => Box::new(brotli::DecompressorWriter::new(writer, 4096)),
+            CompressionAlgorithm::NONE => Box::new(writer)
+        }
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,76 @@
+use std::io::{Read, Write};
+use std::net::TcpStream;
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+use std::time::Duration;
+use crate::socket::Stream;
+
+/// What messages are read from, and written back to, eg for an expect 100-continue.
+/// Only sockets can time out, so anything else, like a TLS stream or an in-memory pipe,
+/// can leave the read timeout alone.
+pub trait Connection: Read + Write {
+    fn set_read_timeout(&self, _timeout: Option<Duration>) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Connection for TcpStream {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> std::io::Result<()> {
+        TcpStream::set_read_timeout(self, timeout)
+    }
+}
+
+#[cfg(unix)]
+impl Connection for UnixStream {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> std::io::Result<()> {
+        UnixStream::set_read_timeout(self, timeout)
+    }
+}
+
+impl Connection for Stream {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> std::io::Result<()> {
+        Stream::set_read_timeout(self, timeout)
+    }
+}
+
+impl<C: Connection + ?Sized> Connection for &mut C {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> std::io::Result<()> {
+        (**self).set_read_timeout(timeout)
+    }
+}
+
+impl<C: Connection + ?Sized> Connection for Box<C> {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> std::io::Result<()> {
+        (**self).set_read_timeout(timeout)
+    }
+}
+
+/// A connection made of two halves, eg captured traffic to read from and a buffer to write to.
+pub struct Duplex<R: Read, W: Write> {
+    pub reader: R,
+    pub writer: W,
+}
+
+impl<R: Read, W: Write> Duplex<R, W> {
+    pub fn new(reader: R, writer: W) -> Duplex<R, W> {
+        Duplex { reader, writer }
+    }
+}
+
+impl<R: Read, W: Write> Read for Duplex<R, W> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.reader.read(buf)
+    }
+}
+
+impl<R: Read, W: Write> Write for Duplex<R, W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.writer.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+impl<R: Read, W: Write> Connection for Duplex<R, W> {}
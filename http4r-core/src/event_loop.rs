@@ -0,0 +1,311 @@
+use std::collections::{HashMap, VecDeque};
+use std::io::ErrorKind;
+use std::os::unix::io::AsRawFd;
+use std::sync::Arc;
+use std::sync::mpsc::{channel, Receiver};
+use std::time::Instant;
+use mio::{Events, Interest, Poll, Token, Waker};
+use mio::unix::SourceFd;
+use crate::handler::Handler;
+use crate::server::{ClosesOnDrop, Connections, OverloadPolicy, Server, ServerOptions};
+use crate::socket::{Listener, Stream};
+use crate::thread_pool::ThreadPool;
+
+const LISTENER: Token = Token(usize::MAX);
+const WAKER: Token = Token(usize::MAX - 1);
+
+/// Waits on every connection at once, until the head of its next request has arrived,
+/// and only then hands it to a thread from the pool to be handled.
+/// Once the handler is done, the connection comes back here to wait for the request after that.
+pub(crate) struct EventLoop {
+    poll: Poll,
+    listener: Listener,
+    waker: Arc<Waker>,
+}
+
+/// A connection waiting for the head of its next request.
+struct Waiting {
+    stream: Stream,
+    // how many requests it has had so far
+    requests: usize,
+    idle_since: Instant,
+    // when the first bytes of the request arrived, after which the rest of the head has to arrive in time
+    started: Option<Instant>,
+}
+
+impl Waiting {
+    fn deadline(&self, options: &ServerOptions) -> Instant {
+        match self.started {
+            Some(started) => started + options.headers_timeout,
+            None => self.idle_since + options.keep_alive_timeout,
+        }
+    }
+}
+
+enum Arrived {
+    Nothing,
+    PartOfTheHead,
+    TheHead,
+    Closed,
+}
+
+impl EventLoop {
+    pub(crate) fn new(listener: Listener) -> std::io::Result<EventLoop> {
+        listener.set_nonblocking(true)?;
+        let poll = Poll::new()?;
+        poll.registry().register(&mut SourceFd(&listener.as_raw_fd()), LISTENER, Interest::READABLE)?;
+        let waker = Arc::new(Waker::new(poll.registry(), WAKER)?);
+        Ok(EventLoop { poll, listener, waker })
+    }
+
+    pub(crate) fn run<F, H>(mut self, handler: Arc<F>, options: ServerOptions, connections: Arc<Connections>)
+        where F: Fn() -> Result<H, String> + Send + Sync + 'static, H: Handler {
+        let (give_back, given_back) = channel::<(usize, Stream, usize)>();
+        let waker = self.waker.clone();
+        let worker_options = options.clone();
+        let open_connections = connections.clone();
+        // dropping the pool at the end waits for the requests it is handling to finish
+        let pool = ThreadPool::with_when_free(options.threadpool_size, options.accept_queue_size, move |(id, stream, requests): (usize, Stream, usize)| {
+            // a new connection over TLS comes here for its handshake, and then goes back to wait for its first request
+            #[cfg(feature = "tls")]
+            if let (Some(tls), false) = (&worker_options.tls, matches!(stream, Stream::Tls(_))) {
+                let handshaken = tls.accept(stream, worker_options.headers_timeout)
+                    .map(|stream| give_back.send((id, stream, requests)).is_ok());
+                if !handshaken.unwrap_or(false) {
+                    open_connections.close(id);
+                }
+                return;
+            }
+            let open = ClosesOnDrop::new(&open_connections, id);
+            // once we are stopping no one is waiting for it, so it is closed
+            if let Some((stream, requests)) = Server::serve_connection(&handler, stream, id, &worker_options, &open_connections, requests, true) {
+                if give_back.send((id, stream, requests)).is_ok() {
+                    open.keep_open();
+                }
+            }
+        }, move || {
+            // either way this thread is free again, for a connection that is waiting for one
+            let _ = waker.wake();
+        });
+        let mut waiting: HashMap<usize, Waiting> = HashMap::new();
+        // the connections whose head has arrived, waiting for a thread to be free, as we never wait for one here
+        let mut pending: VecDeque<(usize, Stream, usize)> = VecDeque::new();
+        let mut events = Events::with_capacity(1024);
+        // big enough to see the whole head, as any bigger and reading it is going to fail anyway
+        let mut peeked = vec![0; options.request_line_size + options.headers_size];
+        let mut next_deadline: Option<Instant> = None;
+
+        while !connections.stopping() {
+            let timeout = next_deadline.map(|deadline| deadline.saturating_duration_since(Instant::now()));
+            if let Err(e) = self.poll.poll(&mut events, timeout) {
+                if e.kind() == ErrorKind::Interrupted {
+                    continue;
+                }
+                break;
+            }
+            let mut ready = vec!();
+            for event in events.iter() {
+                match event.token() {
+                    // while connections are waiting for a thread, the rest wait in the listen backlog
+                    LISTENER if !pending.is_empty() => {}
+                    LISTENER => self.accept(&pool, &mut pending, &mut waiting, &mut next_deadline, &options, &connections),
+                    WAKER => {
+                        // over TLS, the next request may have been read off the socket already, so we look straight away
+                        ready.extend(self.take_back(&given_back, &mut waiting, &mut next_deadline, &options, &connections));
+                        if !pending.is_empty() {
+                            Self::dispatch_pending(&pool, &mut pending);
+                            // we are only told about the listener once, so we accept what we did not before
+                            if pending.is_empty() {
+                                self.accept(&pool, &mut pending, &mut waiting, &mut next_deadline, &options, &connections);
+                            }
+                        }
+                    }
+                    Token(id) => ready.push(id),
+                }
+            }
+            for id in ready {
+                let arrived = match waiting.get(&id) {
+                    Some(connection) => Self::arrived(&connection.stream, &mut peeked),
+                    None => continue,
+                };
+                match arrived {
+                    Arrived::Nothing => {}
+                    Arrived::PartOfTheHead => {
+                        let connection = waiting.get_mut(&id).unwrap();
+                        if connection.started.is_none() {
+                            // the request has started, so it is not idle any more
+                            connection.started = Some(Instant::now());
+                            connections.set_idle(id, false);
+                            Self::earlier(&mut next_deadline, connection.deadline(&options));
+                        }
+                    }
+                    Arrived::TheHead => {
+                        let connection = self.stop_waiting(id, &mut waiting);
+                        Self::dispatch(&pool, &mut pending, id, connection, &options, &connections);
+                    }
+                    Arrived::Closed => {
+                        self.stop_waiting(id, &mut waiting);
+                        connections.close(id);
+                    }
+                }
+            }
+            if next_deadline.map(|deadline| Instant::now() >= deadline).unwrap_or(false) {
+                next_deadline = self.time_out(&mut waiting, &options, &connections);
+            }
+        }
+
+        // the requests that have started get to finish, whereas the idle connections are closed
+        let ids: Vec<usize> = waiting.keys().cloned().collect();
+        for id in ids {
+            let connection = self.stop_waiting(id, &mut waiting);
+            if connection.started.is_some() {
+                Self::dispatch(&pool, &mut pending, id, connection, &options, &connections);
+            } else {
+                connections.close(id);
+            }
+        }
+        // we are stopping, so there is nothing else for us to do while we wait for a thread to be free
+        for (id, stream, requests) in pending.drain(..) {
+            if pool.send((id, stream, requests)).is_err() {
+                connections.close(id);
+            }
+        }
+        drop(pool);
+        for (id, _, _) in given_back.try_iter() {
+            connections.close(id);
+        }
+    }
+
+    fn accept(&self, pool: &ThreadPool<(usize, Stream, usize)>, pending: &mut VecDeque<(usize, Stream, usize)>, waiting: &mut HashMap<usize, Waiting>, next_deadline: &mut Option<Instant>, options: &ServerOptions, connections: &Connections) {
+        // the handshakes wait for a thread too
+        while pending.is_empty() {
+            let stream = match self.listener.accept() {
+                Ok(stream) => stream,
+                // either there are no more to accept for now, or eg we have run out of file descriptors
+                Err(_) => return,
+            };
+            // the handle wakes us up to tell us to stop
+            if connections.stopping() {
+                return;
+            }
+            if let Some(id) = connections.open(&stream) {
+                // the handshake is done on a thread from the pool, as we can not see the head of the request until then
+                if options.is_tls() {
+                    let now = Instant::now();
+                    Self::dispatch(pool, pending, id, Waiting { stream, requests: 0, idle_since: now, started: Some(now) }, options, connections);
+                    continue;
+                }
+                self.wait_for_next_request(id, stream, 0, waiting, next_deadline, options, connections);
+            }
+        }
+    }
+
+    // the connections that a thread from the pool is done with, until their next request
+    fn take_back(&self, given_back: &Receiver<(usize, Stream, usize)>, waiting: &mut HashMap<usize, Waiting>, next_deadline: &mut Option<Instant>, options: &ServerOptions, connections: &Connections) -> Vec<usize> {
+        given_back.try_iter().map(|(id, stream, requests)| {
+            self.wait_for_next_request(id, stream, requests, waiting, next_deadline, options, connections);
+            id
+        }).collect()
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn wait_for_next_request(&self, id: usize, stream: Stream, requests: usize, waiting: &mut HashMap<usize, Waiting>, next_deadline: &mut Option<Instant>, options: &ServerOptions, connections: &Connections) {
+        let registered = stream.set_nonblocking(true)
+            .and_then(|_| self.poll.registry().register(&mut SourceFd(&stream.as_raw_fd()), Token(id), Interest::READABLE));
+        if registered.is_err() {
+            connections.close(id);
+            return;
+        }
+        connections.set_idle(id, true);
+        let connection = Waiting { stream, requests, idle_since: Instant::now(), started: None };
+        Self::earlier(next_deadline, connection.deadline(options));
+        waiting.insert(id, connection);
+    }
+
+    fn stop_waiting(&self, id: usize, waiting: &mut HashMap<usize, Waiting>) -> Waiting {
+        let connection = waiting.remove(&id).unwrap();
+        let _ = self.poll.registry().deregister(&mut SourceFd(&connection.stream.as_raw_fd()));
+        connection
+    }
+
+    // the head has arrived once there is an empty line, or once there is too much of it to be read anyway
+    fn arrived(stream: &Stream, peeked: &mut [u8]) -> Arrived {
+        // over TLS each peek decrypts a bit more, and what it has already read off the socket we will not be woken up for
+        let mut read = 0;
+        while read < peeked.len() {
+            match stream.peek(peeked) {
+                Ok(0) => return Arrived::Closed,
+                Ok(more) if more > read => read = more,
+                Ok(_) => break,
+                Err(e) if e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::Interrupted => break,
+                Err(_) => return Arrived::Closed,
+            }
+        }
+        let head = &peeked[..read];
+        // a bare line feed is not allowed, but it is up to reading the message to say so
+        if read == 0 {
+            Arrived::Nothing
+        } else if read == peeked.len() || head.windows(4).any(|end| end == b"\r\n\r\n") || head.windows(2).any(|end| end == b"\n\n") {
+            Arrived::TheHead
+        } else {
+            Arrived::PartOfTheHead
+        }
+    }
+
+    fn dispatch(pool: &ThreadPool<(usize, Stream, usize)>, pending: &mut VecDeque<(usize, Stream, usize)>, id: usize, connection: Waiting, options: &ServerOptions, connections: &Connections) {
+        if connection.stream.set_nonblocking(false).is_err() {
+            connections.close(id);
+            return;
+        }
+        connections.set_idle(id, false);
+        // behind the ones that have waited longer
+        if !pending.is_empty() {
+            pending.push_back((id, connection.stream, connection.requests));
+            return;
+        }
+        if let Err((id, stream, requests)) = pool.try_send((id, connection.stream, connection.requests)) {
+            match &options.overload_policy {
+                // a thread finishing wakes us up to try again
+                OverloadPolicy::Queue => pending.push_back((id, stream, requests)),
+                OverloadPolicy::Reject { retry_after } => {
+                    Server::reject(stream, retry_after);
+                    connections.close(id);
+                }
+                OverloadPolicy::Close => connections.close(id),
+            }
+        }
+    }
+
+    fn dispatch_pending(pool: &ThreadPool<(usize, Stream, usize)>, pending: &mut VecDeque<(usize, Stream, usize)>) {
+        while let Some(next) = pending.pop_front() {
+            if let Err(next) = pool.try_send(next) {
+                pending.push_front(next);
+                return;
+            }
+        }
+    }
+
+    // closes the connections that have waited too long, and returns when the next one will have
+    fn time_out(&self, waiting: &mut HashMap<usize, Waiting>, options: &ServerOptions, connections: &Connections) -> Option<Instant> {
+        let now = Instant::now();
+        let timed_out: Vec<usize> = waiting.iter()
+            .filter(|(_, connection)| connection.deadline(options) <= now)
+            .map(|(id, _)| *id)
+            .collect();
+        for id in timed_out {
+            let connection = self.stop_waiting(id, waiting);
+            // an idle connection has not started a request, so there is no one to tell
+            if connection.started.is_some() && connection.stream.set_nonblocking(false).is_ok() {
+                Server::time_out(connection.stream);
+            }
+            connections.close(id);
+        }
+        waiting.values().map(|connection| connection.deadline(options)).min()
+    }
+
+    fn earlier(next_deadline: &mut Option<Instant>, deadline: Instant) {
+        if next_deadline.map(|next| deadline < next).unwrap_or(true) {
+            *next_deadline = Some(deadline);
+        }
+    }
+}
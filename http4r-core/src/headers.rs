@@ -1,3 +1,5 @@
+use crate::http_message::{is_token, MessageError};
+
 pub struct Headers {
     pub vec: HeadersType,
 }
@@ -163,36 +165,58 @@ impl Headers {
         return self.get(header_name).is_some();
     }
 
-    pub fn content_length_header(&self) -> Option<Result<usize, String>> {
-        let value = self.get("Content-Length");
-        let value = Self::parse_or_else_value(value);
-        match value {
-            Some(Err(ref header_value)) => {
-                let split = header_value.split(", ").map(|it| it.to_string()).collect::<Vec<String>>();
-                let first = split.first().map(|it| it.to_string());
-                if split.iter().all(|v| v == first.as_ref().unwrap()) {
-                    Self::parse_or_else_value(first)
-                } else {
-                    value.clone()
-                }
+    /*
+     https://www.rfc-editor.org/rfc/rfc9110#section-8.6
+     Content-Length = 1*DIGIT
+     a recipient MUST either reject the message as invalid or replace the duplicated field-values
+     with a single valid Content-Length field containing that decimal value
+     */
+    pub fn content_length_header(&self) -> Option<Result<usize, MessageError>> {
+        self.get("Content-Length").map(|value| {
+            let values = value.split(",").map(|it| it.trim()).collect::<Vec<&str>>();
+            let first = values[0];
+            if values.iter().any(|v| v.is_empty() || !v.chars().all(|c| c.is_ascii_digit())) {
+                Err(MessageError::InvalidContentLength(format!("Content Length header couldn't be parsed, got {}", first)))
+            } else if values.iter().any(|v| v != &first) {
+                Err(MessageError::ConflictingContentLength(format!("Content Length header has conflicting values {}", value)))
+            } else {
+                first.parse::<usize>()
+                    .map_err(|_| MessageError::InvalidContentLength(format!("Content Length header couldn't be parsed, got {}", first)))
             }
-            _ => value
-        }
-    }
-
-    fn parse_or_else_value(value: Option<String>) -> Option<Result<usize, String>> {
-        value.as_ref().map(|x| x.parse::<usize>())
-            .map(|r| r.map_err(|_| value.unwrap()))
+        })
     }
 
-    pub fn parse_from(header_string: &str) -> Headers {
+    /*
+     https://www.rfc-editor.org/rfc/rfc9112#section-5
+     field-line   = field-name ":" OWS field-value OWS
+     No whitespace is allowed between the field name and colon.
+     A server MUST reject, with a response status code of 400 (Bad Request), any received request message
+     that contains whitespace between a header field name and colon.
+     https://www.rfc-editor.org/rfc/rfc9112#section-5.2
+     A server that receives an obs-fold in a request message ... MUST either reject the message by sending
+     a 400 (Bad Request) ... or replace each received obs-fold with one or more SP octets
+     */
+    pub fn parse_from(header_string: &str) -> Result<Headers, MessageError> {
         if header_string.is_empty() {
-            return Headers::empty();
+            return Ok(Headers::empty());
         }
-        header_string.split("\r\n").fold(Headers::empty(), |acc, pair| {
-            let pair = pair.split(": ").collect::<Vec<&str>>();
-
-            acc.add((pair[0], pair[1]))
+        header_string.split("\r\n").try_fold(Headers::empty(), |acc, line| {
+            if line.contains('\n') {
+                return Err(MessageError::BareLineFeed(format!("Line feed without a carriage return in header {}", line)));
+            }
+            if line.starts_with(' ') || line.starts_with('\t') {
+                return Err(MessageError::ObsFold(format!("Header folded onto more than one line {}", line)));
+            }
+            let mut pair = line.splitn(2, ":");
+            match (pair.next(), pair.next()) {
+                (Some(name), Some(_)) if name.ends_with(' ') || name.ends_with('\t') => {
+                    Err(MessageError::WhitespaceBeforeColon(format!("Whitespace before the colon in header {}", line)))
+                }
+                (Some(name), Some(value)) if is_token(name) && !value.contains('\r') => {
+                    Ok(acc.add((name, value.trim_matches(|c| c == ' ' || c == '\t'))))
+                }
+                _ => Err(MessageError::MalformedHeader(format!("Malformed header {}", line)))
+            }
         })
     }
 
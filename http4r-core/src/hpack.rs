@@ -0,0 +1,424 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::OnceLock;
+
+/*
+ https://www.rfc-editor.org/rfc/rfc7541
+ HPACK, the compression for the headers of HTTP/2.
+ Each side keeps a table of the headers it has seen, so that a header sent again is only an index into it.
+ The decoder keeps the table the other side tells it to,
+ whereas the encoder only ever refers to the static table, so that it never has to tell the other side about a table of its own.
+ */
+
+// https://www.rfc-editor.org/rfc/rfc7541#appendix-A
+static STATIC_TABLE: [(&str, &str); 61] = [
+    (":authority", ""),
+    (":method", "GET"),
+    (":method", "POST"),
+    (":path", "/"),
+    (":path", "/index.html"),
+    (":scheme", "http"),
+    (":scheme", "https"),
+    (":status", "200"),
+    (":status", "204"),
+    (":status", "206"),
+    (":status", "304"),
+    (":status", "400"),
+    (":status", "404"),
+    (":status", "500"),
+    ("accept-charset", ""),
+    ("accept-encoding", "gzip, deflate"),
+    ("accept-language", ""),
+    ("accept-ranges", ""),
+    ("accept", ""),
+    ("access-control-allow-origin", ""),
+    ("age", ""),
+    ("allow", ""),
+    ("authorization", ""),
+    ("cache-control", ""),
+    ("content-disposition", ""),
+    ("content-encoding", ""),
+    ("content-language", ""),
+    ("content-length", ""),
+    ("content-location", ""),
+    ("content-range", ""),
+    ("content-type", ""),
+    ("cookie", ""),
+    ("date", ""),
+    ("etag", ""),
+    ("expect", ""),
+    ("expires", ""),
+    ("from", ""),
+    ("host", ""),
+    ("if-match", ""),
+    ("if-modified-since", ""),
+    ("if-none-match", ""),
+    ("if-range", ""),
+    ("if-unmodified-since", ""),
+    ("last-modified", ""),
+    ("link", ""),
+    ("location", ""),
+    ("max-forwards", ""),
+    ("proxy-authenticate", ""),
+    ("proxy-authorization", ""),
+    ("range", ""),
+    ("referer", ""),
+    ("refresh", ""),
+    ("retry-after", ""),
+    ("server", ""),
+    ("set-cookie", ""),
+    ("strict-transport-security", ""),
+    ("transfer-encoding", ""),
+    ("user-agent", ""),
+    ("vary", ""),
+    ("via", ""),
+    ("www-authenticate", ""),
+];
+
+// https://www.rfc-editor.org/rfc/rfc7541#appendix-B
+// the code and how many bits long it is, for each octet and then the end of string
+static HUFFMAN_CODES: [(u32, u8); 257] = [
+    (0x1ff8, 13), (0x7fffd8, 23), (0xfffffe2, 28), (0xfffffe3, 28), (0xfffffe4, 28), (0xfffffe5, 28),
+    (0xfffffe6, 28), (0xfffffe7, 28), (0xfffffe8, 28), (0xffffea, 24), (0x3ffffffc, 30), (0xfffffe9, 28),
+    (0xfffffea, 28), (0x3ffffffd, 30), (0xfffffeb, 28), (0xfffffec, 28), (0xfffffed, 28), (0xfffffee, 28),
+    (0xfffffef, 28), (0xffffff0, 28), (0xffffff1, 28), (0xffffff2, 28), (0x3ffffffe, 30), (0xffffff3, 28),
+    (0xffffff4, 28), (0xffffff5, 28), (0xffffff6, 28), (0xffffff7, 28), (0xffffff8, 28), (0xffffff9, 28),
+    (0xffffffa, 28), (0xffffffb, 28), (0x14, 6), (0x3f8, 10), (0x3f9, 10), (0xffa, 12),
+    (0x1ff9, 13), (0x15, 6), (0xf8, 8), (0x7fa, 11), (0x3fa, 10), (0x3fb, 10),
+    (0xf9, 8), (0x7fb, 11), (0xfa, 8), (0x16, 6), (0x17, 6), (0x18, 6),
+    (0x0, 5), (0x1, 5), (0x2, 5), (0x19, 6), (0x1a, 6), (0x1b, 6),
+    (0x1c, 6), (0x1d, 6), (0x1e, 6), (0x1f, 6), (0x5c, 7), (0xfb, 8),
+    (0x7ffc, 15), (0x20, 6), (0xffb, 12), (0x3fc, 10), (0x1ffa, 13), (0x21, 6),
+    (0x5d, 7), (0x5e, 7), (0x5f, 7), (0x60, 7), (0x61, 7), (0x62, 7),
+    (0x63, 7), (0x64, 7), (0x65, 7), (0x66, 7), (0x67, 7), (0x68, 7),
+    (0x69, 7), (0x6a, 7), (0x6b, 7), (0x6c, 7), (0x6d, 7), (0x6e, 7),
+    (0x6f, 7), (0x70, 7), (0x71, 7), (0x72, 7), (0xfc, 8), (0x73, 7),
+    (0xfd, 8), (0x1ffb, 13), (0x7fff0, 19), (0x1ffc, 13), (0x3ffc, 14), (0x22, 6),
+    (0x7ffd, 15), (0x3, 5), (0x23, 6), (0x4, 5), (0x24, 6), (0x5, 5),
+    (0x25, 6), (0x26, 6), (0x27, 6), (0x6, 5), (0x74, 7), (0x75, 7),
+    (0x28, 6), (0x29, 6), (0x2a, 6), (0x7, 5), (0x2b, 6), (0x76, 7),
+    (0x2c, 6), (0x8, 5), (0x9, 5), (0x2d, 6), (0x77, 7), (0x78, 7),
+    (0x79, 7), (0x7a, 7), (0x7b, 7), (0x7ffe, 15), (0x7fc, 11), (0x3ffd, 14),
+    (0x1ffd, 13), (0xffffffc, 28), (0xfffe6, 20), (0x3fffd2, 22), (0xfffe7, 20), (0xfffe8, 20),
+    (0x3fffd3, 22), (0x3fffd4, 22), (0x3fffd5, 22), (0x7fffd9, 23), (0x3fffd6, 22), (0x7fffda, 23),
+    (0x7fffdb, 23), (0x7fffdc, 23), (0x7fffdd, 23), (0x7fffde, 23), (0xffffeb, 24), (0x7fffdf, 23),
+    (0xffffec, 24), (0xffffed, 24), (0x3fffd7, 22), (0x7fffe0, 23), (0xffffee, 24), (0x7fffe1, 23),
+    (0x7fffe2, 23), (0x7fffe3, 23), (0x7fffe4, 23), (0x1fffdc, 21), (0x3fffd8, 22), (0x7fffe5, 23),
+    (0x3fffd9, 22), (0x7fffe6, 23), (0x7fffe7, 23), (0xffffef, 24), (0x3fffda, 22), (0x1fffdd, 21),
+    (0xfffe9, 20), (0x3fffdb, 22), (0x3fffdc, 22), (0x7fffe8, 23), (0x7fffe9, 23), (0x1fffde, 21),
+    (0x7fffea, 23), (0x3fffdd, 22), (0x3fffde, 22), (0xfffff0, 24), (0x1fffdf, 21), (0x3fffdf, 22),
+    (0x7fffeb, 23), (0x7fffec, 23), (0x1fffe0, 21), (0x1fffe1, 21), (0x3fffe0, 22), (0x1fffe2, 21),
+    (0x7fffed, 23), (0x3fffe1, 22), (0x7fffee, 23), (0x7fffef, 23), (0xfffea, 20), (0x3fffe2, 22),
+    (0x3fffe3, 22), (0x3fffe4, 22), (0x7ffff0, 23), (0x3fffe5, 22), (0x3fffe6, 22), (0x7ffff1, 23),
+    (0x3ffffe0, 26), (0x3ffffe1, 26), (0xfffeb, 20), (0x7fff1, 19), (0x3fffe7, 22), (0x7ffff2, 23),
+    (0x3fffe8, 22), (0x1ffffec, 25), (0x3ffffe2, 26), (0x3ffffe3, 26), (0x3ffffe4, 26), (0x7ffffde, 27),
+    (0x7ffffdf, 27), (0x3ffffe5, 26), (0xfffff1, 24), (0x1ffffed, 25), (0x7fff2, 19), (0x1fffe3, 21),
+    (0x3ffffe6, 26), (0x7ffffe0, 27), (0x7ffffe1, 27), (0x3ffffe7, 26), (0x7ffffe2, 27), (0xfffff2, 24),
+    (0x1fffe4, 21), (0x1fffe5, 21), (0x3ffffe8, 26), (0x3ffffe9, 26), (0xffffffd, 28), (0x7ffffe3, 27),
+    (0x7ffffe4, 27), (0x7ffffe5, 27), (0xfffec, 20), (0xfffff3, 24), (0xfffed, 20), (0x1fffe6, 21),
+    (0x3fffe9, 22), (0x1fffe7, 21), (0x1fffe8, 21), (0x7ffff3, 23), (0x3fffea, 22), (0x3fffeb, 22),
+    (0x1ffffee, 25), (0x1ffffef, 25), (0xfffff4, 24), (0xfffff5, 24), (0x3ffffea, 26), (0x7ffff4, 23),
+    (0x3ffffeb, 26), (0x7ffffe6, 27), (0x3ffffec, 26), (0x3ffffed, 26), (0x7ffffe7, 27), (0x7ffffe8, 27),
+    (0x7ffffe9, 27), (0x7ffffea, 27), (0x7ffffeb, 27), (0xffffffe, 28), (0x7ffffec, 27), (0x7ffffed, 27),
+    (0x7ffffee, 27), (0x7ffffef, 27), (0x7fffff0, 27), (0x3ffffee, 26), (0x3fffffff, 30),
+];
+
+// headers that must not end up in a table, where they could be worked out from how well they compress
+static NEVER_INDEXED: [&str; 3] = ["authorization", "cookie", "set-cookie"];
+
+#[derive(Debug, PartialEq)]
+pub enum HpackError {
+    // the block can not be decoded, so the table may now be out of step with the other side
+    Compression(String),
+    // the headers decode to more than we will take, however small the block was
+    HeaderListTooBig(usize),
+}
+
+impl fmt::Display for HpackError {
+    fn fmt(&self, format: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HpackError::Compression(msg) => format.write_str(msg),
+            HpackError::HeaderListTooBig(max) => write!(format, "Header list must be no more than {} bytes", max),
+        }
+    }
+}
+
+impl From<String> for HpackError {
+    fn from(msg: String) -> HpackError {
+        HpackError::Compression(msg)
+    }
+}
+
+/// Decodes header blocks, keeping the dynamic table in step with the encoder on the other side.
+pub struct Decoder {
+    table: DynamicTable,
+    // the most the other side is allowed to make the table, ie our SETTINGS_HEADER_TABLE_SIZE
+    max_table_size: usize,
+}
+
+impl Decoder {
+    pub fn new(max_table_size: usize) -> Decoder {
+        Decoder { table: DynamicTable::new(max_table_size), max_table_size }
+    }
+
+    /// Decodes a header block into no more than max_header_list_size bytes of headers,
+    /// so that a small block of references to a big table entry can not use up our memory.
+    pub fn decode(&mut self, block: &[u8], max_header_list_size: usize) -> Result<Vec<(String, String)>, HpackError> {
+        let mut headers = vec!();
+        let mut header_list_size = 0;
+        let mut at = 0;
+        while at < block.len() {
+            let first = block[at];
+            if first & 0x80 != 0 {
+                // https://www.rfc-editor.org/rfc/rfc7541#section-6.1
+                let index = decode_integer(block, &mut at, 7)?;
+                let (name, value) = self.entry(index)?;
+                headers.push((name, value));
+            } else if first & 0x40 != 0 {
+                // https://www.rfc-editor.org/rfc/rfc7541#section-6.2.1
+                let (name, value) = self.literal(block, &mut at, 6)?;
+                self.table.insert(name.clone(), value.clone());
+                headers.push((name, value));
+            } else if first & 0x20 != 0 {
+                // https://www.rfc-editor.org/rfc/rfc7541#section-6.3
+                if !headers.is_empty() {
+                    return Err(HpackError::Compression("Dynamic table size update must come before any headers".to_string()));
+                }
+                let size = decode_integer(block, &mut at, 5)?;
+                if size > self.max_table_size {
+                    return Err(HpackError::Compression(format!("Dynamic table size must be no more than {}", self.max_table_size)));
+                }
+                self.table.resize(size);
+                continue;
+            } else {
+                // without indexing, or never indexed, which are the same to us as we do not pass headers on as they are
+                // https://www.rfc-editor.org/rfc/rfc7541#section-6.2.2
+                let (name, value) = self.literal(block, &mut at, 4)?;
+                headers.push((name, value));
+            }
+            /*
+             https://www.rfc-editor.org/rfc/rfc9113#section-6.5.2
+             The value is based on the uncompressed size of header fields, including the length of the name and value
+             in octets plus an overhead of 32 octets for each header field.
+             */
+            let (name, value) = headers.last().unwrap();
+            header_list_size += name.len() + value.len() + 32;
+            if header_list_size > max_header_list_size {
+                return Err(HpackError::HeaderListTooBig(max_header_list_size));
+            }
+        }
+        Ok(headers)
+    }
+
+    fn literal(&self, block: &[u8], at: &mut usize, prefix: u8) -> Result<(String, String), String> {
+        let index = decode_integer(block, at, prefix)?;
+        let name = if index == 0 {
+            decode_string(block, at)?
+        } else {
+            self.entry(index)?.0
+        };
+        let value = decode_string(block, at)?;
+        Ok((name, value))
+    }
+
+    fn entry(&self, index: usize) -> Result<(String, String), String> {
+        match index {
+            0 => Err("Index must not be 0".to_string()),
+            index if index <= STATIC_TABLE.len() => {
+                let (name, value) = STATIC_TABLE[index - 1];
+                Ok((name.to_string(), value.to_string()))
+            }
+            index => self.table.get(index - STATIC_TABLE.len() - 1)
+                .ok_or(format!("Index {} is not in the table", index)),
+        }
+    }
+}
+
+/// Encodes headers without adding to a dynamic table, so the other side's table stays empty.
+pub fn encode(headers: &[(String, String)], into: &mut Vec<u8>) {
+    for (name, value) in headers {
+        let name = name.as_str();
+        let value = value.as_str();
+        match STATIC_TABLE.iter().position(|entry| *entry == (name, value)) {
+            Some(index) => encode_integer(index + 1, 7, 0x80, into),
+            None => {
+                let never_indexed = NEVER_INDEXED.contains(&name);
+                let flags = if never_indexed { 0x10 } else { 0x00 };
+                match STATIC_TABLE.iter().position(|entry| entry.0 == name) {
+                    Some(index) => encode_integer(index + 1, 4, flags, into),
+                    None => {
+                        into.push(flags);
+                        encode_string(name, into);
+                    }
+                }
+                encode_string(value, into);
+            }
+        }
+    }
+}
+
+/*
+ https://www.rfc-editor.org/rfc/rfc7541#section-4
+ The size of an entry is the sum of its name's length in octets, its value's length in octets, and 32.
+ */
+struct DynamicTable {
+    // newest first, as that is the order they are indexed in
+    entries: VecDeque<(String, String)>,
+    size: usize,
+    max_size: usize,
+}
+
+impl DynamicTable {
+    fn new(max_size: usize) -> DynamicTable {
+        DynamicTable { entries: VecDeque::new(), size: 0, max_size }
+    }
+
+    fn get(&self, index: usize) -> Option<(String, String)> {
+        self.entries.get(index).cloned()
+    }
+
+    // an entry bigger than the whole table empties it and is not added
+    fn insert(&mut self, name: String, value: String) {
+        let size = name.len() + value.len() + 32;
+        self.evict_until(self.max_size.saturating_sub(size));
+        if size <= self.max_size {
+            self.size += size;
+            self.entries.push_front((name, value));
+        }
+    }
+
+    fn resize(&mut self, max_size: usize) {
+        self.max_size = max_size;
+        self.evict_until(max_size);
+    }
+
+    fn evict_until(&mut self, size: usize) {
+        while self.size > size {
+            match self.entries.pop_back() {
+                Some((name, value)) => self.size -= name.len() + value.len() + 32,
+                None => break,
+            }
+        }
+    }
+}
+
+// https://www.rfc-editor.org/rfc/rfc7541#section-5.1
+fn decode_integer(block: &[u8], at: &mut usize, prefix: u8) -> Result<usize, String> {
+    let max_prefix = (1usize << prefix) - 1;
+    let first = *block.get(*at).ok_or("Header block ended part way through an integer")?;
+    *at += 1;
+    let mut value = (first as usize) & max_prefix;
+    if value < max_prefix {
+        return Ok(value);
+    }
+    let mut shift = 0;
+    loop {
+        let octet = *block.get(*at).ok_or("Header block ended part way through an integer")?;
+        *at += 1;
+        // anything this big is not a length or an index we could have
+        if shift > 28 {
+            return Err("Integer is too big".to_string());
+        }
+        value += ((octet & 0x7f) as usize) << shift;
+        shift += 7;
+        if octet & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+}
+
+fn encode_integer(value: usize, prefix: u8, flags: u8, into: &mut Vec<u8>) {
+    let max_prefix = (1usize << prefix) - 1;
+    if value < max_prefix {
+        into.push(flags | value as u8);
+        return;
+    }
+    into.push(flags | max_prefix as u8);
+    let mut rest = value - max_prefix;
+    while rest >= 128 {
+        into.push((rest % 128) as u8 | 0x80);
+        rest /= 128;
+    }
+    into.push(rest as u8);
+}
+
+// https://www.rfc-editor.org/rfc/rfc7541#section-5.2
+fn decode_string(block: &[u8], at: &mut usize) -> Result<String, String> {
+    let huffman = block.get(*at).map(|first| first & 0x80 != 0).unwrap_or(false);
+    let length = decode_integer(block, at, 7)?;
+    let end = at.checked_add(length).filter(|end| *end <= block.len())
+        .ok_or("Header block ended part way through a string")?;
+    let octets = &block[*at..end];
+    *at = end;
+    let decoded = if huffman { huffman_decode(octets)? } else { octets.to_vec() };
+    Ok(String::from_utf8_lossy(&decoded).to_string())
+}
+
+// we do not bother with huffman coding, as the other side has to understand a plain string too
+fn encode_string(str: &str, into: &mut Vec<u8>) {
+    encode_integer(str.len(), 7, 0x00, into);
+    into.extend_from_slice(str.as_bytes());
+}
+
+// each node has a child for a 0 bit and a 1 bit, either another node or, once negative, the symbol it decodes to
+fn huffman_tree() -> &'static Vec<[i32; 2]> {
+    static TREE: OnceLock<Vec<[i32; 2]>> = OnceLock::new();
+    TREE.get_or_init(|| {
+        let mut tree = vec!([0, 0]);
+        for (symbol, (code, length)) in HUFFMAN_CODES.iter().enumerate() {
+            let mut node = 0;
+            for bit in (0..*length).rev() {
+                let branch = ((code >> bit) & 1) as usize;
+                if bit == 0 {
+                    tree[node][branch] = -(symbol as i32) - 1;
+                } else {
+                    if tree[node][branch] == 0 {
+                        tree.push([0, 0]);
+                        tree[node][branch] = (tree.len() - 1) as i32;
+                    }
+                    node = tree[node][branch] as usize;
+                }
+            }
+        }
+        tree
+    })
+}
+
+/*
+ https://www.rfc-editor.org/rfc/rfc7541#section-5.2
+ A padding strictly longer than 7 bits MUST be treated as a decoding error.
+ A padding not corresponding to the most significant bits of the code for the EOS symbol MUST be treated as a decoding error.
+ A Huffman-encoded string literal containing the EOS symbol MUST be treated as a decoding error.
+ */
+pub fn huffman_decode(octets: &[u8]) -> Result<Vec<u8>, String> {
+    let tree = huffman_tree();
+    let mut decoded = Vec::with_capacity(octets.len() * 8 / 5);
+    let mut node = 0;
+    let mut bits_since_symbol = 0;
+    let mut all_ones = true;
+    for octet in octets {
+        for bit in (0..8).rev() {
+            let branch = ((octet >> bit) & 1) as usize;
+            bits_since_symbol += 1;
+            all_ones = all_ones && branch == 1;
+            match tree[node][branch] {
+                0 => return Err("Invalid huffman code".to_string()),
+                next if next < 0 => {
+                    let symbol = (-next - 1) as usize;
+                    if symbol == 256 {
+                        return Err("Huffman string must not contain the end of string symbol".to_string());
+                    }
+                    decoded.push(symbol as u8);
+                    node = 0;
+                    bits_since_symbol = 0;
+                    all_ones = true;
+                }
+                next => node = next as usize,
+            }
+        }
+    }
+    if bits_since_symbol > 7 || !all_ones {
+        return Err("Invalid huffman padding".to_string());
+    }
+    Ok(decoded)
+}
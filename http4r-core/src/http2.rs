@@ -0,0 +1,1419 @@
+use std::cmp::min;
+use std::fmt;
+use std::collections::{HashMap, VecDeque};
+use std::io::{ErrorKind, Read, Write};
+use std::net::Shutdown;
+use std::panic::{AssertUnwindSafe, catch_unwind};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, Instant};
+use crate::client::{ClientError, ClientOptions, WithDeadline};
+use crate::connection::Connection;
+use crate::codex::Codex;
+use crate::handler::Handler;
+use crate::headers::Headers;
+use crate::hpack;
+use crate::hpack::{Decoder, HpackError};
+use crate::http_message::{compression_from, limited_decoder, MessageError, Method, Request, RequestOptions, Response, Status, Trailers, two_pt_oh};
+use crate::http_message::Body::{BodyBytes, BodyStream, BodyString};
+use crate::http_message::Body;
+use crate::http_message::Method::HEAD;
+use crate::server::{Connections, ServerOptions};
+use crate::socket::Stream;
+use crate::uri::Uri;
+
+/*
+ https://www.rfc-editor.org/rfc/rfc9113
+ HTTP/2 sends each request and response as a stream of frames, many streams at once over the one connection.
+ Each stream is still one request and one response, so handlers see the same Request and Response as over HTTP/1.1.
+ */
+
+// https://www.rfc-editor.org/rfc/rfc9113#section-3.4
+pub const PREFACE: &[u8; 24] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+
+// https://www.rfc-editor.org/rfc/rfc9113#section-6
+pub const DATA: u8 = 0x0;
+pub const HEADERS: u8 = 0x1;
+pub const PRIORITY: u8 = 0x2;
+pub const RST_STREAM: u8 = 0x3;
+pub const SETTINGS: u8 = 0x4;
+pub const PUSH_PROMISE: u8 = 0x5;
+pub const PING: u8 = 0x6;
+pub const GOAWAY: u8 = 0x7;
+pub const WINDOW_UPDATE: u8 = 0x8;
+pub const CONTINUATION: u8 = 0x9;
+
+pub const END_STREAM: u8 = 0x1;
+pub const ACK: u8 = 0x1;
+pub const END_HEADERS: u8 = 0x4;
+pub const PADDED: u8 = 0x8;
+pub const PRIORITY_FLAG: u8 = 0x20;
+
+// https://www.rfc-editor.org/rfc/rfc9113#section-6.5.2
+pub const SETTINGS_HEADER_TABLE_SIZE: u16 = 0x1;
+pub const SETTINGS_ENABLE_PUSH: u16 = 0x2;
+pub const SETTINGS_MAX_CONCURRENT_STREAMS: u16 = 0x3;
+pub const SETTINGS_INITIAL_WINDOW_SIZE: u16 = 0x4;
+pub const SETTINGS_MAX_FRAME_SIZE: u16 = 0x5;
+pub const SETTINGS_MAX_HEADER_LIST_SIZE: u16 = 0x6;
+
+// https://www.rfc-editor.org/rfc/rfc9113#section-7
+pub const NO_ERROR: u32 = 0x0;
+pub const PROTOCOL_ERROR: u32 = 0x1;
+pub const INTERNAL_ERROR: u32 = 0x2;
+pub const FLOW_CONTROL_ERROR: u32 = 0x3;
+pub const STREAM_CLOSED: u32 = 0x5;
+pub const FRAME_SIZE_ERROR: u32 = 0x6;
+pub const REFUSED_STREAM: u32 = 0x7;
+pub const CANCEL: u32 = 0x8;
+pub const COMPRESSION_ERROR: u32 = 0x9;
+pub const ENHANCE_YOUR_CALM: u32 = 0xb;
+
+// https://www.rfc-editor.org/rfc/rfc9113#section-6.9.1
+const MAX_WINDOW_SIZE: i64 = 2147483647;
+const DEFAULT_WINDOW_SIZE: i64 = 65535;
+const DEFAULT_MAX_FRAME_SIZE: usize = 16384;
+
+// https://www.rfc-editor.org/rfc/rfc9113#section-8.2.2
+static CONNECTION_SPECIFIC_HEADERS: [&str; 5] = ["connection", "keep-alive", "proxy-connection", "transfer-encoding", "upgrade"];
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Http2Options {
+    // how many requests a client can have in flight on one connection, each has a thread of its own
+    pub max_concurrent_streams: u32,
+    // how many requests the server handles at once over every connection, past which they are refused
+    pub max_streams: usize,
+    // how much of a request body a client can send before the handler reads it
+    pub initial_window_size: u32,
+    pub max_frame_size: u32,
+    // how big the other side can make the table of headers it has sent, to save sending them again
+    pub header_table_size: u32,
+}
+
+impl Default for Http2Options {
+    fn default() -> Http2Options {
+        Http2Options {
+            max_concurrent_streams: 100,
+            max_streams: 100,
+            initial_window_size: 1048576,
+            max_frame_size: 16384,
+            header_table_size: 4096,
+        }
+    }
+}
+
+/// https://www.rfc-editor.org/rfc/rfc9113#section-4.1
+pub struct Frame {
+    pub kind: u8,
+    pub flags: u8,
+    pub stream_id: u32,
+    pub payload: Vec<u8>,
+}
+
+impl Frame {
+    pub fn new(kind: u8, flags: u8, stream_id: u32, payload: Vec<u8>) -> Frame {
+        Frame { kind, flags, stream_id, payload }
+    }
+
+    pub fn has(&self, flag: u8) -> bool {
+        self.flags & flag != 0
+    }
+
+    // the data or header block, without the padding or priority that can come with it
+    fn content(&self) -> Result<&[u8], Http2Error> {
+        let mut content = self.payload.as_slice();
+        let mut padding = 0;
+        if self.has(PADDED) && (self.kind == DATA || self.kind == HEADERS) {
+            padding = *content.first().ok_or(connection_error(FRAME_SIZE_ERROR, "Padded frame has no pad length"))? as usize;
+            content = &content[1..];
+        }
+        if self.has(PRIORITY_FLAG) && self.kind == HEADERS {
+            content = content.get(5..).ok_or(connection_error(FRAME_SIZE_ERROR, "Headers frame too short for its priority"))?;
+        }
+        // https://www.rfc-editor.org/rfc/rfc9113#section-6.1
+        // If the length of the padding is the length of the frame payload or greater, the recipient MUST treat this as a connection error
+        if padding > content.len() {
+            return Err(connection_error(PROTOCOL_ERROR, "Padding is longer than the frame"));
+        }
+        Ok(&content[..content.len() - padding])
+    }
+
+    fn u32_at(&self, at: usize) -> u32 {
+        u32::from_be_bytes([self.payload[at], self.payload[at + 1], self.payload[at + 2], self.payload[at + 3]])
+    }
+}
+
+#[derive(Debug)]
+pub enum Http2Error {
+    // the connection can not be used any more, so we say why in a GOAWAY
+    Connection(u32, String),
+    // only the stream went wrong, so it is reset and the rest of the connection carries on
+    Stream(u32, u32, String),
+    Io(std::io::Error),
+}
+
+impl fmt::Display for Http2Error {
+    fn fmt(&self, format: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Http2Error::Connection(code, msg) => write!(format, "Connection error {}: {}", code, msg),
+            Http2Error::Stream(id, code, msg) => write!(format, "Stream {} error {}: {}", id, code, msg),
+            Http2Error::Io(e) => write!(format, "{}", e),
+        }
+    }
+}
+
+fn connection_error(code: u32, msg: &str) -> Http2Error {
+    Http2Error::Connection(code, msg.to_string())
+}
+
+fn stream_error(stream_id: u32, code: u32, msg: &str) -> Http2Error {
+    Http2Error::Stream(stream_id, code, msg.to_string())
+}
+
+/// Reads the next frame, which must be no bigger than the most we said we would take.
+pub fn read_frame<R: Read>(reader: &mut R, max_frame_size: u32) -> Result<Frame, Http2Error> {
+    let mut head = [0; 9];
+    reader.read_exact(&mut head).map_err(Http2Error::Io)?;
+    let length = u32::from_be_bytes([0, head[0], head[1], head[2]]);
+    if length > max_frame_size {
+        return Err(Http2Error::Connection(FRAME_SIZE_ERROR, format!("Frame must be no more than {} bytes", max_frame_size)));
+    }
+    let mut payload = vec![0; length as usize];
+    reader.read_exact(&mut payload).map_err(Http2Error::Io)?;
+    // the reserved bit is ignored
+    let stream_id = u32::from_be_bytes([head[5], head[6], head[7], head[8]]) & 0x7fffffff;
+    Ok(Frame::new(head[3], head[4], stream_id, payload))
+}
+
+pub fn write_frame<W: Write>(writer: &mut W, frame: &Frame) -> std::io::Result<()> {
+    let length = (frame.payload.len() as u32).to_be_bytes();
+    let mut bytes = Vec::with_capacity(9 + frame.payload.len());
+    bytes.extend_from_slice(&length[1..]);
+    bytes.push(frame.kind);
+    bytes.push(frame.flags);
+    bytes.extend_from_slice(&frame.stream_id.to_be_bytes());
+    bytes.extend_from_slice(&frame.payload);
+    writer.write_all(&bytes)
+}
+
+fn settings_frame(options: &Http2Options, max_header_list_size: usize, is_server: bool) -> Frame {
+    let mut settings = vec!(
+        (SETTINGS_HEADER_TABLE_SIZE, options.header_table_size),
+        (SETTINGS_INITIAL_WINDOW_SIZE, options.initial_window_size),
+        (SETTINGS_MAX_FRAME_SIZE, options.max_frame_size),
+        (SETTINGS_MAX_HEADER_LIST_SIZE, max_header_list_size as u32),
+    );
+    if is_server {
+        settings.push((SETTINGS_MAX_CONCURRENT_STREAMS, options.max_concurrent_streams));
+    } else {
+        // we would have nowhere to put a response we did not ask for
+        settings.push((SETTINGS_ENABLE_PUSH, 0));
+    }
+    let mut payload = vec!();
+    for (id, value) in settings {
+        payload.extend_from_slice(&id.to_be_bytes());
+        payload.extend_from_slice(&value.to_be_bytes());
+    }
+    Frame::new(SETTINGS, 0, 0, payload)
+}
+
+// https://www.rfc-editor.org/rfc/rfc9113#section-6.5
+fn settings_from(frame: &Frame) -> Result<Vec<(u16, u32)>, Http2Error> {
+    if frame.stream_id != 0 {
+        return Err(connection_error(PROTOCOL_ERROR, "Settings must be for the connection"));
+    }
+    if frame.has(ACK) {
+        return if frame.payload.is_empty() { Ok(vec!()) } else { Err(connection_error(FRAME_SIZE_ERROR, "Settings ack must be empty")) };
+    }
+    if !frame.payload.len().is_multiple_of(6) {
+        return Err(connection_error(FRAME_SIZE_ERROR, "Settings must be a multiple of 6 bytes"));
+    }
+    let mut settings = vec!();
+    for setting in frame.payload.chunks(6) {
+        let id = u16::from_be_bytes([setting[0], setting[1]]);
+        let value = u32::from_be_bytes([setting[2], setting[3], setting[4], setting[5]]);
+        match id {
+            SETTINGS_ENABLE_PUSH if value > 1 => return Err(connection_error(PROTOCOL_ERROR, "Enable push must be 0 or 1")),
+            SETTINGS_INITIAL_WINDOW_SIZE if value as i64 > MAX_WINDOW_SIZE => return Err(connection_error(FLOW_CONTROL_ERROR, "Initial window size is too big")),
+            SETTINGS_MAX_FRAME_SIZE if !(16384..=16777215).contains(&value) => return Err(connection_error(PROTOCOL_ERROR, "Max frame size must be between 16384 and 16777215")),
+            _ => settings.push((id, value)),
+        }
+    }
+    Ok(settings)
+}
+
+fn window_update_frame(stream_id: u32, increment: usize) -> Frame {
+    Frame::new(WINDOW_UPDATE, 0, stream_id, (increment as u32).to_be_bytes().to_vec())
+}
+
+// https://www.rfc-editor.org/rfc/rfc9113#section-6.9
+fn window_increment(frame: &Frame) -> Result<i64, Http2Error> {
+    if frame.payload.len() != 4 {
+        return Err(connection_error(FRAME_SIZE_ERROR, "Window update must be 4 bytes"));
+    }
+    match frame.u32_at(0) & 0x7fffffff {
+        0 if frame.stream_id == 0 => Err(connection_error(PROTOCOL_ERROR, "Window update must not be 0")),
+        0 => Err(stream_error(frame.stream_id, PROTOCOL_ERROR, "Window update must not be 0")),
+        increment => Ok(increment as i64),
+    }
+}
+
+fn rst_stream_frame(stream_id: u32, code: u32) -> Frame {
+    Frame::new(RST_STREAM, 0, stream_id, code.to_be_bytes().to_vec())
+}
+
+fn goaway_frame(last_stream_id: u32, code: u32, msg: &str) -> Frame {
+    let mut payload = last_stream_id.to_be_bytes().to_vec();
+    payload.extend_from_slice(&code.to_be_bytes());
+    payload.extend_from_slice(msg.as_bytes());
+    Frame::new(GOAWAY, 0, 0, payload)
+}
+
+// a header block that is too big for one frame carries on in CONTINUATION frames
+fn write_header_block<W: Write>(writer: &mut W, stream_id: u32, fields: &[(String, String)], end_stream: bool, max_frame_size: usize) -> std::io::Result<()> {
+    let mut block = vec!();
+    hpack::encode(fields, &mut block);
+    let mut fragments = block.chunks(max_frame_size).peekable();
+    let mut kind = HEADERS;
+    let mut bytes = vec!();
+    loop {
+        let fragment = fragments.next().unwrap_or(&[]);
+        let is_last = fragments.peek().is_none();
+        let mut flags = if is_last { END_HEADERS } else { 0 };
+        if kind == HEADERS && end_stream {
+            flags |= END_STREAM;
+        }
+        write_frame(&mut bytes, &Frame::new(kind, flags, stream_id, fragment.to_vec()))?;
+        if is_last {
+            break;
+        }
+        kind = CONTINUATION;
+    }
+    // written in one go, so that nothing else can come in between
+    writer.write_all(&bytes)
+}
+
+// reads the CONTINUATION frames of a header block, if it did not fit in the HEADERS frame
+fn header_block<R: Read>(reader: &mut R, frame: &Frame, max_frame_size: u32, max_block_size: usize) -> Result<Vec<u8>, Http2Error> {
+    let mut block = frame.content()?.to_vec();
+    let mut end_headers = frame.has(END_HEADERS);
+    while !end_headers {
+        let continuation = read_frame(reader, max_frame_size)?;
+        if continuation.kind != CONTINUATION || continuation.stream_id != frame.stream_id {
+            return Err(connection_error(PROTOCOL_ERROR, "Header block must carry on in continuation frames"));
+        }
+        block.extend_from_slice(&continuation.payload);
+        if block.len() > max_block_size {
+            return Err(Http2Error::Connection(COMPRESSION_ERROR, format!("Header block must be no more than {} bytes", max_block_size)));
+        }
+        end_headers = continuation.has(END_HEADERS);
+    }
+    Ok(block)
+}
+
+fn decode(decoder: &mut Decoder, block: &[u8], max_header_list_size: usize) -> Result<Vec<(String, String)>, Http2Error> {
+    decoder.decode(block, max_header_list_size).map_err(|e| match e {
+        // we stop part way through the block, so the table is out of step and the connection can not carry on
+        HpackError::HeaderListTooBig(_) => Http2Error::Connection(ENHANCE_YOUR_CALM, e.to_string()),
+        HpackError::Compression(msg) => Http2Error::Connection(COMPRESSION_ERROR, msg),
+    })
+}
+
+// names in HTTP/2 are lower case, and headers only about the connection mean nothing as each stream is not one
+fn header_fields(headers: &Headers) -> Vec<(String, String)> {
+    headers.vec.iter()
+        .map(|(name, value)| (name.to_lowercase(), value.to_string()))
+        .filter(|(name, value)| !CONNECTION_SPECIFIC_HEADERS.contains(&name.as_str()) && !(name == "te" && value != "trailers"))
+        .collect()
+}
+
+fn headers_from(fields: Vec<(String, String)>) -> Headers {
+    let mut headers = Headers::empty();
+    for (name, value) in fields {
+        headers = headers.add((name.as_str(), value.as_str()));
+    }
+    headers
+}
+
+fn is_timeout(e: &std::io::Error) -> bool {
+    e.kind() == ErrorKind::TimedOut || e.kind() == ErrorKind::WouldBlock
+}
+
+/// Whether the client has started the connection with the HTTP/2 preface,
+/// either because it knew we would speak HTTP/2 or because we agreed to over ALPN.
+pub(crate) fn preface_arrives(stream: &Stream, timeout: Duration) -> bool {
+    let give_up_at = Instant::now() + timeout;
+    let _ = stream.set_read_timeout(Some(timeout));
+    let mut peeked = [0; 24];
+    loop {
+        match stream.peek(&mut peeked) {
+            Ok(0) | Err(_) => return false,
+            Ok(peeked_so_far) if peeked[..peeked_so_far] != PREFACE[..peeked_so_far] => return false,
+            Ok(peeked_so_far) if peeked_so_far == PREFACE.len() => return true,
+            // it looks like the preface so far, so wait for the rest of it
+            Ok(_) if Instant::now() >= give_up_at => return false,
+            Ok(_) => thread::sleep(Duration::from_millis(1)),
+        }
+    }
+}
+
+/// The state of a connection, shared by the thread that reads its frames and the threads handling its streams.
+struct Shared {
+    state: Mutex<ConnectionState>,
+    // anything a stream might be waiting on has changed, eg more of its body arrived or it can send more
+    changed: Condvar,
+    writer: Mutex<Stream>,
+}
+
+struct ConnectionState {
+    streams: HashMap<u32, StreamState>,
+    // how much more we can send on the connection as a whole, and how much each new stream starts with
+    send_window: i64,
+    initial_send_window: i64,
+    // how much more the client can send us on the connection as a whole
+    receive_window: i64,
+    max_frame_size: usize,
+    last_stream_id: u32,
+    requests: usize,
+    // we have said we will not take any more streams
+    going_away: bool,
+    closed: bool,
+}
+
+struct StreamState {
+    body: VecDeque<u8>,
+    end_stream: bool,
+    trailers: Vec<(String, String)>,
+    content_length: Option<usize>,
+    received: usize,
+    send_window: i64,
+    receive_window: i64,
+    reset: bool,
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, ConnectionState> {
+        self.state.lock().unwrap()
+    }
+
+    fn write(&self, frame: Frame) -> std::io::Result<()> {
+        let mut writer = self.writer.lock().unwrap();
+        write_frame(&mut *writer, &frame)?;
+        writer.flush()
+    }
+
+    fn write_headers(&self, stream_id: u32, fields: &[(String, String)], end_stream: bool) -> std::io::Result<()> {
+        let max_frame_size = self.lock().max_frame_size;
+        let mut writer = self.writer.lock().unwrap();
+        write_header_block(&mut *writer, stream_id, fields, end_stream, max_frame_size)?;
+        writer.flush()
+    }
+
+    // waits until either the window lets us send some of the data, or there is no point waiting any more
+    fn send_data(&self, stream_id: u32, mut data: &[u8], end_stream: bool, timeout: Duration) -> std::io::Result<()> {
+        loop {
+            let mut state = self.lock();
+            let give_up_at = Instant::now() + timeout;
+            let sending = loop {
+                if state.closed {
+                    return Err(std::io::Error::new(ErrorKind::BrokenPipe, "Connection closed"));
+                }
+                let stream = state.streams.get(&stream_id);
+                if stream.map(|stream| stream.reset).unwrap_or(true) {
+                    return Err(std::io::Error::new(ErrorKind::ConnectionReset, "Stream reset"));
+                }
+                let window = min(state.send_window, stream.unwrap().send_window);
+                let sending = min(min(window.max(0) as usize, data.len()), state.max_frame_size);
+                if sending > 0 || data.is_empty() {
+                    break sending;
+                }
+                let now = Instant::now();
+                if now >= give_up_at {
+                    return Err(std::io::Error::new(ErrorKind::TimedOut, "Timed out waiting for the window to open"));
+                }
+                state = self.changed.wait_timeout(state, give_up_at - now).unwrap().0;
+            };
+            state.send_window -= sending as i64;
+            state.streams.get_mut(&stream_id).unwrap().send_window -= sending as i64;
+            drop(state);
+            let is_last = sending == data.len();
+            let flags = if is_last && end_stream { END_STREAM } else { 0 };
+            self.write(Frame::new(DATA, flags, stream_id, data[..sending].to_vec()))?;
+            data = &data[sending..];
+            if is_last {
+                return Ok(());
+            }
+        }
+    }
+
+    fn close(&self) {
+        self.lock().closed = true;
+        self.changed.notify_all();
+    }
+}
+
+/// What a client sent to start a stream, checked but not yet made into a Request,
+/// as a Request has to be made on the thread that handles it.
+struct RequestHead {
+    method: String,
+    path: String,
+    authority: Option<String>,
+    fields: Vec<(String, String)>,
+    end_stream: bool,
+}
+
+/*
+ https://www.rfc-editor.org/rfc/rfc9113#section-8.3.1
+ All HTTP/2 requests MUST include exactly one valid value for the ":method", ":scheme", and ":path"
+ pseudo-header fields, unless they are CONNECT requests.
+ https://www.rfc-editor.org/rfc/rfc9113#section-8.2.1
+ A field name MUST NOT contain characters in the ranges 0x00-0x20, 0x41-0x5a, or 0x7f-0xff (all ranges inclusive).
+ */
+fn request_head(stream_id: u32, fields: Vec<(String, String)>, end_stream: bool) -> Result<RequestHead, Http2Error> {
+    let malformed = |msg: &str| stream_error(stream_id, PROTOCOL_ERROR, msg);
+    let (mut method, mut scheme, mut path, mut authority) = (None, None, None, None);
+    let mut regular = vec!();
+    let mut cookies = vec!();
+    for (name, value) in fields {
+        if let Some(pseudo) = name.strip_prefix(':') {
+            if !regular.is_empty() || !cookies.is_empty() {
+                return Err(malformed("Pseudo headers must come before the other headers"));
+            }
+            let slot = match pseudo {
+                "method" => &mut method,
+                "scheme" => &mut scheme,
+                "path" => &mut path,
+                "authority" => &mut authority,
+                _ => return Err(malformed("Unknown pseudo header")),
+            };
+            if slot.replace(value).is_some() {
+                return Err(malformed("Pseudo headers must only be sent once"));
+            }
+        } else if name.bytes().any(|b| b <= 0x20 || b.is_ascii_uppercase() || b >= 0x7f) {
+            return Err(malformed("Header names must be lower case"));
+        } else if CONNECTION_SPECIFIC_HEADERS.contains(&name.as_str()) || (name == "te" && value != "trailers") {
+            return Err(malformed("Headers about the connection are not allowed"));
+        } else if name == "cookie" {
+            // https://www.rfc-editor.org/rfc/rfc9113#section-8.2.3
+            cookies.push(value);
+        } else {
+            regular.push((name, value));
+        }
+    }
+    if !cookies.is_empty() {
+        regular.push(("cookie".to_string(), cookies.join("; ")));
+    }
+    match (method, scheme, path) {
+        (Some(method), Some(_), Some(path)) if !path.is_empty() => Ok(RequestHead { method, path, authority, fields: regular, end_stream }),
+        _ => Err(malformed("Requests must have a method, scheme and path")),
+    }
+}
+
+/// Serves the streams on an HTTP/2 connection, each on a thread of its own, until it closes.
+pub(crate) fn serve_connection<F, H>(handler: &Arc<F>, stream: Stream, id: usize, options: &ServerOptions, http2: &Http2Options, connections: &Connections)
+    where F: Fn() -> Result<H, String> + Send + Sync + 'static, H: Handler {
+    let mut reader = match stream.try_clone() {
+        Ok(reader) => reader,
+        Err(_) => return,
+    };
+    let _ = stream.set_write_timeout(Some(options.write_timeout));
+    let shared = Shared {
+        state: Mutex::new(ConnectionState {
+            streams: HashMap::new(),
+            send_window: DEFAULT_WINDOW_SIZE,
+            initial_send_window: DEFAULT_WINDOW_SIZE,
+            receive_window: http2.initial_window_size as i64,
+            max_frame_size: DEFAULT_MAX_FRAME_SIZE,
+            last_stream_id: 0,
+            requests: 0,
+            going_away: false,
+            closed: false,
+        }),
+        changed: Condvar::new(),
+        writer: Mutex::new(stream),
+    };
+
+    thread::scope(|scope| {
+        let result = start(&mut reader, &shared, http2, options)
+            .and_then(|_| read_frames(scope, handler, &mut reader, &shared, id, options, http2, connections));
+        let last_stream_id = shared.lock().last_stream_id;
+        match result {
+            Err(Http2Error::Connection(code, msg)) => { let _ = shared.write(goaway_frame(last_stream_id, code, msg.as_str())); }
+            // it went quiet, or we are stopping
+            Ok(()) => { let _ = shared.write(goaway_frame(last_stream_id, NO_ERROR, "")); }
+            // there is no one to tell
+            Err(_) => {}
+        }
+        shared.close();
+    });
+    let _ = shared.writer.lock().unwrap().shutdown(Shutdown::Both);
+}
+
+/*
+ https://www.rfc-editor.org/rfc/rfc9113#section-3.4
+ The server connection preface consists of a potentially empty SETTINGS frame that MUST be the first
+ frame the server sends in the HTTP/2 connection.
+ Clients and servers MUST treat an invalid connection preface as a connection error of type PROTOCOL_ERROR.
+ */
+fn start(reader: &mut Stream, shared: &Shared, http2: &Http2Options, options: &ServerOptions) -> Result<(), Http2Error> {
+    let _ = reader.set_read_timeout(Some(options.headers_timeout));
+    let mut preface = [0; 24];
+    reader.read_exact(&mut preface).map_err(Http2Error::Io)?;
+    shared.write(settings_frame(http2, options.headers_size, true)).map_err(Http2Error::Io)?;
+    // the connection's own window can only be opened up with a window update
+    if http2.initial_window_size as i64 > DEFAULT_WINDOW_SIZE {
+        shared.write(window_update_frame(0, http2.initial_window_size as usize - DEFAULT_WINDOW_SIZE as usize)).map_err(Http2Error::Io)?;
+    }
+    let frame = read_frame(reader, http2.max_frame_size)?;
+    if frame.kind != SETTINGS || frame.has(ACK) {
+        return Err(connection_error(PROTOCOL_ERROR, "Connection must start with settings"));
+    }
+    apply_settings(shared, &frame)
+}
+
+fn apply_settings(shared: &Shared, frame: &Frame) -> Result<(), Http2Error> {
+    let settings = settings_from(frame)?;
+    if frame.has(ACK) {
+        return Ok(());
+    }
+    {
+        let mut state = shared.lock();
+        for (id, value) in settings {
+            match id {
+                // https://www.rfc-editor.org/rfc/rfc9113#section-6.9.2
+                // changes the window of every stream by the difference
+                SETTINGS_INITIAL_WINDOW_SIZE => {
+                    let difference = value as i64 - state.initial_send_window;
+                    state.initial_send_window = value as i64;
+                    for stream in state.streams.values_mut() {
+                        stream.send_window += difference;
+                        if stream.send_window > MAX_WINDOW_SIZE {
+                            return Err(connection_error(FLOW_CONTROL_ERROR, "Window size is too big"));
+                        }
+                    }
+                }
+                SETTINGS_MAX_FRAME_SIZE => state.max_frame_size = value as usize,
+                // we only encode with the static table, so we do not mind how big their table is
+                _ => {}
+            }
+        }
+    }
+    shared.changed.notify_all();
+    shared.write(Frame::new(SETTINGS, ACK, 0, vec!())).map_err(Http2Error::Io)
+}
+
+// returns once the connection has nothing more to do, or with the error that means it has to close
+#[allow(clippy::too_many_arguments)]
+fn read_frames<'scope, 'env, F, H>(
+    scope: &'scope thread::Scope<'scope, 'env>,
+    handler: &'env Arc<F>,
+    reader: &mut Stream,
+    shared: &'env Shared,
+    id: usize,
+    options: &'env ServerOptions,
+    http2: &'env Http2Options,
+    connections: &'env Connections,
+) -> Result<(), Http2Error>
+    where F: Fn() -> Result<H, String> + Send + Sync + 'static, H: Handler {
+    let mut decoder = Decoder::new(http2.header_table_size as usize);
+    loop {
+        let (idle, going_away) = {
+            let state = shared.lock();
+            if state.closed {
+                return Ok(());
+            }
+            (state.streams.is_empty(), state.going_away)
+        };
+        // marked idle before we check, so that if we start stopping after we check, it will be closed while we wait
+        connections.set_idle(id, idle);
+        if idle && (going_away || connections.stopping()) {
+            connections.set_idle(id, false);
+            return Ok(());
+        }
+        // peek rather than read, so that a timeout never leaves us part way through a frame
+        let _ = reader.set_read_timeout(Some(options.keep_alive_timeout));
+        let arrived = reader.peek(&mut [0; 1]);
+        connections.set_idle(id, false);
+        match arrived {
+            Ok(0) => return Err(Http2Error::Io(std::io::Error::new(ErrorKind::UnexpectedEof, "Connection closed"))),
+            Ok(_) => {}
+            // the streams are busy with their handlers, so there may be nothing to read for a while
+            Err(e) if is_timeout(&e) && !idle => continue,
+            Err(e) if is_timeout(&e) => return Ok(()),
+            Err(e) => return Err(Http2Error::Io(e)),
+        }
+
+        let _ = reader.set_read_timeout(Some(options.headers_timeout));
+        let frame = read_frame(reader, http2.max_frame_size)?;
+        let result = match frame.kind {
+            HEADERS => {
+                let block = header_block(reader, &frame, http2.max_frame_size, options.headers_size * 4)?;
+                // decoded even if we are not going to use it, so our table stays in step with theirs
+                // a bit over what we advertise, so that headers that are just too big get a 431 rather than a GOAWAY
+                let fields = decode(&mut decoder, &block, options.headers_size * 4)?;
+                headers(scope, handler, shared, &frame, fields, options, http2, connections)
+            }
+            DATA => data(shared, &frame),
+            PRIORITY if frame.stream_id == 0 => Err(connection_error(PROTOCOL_ERROR, "Priority must be for a stream")),
+            PRIORITY if frame.payload.len() != 5 => Err(stream_error(frame.stream_id, FRAME_SIZE_ERROR, "Priority must be 5 bytes")),
+            // we answer streams in whatever order their handlers finish
+            PRIORITY => Ok(()),
+            RST_STREAM => reset(shared, &frame),
+            SETTINGS => apply_settings(shared, &frame),
+            // https://www.rfc-editor.org/rfc/rfc9113#section-8.4
+            // A client cannot push. Thus, servers MUST treat the receipt of a PUSH_PROMISE frame as a connection error
+            PUSH_PROMISE => Err(connection_error(PROTOCOL_ERROR, "Clients must not push")),
+            PING => ping(shared, &frame),
+            GOAWAY if frame.stream_id != 0 => Err(connection_error(PROTOCOL_ERROR, "Goaway must be for the connection")),
+            // the client will not start any more streams, so we close once the ones it has started are done
+            GOAWAY => {
+                shared.lock().going_away = true;
+                Ok(())
+            }
+            WINDOW_UPDATE => window_update(shared, &frame),
+            CONTINUATION => Err(connection_error(PROTOCOL_ERROR, "Continuation must follow headers")),
+            // https://www.rfc-editor.org/rfc/rfc9113#section-5.5
+            // Implementations MUST ignore unknown or unsupported values in all extensible protocol elements
+            _ => Ok(()),
+        };
+        match result {
+            Err(Http2Error::Stream(stream_id, code, _)) => {
+                if let Some(stream) = shared.lock().streams.get_mut(&stream_id) {
+                    stream.reset = true;
+                }
+                shared.changed.notify_all();
+                shared.write(rst_stream_frame(stream_id, code)).map_err(Http2Error::Io)?;
+            }
+            other => other?,
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn headers<'scope, 'env, F, H>(
+    scope: &'scope thread::Scope<'scope, 'env>,
+    handler: &'env Arc<F>,
+    shared: &'env Shared,
+    frame: &Frame,
+    fields: Vec<(String, String)>,
+    options: &'env ServerOptions,
+    http2: &'env Http2Options,
+    connections: &'env Connections,
+) -> Result<(), Http2Error>
+    where F: Fn() -> Result<H, String> + Send + Sync + 'static, H: Handler {
+    let stream_id = frame.stream_id;
+    let end_stream = frame.has(END_STREAM);
+    if stream_id == 0 || stream_id.is_multiple_of(2) {
+        return Err(connection_error(PROTOCOL_ERROR, "Clients must start streams with odd ids"));
+    }
+    let mut state = shared.lock();
+    // https://www.rfc-editor.org/rfc/rfc9113#section-8.1
+    // trailers come in a second HEADERS frame, which must end the stream
+    if let Some(stream) = state.streams.get_mut(&stream_id) {
+        if stream.end_stream {
+            return Err(stream_error(stream_id, STREAM_CLOSED, "Stream has already ended"));
+        }
+        if !end_stream {
+            return Err(stream_error(stream_id, PROTOCOL_ERROR, "Trailers must end the stream"));
+        }
+        if fields.iter().any(|(name, _)| name.starts_with(':')) {
+            return Err(stream_error(stream_id, PROTOCOL_ERROR, "Trailers must not have pseudo headers"));
+        }
+        stream.trailers = fields;
+        stream.end_stream = true;
+        drop(state);
+        shared.changed.notify_all();
+        return Ok(());
+    }
+    // https://www.rfc-editor.org/rfc/rfc9113#section-5.1.1
+    // The identifier of a newly established stream MUST be numerically greater than all streams that the initiating endpoint has opened
+    if stream_id <= state.last_stream_id {
+        return Err(connection_error(STREAM_CLOSED, "Stream has already closed"));
+    }
+    state.last_stream_id = stream_id;
+    // https://www.rfc-editor.org/rfc/rfc9113#section-6.8
+    // streams after the last one we said we would take are ignored
+    if state.going_away {
+        return Ok(());
+    }
+    if state.streams.len() >= http2.max_concurrent_streams as usize {
+        return Err(stream_error(stream_id, REFUSED_STREAM, "Too many streams at once"));
+    }
+    let head = request_head(stream_id, fields, end_stream)?;
+    let content_length = match head.fields.iter().find(|(name, _)| name == "content-length").map(|(_, value)| value.parse::<usize>()) {
+        Some(Ok(length)) => Some(length),
+        Some(Err(_)) => return Err(stream_error(stream_id, PROTOCOL_ERROR, "Invalid content length")),
+        None => None,
+    };
+    if end_stream && content_length.unwrap_or(0) != 0 {
+        return Err(stream_error(stream_id, PROTOCOL_ERROR, "Body is shorter than its content length"));
+    }
+    // https://www.rfc-editor.org/rfc/rfc9113#section-8.7
+    // REFUSED_STREAM tells the client the request was not processed, so it can try again
+    if !connections.open_stream(http2.max_streams) {
+        return Err(stream_error(stream_id, REFUSED_STREAM, "Too many streams on the server at once"));
+    }
+    let initial_send_window = state.initial_send_window;
+    state.streams.insert(stream_id, StreamState {
+        body: VecDeque::new(),
+        end_stream,
+        trailers: vec!(),
+        content_length,
+        received: 0,
+        send_window: initial_send_window,
+        receive_window: http2.initial_window_size as i64,
+        reset: false,
+    });
+    state.requests += 1;
+    let last_request = state.requests >= options.max_requests_per_connection;
+    if last_request {
+        state.going_away = true;
+    }
+    drop(state);
+
+    scope.spawn(move || {
+        // a handler that panics must still give its stream back, or the server would refuse streams for good
+        let _ = catch_unwind(AssertUnwindSafe(|| handle_stream(handler, shared, stream_id, head, options)));
+        finish_stream(shared, stream_id, connections);
+        connections.close_stream();
+    });
+    if last_request {
+        shared.write(goaway_frame(stream_id, NO_ERROR, "")).map_err(Http2Error::Io)?;
+    }
+    Ok(())
+}
+
+fn data(shared: &Shared, frame: &Frame) -> Result<(), Http2Error> {
+    let stream_id = frame.stream_id;
+    if stream_id == 0 {
+        return Err(connection_error(PROTOCOL_ERROR, "Data must be for a stream"));
+    }
+    let content = frame.content()?;
+    // the padding counts towards the windows, so we give it back straight away
+    let flow = frame.payload.len();
+    let mut give_back = (0, flow - content.len());
+    {
+        let mut state = shared.lock();
+        state.receive_window -= flow as i64;
+        if state.receive_window < 0 {
+            return Err(connection_error(FLOW_CONTROL_ERROR, "Sent more than the connection window"));
+        }
+        let last_stream_id = state.last_stream_id;
+        match state.streams.get_mut(&stream_id) {
+            Some(stream) if stream.end_stream => return Err(stream_error(stream_id, STREAM_CLOSED, "Stream has already ended")),
+            Some(stream) => {
+                stream.receive_window -= flow as i64;
+                if stream.receive_window < 0 {
+                    return Err(stream_error(stream_id, FLOW_CONTROL_ERROR, "Sent more than the stream window"));
+                }
+                stream.received += content.len();
+                stream.end_stream = frame.has(END_STREAM);
+                // https://www.rfc-editor.org/rfc/rfc9113#section-8.1.1
+                // A request or response is also malformed if the value of a content-length header field does not equal the sum of the DATA frame payload lengths
+                let too_long = stream.content_length.map(|length| stream.received > length).unwrap_or(false);
+                let too_short = stream.end_stream && stream.content_length.map(|length| stream.received < length).unwrap_or(false);
+                if too_long || too_short {
+                    return Err(stream_error(stream_id, PROTOCOL_ERROR, "Body does not match its content length"));
+                }
+                stream.body.extend(content);
+                give_back.0 = give_back.1;
+            }
+            None if stream_id > last_stream_id => return Err(connection_error(PROTOCOL_ERROR, "Data for a stream that has not started")),
+            // the stream has finished, we may have reset it before the client knew, but the connection still has to have it back
+            None => give_back.1 = flow,
+        }
+        state.receive_window += give_back.1 as i64;
+        if let Some(stream) = state.streams.get_mut(&stream_id) {
+            stream.receive_window += give_back.0 as i64;
+        }
+    }
+    shared.changed.notify_all();
+    if give_back.1 > 0 {
+        shared.write(window_update_frame(0, give_back.1)).map_err(Http2Error::Io)?;
+    }
+    if give_back.0 > 0 && !frame.has(END_STREAM) {
+        shared.write(window_update_frame(stream_id, give_back.0)).map_err(Http2Error::Io)?;
+    }
+    Ok(())
+}
+
+fn reset(shared: &Shared, frame: &Frame) -> Result<(), Http2Error> {
+    if frame.stream_id == 0 {
+        return Err(connection_error(PROTOCOL_ERROR, "Reset must be for a stream"));
+    }
+    if frame.payload.len() != 4 {
+        return Err(connection_error(FRAME_SIZE_ERROR, "Reset must be 4 bytes"));
+    }
+    let mut state = shared.lock();
+    if frame.stream_id > state.last_stream_id {
+        return Err(connection_error(PROTOCOL_ERROR, "Reset for a stream that has not started"));
+    }
+    if let Some(stream) = state.streams.get_mut(&frame.stream_id) {
+        stream.reset = true;
+    }
+    drop(state);
+    shared.changed.notify_all();
+    Ok(())
+}
+
+fn ping(shared: &Shared, frame: &Frame) -> Result<(), Http2Error> {
+    if frame.stream_id != 0 {
+        return Err(connection_error(PROTOCOL_ERROR, "Ping must be for the connection"));
+    }
+    if frame.payload.len() != 8 {
+        return Err(connection_error(FRAME_SIZE_ERROR, "Ping must be 8 bytes"));
+    }
+    if frame.has(ACK) {
+        return Ok(());
+    }
+    shared.write(Frame::new(PING, ACK, 0, frame.payload.clone())).map_err(Http2Error::Io)
+}
+
+fn window_update(shared: &Shared, frame: &Frame) -> Result<(), Http2Error> {
+    let increment = window_increment(frame)?;
+    {
+        let mut state = shared.lock();
+        if frame.stream_id == 0 {
+            state.send_window += increment;
+            if state.send_window > MAX_WINDOW_SIZE {
+                return Err(connection_error(FLOW_CONTROL_ERROR, "Window size is too big"));
+            }
+        } else if let Some(stream) = state.streams.get_mut(&frame.stream_id) {
+            stream.send_window += increment;
+            if stream.send_window > MAX_WINDOW_SIZE {
+                return Err(stream_error(frame.stream_id, FLOW_CONTROL_ERROR, "Window size is too big"));
+            }
+        }
+    }
+    shared.changed.notify_all();
+    Ok(())
+}
+
+fn handle_stream<F, H>(handler: &Arc<F>, shared: &Shared, stream_id: u32, head: RequestHead, options: &ServerOptions)
+    where F: Fn() -> Result<H, String> + Send + Sync + 'static, H: Handler {
+    let headers_size: usize = head.fields.iter().map(|(name, value)| name.len() + value.len() + 4).sum();
+    let content_length = head.fields.iter().find(|(name, _)| name == "content-length")
+        .and_then(|(_, value)| value.parse::<usize>().ok());
+    let mut headers = headers_from(head.fields);
+    // the authority is the Host of HTTP/1.1, so that handlers find it in the same place
+    if let Some(authority) = &head.authority {
+        if !headers.has("Host") {
+            headers = headers.add(("Host", authority.as_str()));
+        }
+    }
+    // turned away before the handler is called, as they would be over HTTP/1.1
+    let body_too_big = format!("Body must be no more than {} bytes", options.max_body_size);
+    let rejected = if headers_size > options.headers_size {
+        Some(Response::request_header_fields_too_large(Headers::empty(), BodyString("Headers too big")))
+    } else if headers.vec.len() > options.max_headers {
+        Some(Response::request_header_fields_too_large(Headers::empty(), BodyString("Too many headers")))
+    } else if content_length.map(|length| length > options.max_body_size).unwrap_or(false) {
+        Some(Response::content_too_large(Headers::empty(), BodyString(body_too_big.as_str())))
+    } else {
+        None
+    };
+    if let Some(response) = rejected {
+        let _ = write_response(shared, stream_id, response, RequestOptions::default(), options);
+        return;
+    }
+    let method = match Method::parse(head.method.as_str()) {
+        Ok(method) => method,
+        Err(_) => {
+            let _ = write_response(shared, stream_id, Response::not_implemented(Headers::empty(), BodyString("Unknown method")), RequestOptions::default(), options);
+            return;
+        }
+    };
+
+    let trailers = Trailers::empty();
+    let body = if head.end_stream {
+        Body::empty()
+    } else {
+        let body: Box<dyn Read> = Box::new(StreamBody { shared, stream_id, trailers: trailers.clone(), timeout: options.body_timeout, left: options.max_body_size, max_body_size: options.max_body_size });
+        let compression = compression_from(headers.get("Content-Encoding"));
+        if compression.is_some() {
+            BodyStream(limited_decoder(body, &compression, options.max_decompressed_size))
+        } else {
+            BodyStream(body)
+        }
+    };
+    let mut request_options = RequestOptions::from(&headers);
+    request_options.responding_to_head = method == HEAD;
+    let request = Request {
+        headers,
+        body,
+        uri: Uri::parse(head.path.as_str()),
+        method,
+        version: two_pt_oh(),
+        trailers,
+    };
+
+    let mut responded = false;
+    let mut h = handler().unwrap();
+    h.handle(request, |response| {
+        responded = true;
+        let _ = write_response(shared, stream_id, response, request_options, options);
+    });
+    if !responded {
+        let _ = shared.write(rst_stream_frame(stream_id, INTERNAL_ERROR));
+    }
+}
+
+// the stream is closed once we have responded, so the rest of the request body is not going to be read
+fn finish_stream(shared: &Shared, stream_id: u32, connections: &Connections) {
+    let (stream, no_more_streams) = {
+        let mut state = shared.lock();
+        let stream = state.streams.remove(&stream_id);
+        if let Some(stream) = &stream {
+            state.receive_window += stream.body.len() as i64;
+        }
+        (stream, state.streams.is_empty() && (state.going_away || connections.stopping()))
+    };
+    if let Some(stream) = stream {
+        if !stream.body.is_empty() {
+            let _ = shared.write(window_update_frame(0, stream.body.len()));
+        }
+        /*
+         https://www.rfc-editor.org/rfc/rfc9113#section-8.1
+         A server can send a complete response prior to the client sending an entire request if the response
+         does not depend on any portion of the request that has not been sent and received. When this is true,
+         a server MAY request that the client abort transmission of a request without error by sending a
+         RST_STREAM with an error code of NO_ERROR after sending a complete response.
+         */
+        if !stream.end_stream && !stream.reset {
+            let _ = shared.write(rst_stream_frame(stream_id, NO_ERROR));
+        }
+    }
+    // the thread reading frames may be waiting for a frame that is never coming
+    if no_more_streams {
+        let last_stream_id = shared.lock().last_stream_id;
+        let _ = shared.write(goaway_frame(last_stream_id, NO_ERROR, ""));
+        shared.close();
+        let _ = shared.writer.lock().unwrap().shutdown(Shutdown::Both);
+    }
+}
+
+/*
+ https://www.rfc-editor.org/rfc/rfc9113#section-8.1
+ The response is a HEADERS frame with the status, any DATA frames with the body,
+ and a HEADERS frame with the trailers if there are any, the last of which ends the stream.
+ Compression is as it would be over HTTP/1.1.
+ */
+fn write_response(shared: &Shared, stream_id: u32, response: Response, request_options: RequestOptions, options: &ServerOptions) -> std::io::Result<()> {
+    let mut headers = response.headers;
+    let compression = if headers.get("Content-Encoding").map(|ce| ce.to_lowercase() == "none").unwrap_or(false) {
+        headers = headers.remove("Content-Encoding");
+        compression_from(None)
+    } else {
+        let compression = request_options.write_response_compression()
+            .or(compression_from(headers.get("Content-Encoding")));
+        if compression.is_some() {
+            // compressed as it is sent, so we do not know how long it will be
+            headers = headers.replace(("Content-Encoding", compression.to_string_for_content_encoding().as_str())).remove("Content-Length");
+        }
+        compression
+    };
+    let mut fields = vec!((":status".to_string(), response.status.value().to_string()));
+    fields.extend(header_fields(&headers));
+
+    let status = response.status.value();
+    let cannot_have_body = status < 200 || status == Status::NoContent.value() || status == Status::NotModified.value();
+    if cannot_have_body || request_options.responding_to_head {
+        return shared.write_headers(stream_id, &fields, true);
+    }
+    shared.write_headers(stream_id, &fields, false)?;
+
+    let body: Box<dyn Read> = match response.body {
+        BodyString(str) => Box::new(str.as_bytes()),
+        BodyBytes(bytes) => Box::new(bytes),
+        BodyStream(reader) => reader,
+    };
+    let mut body = if compression.is_some() { Codex::encode_reader(body, &compression) } else { body };
+    let mut buffer = vec![0; DEFAULT_MAX_FRAME_SIZE];
+    loop {
+        let read = body.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        shared.send_data(stream_id, &buffer[..read], false, options.write_timeout)?;
+    }
+    let trailers = response.trailers.get();
+    if trailers.is_empty() {
+        shared.send_data(stream_id, &[], true, options.write_timeout)
+    } else {
+        shared.write_headers(stream_id, &header_fields(&trailers), true)
+    }
+}
+
+/// A request body, as it arrives in DATA frames.
+/// Reading it opens up the window for the client to send more.
+struct StreamBody<'s> {
+    shared: &'s Shared,
+    stream_id: u32,
+    trailers: Trailers,
+    timeout: Duration,
+    left: usize,
+    max_body_size: usize,
+}
+
+impl<'s> Read for StreamBody<'s> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let give_up_at = Instant::now() + self.timeout;
+        let mut state = self.shared.lock();
+        loop {
+            let closed = state.closed;
+            let stream = state.streams.get_mut(&self.stream_id)
+                .ok_or(std::io::Error::new(ErrorKind::ConnectionAborted, "Stream has closed"))?;
+            if !stream.body.is_empty() {
+                let read = min(buf.len(), stream.body.len());
+                for (i, byte) in stream.body.drain(..read).enumerate() {
+                    buf[i] = byte;
+                }
+                if read > self.left {
+                    return Err(MessageError::BodyTooBig(format!("Body must be no more than {} bytes", self.max_body_size)).to_io_error());
+                }
+                self.left -= read;
+                stream.receive_window += read as i64;
+                let end_stream = stream.end_stream;
+                state.receive_window += read as i64;
+                drop(state);
+                let _ = self.shared.write(window_update_frame(0, read));
+                if !end_stream {
+                    let _ = self.shared.write(window_update_frame(self.stream_id, read));
+                }
+                return Ok(read);
+            }
+            if stream.end_stream {
+                if !stream.trailers.is_empty() {
+                    self.trailers.set(headers_from(std::mem::take(&mut stream.trailers)));
+                }
+                return Ok(0);
+            }
+            if stream.reset || closed {
+                return Err(std::io::Error::new(ErrorKind::ConnectionAborted, "Stream was reset"));
+            }
+            let now = Instant::now();
+            if now >= give_up_at {
+                return Err(MessageError::Timeout("Timed out waiting for the body".to_string()).to_io_error());
+            }
+            state = self.shared.changed.wait_timeout(state, give_up_at - now).unwrap().0;
+        }
+    }
+}
+
+/// The client's side of a connection, which only ever has the one stream on it.
+struct ClientConnection {
+    stream: WithDeadline,
+    decoder: Decoder,
+    // the most the headers of the response can decode to, ie our SETTINGS_MAX_HEADER_LIST_SIZE
+    max_header_list_size: usize,
+    http2: Http2Options,
+    max_frame_size: usize,
+    send_window: i64,
+    stream_send_window: i64,
+    initial_send_window: i64,
+    response: Option<(Vec<(String, String)>, bool)>,
+    body: VecDeque<u8>,
+    end_stream: bool,
+    trailers: Vec<(String, String)>,
+    // the server has said it does not want the rest of the body
+    reset: bool,
+}
+
+const STREAM_ID: u32 = 1;
+
+impl ClientConnection {
+    fn write(&mut self, frame: Frame) -> Result<(), Http2Error> {
+        write_frame(&mut self.stream, &frame).map_err(Http2Error::Io)
+    }
+
+    // reads and acts on the next frame
+    fn next_frame(&mut self) -> Result<(), Http2Error> {
+        let frame = read_frame(&mut self.stream, self.http2.max_frame_size)?;
+        match frame.kind {
+            HEADERS if frame.stream_id != STREAM_ID => Err(connection_error(PROTOCOL_ERROR, "Headers for a stream we did not start")),
+            HEADERS => {
+                let block = header_block(&mut self.stream, &frame, self.http2.max_frame_size, 1048576)?;
+                let fields = decode(&mut self.decoder, &block, self.max_header_list_size)?;
+                let end_stream = frame.has(END_STREAM);
+                match &self.response {
+                    None => {
+                        // https://www.rfc-editor.org/rfc/rfc9113#section-8.1
+                        // interim responses come first, and are of no interest to us
+                        let status = fields.iter().find(|(name, _)| name == ":status").map(|(_, value)| value.clone());
+                        match status.and_then(|status| status.parse::<u32>().ok()) {
+                            Some(status) if status < 200 => {}
+                            Some(_) => {
+                                self.end_stream = end_stream;
+                                self.response = Some((fields, end_stream));
+                            }
+                            None => return Err(stream_error(STREAM_ID, PROTOCOL_ERROR, "Response must have a status")),
+                        }
+                    }
+                    Some(_) if !end_stream => return Err(stream_error(STREAM_ID, PROTOCOL_ERROR, "Trailers must end the stream")),
+                    Some(_) => {
+                        self.trailers = fields;
+                        self.end_stream = true;
+                    }
+                }
+                Ok(())
+            }
+            DATA if frame.stream_id != STREAM_ID => Err(connection_error(PROTOCOL_ERROR, "Data for a stream we did not start")),
+            DATA if self.response.is_none() => Err(stream_error(STREAM_ID, PROTOCOL_ERROR, "Data before the response headers")),
+            DATA => {
+                let content = frame.content()?;
+                self.body.extend(content);
+                self.end_stream = frame.has(END_STREAM);
+                let padding = frame.payload.len() - content.len();
+                if padding > 0 {
+                    self.write(window_update_frame(0, padding))?;
+                }
+                Ok(())
+            }
+            RST_STREAM if frame.payload.len() != 4 => Err(connection_error(FRAME_SIZE_ERROR, "Reset must be 4 bytes")),
+            RST_STREAM => {
+                let code = frame.u32_at(0);
+                // the server has the whole response it needs, and does not want the rest of the body
+                if code == NO_ERROR && self.response.is_some() {
+                    self.reset = true;
+                    return Ok(());
+                }
+                Err(Http2Error::Stream(STREAM_ID, code, format!("Server reset the stream with error {}", code)))
+            }
+            SETTINGS => {
+                let settings = settings_from(&frame)?;
+                if frame.has(ACK) {
+                    return Ok(());
+                }
+                for (id, value) in settings {
+                    match id {
+                        SETTINGS_INITIAL_WINDOW_SIZE => {
+                            self.stream_send_window += value as i64 - self.initial_send_window;
+                            self.initial_send_window = value as i64;
+                        }
+                        SETTINGS_MAX_FRAME_SIZE => self.max_frame_size = value as usize,
+                        _ => {}
+                    }
+                }
+                self.write(Frame::new(SETTINGS, ACK, 0, vec!()))
+            }
+            PING if frame.payload.len() != 8 => Err(connection_error(FRAME_SIZE_ERROR, "Ping must be 8 bytes")),
+            PING if frame.has(ACK) => Ok(()),
+            PING => self.write(Frame::new(PING, ACK, 0, frame.payload.clone())),
+            WINDOW_UPDATE => {
+                let increment = window_increment(&frame)?;
+                if frame.stream_id == 0 {
+                    self.send_window += increment;
+                } else {
+                    self.stream_send_window += increment;
+                }
+                Ok(())
+            }
+            GOAWAY if frame.payload.len() < 8 => Err(connection_error(FRAME_SIZE_ERROR, "Goaway must be at least 8 bytes")),
+            // the server is closing, which only matters if it is not going to answer us
+            GOAWAY if frame.u32_at(0) & 0x7fffffff < STREAM_ID || frame.u32_at(4) != NO_ERROR => {
+                let debug = String::from_utf8_lossy(&frame.payload[8..]).to_string();
+                Err(Http2Error::Stream(STREAM_ID, frame.u32_at(4), format!("Server went away with error {}: {}", frame.u32_at(4), debug)))
+            }
+            GOAWAY => Ok(()),
+            PUSH_PROMISE => Err(connection_error(PROTOCOL_ERROR, "We said not to push")),
+            CONTINUATION => Err(connection_error(PROTOCOL_ERROR, "Continuation must follow headers")),
+            _ => Ok(()),
+        }
+    }
+
+    fn send_body(&mut self, body: &mut dyn Read, trailers: Headers) -> Result<(), Http2Error> {
+        let mut buffer = vec![0; DEFAULT_MAX_FRAME_SIZE];
+        loop {
+            let read = body.read(&mut buffer).map_err(Http2Error::Io)?;
+            if read == 0 {
+                break;
+            }
+            let mut data = &buffer[..read];
+            while !data.is_empty() {
+                // the server has already responded in full, eg saying the body is too big
+                if self.reset || self.end_stream {
+                    return Ok(());
+                }
+                let window = min(self.send_window, self.stream_send_window).max(0) as usize;
+                if window == 0 {
+                    self.next_frame()?;
+                    continue;
+                }
+                let sending = min(min(window, data.len()), self.max_frame_size);
+                self.send_window -= sending as i64;
+                self.stream_send_window -= sending as i64;
+                self.write(Frame::new(DATA, 0, STREAM_ID, data[..sending].to_vec()))?;
+                data = &data[sending..];
+            }
+        }
+        if trailers.is_empty() {
+            self.write(Frame::new(DATA, END_STREAM, STREAM_ID, vec!()))
+        } else {
+            let max_frame_size = self.max_frame_size;
+            write_header_block(&mut self.stream, STREAM_ID, &header_fields(&trailers), true, max_frame_size).map_err(Http2Error::Io)
+        }
+    }
+
+    fn error(&mut self, error: Http2Error) -> ClientError {
+        match &error {
+            Http2Error::Connection(code, msg) => { let _ = self.write(goaway_frame(0, *code, msg)); }
+            Http2Error::Stream(_, code, _) if *code != NO_ERROR => { let _ = self.write(rst_stream_frame(STREAM_ID, CANCEL)); }
+            _ => {}
+        }
+        match error {
+            Http2Error::Io(e) if is_timeout(&e) => ClientError::ReadTimeout(format!("Timed out reading the response: {}", e)),
+            Http2Error::Io(e) => ClientError::BadResponse(MessageError::ConnectionClosed(e.to_string())),
+            error => ClientError::Http2(error.to_string()),
+        }
+    }
+}
+
+/// Sends the request over HTTP/2 and reads the head of the response,
+/// the body is read from the connection as the handler reads it.
+pub(crate) fn send_request(stream: Stream, req: Request, authority: &str, scheme: &str, options: &ClientOptions, http2: &Http2Options, started: Instant) -> Result<Response<'static>, ClientError> {
+    let mut connection = ClientConnection {
+        // every read and write, of the body too, only gets what is left of the total timeout
+        stream: WithDeadline::new(stream, options.total_timeout.map(|total| started + total)),
+        decoder: Decoder::new(http2.header_table_size as usize),
+        max_header_list_size: options.headers_size,
+        http2: http2.clone(),
+        max_frame_size: DEFAULT_MAX_FRAME_SIZE,
+        send_window: DEFAULT_WINDOW_SIZE,
+        stream_send_window: DEFAULT_WINDOW_SIZE,
+        initial_send_window: DEFAULT_WINDOW_SIZE,
+        response: None,
+        body: VecDeque::new(),
+        end_stream: false,
+        trailers: vec!(),
+        reset: false,
+    };
+    let _ = connection.stream.set_read_timeout(Some(options.read_timeout));
+    let is_head = req.method == HEAD;
+    let (status, headers, end_stream) = match send(&mut connection, req, authority, scheme, options, http2) {
+        Ok(head) => head,
+        Err(Http2Error::Io(e)) if is_timeout(&e) && options.total_timeout.map(|total| started.elapsed() >= total).unwrap_or(false) => {
+            return Err(ClientError::TotalTimeout(format!("Timed out after {}ms waiting for the response", options.total_timeout.unwrap().as_millis())));
+        }
+        Err(e) => return Err(connection.error(e)),
+    };
+    if let Some(Ok(length)) = headers.content_length_header() {
+        if length > options.max_body_size {
+            return Err(ClientError::BadResponse(MessageError::BodyTooBig(format!("Body must be no more than {} bytes", options.max_body_size))));
+        }
+    }
+    let trailers = Trailers::empty();
+    let body = if end_stream || is_head {
+        Body::empty()
+    } else {
+        let compression = compression_from(headers.get("Content-Encoding"));
+        let body: Box<dyn Read> = Box::new(ResponseBody {
+            connection,
+            trailers: trailers.clone(),
+            left: options.max_body_size,
+            max_body_size: options.max_body_size,
+        });
+        if compression.is_some() {
+            BodyStream(limited_decoder(body, &compression, options.max_decompressed_size))
+        } else {
+            BodyStream(body)
+        }
+    };
+    Ok(Response {
+        headers,
+        body,
+        status: Status::from_code(status),
+        version: two_pt_oh(),
+        trailers,
+    })
+}
+
+// sends the request and waits for the head of the response, ie its status, headers and whether there is a body
+fn send(connection: &mut ClientConnection, req: Request, authority: &str, scheme: &str, options: &ClientOptions, http2: &Http2Options) -> Result<(u32, Headers, bool), Http2Error> {
+    let mut start = PREFACE.to_vec();
+    write_frame(&mut start, &settings_frame(http2, options.headers_size, false)).map_err(Http2Error::Io)?;
+    if http2.initial_window_size as i64 > DEFAULT_WINDOW_SIZE {
+        write_frame(&mut start, &window_update_frame(0, http2.initial_window_size as usize - DEFAULT_WINDOW_SIZE as usize)).map_err(Http2Error::Io)?;
+    }
+    connection.stream.write_all(&start).map_err(Http2Error::Io)?;
+
+    let path = req.uri.to_string();
+    let compression = compression_from(req.headers.get("Content-Encoding"));
+    // the authority takes the place of Host, and there is nothing to wait for before sending the body
+    let mut headers = req.headers.remove("Host").remove("Expect");
+    if compression.is_some() {
+        headers = headers.remove("Content-Length");
+    }
+    let mut fields = vec!(
+        (":method".to_string(), req.method.value()),
+        (":scheme".to_string(), scheme.to_string()),
+        (":authority".to_string(), authority.to_string()),
+        (":path".to_string(), if path.is_empty() { "/".to_string() } else { path }),
+    );
+    fields.extend(header_fields(&headers));
+    let trailers = req.trailers.get();
+    let no_body = !req.body.is_body_stream() && req.body.length() == 0 && trailers.is_empty();
+    let max_frame_size = connection.max_frame_size;
+    write_header_block(&mut connection.stream, STREAM_ID, &fields, no_body, max_frame_size).map_err(Http2Error::Io)?;
+    if !no_body {
+        let body: Box<dyn Read> = match req.body {
+            BodyString(str) => Box::new(str.as_bytes()),
+            BodyBytes(bytes) => Box::new(bytes),
+            BodyStream(reader) => reader,
+        };
+        let mut body = if compression.is_some() { Codex::encode_reader(body, &compression) } else { body };
+        connection.send_body(&mut body, trailers)?;
+    }
+
+    while connection.response.is_none() {
+        connection.next_frame()?;
+    }
+
+    let (fields, end_stream) = connection.response.as_ref().unwrap();
+    let status = fields.iter().find(|(name, _)| name == ":status").and_then(|(_, value)| value.parse::<u32>().ok()).unwrap();
+    let headers = headers_from(fields.iter().filter(|(name, _)| !name.starts_with(':')).cloned().collect());
+    Ok((status, headers, *end_stream))
+}
+
+/// A response body, read from the connection as the handler reads it.
+struct ResponseBody {
+    connection: ClientConnection,
+    trailers: Trailers,
+    left: usize,
+    max_body_size: usize,
+}
+
+impl Read for ResponseBody {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        loop {
+            let connection = &mut self.connection;
+            if !connection.body.is_empty() {
+                let read = min(buf.len(), connection.body.len());
+                for (i, byte) in connection.body.drain(..read).enumerate() {
+                    buf[i] = byte;
+                }
+                if read > self.left {
+                    return Err(MessageError::BodyTooBig(format!("Body must be no more than {} bytes", self.max_body_size)).to_io_error());
+                }
+                self.left -= read;
+                // we have made room for more, of the stream and of the connection
+                let _ = connection.write(window_update_frame(0, read));
+                if !connection.end_stream {
+                    let _ = connection.write(window_update_frame(STREAM_ID, read));
+                }
+                return Ok(read);
+            }
+            if connection.end_stream {
+                if !connection.trailers.is_empty() {
+                    self.trailers.set(headers_from(std::mem::take(&mut connection.trailers)));
+                }
+                return Ok(0);
+            }
+            if let Err(e) = connection.next_frame() {
+                return Err(match e {
+                    Http2Error::Io(e) => e,
+                    e => std::io::Error::new(ErrorKind::InvalidData, e.to_string()),
+                });
+            }
+        }
+    }
+}
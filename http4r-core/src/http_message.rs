@@ -16,17 +16,21 @@
     along with this program.  If not, see <https://www.gnu.org/licenses/>.
  */
 
-use std::io::{copy, Read, Write};
-use std::net::TcpStream;
+use std::cell::RefCell;
+use std::cmp::min;
+use std::io::{copy, Cursor, ErrorKind, Read, sink, Write};
+use std::rc::Rc;
 use std::str;
 use std::str::from_utf8;
+use std::time::{Duration, Instant};
 use crate::codex::Codex;
 
 use crate::headers::{DISALLOWED_TRAILERS, Headers};
-use crate::http_message::Body::{BodyStream, BodyString};
+use crate::http_message::Body::{BodyBytes, BodyStream, BodyString};
 use crate::http_message::CompressionAlgorithm::{BROTLI, DEFLATE, GZIP, NONE};
 use crate::http_message::Method::{CONNECT, DELETE, GET, HEAD, OPTIONS, PATCH, POST, PUT, TRACE};
-use crate::http_message::Status::{BadRequest, Forbidden, InternalServerError, LengthRequired, MovedPermanently, NotFound, OK, Unknown};
+use crate::http_message::Status::{Accepted, BadGateway, BadRequest, Conflict, Continue, Created, ExpectationFailed, Found, GatewayTimeout, MethodNotAllowed, NoContent, NotModified, PermanentRedirect, RequestTimeout, SeeOther, ServiceUnavailable, TemporaryRedirect, TooManyRequests, Unauthorized, Forbidden, HttpVersionNotSupported, InternalServerError, LengthRequired, MovedPermanently, NotFound, NotImplemented, OK, ContentTooLarge, RequestHeaderFieldsTooLarge, URITooLong};
+use crate::connection::{Connection, Duplex};
 use crate::uri::Uri;
 
 pub enum HttpMessage<'a> {
@@ -63,42 +67,162 @@ impl<'a> HttpMessage<'a> {
     }
 }
 
-#[allow(unused_assignments)]
-pub fn read_message_from_wire<'a>(
-    mut stream: TcpStream,
-    mut reader: &'a mut [u8],
-    mut start_line_writer: &'a mut Vec<u8>,
-    mut headers_writer: &'a mut Vec<u8>,
+/// Where a connection is up to in between messages.
+/// The reader is shared by every message read from the same connection,
+/// so whatever is left over in it after one message belongs to the next one.
+pub struct WireState {
+    pub up_to_in_reader: usize,
+    pub read_bytes_from_stream: usize,
+    pub body_left_on_stream: usize,
+    // the client is waiting for a 100 Continue before it sends the body
+    pub expects_continue: bool,
+    // where we are up to in a chunked body that has not been read to the end yet
+    chunked_body: Option<ChunkedMetadata>,
+    // how much of the chunked body has been decoded so far
+    chunked_body_read: usize,
+    // the body could not be read, so we do not know where the next message starts
+    broken: bool,
+    pub limits: MessageLimits,
+}
+
+impl WireState {
+    pub fn new() -> WireState {
+        WireState::with_limits(MessageLimits::default())
+    }
+
+    pub fn with_limits(limits: MessageLimits) -> WireState {
+        WireState { up_to_in_reader: 0, read_bytes_from_stream: 0, body_left_on_stream: 0, expects_continue: false, chunked_body: None, chunked_body_read: 0, broken: false, limits }
+    }
+
+    pub fn has_leftover_bytes(&self) -> bool {
+        self.up_to_in_reader > 0 && self.read_bytes_from_stream > self.up_to_in_reader
+    }
+
+    fn set_leftover(&mut self, up_to_in_reader: usize, read_bytes_from_stream: usize) {
+        if up_to_in_reader > 0 && read_bytes_from_stream > up_to_in_reader {
+            self.up_to_in_reader = up_to_in_reader;
+            self.read_bytes_from_stream = read_bytes_from_stream;
+        } else {
+            self.up_to_in_reader = 0;
+            self.read_bytes_from_stream = 0;
+        }
+    }
+}
+
+impl Default for WireState {
+    fn default() -> WireState {
+        WireState::new()
+    }
+}
+
+/// The limits on a message that are not already set by the size of the buffers it is read into,
+/// ie the start line, headers and trailers can be no bigger than the capacity of their writers.
+#[derive(Copy, Clone, Debug)]
+pub struct MessageLimits {
+    pub max_headers: usize,
+    pub max_body_size: usize,
+    // a small compressed body can decompress into a very big one
+    pub max_decompressed_size: usize,
+    // how long the start line and headers can take altogether, so they cannot be trickled in a byte at a time
+    pub head_timeout: Option<Duration>,
+    // how long to wait for any one read, including reads of the body
+    pub read_timeout: Option<Duration>,
+}
+
+impl Default for MessageLimits {
+    fn default() -> MessageLimits {
+        MessageLimits {
+            max_headers: 100,
+            max_body_size: 10485760,
+            max_decompressed_size: 10485760,
+            head_timeout: None,
+            read_timeout: None,
+        }
+    }
+}
+
+impl MessageLimits {
+    fn has_timeouts(&self) -> bool {
+        self.head_timeout.is_some() || self.read_timeout.is_some()
+    }
+}
+
+#[allow(unused_assignments, clippy::too_many_arguments)]
+pub fn read_message_from_wire<'a, C: Connection + 'a>(
+    mut stream: C,
+    reader: &'a mut [u8],
+    wire: &'a mut WireState,
+    is_response_to_head: bool,
+    start_line_writer: &'a mut Vec<u8>,
+    headers_writer: &'a mut Vec<u8>,
     chunks_writer: &'a mut Vec<u8>,
     compress_writer: &'a mut Vec<u8>,
     trailers_writer: &'a mut Vec<u8>,
 ) -> Result<HttpMessage<'a>, MessageError> {
+    let limits = wire.limits;
+    let head_deadline = limits.head_timeout.map(|timeout| Instant::now() + timeout);
+    if limits.has_timeouts() {
+        let _ = stream.set_read_timeout(limits.read_timeout);
+    }
     let (mut read_bytes_from_stream, mut up_to_in_reader, mut result) =
-        read(&mut stream, &mut reader, &mut start_line_writer, 0, 0, None,
+        read(&mut stream, reader, start_line_writer, wire.read_bytes_from_stream, wire.up_to_in_reader, None, head_deadline, limits.read_timeout,
              |reader, writer, _| { start_line_(reader, writer) },
         );
-    let start_line = str::from_utf8(start_line_writer).unwrap().split(" ").collect::<Vec<&str>>();
-    let (part1, part2, part3) = (start_line[0], start_line[1], start_line[2]);
-    let is_response = part1.starts_with("HTTP");
+    wire.set_leftover(0, 0);
+    wire.expects_continue = false;
+    wire.chunked_body = None;
+    if result.is_err() {
+        return Err(result.err());
+    }
+    let (part1, part2, part3) = start_line_parts(start_line_writer)?;
+    let is_response = part1.starts_with("HTTP/");
     let is_request = !is_response;
-    let method_can_have_body = vec!("POST", "PUT", "PATCH", "DELETE").contains(&part1);
+    let version = http_version_from(if is_response { part1 } else { part3 })?;
+    // the method of a response does not matter, it is never used
+    let method = if is_request { Method::parse(part1)? } else { GET };
+    let method_can_have_body = method.can_have_body();
+    // other methods might not have a body, so if they do not say how long it is then it is empty
+    let length_required = is_request && [POST, PUT, PATCH, DELETE].contains(&method);
 
     (read_bytes_from_stream, up_to_in_reader, result) =
-        read(&mut stream, &mut reader, &mut headers_writer, read_bytes_from_stream, up_to_in_reader, None,
+        read(&mut stream, reader, headers_writer, read_bytes_from_stream, up_to_in_reader, None, head_deadline, limits.read_timeout,
              |reader, writer, _| { headers_(reader, writer) },
         );
+    // the body is read a bit at a time, as and when the handler wants it, so there is no deadline for all of it
+    if head_deadline.is_some() {
+        let _ = stream.set_read_timeout(limits.read_timeout);
+    }
 
     if result.is_err() {
         return Err(result.err());
     }
-    let header_string = from_utf8(headers_writer.as_slice()).unwrap();
-    let mut headers = Headers::parse_from(header_string);
-
-    if let Err(e) = check_valid_content_length_or_transfer_encoding(&headers, is_response, method_can_have_body) {
-        return Err(e);
-    }
+    let header_string = from_utf8(headers_writer.as_slice())
+        .map_err(|_| MessageError::MalformedHeader("Headers are not valid utf-8".to_string()))?;
+    let mut headers = Headers::parse_from(header_string)?;
+    if headers.vec.len() > limits.max_headers {
+        return Err(MessageError::TooManyHeaders(format!("There must be no more than {} headers", limits.max_headers)));
+    }
+
+    check_valid_content_length_or_transfer_encoding(&headers, length_required)?;
+    /*
+     https://www.rfc-editor.org/rfc/rfc9112#section-6.1
+     A server MAY reject a request that contains both Content-Length and Transfer-Encoding or process such
+     a request in accordance with the Transfer-Encoding alone.
+     If a Transfer-Encoding header field is present in a request and the chunked transfer coding is not
+     the final encoding, the message body length cannot be determined reliably; the server MUST respond
+     with the 400 (Bad Request) status code and then close the connection.
+     If it is present in a response and the chunked transfer coding is not the final encoding,
+     the message body length is determined by reading the connection until it is closed by the server.
+     */
     let transfer_encoding = headers.get("Transfer-Encoding");
-    if headers.has("Content-Length") && transfer_encoding.is_some() {
+    let is_chunked = transfer_encoding.as_ref().map(|te| chunked_is_final(te)).unwrap_or(false);
+    if let Some(te) = &transfer_encoding {
+        if is_request && headers.has("Content-Length") {
+            return Err(MessageError::ContentLengthAndTransferEncoding("Content-Length and Transfer-Encoding must not both be provided".to_string()));
+        }
+        if is_request && !is_chunked {
+            return Err(MessageError::TransferEncodingNotChunked(format!("Transfer-Encoding must end in chunked, got {}", te)));
+        }
         headers = headers.remove("Content-Length");
     }
     let is_version_1_0 = part3 == "HTTP/1.0";
@@ -107,27 +231,78 @@ pub fn read_message_from_wire<'a>(
     let compression = request_options.read_compression();
     let content_length = headers.content_length_header();
 
-    let result = if let Some(_encoding) = transfer_encoding {
-        chunked_body_and_trailers(reader, stream, up_to_in_reader, read_bytes_from_stream, chunks_writer, compress_writer, trailers_writer, &compression)
+    // https://datatracker.ietf.org/doc/html/rfc7230#section-3.3.3
+    // any response to a HEAD request is terminated by the first empty line after the header fields
+    let cannot_have_body = is_response && (part2.starts_with("1") || part2 == "204" || part2 == "304" || is_response_to_head);
+    let is_close_delimited = is_response && !cannot_have_body && content_length.is_none() && !is_chunked;
+
+    // https://datatracker.ietf.org/doc/html/rfc7231#section-5.1.1
+    let expects_continue = is_request && part3 == "HTTP/1.1" && expects_continue(&headers)
+        && read_bytes_from_stream == up_to_in_reader;
+
+    let result = if cannot_have_body {
+        // so anything after the head is the next message
+        wire.set_leftover(up_to_in_reader, read_bytes_from_stream);
+        Ok((Body::empty(), Trailers::empty(), Some(0)))
+    } else if is_close_delimited {
+        close_delimited_body(reader, stream, up_to_in_reader, read_bytes_from_stream, compression, limits.max_decompressed_size)
+    } else if is_chunked {
+        wire.expects_continue = expects_continue && method_can_have_body;
+        let ignore_body = is_request && !method_can_have_body;
+        // http/1.0 user agents need to be given a content length, so we need the whole body to know it
+        let read_whole_body = is_request && is_version_1_0;
+        chunked_body_and_trailers(reader, stream, wire, up_to_in_reader, read_bytes_from_stream, ignore_body, read_whole_body, chunks_writer, compress_writer, trailers_writer, &compression)
     } else {
-        simple_body(reader, stream, up_to_in_reader, read_bytes_from_stream, is_request, method_can_have_body, content_length, compression, compress_writer)
+        simple_body(reader, stream, wire, up_to_in_reader, read_bytes_from_stream, is_request, method_can_have_body, expects_continue, content_length, is_textual(&headers), compression, compress_writer, limits)
     };
     if result.is_err() {
         return Err(result.err().unwrap());
     }
     let (body, trailers, content_length) = result.unwrap();
 
-    if headers.get("Transfer-Encoding").is_none() {
-        headers = headers.replace(("Content-Length", content_length.to_string().as_str()));
+    if headers.get("Transfer-Encoding").is_none() && !is_close_delimited && !cannot_have_body {
+        headers = match content_length {
+            Some(content_length) => headers.replace(("Content-Length", content_length.to_string().as_str())),
+            // we are decompressing the body as it streams, so we do not know how long it is
+            None => headers.remove("Content-Length"),
+        };
     }
     // should only be doing this if we are talking to a user agent that does not accept chunked encoding
     // otherwise keep chunked encoding header
     if is_request && is_version_1_0 {
-        headers = headers.add(("Content-Length", content_length.to_string().as_str()))
-            .remove("Transfer-Encoding");
+        if let Some(content_length) = content_length {
+            headers = headers.add(("Content-Length", content_length.to_string().as_str()))
+                .remove("Transfer-Encoding");
+        }
     }
 
-    message(part1.to_string(), part2, part3.clone().to_string(), is_response, body, headers, trailers)
+    message(method, part2, part3, version, is_response, body, headers, trailers)
+}
+
+/*
+ https://datatracker.ietf.org/doc/html/rfc7230#section-3.1
+ start-line     = request-line / status-line
+ request-line   = method SP request-target SP HTTP-version CRLF
+ status-line    = HTTP-version SP status-code SP reason-phrase CRLF
+ */
+fn start_line_parts(start_line_writer: &[u8]) -> Result<(&str, &str, &str), MessageError> {
+    let start_line = str::from_utf8(start_line_writer)
+        .map_err(|_| MessageError::MalformedStartLine("Start line is not valid utf-8".to_string()))?;
+    let malformed = || MessageError::MalformedStartLine(format!("Malformed start line {}", start_line));
+    if start_line.starts_with("HTTP/") {
+        // the reason phrase can have spaces in it, or be left out altogether
+        let mut parts = start_line.splitn(3, " ");
+        let version = parts.next().ok_or_else(malformed)?;
+        let status = parts.next().filter(|status| status.len() == 3 && status.chars().all(|c| c.is_ascii_digit()))
+            .ok_or_else(malformed)?;
+        Ok((version, status, parts.next().unwrap_or("")))
+    } else {
+        let parts = start_line.split(" ").collect::<Vec<&str>>();
+        if parts.len() != 3 || parts.iter().any(|part| part.is_empty()) {
+            return Err(malformed());
+        }
+        Ok((parts[0], parts[1], parts[2]))
+    }
 }
 
 #[derive(Clone)]
@@ -194,13 +369,16 @@ impl ReadMetadata {
     }
 }
 
-fn read(
-    stream: &mut TcpStream,
-    mut reader: &mut [u8],
+#[allow(clippy::too_many_arguments)]
+fn read<C: Connection>(
+    stream: &mut C,
+    reader: &mut [u8],
     writer: &mut Vec<u8>,
     mut read_bytes_from_stream: usize,
     mut up_to_in_reader: usize,
     mut metadata: Option<ReadMetadata>,
+    deadline: Option<Instant>,
+    read_timeout: Option<Duration>,
     fun: fn(&mut [u8], &mut Vec<u8>, Option<ReadMetadata>) -> ReadResult,
 ) -> (usize, usize, ReadResult) {
     let mut finished = false;
@@ -225,7 +403,20 @@ fn read(
             };
             if finished { break; }
         } else {
-            read_bytes_from_stream = stream.read(&mut reader).unwrap();
+            if let Some(deadline) = deadline {
+                // however long it has been since the last read, we only wait until the deadline
+                let left = deadline.saturating_duration_since(Instant::now());
+                if left.is_zero() {
+                    return (0, 0, ReadResult::Err(timed_out()));
+                }
+                let _ = stream.set_read_timeout(Some(read_timeout.map(|timeout| min(timeout, left)).unwrap_or(left)));
+            }
+            read_bytes_from_stream = match stream.read(reader) {
+                Ok(0) => return (0, 0, ReadResult::Err(MessageError::ConnectionClosed("Connection closed before message was read".to_string()))),
+                Ok(read_bytes) => read_bytes,
+                Err(e) if is_timeout(&e) => return (0, 0, ReadResult::Err(timed_out())),
+                Err(e) => return (0, 0, ReadResult::Err(MessageError::IoError(e.to_string()))),
+            };
             result = fun(&mut reader[..read_bytes_from_stream], writer, metadata);
             if result.is_err() {
                 return (0, 0, ReadResult::Err(result.err()));
@@ -236,20 +427,29 @@ fn read(
     (read_bytes_from_stream, up_to_in_reader, result)
 }
 
-fn message<'a>(part1: String, part2: &'a str, part3: String, is_response: bool, body: Body<'a>, headers: Headers, trailers: Headers) -> Result<HttpMessage<'a>, MessageError> {
+fn is_timeout(error: &std::io::Error) -> bool {
+    // which of the two we get depends on the platform
+    error.kind() == ErrorKind::WouldBlock || error.kind() == ErrorKind::TimedOut
+}
+
+fn timed_out() -> MessageError {
+    MessageError::Timeout("Timed out waiting for the message".to_string())
+}
+
+#[allow(clippy::too_many_arguments)]
+fn message<'a>(method: Method, part2: &'a str, reason: &str, version: (u8, u8), is_response: bool, body: Body<'a>, headers: Headers, trailers: Trailers) -> Result<HttpMessage<'a>, MessageError> {
+    let (major, minor) = version;
     if is_response {
-        let (major, minor) = http_version_from(part1.as_str());
         Ok(HttpMessage::Response(Response {
-            status: Status::from(part2),
+            status: Status::parse(part2, reason)?,
             headers,
             body,
             version: HttpVersion { major, minor },
             trailers,
         }))
     } else {
-        let (major, minor) = http_version_from(part3.as_str());
         Ok(HttpMessage::Request(Request {
-            method: Method::from(part1.as_str()),
+            method,
             uri: Uri::parse(part2),
             headers,
             body,
@@ -259,262 +459,660 @@ fn message<'a>(part1: String, part2: &'a str, part3: String, is_response: bool,
     }
 }
 
-fn simple_body<'a>(
+#[allow(clippy::too_many_arguments)]
+fn simple_body<'a, C: Connection + 'a>(
     reader: &'a mut [u8],
-    stream: TcpStream,
+    stream: C,
+    wire: &'a mut WireState,
     up_to_in_reader: usize,
     read_bytes_from_stream: usize,
     is_request: bool,
     method_can_have_body: bool,
-    content_length: Option<Result<usize, String>>,
+    expects_continue: bool,
+    content_length: Option<Result<usize, MessageError>>,
+    is_textual: bool,
     compression: CompressionAlgorithm,
-    mut compress_writer: &'a mut Vec<u8>,
-) -> Result<(Body<'a>, Headers, usize), MessageError> {
+    compress_writer: &'a mut Vec<u8>,
+    limits: MessageLimits,
+) -> Result<(Body<'a>, Trailers, Option<usize>), MessageError> {
     let bytes_left_in_reader = read_bytes_from_stream - up_to_in_reader;
     let (body, content_length) = match content_length {
+        // even if we would ignore the body, we cannot tell where the next message starts
+        Some(Err(error)) => {
+            return Err(error);
+        }
+        // nor do we want to skip over that much of it
+        Some(Ok(content_length)) if content_length > limits.max_body_size => {
+            return Err(body_too_big(limits.max_body_size));
+        }
+        Some(Ok(content_length)) if is_request && !method_can_have_body => {
+            // the body is ignored but it is still part of the message, so skip over it
+            if bytes_left_in_reader >= content_length {
+                wire.set_leftover(up_to_in_reader + content_length, read_bytes_from_stream);
+            } else {
+                wire.body_left_on_stream = content_length - bytes_left_in_reader;
+                wire.expects_continue = expects_continue;
+            }
+            (Body::empty(), Some(0))
+        }
         Some(_) if is_request && !method_can_have_body => {
-            (Body::empty(), 0)
+            (Body::empty(), Some(0))
         }
-        // we have read the whole body in the first read
-        Some(Ok(content_length)) if bytes_left_in_reader == content_length => {
-            if compression.is_some() {
-                decompress(&compression, &mut compress_writer, &mut reader[up_to_in_reader..read_bytes_from_stream].to_vec());
-                let result = str::from_utf8(compress_writer.as_slice()).unwrap();
-                (Body::BodyString(result), compress_writer.len())
+        // small compressed bodies are decoded up front, if they fit, so that we know how long they are
+        Some(Ok(content_length)) if compression.is_some() && content_length <= reader.len() && !expects_continue => {
+            let end_of_body = min(read_bytes_from_stream, up_to_in_reader + content_length);
+            let mut encoded = reader[up_to_in_reader..end_of_body].to_vec();
+            if bytes_left_in_reader >= content_length {
+                wire.set_leftover(end_of_body, read_bytes_from_stream);
+            } else {
+                let rest = (content_length - bytes_left_in_reader) as u64;
+                let read = stream.take(rest).read_to_end(&mut encoded).unwrap_or(0);
+                if read as u64 != rest {
+                    return Err(MessageError::ConnectionClosed("Connection closed before body was read".to_string()));
+                }
+            }
+            if decode_if_it_fits(encoded.as_slice(), &compression, compress_writer, limits.max_decompressed_size) {
+                let length = compress_writer.len();
+                (Body::from_bytes(compress_writer.as_slice(), is_textual), Some(length))
             } else {
-                let result = str::from_utf8(&reader[up_to_in_reader..read_bytes_from_stream]).unwrap();
-                (Body::BodyString(result), content_length)
+                (Body::BodyStream(limited_decoder(Cursor::new(encoded), &compression, limits.max_decompressed_size)), None)
             }
         }
+        // we have read the whole body in the first read
+        Some(Ok(content_length)) if bytes_left_in_reader >= content_length => {
+            let end_of_body = up_to_in_reader + content_length;
+            wire.set_leftover(end_of_body, read_bytes_from_stream);
+            (Body::from_bytes(&reader[up_to_in_reader..end_of_body], is_textual), Some(content_length))
+        }
         Some(Ok(content_length)) => {
             // we need to read more to get the body
+            wire.body_left_on_stream = content_length - bytes_left_in_reader;
+            wire.expects_continue = expects_continue;
+            let WireState { body_left_on_stream, expects_continue, .. } = wire;
+            let rest = BodyLeftOnStream { stream, left: body_left_on_stream, expects_continue };
+            let body = reader[up_to_in_reader..read_bytes_from_stream].chain(rest);
             if compression.is_some() {
-                let mut rest = Vec::new();
-                let _read = stream.take((content_length - bytes_left_in_reader) as u64).read_to_end(&mut rest).unwrap();
-                let mut whole = reader[up_to_in_reader..read_bytes_from_stream].to_vec();
-                whole.append(&mut rest);
-                decompress(&compression, &mut compress_writer, &mut whole);
-                let result = str::from_utf8(compress_writer.as_slice()).unwrap();
-                (Body::BodyString(result), compress_writer.len())
+                (Body::BodyStream(limited_decoder(body, &compression, limits.max_decompressed_size)), None)
             } else {
-                let rest = stream.take((content_length - bytes_left_in_reader) as u64);
-                (Body::BodyStream(Box::new(reader[up_to_in_reader..read_bytes_from_stream].chain(rest))), content_length)
+                (Body::BodyStream(Box::new(body)), Some(content_length))
             }
         }
-        Some(Err(error)) => {
-            return Err(MessageError::InvalidContentLength(format!("Content Length header couldn't be parsed, got {}", error).to_string()));
+        // there is no body, so anything after the head is the next message
+        _ => {
+            wire.set_leftover(up_to_in_reader, read_bytes_from_stream);
+            (Body::empty(), Some(0))
         }
-        _ => (Body::empty(), 0)
     };
-    Ok((body, Headers::empty(), content_length))
+    Ok((body, Trailers::empty(), content_length))
+}
+
+fn decode_if_it_fits(encoded: &[u8], compression: &CompressionAlgorithm, writer: &mut Vec<u8>, max_decompressed_size: usize) -> bool {
+    let mut decoder = limited_decoder(encoded, compression, max_decompressed_size);
+    let limit = writer.capacity() as u64;
+    let fits = (&mut decoder).take(limit).read_to_end(writer).is_ok()
+        && decoder.read(&mut [0; 1]).map(|read| read == 0).unwrap_or(false);
+    if !fits {
+        writer.clear();
+    }
+    fits
+}
+
+fn body_too_big(max_body_size: usize) -> MessageError {
+    MessageError::BodyTooBig(format!("Body must be no more than {} bytes", max_body_size))
+}
+
+fn decompressed_body_too_big(max_decompressed_size: usize) -> MessageError {
+    MessageError::BodyTooBig(format!("Decompressed body must be no more than {} bytes", max_decompressed_size))
+}
+
+pub(crate) fn limited_decoder<'a, R: Read + 'a>(reader: R, compression: &CompressionAlgorithm, max_decompressed_size: usize) -> Box<dyn Read + 'a> {
+    Box::new(LimitedDecoder { decoder: Codex::decode_reader(reader, compression), left: max_decompressed_size, limit: max_decompressed_size })
+}
+
+/// Decodes a compressed body until it gets bigger than it is allowed to be.
+struct LimitedDecoder<'a> {
+    decoder: Box<dyn Read + 'a>,
+    left: usize,
+    limit: usize,
 }
 
-fn chunked_body_and_trailers<'a>(
+impl<'a> Read for LimitedDecoder<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        // ask for one more than is left, so that we know if there is too much
+        let up_to = min(buf.len(), self.left.saturating_add(1));
+        let read = self.decoder.read(&mut buf[..up_to])?;
+        if read > self.left {
+            return Err(decompressed_body_too_big(self.limit).to_io_error());
+        }
+        self.left -= read;
+        Ok(read)
+    }
+}
+
+/// The rest of a Content-Length body that is still on the stream.
+/// Counts down as it is read so that, if the handler does not read all of it,
+/// the server knows how much to skip before reading the next message on the connection.
+/// If the client is waiting to be told to continue, then we tell it on the first read,
+/// so that a handler can reject the body without the client ever sending it.
+struct BodyLeftOnStream<'a, C: Connection> {
+    stream: C,
+    left: &'a mut usize,
+    expects_continue: &'a mut bool,
+}
+
+impl<'a, C: Connection> Read for BodyLeftOnStream<'a, C> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if *self.left == 0 {
+            return Ok(0);
+        }
+        if *self.expects_continue {
+            write_continue(&mut self.stream)?;
+            *self.expects_continue = false;
+        }
+        let up_to = min(buf.len(), *self.left);
+        let read = self.stream.read(&mut buf[..up_to])
+            .map_err(|e| if is_timeout(&e) { timed_out().to_io_error() } else { e })?;
+        // otherwise a body that was cut short would look like all of it
+        if read == 0 {
+            return Err(MessageError::ConnectionClosed("Connection closed before body was read".to_string()).to_io_error());
+        }
+        *self.left -= read;
+        Ok(read)
+    }
+}
+
+pub fn expects_continue(headers: &Headers) -> bool {
+    headers.get("Expect").map(|e| e.to_lowercase() == "100-continue").unwrap_or(false)
+}
+
+fn write_continue<W: Write>(stream: &mut W) -> std::io::Result<()> {
+    let interim_response = format!("HTTP/1.1 {} {}\r\n\r\n", Continue.value(), Continue.to_string());
+    stream.write_all(interim_response.as_bytes())
+}
+
+// a response with neither Content-Length nor Transfer-Encoding goes on until the server closes the connection
+fn close_delimited_body<'a, C: Connection + 'a>(
     reader: &'a mut [u8],
-    mut stream: TcpStream,
+    stream: C,
     up_to_in_reader: usize,
     read_bytes_from_stream: usize,
+    compression: CompressionAlgorithm,
+    max_decompressed_size: usize,
+) -> Result<(Body<'a>, Trailers, Option<usize>), MessageError> {
+    let body = reader[up_to_in_reader..read_bytes_from_stream].chain(stream);
+    Ok((Body::BodyStream(limited_decoder(body, &compression, max_decompressed_size)), Trailers::empty(), None))
+}
+
+#[allow(clippy::too_many_arguments)]
+fn chunked_body_and_trailers<'a, C: Connection + 'a>(
+    reader: &'a mut [u8],
+    stream: C,
+    wire: &'a mut WireState,
+    up_to_in_reader: usize,
+    read_bytes_from_stream: usize,
+    ignore_body: bool,
+    read_whole_body: bool,
     chunks_writer: &'a mut Vec<u8>,
     compress_writer: &'a mut Vec<u8>,
     trailers_writer: &'a mut Vec<u8>,
     compression: &CompressionAlgorithm,
-) -> Result<(Body<'a>, Headers, usize), MessageError> {
-    let result = read_body_and_trailers(reader, &mut stream, up_to_in_reader, read_bytes_from_stream, chunks_writer, trailers_writer);
-    if result.is_err() {
-        return Err(result.err().unwrap());
+) -> Result<(Body<'a>, Trailers, Option<usize>), MessageError> {
+    wire.set_leftover(up_to_in_reader, read_bytes_from_stream);
+    wire.chunked_body = Some(ChunkedMetadata { mode: ReadMode::Metadata, chunk_size: 0, bytes_of_this_chunk_read: 0 });
+    wire.chunked_body_read = 0;
+    let max_decompressed_size = wire.limits.max_decompressed_size;
+    let trailers = Trailers::empty();
+
+    if ignore_body {
+        // the body is ignored but it is still part of the message, so skip over it
+        let mut body = ChunkedBody { stream, reader, wire, decoded: chunks_writer, trailers_writer, trailers: trailers.clone() };
+        body.read_rest(None)?;
+        return Ok((Body::empty(), trailers, Some(0)));
+    }
+    if !read_whole_body {
+        let body = ChunkedBody { stream, reader, wire, decoded: chunks_writer, trailers_writer, trailers: trailers.clone() };
+        if compression.is_none() {
+            return Ok((BodyStream(Box::new(body)), trailers, None));
+        }
+        let body = SharedChunkedBody { body: Rc::new(RefCell::new(body)) };
+        let decoded = DecodedChunkedBody { decoder: limited_decoder(body.clone(), compression, max_decompressed_size), body };
+        return Ok((BodyStream(Box::new(decoded)), trailers, None));
     }
-    let chunked_body_bytes_read = result.unwrap();
-    let trailer_string = from_utf8(trailers_writer.as_slice()).unwrap();
-    let trailers = Headers::parse_from(trailer_string);
 
-    let body = if compression.is_some() {
-        decompress(&compression, compress_writer, chunks_writer);
-        BodyStream(Box::new(compress_writer.take(compress_writer.len() as u64)))
+    let mut whole = Vec::new();
+    let mut body = ChunkedBody { stream, reader, wire, decoded: chunks_writer, trailers_writer, trailers: trailers.clone() };
+    body.read_rest(Some(&mut whole))?;
+    if compression.is_some() {
+        let mut decoder = Codex::decode_reader(whole.as_slice(), compression);
+        let decoded = (&mut decoder).take(max_decompressed_size.saturating_add(1) as u64).read_to_end(compress_writer)
+            .map_err(|e| MessageError::IoError(e.to_string()))?;
+        if decoded > max_decompressed_size {
+            return Err(decompressed_body_too_big(max_decompressed_size));
+        }
+        let length = compress_writer.len();
+        Ok((BodyStream(Box::new(compress_writer.take(length as u64))), trailers, Some(length)))
     } else {
-        BodyStream(Box::new(chunks_writer.take(chunks_writer.len() as u64)))
-    };
-    Ok((body, trailers, chunked_body_bytes_read))
+        let length = whole.len();
+        Ok((BodyStream(Box::new(Cursor::new(whole))), trailers, Some(length)))
+    }
 }
 
-#[allow(unused_assignments)]
-fn read_body_and_trailers(reader: &mut [u8], mut stream: &mut TcpStream, up_to_in_reader: usize, read_bytes_from_stream: usize, chunks_writer: &mut Vec<u8>, trailers_writer: &mut Vec<u8>) -> Result<usize, MessageError> {
-    let metadata = Some(ReadMetadata::chunked(ReadMode::Metadata, 0, 0));
-    let (mut read_bytes_from_stream, mut up_to_in_reader, mut result) =
-        read(&mut stream, reader, chunks_writer, read_bytes_from_stream, up_to_in_reader, metadata, |reader, writer, metadata| {
-            let meta = metadata.unwrap().to_chunked_metadata();
-            body_chunks_(reader, writer, meta.mode, meta.bytes_of_this_chunk_read, meta.chunk_size)
-        });
-    if result.is_err() {
-        return Err(result.err());
+/// A chunked body, decoded from the stream as it is read.
+/// Where we are up to is kept in the WireState, so that if the handler does not read all of the body
+/// the server can skip the rest of it before reading the next message on the connection.
+/// The trailers come after the last chunk, so they are only set once the body has been read to the end.
+struct ChunkedBody<'a, C: Connection> {
+    stream: C,
+    reader: &'a mut [u8],
+    wire: &'a mut WireState,
+    decoded: &'a mut Vec<u8>,
+    trailers_writer: &'a mut Vec<u8>,
+    trailers: Trailers,
+}
+
+impl<'a, C: Connection> ChunkedBody<'a, C> {
+    fn read_chunks(&mut self, buf: &mut [u8]) -> Result<usize, MessageError> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        loop {
+            let metadata = match self.wire.chunked_body {
+                Some(metadata) => metadata,
+                None => return Ok(0),
+            };
+            if self.wire.expects_continue {
+                self.wire.expects_continue = false;
+                write_continue(&mut self.stream).map_err(|e| MessageError::ConnectionClosed(e.to_string()))?;
+            }
+            if self.wire.up_to_in_reader >= self.wire.read_bytes_from_stream {
+                self.wire.read_bytes_from_stream = match self.stream.read(self.reader) {
+                    Ok(0) => return Err(MessageError::ConnectionClosed("Connection closed before chunked body was read".to_string())),
+                    Ok(read_bytes) => read_bytes,
+                    Err(e) if is_timeout(&e) => return Err(timed_out()),
+                    Err(e) => return Err(MessageError::ConnectionClosed(e.to_string())),
+                };
+                self.wire.up_to_in_reader = 0;
+            }
+            // decoding never makes the body bigger, so only decode as much as will fit in buf
+            let start = self.wire.up_to_in_reader;
+            let end = min(self.wire.read_bytes_from_stream, start + buf.len());
+            self.decoded.clear();
+            let result = body_chunks_(&self.reader[start..end], self.decoded, metadata.mode, metadata.bytes_of_this_chunk_read, metadata.chunk_size);
+            if result.is_err() {
+                return Err(result.err());
+            }
+            let (finished, up_to_in_reader, new_metadata) = result.unwrap();
+            let decoded = self.decoded.len();
+            self.wire.chunked_body_read += decoded;
+            if self.wire.chunked_body_read > self.wire.limits.max_body_size {
+                return Err(body_too_big(self.wire.limits.max_body_size));
+            }
+            buf[..decoded].copy_from_slice(self.decoded.as_slice());
+
+            if finished {
+                self.wire.up_to_in_reader = start + up_to_in_reader;
+                self.read_trailers()?;
+                return Ok(decoded);
+            }
+            self.wire.up_to_in_reader = end;
+            self.wire.chunked_body = new_metadata.map(|metadata| metadata.to_chunked_metadata());
+            if decoded > 0 {
+                return Ok(decoded);
+            }
+        }
     }
-    let (_finished, chunked_body_bytes_read, _metadata) = result.unwrap();
 
-    let more_bytes_to_read_after_body = up_to_in_reader < read_bytes_from_stream;
-    if more_bytes_to_read_after_body {
-        (read_bytes_from_stream, up_to_in_reader, result) =
-            read(&mut stream, reader, trailers_writer, read_bytes_from_stream, up_to_in_reader, metadata, |reader, writer, _metadata| {
-                trailers_(reader, writer)
-            });
+    // the trailer section always follows the last chunk, even if it is just an empty line
+    fn read_trailers(&mut self) -> Result<(), MessageError> {
+        let (read_bytes_from_stream, up_to_in_reader, result) =
+            read(&mut self.stream, self.reader, self.trailers_writer, self.wire.read_bytes_from_stream, self.wire.up_to_in_reader, None, None, None,
+                 |reader, writer, _metadata| { trailers_(reader, writer) },
+            );
+        if result.is_err() {
+            return Err(result.err());
+        }
+        let trailer_string = from_utf8(self.trailers_writer.as_slice())
+            .map_err(|_| MessageError::MalformedHeader("Trailers are not valid utf-8".to_string()))?;
+        let trailers = Headers::parse_from(trailer_string)?;
+        if trailers.vec.len() > self.wire.limits.max_headers {
+            return Err(MessageError::TooManyHeaders(format!("There must be no more than {} trailers", self.wire.limits.max_headers)));
+        }
+        self.trailers.set(trailers);
+        // anything after the trailers is the start of the next message
+        self.wire.set_leftover(up_to_in_reader, read_bytes_from_stream);
+        self.wire.chunked_body = None;
+        Ok(())
+    }
+
+    // reads the rest of the body, keeping it if there is somewhere to keep it
+    fn read_rest(&mut self, mut keep: Option<&mut Vec<u8>>) -> Result<(), MessageError> {
+        let mut buffer = [0; 4096];
+        loop {
+            let read = self.read_chunks(&mut buffer)?;
+            if read == 0 {
+                return Ok(());
+            }
+            if let Some(whole) = keep.as_mut() {
+                whole.extend_from_slice(&buffer[..read]);
+            }
+        }
     }
-    if result.is_err() {
-        return Err(result.err());
+}
+
+impl<'a, C: Connection> Read for ChunkedBody<'a, C> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.read_chunks(buf).map_err(|e| {
+            self.wire.broken = true;
+            e.to_io_error()
+        })
+    }
+}
+
+struct SharedChunkedBody<'a, C: Connection> {
+    body: Rc<RefCell<ChunkedBody<'a, C>>>,
+}
+
+// derived Clone would need C to be Clone, when it is only the Rc that is cloned
+impl<'a, C: Connection> Clone for SharedChunkedBody<'a, C> {
+    fn clone(&self) -> Self {
+        SharedChunkedBody { body: self.body.clone() }
+    }
+}
+
+impl<'a, C: Connection> Read for SharedChunkedBody<'a, C> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.body.borrow_mut().read(buf)
     }
-    Ok(chunked_body_bytes_read)
+}
+
+/// The decoder stops reading at the end of the compressed data,
+/// but the chunked body carries on to the last chunk and the trailers after it.
+struct DecodedChunkedBody<'a, C: Connection> {
+    decoder: Box<dyn Read + 'a>,
+    body: SharedChunkedBody<'a, C>,
+}
+
+impl<'a, C: Connection> Read for DecodedChunkedBody<'a, C> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let read = self.decoder.read(buf)?;
+        if read == 0 && !buf.is_empty() {
+            self.body.body.borrow_mut().read_rest(None).map_err(|e| e.to_io_error())?;
+        }
+        Ok(read)
+    }
+}
+
+/// The handler may not have read all of the body of the last message read on the connection,
+/// in which case it is still on the stream and needs skipping before we read the next message.
+/// Returns whether we could skip it.
+pub fn skip_unread_body<C: Connection>(stream: &mut C, reader: &mut [u8], wire: &mut WireState, chunks_writer: &mut Vec<u8>, trailers_writer: &mut Vec<u8>) -> bool {
+    // the handler responded without reading the body, so the client may or may not send it now
+    if wire.expects_continue || wire.broken {
+        return false;
+    }
+    if wire.chunked_body.is_some() {
+        let mut body = ChunkedBody { stream: &mut *stream, reader, wire, decoded: chunks_writer, trailers_writer, trailers: Trailers::empty() };
+        return body.read_rest(None).is_ok();
+    }
+    if wire.body_left_on_stream == 0 {
+        return true;
+    }
+    let left = wire.body_left_on_stream as u64;
+    wire.body_left_on_stream = 0;
+    copy(&mut stream.take(left), &mut sink()).map(|skipped| skipped == left).unwrap_or(false)
+}
+
+/// The buffers a message is parsed into, which the message borrows from once it is parsed.
+/// They can be used again for the next message, once that one is done with.
+pub struct MessageBuffers {
+    reader: Vec<u8>,
+    wire: WireState,
+    start_line: Vec<u8>,
+    headers: Vec<u8>,
+    chunks: Vec<u8>,
+    compress: Vec<u8>,
+    trailers: Vec<u8>,
+    body: Vec<u8>,
+}
+
+impl MessageBuffers {
+    pub fn new() -> MessageBuffers {
+        MessageBuffers::with_limits(MessageLimits::default())
+    }
+
+    pub fn with_limits(limits: MessageLimits) -> MessageBuffers {
+        MessageBuffers {
+            reader: vec![0; 4096],
+            wire: WireState::with_limits(limits),
+            // the writers are only allowed to get as big as they start out
+            start_line: Vec::with_capacity(16384),
+            headers: Vec::with_capacity(16384),
+            chunks: Vec::with_capacity(1048576),
+            compress: Vec::with_capacity(1048576),
+            trailers: Vec::with_capacity(16384),
+            body: Vec::new(),
+        }
+    }
+}
+
+impl Default for MessageBuffers {
+    fn default() -> MessageBuffers {
+        MessageBuffers::new()
+    }
+}
+
+/// A message parsed from bytes, and how many of the bytes it took up,
+/// so that whatever comes after it is the start of the next message.
+pub struct ParsedMessage<'a> {
+    pub message: HttpMessage<'a>,
+    pub length: usize,
+}
+
+/// Parses the first message in the bytes, all of its body included, without a connection to read it from.
+/// If the bytes stop before the message does then there is no message yet, so try again once there are more of them.
+/// A response with neither Content-Length nor Transfer-Encoding goes on until the connection closes,
+/// so it is taken to be the rest of the bytes.
+pub fn parse_message<'a>(bytes: &[u8], is_response_to_head: bool, buffers: &'a mut MessageBuffers) -> Result<Option<ParsedMessage<'a>>, MessageError> {
+    let MessageBuffers { reader, wire, start_line, headers, chunks, compress, trailers, body } = buffers;
+    *wire = WireState::with_limits(wire.limits);
+    for writer in [&mut *start_line, &mut *headers, &mut *chunks, &mut *compress, &mut *trailers, &mut *body] {
+        writer.clear();
+    }
+    let mut rest = bytes;
+    let read = {
+        // there is no one to tell to continue, so that goes nowhere
+        let mut connection = Duplex::new(&mut rest, sink());
+        let read = read_message_from_wire(&mut connection, reader, wire, is_response_to_head, start_line, headers, chunks, compress, trailers)
+            .and_then(|message| {
+                let (message_headers, message_body, message_trailers) = match message {
+                    HttpMessage::Request(request) => (request.headers, request.body, request.trailers),
+                    HttpMessage::Response(response) => (response.headers, response.body, response.trailers),
+                };
+                let read_body = match message_body {
+                    BodyString(str) => {
+                        body.extend_from_slice(str.as_bytes());
+                        Ok(())
+                    }
+                    BodyBytes(bytes) => {
+                        body.extend_from_slice(bytes);
+                        Ok(())
+                    }
+                    BodyStream(mut reader) => reader.read_to_end(body).map(|_| ()),
+                };
+                read_body.map(|_| (message_headers, message_trailers))
+                    .map_err(|e| MessageError::IoError(e.to_string()))
+            });
+        // eg the body of a GET, which is not part of the message we give back but is still in the bytes
+        wire.expects_continue = false;
+        if read.is_ok() && !skip_unread_body(&mut connection, reader, wire, chunks, trailers) {
+            return Ok(None);
+        }
+        read
+    };
+    let ran_out = wire.body_left_on_stream > 0 || wire.chunked_body.is_some();
+    let (message_headers, message_trailers) = match read {
+        Err(MessageError::ConnectionClosed(_)) => return Ok(None),
+        Err(_) if ran_out => return Ok(None),
+        Err(e) => return Err(e),
+        Ok(parts) => parts,
+    };
+    let left_in_reader = if wire.has_leftover_bytes() { wire.read_bytes_from_stream - wire.up_to_in_reader } else { 0 };
+    let length = bytes.len() - rest.len() - left_in_reader;
+
+    // the message borrows the start line and the body, now that we are done writing to them
+    let start_line: &'a Vec<u8> = start_line;
+    let body: &'a Vec<u8> = body;
+    let (part1, part2, part3) = start_line_parts(start_line)?;
+    let is_response = part1.starts_with("HTTP/");
+    let version = http_version_from(if is_response { part1 } else { part3 })?;
+    let method = if is_response { GET } else { Method::parse(part1)? };
+    let is_textual = is_textual(&message_headers);
+    let message = message(method, part2, part3, version, is_response, Body::from_bytes(body.as_slice(), is_textual), message_headers, message_trailers)?;
+    Ok(Some(ParsedMessage { message, length }))
+}
+
+/// Writes a message to bytes instead of to a connection.
+pub fn serialize_message(message: HttpMessage, request_options: RequestOptions) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    // nothing can go wrong writing to bytes, only reading a streamed body can, which leaves the message unfinished
+    let _ = write_message_to_wire(&mut bytes, message, request_options);
+    bytes
 }
 
 fn body_chunks_(reader: &[u8], writer: &mut Vec<u8>, mut mode: ReadMode, read_up_to: usize, this_chunk_size: usize) -> ReadResult {
-    let mut prev = vec!('1', '2', '3', '4', '5');
+    // in metadata mode the chunk size is built up digit by digit, as the digits may span reads
     let mut chunk_size: usize = this_chunk_size;
-    let mut chunk_size_hex: String = "".to_string();
-    let mut bytes_of_this_chunk_read_previously = read_up_to;
+    let mut bytes_of_this_chunk_read = read_up_to;
     let mut finished = false;
-    let mut bytes_of_chunk_read_this_pass: usize = 0;
     let mut up_to_in_reader: usize = 0;
 
     for (index, octet) in reader.iter().enumerate() {
-        prev.remove(0);
-        prev.push(*octet as char);
-
         let on_boundary = *octet == b'\n' || *octet == b'\r';
         if mode == ReadMode::Metadata && !on_boundary {
-            chunk_size_hex.push(*octet as char);
+            let digit = (*octet as char).to_digit(16);
+            if digit.is_none() {
+                return ReadResult::Err(MessageError::InvalidBoundaryDigit(format!("Could not parse boundary character {} in chunked encoding", *octet as char)));
+            }
+            // saturates rather than overflows, so that an enormous chunk is just bigger than the body is allowed to be
+            chunk_size = chunk_size.saturating_mul(16).saturating_add(digit.unwrap() as usize);
         } else if mode == ReadMode::Metadata && on_boundary {
-            // if we're on the boundary, continue, or compute chunk length and change mode to read once we've seen \n
+            // if we're on the boundary, continue, or change mode to read once we've seen \n
             if *octet == b'\n' {
-                let result = usize::from_str_radix(&chunk_size_hex, 16);
-                if result.is_err() {
-                    return ReadResult::Err(MessageError::InvalidBoundaryDigit(format!("Could not parse boundary character {} in chunked encoding", &chunk_size_hex)));
-                }
-                // reset chunk_size_hex
-                chunk_size_hex = "".to_string();
-                chunk_size = result.unwrap();
                 if chunk_size == 0 {
+                    // the last chunk, what follows are the trailers (if any) and then an empty line
                     finished = true;
-                    // if more bytes left then assume there are trailers (which means end is 0\r\n otherwise its 0\r\n\r\n)
-                    if reader.len() > index + 3 {
-                        up_to_in_reader = index + 1
-                    } else {
-                        up_to_in_reader = index + 3; // add 3 to go past the next \n (weve hit the first one in 0\r\n\r\n)
-                    }
+                    up_to_in_reader = index + 1;
                     break;
                 }
                 mode = ReadMode::Data;
             }
-            continue;
-        }
-        if mode == ReadMode::Data && bytes_of_chunk_read_this_pass < (chunk_size - bytes_of_this_chunk_read_previously) {
+        } else if mode == ReadMode::Data && bytes_of_this_chunk_read < chunk_size {
             writer.push(*octet);
-            bytes_of_chunk_read_this_pass += 1;
-            let last_iteration = index == reader.len() - 1;
-            if last_iteration {
-                // if last index, add on the bytes read from current chunk
-                bytes_of_this_chunk_read_previously += bytes_of_chunk_read_this_pass;
-                up_to_in_reader = 0;
-                break;
-            }
+            bytes_of_this_chunk_read += 1;
         } else if mode == ReadMode::Data && on_boundary {
             // if we're on the boundary, continue, or change mode to metadata once we've seen \n
             // and reset counters
             if *octet == b'\n' {
-                bytes_of_this_chunk_read_previously = 0;
+                bytes_of_this_chunk_read = 0;
                 chunk_size = 0;
-                bytes_of_chunk_read_this_pass = 0;
                 mode = ReadMode::Metadata;
             }
-            continue;
         }
     }
 
-    let metadata = ReadMetadata::chunked(mode, chunk_size, bytes_of_this_chunk_read_previously);
+    let metadata = ReadMetadata::chunked(mode, chunk_size, bytes_of_this_chunk_read);
     ReadResult::Ok((finished, up_to_in_reader, Some(metadata)))
 }
 
-fn check_valid_content_length_or_transfer_encoding(headers: &Headers, is_response: bool, method_can_have_body: bool) -> Result<(), MessageError> {
-    let is_req_and_method_can_have_body = !is_response && method_can_have_body;
+// responses can be close-delimited, but a request without a length cannot be, as then we could not respond
+// chunked must be the last coding, and only applied once
+fn chunked_is_final(transfer_encoding: &str) -> bool {
+    let codings = transfer_encoding.split(",").map(|coding| coding.trim().to_lowercase()).collect::<Vec<String>>();
+    codings.last().map(|coding| coding == "chunked").unwrap_or(false)
+        && codings.iter().filter(|coding| *coding == "chunked").count() == 1
+}
+
+fn check_valid_content_length_or_transfer_encoding(headers: &Headers, length_required: bool) -> Result<(), MessageError> {
     let no_content_length_or_transfer_encoding = !headers.has("Content-Length") &&
         headers.get("Transfer-Encoding").is_none();
 
-    if (is_req_and_method_can_have_body && no_content_length_or_transfer_encoding)
-        || is_response && no_content_length_or_transfer_encoding {
+    if length_required && no_content_length_or_transfer_encoding {
         return Err(MessageError::NoContentLengthOrTransferEncoding("Content-Length or Transfer-Encoding must be provided".to_string()));
     }
     Ok(())
 }
 
 fn start_line_(reader: &[u8], writer: &mut Vec<u8>) -> ReadResult {
-    let mut prev: Vec<char> = vec!('1', '2', '3', '4');
-    let mut up_to_in_reader = if reader.len() > 0 { reader.len() - 1 } else { 0 };
+    // zero means we got to the end of the reader without finishing the start line
+    let mut up_to_in_reader = 0;
     let mut finished = false;
 
     for (index, octet) in reader.iter().enumerate() {
-        if *octet != b'\r' && *octet != b'\n' {
-            writer.push(*octet);
+        // ignore any empty lines before the start line, eg a stray \r\n after the previous message
+        if writer.is_empty() && (*octet == b'\r' || *octet == b'\n') {
+            continue;
         }
-        if prev[3] == '\r' && *octet == b'\n' {
+        // the \r may have come at the end of the previous read, so look back in the writer for it
+        if *octet == b'\n' && writer.last() == Some(&b'\r') {
+            writer.pop();
             finished = true;
             up_to_in_reader = index + 1;
             break;
         }
+        // https://www.rfc-editor.org/rfc/rfc9112#section-2.2
+        // we do not recognise a single LF as a line terminator, as whatever is in front of us might
+        if *octet == b'\n' {
+            return ReadResult::Err(MessageError::BareLineFeed("Line feed without a carriage return in start line".to_string()));
+        }
+        writer.push(*octet);
         if writer.len() == writer.capacity() {
-            return ReadResult::Err(MessageError::StartLineTooBig(format!("Start line must be less than {}", reader.len())));
+            return ReadResult::Err(MessageError::StartLineTooBig(format!("Start line must be less than {}", writer.capacity())));
         }
-        prev.remove(0);
-        prev.push(*octet as char);
     }
 
     ReadResult::Ok((finished, up_to_in_reader, None))
 }
 
 fn headers_(reader: &[u8], writer: &mut Vec<u8>) -> ReadResult {
-    let mut prev: Vec<char> = vec!('1', '2', '3', '4');
-    let mut up_to_in_reader = reader.len() - 1;
-    let mut finished = false;
+    field_section_(reader, writer, |capacity| MessageError::HeadersTooBig(format!("Headers must be less than {}", capacity)))
+}
 
-    for (index, octet) in reader.iter().enumerate() {
-        writer.push(*octet);
-        up_to_in_reader = index + 1;
-        if prev[1] == '\r' && prev[2] == '\n' && prev[3] == '\r' && *octet == b'\n' {
-            finished = true;
-            writer.pop();
-            writer.pop();
-            writer.pop();
-            writer.pop(); // get rid of previous \r\n\r\n
-            break;
-        }
-        if writer.len() == writer.capacity() {
-            return ReadResult::Err(MessageError::HeadersTooBig(format!("Headers must be less than {}", writer.capacity())));
-        }
+fn trailers_(reader: &[u8], writer: &mut Vec<u8>) -> ReadResult {
+    field_section_(reader, writer, |capacity| MessageError::TrailersTooBig(format!("Trailers must be less than {}", capacity)))
+}
+
+// headers and trailers both come after a line ending in \r\n and finish with an empty line
+fn field_section_(reader: &[u8], writer: &mut Vec<u8>, too_big: fn(usize) -> MessageError) -> ReadResult {
+    // carry on from what we have already written, which starts after the \r\n of the previous line
+    let mut prev: Vec<char> = vec!('1', '2', '\r', '\n');
+    for octet in writer.iter().skip(writer.len().saturating_sub(4)) {
         prev.remove(0);
         prev.push(*octet as char);
     }
-
-    ReadResult::Ok((finished, up_to_in_reader, None))
-}
-
-fn trailers_(buffer: &[u8], writer: &mut Vec<u8>) -> ReadResult {
-    let mut prev: Vec<char> = vec!('1', '2', '3', '4');
+    let mut up_to_in_reader = 0;
     let mut finished = false;
 
-    for (_index, octet) in buffer.iter().enumerate() {
+    for (index, octet) in reader.iter().enumerate() {
+        if *octet == b'\n' && prev[3] != '\r' {
+            return ReadResult::Err(MessageError::BareLineFeed("Line feed without a carriage return in header section".to_string()));
+        }
+        writer.push(*octet);
+        up_to_in_reader = index + 1;
         if prev[1] == '\r' && prev[2] == '\n' && prev[3] == '\r' && *octet == b'\n' {
             finished = true;
+            // get rid of previous \r\n\r\n, or just \r\n if there were no fields
+            let without_ending = writer.len().saturating_sub(4);
+            writer.truncate(without_ending);
             break;
         }
         if writer.len() == writer.capacity() {
-            return ReadResult::Err(MessageError::TrailersTooBig(format!("Trailers must be less than {}", writer.capacity())));
+            return ReadResult::Err(too_big(writer.capacity()));
         }
         prev.remove(0);
         prev.push(*octet as char);
-        writer.push(*octet);
-    }
-
-    if finished {
-        writer.pop();
-        writer.pop();
-        writer.pop(); // get rid of previous \r\n\r\n
     }
 
-    ReadResult::Ok((finished, 0, None))
+    ReadResult::Ok((finished, up_to_in_reader, None))
 }
 
 #[derive(Copy, Clone)]
@@ -579,7 +1177,7 @@ impl CompressionAlgorithm {
 }
 
 #[allow(non_snake_case)]
-pub fn write_message_to_wire(mut stream: &mut TcpStream, message: HttpMessage, request_options: RequestOptions) {
+pub fn write_message_to_wire<W: Write>(mut stream: &mut W, message: HttpMessage, request_options: RequestOptions) -> std::io::Result<()> {
     match message {
         HttpMessage::Request(mut req) => {
             let chunked_encoding_desired = req.headers.has("Transfer-Encoding");
@@ -600,42 +1198,29 @@ pub fn write_message_to_wire(mut stream: &mut TcpStream, message: HttpMessage, r
             let start_line_and_headers = format!("{}{}\r\n\r\n", start_line, headers.to_wire_string());
 
             match req.body {
-                BodyString(str) => {
+                BodyString(_) | BodyBytes(_) => {
+                    let bytes = req.body.as_bytes().unwrap();
                     let is_version_1_1 = req.version == one_pt_one();
                     if chunked_encoding_desired && is_version_1_1 {
-                        write_chunked_string(stream, start_line_and_headers, str.as_bytes(), req.trailers, compression);
+                        write_chunked_string(stream, start_line_and_headers, bytes, req.trailers.get(), compression)
                     } else {
-                        write_string(stream, &compression, start_line_and_headers, str, headers, start_line)
+                        write_string(stream, &compression, start_line_and_headers, bytes, headers, start_line)
                     }
                 }
                 BodyStream(ref mut reader) => {
                     if chunked_encoding_desired && req.version == one_pt_one() {
-                        write_chunked_stream(stream, reader, start_line_and_headers, req.trailers, compression);
+                        write_chunked_stream(stream, reader, start_line_and_headers, &req.trailers, compression)
+                    } else if compression.is_none() {
+                        let mut chain = start_line_and_headers.as_bytes().chain(reader);
+                        copy(&mut chain, &mut stream).map(|_| ())
                     } else {
-                        if compression.is_none() {
-                            let mut chain = start_line_and_headers.as_bytes().chain(reader);
-                            let _copy = copy(&mut chain, &mut stream).unwrap();
-                        } else {
-                            let mut writer = Vec::new();
-                            let mut read = [0; 4096];
-                            loop {
-                                let bytes_read = reader.read(&mut read).unwrap();
-                                if bytes_read == 0 {
-                                    break;
-                                }
-                            }
-                            compress(&compression, &mut writer, &read);
-                            let headers = headers.replace(("Content-Length", writer.len().to_string().as_str()));
-                            let start_line_and_headers = format!("{}{}\r\n\r\n", start_line, headers.to_wire_string());
-                            let mut whole = start_line_and_headers.as_bytes().to_vec();
-                            whole.append(&mut writer);
-                            stream.write(&whole).unwrap();
-                        }
+                        let can_be_chunked = req.version == one_pt_one();
+                        write_compressed_stream(stream, reader, start_line, headers, &req.trailers, &compression, request_options.max_compressed_content_length, can_be_chunked)
                     }
                 }
             }
         }
-        HttpMessage::Response(mut res) => {
+        HttpMessage::Response(res) => {
             let has_transfer_encoding = res.headers.has("Transfer-Encoding");
             let has_content_length = res.headers.has("Content-Length");
             let mut headers = ensure_content_length_or_transfer_encoding(res.headers, &res.body, &res.version, has_transfer_encoding, has_content_length);
@@ -661,47 +1246,60 @@ pub fn write_message_to_wire(mut stream: &mut TcpStream, message: HttpMessage, r
             };
 
             headers = headers.remove("Connection");
+            if let Some(connection) = &request_options.connection {
+                headers = headers.add(("Connection", connection.as_str()));
+            }
 
-            let mut trailers = res.trailers;
-            if !request_options.wants_trailers && !trailers.is_empty() {
+            // trailers the client does not want go in the headers instead,
+            // so if we are streaming the body we have to read it first to find out what they are
+            let buffered_body;
+            let mut body = res.body;
+            if !request_options.wants_trailers && headers.has("Trailer") && res.trailers.is_empty() {
+                if let BodyStream(ref mut reader) = body {
+                    let mut whole = Vec::new();
+                    reader.read_to_end(&mut whole)?;
+                    buffered_body = whole;
+                    body = BodyStream(Box::new(buffered_body.as_slice()));
+                }
+            }
+            let trailers = if !request_options.wants_trailers && !res.trailers.is_empty() {
                 let as_str = request_options.expected_trailers.iter()
                     .map(|x| x.as_str()).collect::<Vec<&str>>();
-                headers = headers.add_all(trailers.filter(as_str));
-                trailers = Headers::empty();
-            }
+                headers = headers.add_all(res.trailers.get().filter(as_str));
+                Trailers::empty()
+            } else {
+                res.trailers
+            };
 
             let start_line = format!("HTTP/1.1 {} {}\r\n", &res.status.value(), &res.status.to_string());
+
+            if status_cannot_have_body(&res.status) || request_options.responding_to_head {
+                let headers = head_only_headers(headers, &body, &res.status, &compression, has_content_length);
+                return stream.write_all(format!("{}{}\r\n\r\n", start_line, headers.to_wire_string()).as_bytes());
+            }
             let status_and_headers = format!("{}{}\r\n\r\n", start_line, headers.to_wire_string());
 
             let chunked_encoding_desired = headers.has("Transfer-Encoding");
 
-            match res.body {
-                BodyString(str) => {
+            match body {
+                BodyString(_) | BodyBytes(_) => {
+                    let bytes = body.as_bytes().unwrap();
                     if chunked_encoding_desired && (res.version == one_pt_one()) {
-                        write_chunked_string(stream, status_and_headers, str.as_bytes(), trailers, compression);
+                        write_chunked_string(stream, status_and_headers, bytes, trailers.get(), compression)
                     } else {
-                        write_string(stream, &compression, status_and_headers, str, headers, start_line)
+                        write_string(stream, &compression, status_and_headers, bytes, headers, start_line)
                     }
                 }
                 BodyStream(ref mut reader) => {
                     if chunked_encoding_desired && res.version == one_pt_one() {
-                        write_chunked_stream(&mut stream, reader, status_and_headers, trailers, compression);
+                        write_chunked_stream(&mut stream, reader, status_and_headers, &trailers, compression)
+                    } else if compression.is_none() {
+                        let mut chain = status_and_headers.as_bytes().chain(reader);
+                        copy(&mut chain, &mut stream).map(|_| ())
                     } else {
-                        if compression.is_none() {
-                            let mut chain = status_and_headers.as_bytes().chain(reader);
-                            let _copy = copy(&mut chain, &mut stream).unwrap();
-                        } else {
-                            let mut writer = Vec::new();
-                            let mut whole = Vec::new();
-                            let _bytes_read = reader.read_to_end(&mut whole).unwrap();
-                            compress(&compression, &mut writer, whole.as_slice());
-
-                            let headers = headers.replace(("Content-length", writer.len().to_string().as_str()));
-                            let headers = headers.replace(("Content-Encoding", compression.to_string_for_content_encoding().as_str()));
-                            let status_and_headers = Response::status_line_and_headers_wire_string(&headers, &res.status);
-                            let mut chain = status_and_headers.as_bytes().chain(writer.as_slice());
-                            let _copy = copy(&mut chain, &mut stream).unwrap();
-                        }
+                        let headers = headers.replace(("Content-Encoding", compression.to_string_for_content_encoding().as_str()));
+                        let can_be_chunked = res.version == one_pt_one();
+                        write_compressed_stream(&mut stream, reader, start_line, headers, &trailers, &compression, request_options.max_compressed_content_length, can_be_chunked)
                     }
                 }
             }
@@ -709,6 +1307,48 @@ pub fn write_message_to_wire(mut stream: &mut TcpStream, message: HttpMessage, r
     }
 }
 
+/*
+ https://www.rfc-editor.org/rfc/rfc9110#section-6.4.1
+ All 1xx (Informational), 204 (No Content), and 304 (Not Modified) responses do not include content.
+ */
+fn status_cannot_have_body(status: &Status) -> bool {
+    // by value, as a registered code with a reason phrase of its own is still that code
+    status.value() < 200 || status.value() == NoContent.value() || status.value() == NotModified.value()
+}
+
+/*
+ https://www.rfc-editor.org/rfc/rfc9110#section-9.3.2
+ The server SHOULD send the same header fields in response to a HEAD request as it would have sent if
+ the request method had been GET. However, a server MAY omit header fields for which a value is determined
+ only while generating the content.
+ https://www.rfc-editor.org/rfc/rfc9110#section-8.6
+ A server MUST NOT send a Content-Length header field in any response with a status code of
+ 1xx (Informational) or 204 (No Content).
+ https://www.rfc-editor.org/rfc/rfc9112#section-6.1
+ A server MUST NOT send a Transfer-Encoding header field in any response with a status code of
+ 1xx (Informational) or 204 (No Content).
+ */
+fn head_only_headers(headers: Headers, body: &Body, status: &Status, compression: &CompressionAlgorithm, has_content_length: bool) -> Headers {
+    if status.value() < 200 || status.value() == NoContent.value() {
+        return headers.remove("Content-Length").remove("Transfer-Encoding");
+    }
+    if status.value() == NotModified.value() {
+        // a 304 can say how long the 200 would have been, but only if the handler knows
+        let headers = headers.remove("Transfer-Encoding");
+        return if has_content_length { headers } else { headers.remove("Content-Length") };
+    }
+    match body.as_bytes() {
+        Some(bytes) if compression.is_some() && !headers.has("Transfer-Encoding") => {
+            let mut writer = Vec::new();
+            compress(compression, &mut writer, bytes);
+            headers.replace(("Content-Length", writer.len().to_string().as_str()))
+        }
+        // we would only know how long the compressed stream is by compressing it
+        None if compression.is_some() => headers.remove("Content-Length"),
+        _ => headers
+    }
+}
+
 fn set_connection_header_if_needed_and_not_present(headers: Headers, chunked_encoding_desired: bool) -> Headers {
     if chunked_encoding_desired && headers.get("Connection").map(|h| !h.contains("TE")).unwrap_or(false) {
         headers.replace(("Connection", headers.get("Connection").map(|mut h| {
@@ -720,24 +1360,24 @@ fn set_connection_header_if_needed_and_not_present(headers: Headers, chunked_enc
     }
 }
 
-fn write_string(stream: &mut TcpStream, compression: &CompressionAlgorithm, start_line_and_headers: String, body: &str, headers: Headers, start_line: String) {
+fn write_string<W: Write>(stream: &mut W, compression: &CompressionAlgorithm, start_line_and_headers: String, body: &[u8], headers: Headers, start_line: String) -> std::io::Result<()> {
     if compression.is_none() {
-        let status_headers_and_body = [start_line_and_headers.as_bytes(), body.as_bytes()].concat();
-        stream.write(status_headers_and_body.as_slice()).unwrap();
+        let status_headers_and_body = [start_line_and_headers.as_bytes(), body].concat();
+        stream.write_all(status_headers_and_body.as_slice())
     } else {
         let mut writer = Vec::new();
-        compress(&compression, &mut writer, body.as_bytes());
+        compress(compression, &mut writer, body);
         let headers = headers.replace(("Content-Length", writer.len().to_string().as_str()));
         let mut start_line = start_line;
         start_line.push_str(headers.to_wire_string().as_str());
         start_line.push_str("\r\n\r\n");
         let mut whole = start_line.as_bytes().to_vec();
         whole.append(&mut writer);
-        stream.write(&whole).unwrap();
+        stream.write_all(&whole)
     }
 }
 
-fn compression_from(option: Option<String>) -> CompressionAlgorithm {
+pub(crate) fn compression_from(option: Option<String>) -> CompressionAlgorithm {
     match option {
         Some(value) if value.contains("br") => CompressionAlgorithm::BROTLI,
         Some(value) if value.contains("gzip") => CompressionAlgorithm::GZIP,
@@ -755,15 +1395,8 @@ fn compress<'a>(compression: &'a CompressionAlgorithm, mut writer: &'a mut Vec<u
     }
 }
 
-fn decompress<'a>(compression: &'a CompressionAlgorithm, writer: &'a mut Vec<u8>, reader: &'a mut Vec<u8>) {
-    match compression {
-        GZIP | DEFLATE | BROTLI => { Codex::decode(reader, writer, compression); }
-        NONE => { writer.write_all(reader).unwrap(); }
-    }
-}
-
 #[allow(unused_assignments)]
-pub fn write_chunked_string(stream: &mut TcpStream, mut first_line: String, chunk: &[u8], trailers: Headers, compression: CompressionAlgorithm) {
+pub fn write_chunked_string<W: Write>(stream: &mut W, mut first_line: String, chunk: &[u8], trailers: Headers, compression: CompressionAlgorithm) -> std::io::Result<()> {
     let mut writer = Vec::new();
     let mut request = Vec::new();
     if compression.is_some() {
@@ -779,7 +1412,7 @@ pub fn write_chunked_string(stream: &mut TcpStream, mut first_line: String, chun
     } else {
         request.extend_from_slice("0\r\n\r\n".as_bytes());
     }
-    stream.write(request.as_slice()).unwrap();
+    stream.write_all(request.as_slice())
 }
 
 fn write_chunk_metadata(first_line: &mut String, length: usize) {
@@ -793,92 +1426,96 @@ https://doc.rust-lang.org/std/io/trait.Read.html#tymethod.read
 It is not an error if the returned value n is smaller than the buffer size, even when the reader is not at the end of the stream yet.
 This may happen for example because fewer bytes are actually available right now (e. g. being close to end-of-file) or because read() was interrupted by a signal.
  */
-pub fn write_chunked_stream<'a>(mut stream: &mut TcpStream, reader: &mut Box<dyn Read + 'a>, first_line_and_headers: String, trailers: Headers, compression: CompressionAlgorithm) {
+pub fn write_chunked_stream<'a, W: Write>(mut stream: &mut W, reader: &mut Box<dyn Read + 'a>, first_line_and_headers: String, trailers: &Trailers, compression: CompressionAlgorithm) -> std::io::Result<()> {
     if compression.is_some() {
-        write_compressed_chunks(&mut stream, reader, &first_line_and_headers, &trailers, &compression);
+        write_compressed_chunks(&mut stream, reader, &first_line_and_headers, trailers, &compression)
     } else {
-        write_simple_chunks(&mut stream, reader, first_line_and_headers, trailers);
+        write_simple_chunks(&mut stream, reader, first_line_and_headers, trailers)
     }
 }
 
-#[allow(unused_assignments)]
-fn write_simple_chunks<'a>(mut stream: &mut TcpStream, reader: &mut Box<dyn Read + 'a>, first_line_and_headers: String, trailers: Headers) {
+fn write_simple_chunks<'a, W: Write>(mut stream: &mut W, reader: &mut Box<dyn Read + 'a>, first_line_and_headers: String, trailers: &Trailers) -> std::io::Result<()> {
     let buffer = &mut [0 as u8; 16384];
-    let mut bytes_read = reader.read(buffer).unwrap_or(0);
-    let mut first_write = true;
-    let mut temp = Vec::new();
-
-    while bytes_read > 0 {
-        temp = Vec::new();
+    // reading the body can make the server send a 100 Continue, which has to go before our head,
+    // so we wait for the first chunk before writing anything
+    let mut head = Some(first_line_and_headers);
+
+    loop {
+        // leave the message unfinished, so that whoever is reading it knows it went wrong
+        let bytes_read = reader.read(buffer)?;
+        if bytes_read == 0 {
+            break;
+        }
+        let mut temp = Vec::new();
         let length_in_hex = format!("{:X}", bytes_read);
         temp.extend_from_slice(length_in_hex.as_bytes());
         temp.push(b'\r');
         temp.push(b'\n');
-        let chunk = &buffer[..bytes_read];
-        temp.extend_from_slice(chunk);
+        temp.extend_from_slice(&buffer[..bytes_read]);
         temp.push(b'\r');
         temp.push(b'\n');
-        // write to wire
-        if first_write {
-            let first_line_and_headers_and_first_chunk = [first_line_and_headers.as_bytes(), temp.as_slice()].concat();
-            let _copy = copy(&mut first_line_and_headers_and_first_chunk.as_slice(), &mut stream).unwrap();
-            first_write = false;
-        } else {
-            let _copy = copy(&mut temp.as_slice(), &mut stream).unwrap();
+        if let Some(head) = head.take() {
+            temp.splice(0..0, head.into_bytes());
         }
-        bytes_read = reader.read(buffer).unwrap();
+        copy(&mut temp.as_slice(), &mut stream)?;
     }
 
-    let mut end = vec!(b'0', b'\r', b'\n');
+    // only now that we have read all of the body do we know the trailers
+    let trailers = trailers.get();
+    let mut end = head.map(|head| head.into_bytes()).unwrap_or(vec!());
+    end.extend_from_slice(b"0\r\n");
     if !trailers.is_empty() {
         end.extend_from_slice(format!("{}\r\n\r\n", trailers.to_wire_string()).as_bytes());
     } else {
         end.push(b'\r');
         end.push(b'\n');
     }
-    stream.write(end.as_slice()).unwrap();
+    stream.write_all(end.as_slice())
 }
 
-fn write_compressed_chunks<'a>(mut stream: &mut TcpStream, reader: &mut Box<dyn Read + 'a>, first_line_and_headers: &String, trailers: &Headers, compression: &CompressionAlgorithm) {
-    let buffer = &mut [0 as u8; 16384];
-    let mut bytes_read = reader.read(buffer).unwrap_or(0);
-    let mut temp = Vec::new();
+fn write_compressed_chunks<'a, W: Write>(stream: &mut W, reader: &mut Box<dyn Read + 'a>, first_line_and_headers: &str, trailers: &Trailers, compression: &CompressionAlgorithm) -> std::io::Result<()> {
+    // each chunk is a piece of the compressed body, compressed as the body streams
+    let mut compressed = Codex::encode_reader(reader, compression);
+    write_simple_chunks(stream, &mut compressed, first_line_and_headers.to_string(), trailers)
+}
 
-    while bytes_read > 0 {
-        let chunk = &buffer[..bytes_read];
-        temp.extend_from_slice(chunk);
-        bytes_read = reader.read(buffer).unwrap();
-    }
 
-    let mut writer = Vec::new();
-    compress(&compression, &mut writer, temp.as_slice());
-    let compressed_length_in_hex = format!("{:X}", writer.len());
-    let reversed = compressed_length_in_hex.chars().rev().collect::<String>();
-    writer.insert(0, b'\n');
-    writer.insert(0, b'\r');
-    for byte in reversed.as_bytes() {
-        writer.insert(0, *byte)
-    }
-    writer.push(b'\r');
-    writer.push(b'\n');
-
-    let mut end = vec!(b'0', b'\r', b'\n');
-    if !trailers.is_empty() {
-        end.extend_from_slice(format!("{}\r\n\r\n", trailers.to_wire_string()).as_bytes());
+/// We only know the Content-Length of a compressed stream once we have compressed all of it,
+/// so if it gets too big to hold on to then we send it chunked instead.
+#[allow(clippy::too_many_arguments)]
+fn write_compressed_stream<'a, W: Write>(
+    stream: &mut W,
+    reader: &mut Box<dyn Read + 'a>,
+    start_line: String,
+    headers: Headers,
+    trailers: &Trailers,
+    compression: &CompressionAlgorithm,
+    max_compressed_content_length: usize,
+    can_be_chunked: bool,
+) -> std::io::Result<()> {
+    let mut compressed = Codex::encode_reader(reader, compression);
+    let mut whole = Vec::new();
+    let limit = if can_be_chunked { max_compressed_content_length as u64 + 1 } else { u64::MAX };
+    (&mut compressed).take(limit).read_to_end(&mut whole)?;
+
+    if whole.len() <= max_compressed_content_length || !can_be_chunked {
+        let headers = headers.replace(("Content-Length", whole.len().to_string().as_str()));
+        let start_line_and_headers = format!("{}{}\r\n\r\n", start_line, headers.to_wire_string());
+        let mut chain = start_line_and_headers.as_bytes().chain(whole.as_slice());
+        copy(&mut chain, stream).map(|_| ())
     } else {
-        end.push(b'\r');
-        end.push(b'\n');
+        let headers = headers.remove("Content-Length").replace(("Transfer-Encoding", "chunked"));
+        let start_line_and_headers = format!("{}{}\r\n\r\n", start_line, headers.to_wire_string());
+        let mut rest: Box<dyn Read> = Box::new(Cursor::new(whole).chain(compressed));
+        write_simple_chunks(stream, &mut rest, start_line_and_headers, trailers)
     }
-    let message = [first_line_and_headers.as_bytes(), writer.as_slice(), end.as_slice()].concat();
-    let _copy = copy(&mut message.as_slice(), &mut stream).unwrap();
 }
 
-
 pub fn ensure_content_length_or_transfer_encoding(headers: Headers, body: &Body, version: &HttpVersion, chunked_encoding_desired: bool, has_content_length: bool) -> Headers {
     if chunked_encoding_desired && has_content_length {
         headers.remove("Content-Length")
     } else if !chunked_encoding_desired && !has_content_length {
-        if body.is_body_string() {
+        if !body.is_body_stream() {
             headers.add(("Content-Length", body.length().to_string().as_str()))
         } else if body.is_body_stream() && version == &one_pt_one() {
             headers.add(("Transfer-Encoding", "chunked"))
@@ -915,11 +1552,22 @@ pub fn most_desired_encoding(str: Option<String>) -> Option<String> {
 }
 
 
-fn http_version_from(str: &str) -> (u8, u8) {
-    let mut version_chars = str.chars();
-    let major = version_chars.nth(0);
-    let minor = version_chars.nth(2);
-    (major.unwrap() as u8, minor.unwrap() as u8)
+/*
+ https://datatracker.ietf.org/doc/html/rfc7230#section-2.6
+ HTTP-version  = HTTP-name "/" DIGIT "." DIGIT
+ A server can send a 505 (HTTP Version Not Supported) response if it wishes, for any reason,
+ to refuse service of the client's major protocol version.
+ */
+fn http_version_from(str: &str) -> Result<(u8, u8), MessageError> {
+    let digits = str.strip_prefix("HTTP/")
+        .map(|version| version.chars().collect::<Vec<char>>())
+        .filter(|version| version.len() == 3 && version[1] == '.')
+        .and_then(|version| version[0].to_digit(10).zip(version[2].to_digit(10)));
+    match digits {
+        Some((1, minor)) => Ok((1, minor as u8)),
+        Some(_) => Err(MessageError::UnsupportedVersion(format!("Only HTTP/1.x is supported, got {}", str))),
+        None => Err(MessageError::MalformedStartLine(format!("Invalid http version {}", str))),
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -930,6 +1578,21 @@ pub enum MessageError {
     HeadersTooBig(String),
     TrailersTooBig(String),
     InvalidBoundaryDigit(String),
+    ConnectionClosed(String),
+    MalformedStartLine(String),
+    MalformedHeader(String),
+    UnsupportedVersion(String),
+    UnknownMethod(String),
+    IoError(String),
+    ContentLengthAndTransferEncoding(String),
+    TransferEncodingNotChunked(String),
+    WhitespaceBeforeColon(String),
+    ObsFold(String),
+    BareLineFeed(String),
+    ConflictingContentLength(String),
+    TooManyHeaders(String),
+    BodyTooBig(String),
+    Timeout(String),
 }
 
 impl MessageError {
@@ -941,6 +1604,48 @@ impl MessageError {
             MessageError::HeadersTooBig(_) => "Headers too big".to_string(),
             MessageError::TrailersTooBig(_) => "Trailers too big".to_string(),
             MessageError::InvalidBoundaryDigit(_) => "Invalid boundary digit in chunked encoding".to_string(),
+            MessageError::ConnectionClosed(_) => "Connection closed".to_string(),
+            MessageError::MalformedStartLine(_) => "Malformed start line".to_string(),
+            MessageError::MalformedHeader(_) => "Malformed header".to_string(),
+            MessageError::UnsupportedVersion(_) => "Unsupported http version".to_string(),
+            MessageError::UnknownMethod(_) => "Unknown method".to_string(),
+            MessageError::IoError(_) => "Io error".to_string(),
+            MessageError::ContentLengthAndTransferEncoding(_) => "Both Content-Length and Transfer-Encoding".to_string(),
+            MessageError::TransferEncodingNotChunked(_) => "Transfer-Encoding does not end in chunked".to_string(),
+            MessageError::WhitespaceBeforeColon(_) => "Whitespace before header colon".to_string(),
+            MessageError::ObsFold(_) => "Obsolete line folding".to_string(),
+            MessageError::BareLineFeed(_) => "Bare line feed".to_string(),
+            MessageError::ConflictingContentLength(_) => "Conflicting content lengths".to_string(),
+            MessageError::TooManyHeaders(_) => "Too many headers".to_string(),
+            MessageError::BodyTooBig(_) => "Body too big".to_string(),
+            MessageError::Timeout(_) => "Timed out".to_string(),
+        }
+    }
+
+    // for when we find out the message is bad while its body is being read
+    pub fn to_io_error(self) -> std::io::Error {
+        match self {
+            MessageError::ConnectionClosed(msg) => std::io::Error::new(ErrorKind::UnexpectedEof, msg),
+            MessageError::IoError(msg) => std::io::Error::other(msg),
+            MessageError::Timeout(msg) => std::io::Error::new(ErrorKind::TimedOut, msg),
+            MessageError::MalformedStartLine(msg)
+            | MessageError::MalformedHeader(msg)
+            | MessageError::UnsupportedVersion(msg)
+            | MessageError::UnknownMethod(msg)
+            | MessageError::ContentLengthAndTransferEncoding(msg)
+            | MessageError::TransferEncodingNotChunked(msg)
+            | MessageError::WhitespaceBeforeColon(msg)
+            | MessageError::ObsFold(msg)
+            | MessageError::BareLineFeed(msg)
+            | MessageError::ConflictingContentLength(msg)
+            | MessageError::TooManyHeaders(msg)
+            | MessageError::BodyTooBig(msg)
+            | MessageError::InvalidContentLength(msg)
+            | MessageError::NoContentLengthOrTransferEncoding(msg)
+            | MessageError::StartLineTooBig(msg)
+            | MessageError::HeadersTooBig(msg)
+            | MessageError::TrailersTooBig(msg)
+            | MessageError::InvalidBoundaryDigit(msg) => std::io::Error::new(ErrorKind::InvalidData, msg),
         }
     }
 }
@@ -961,6 +1666,7 @@ impl<'a> Response<'a> {
 
 pub enum Body<'a> {
     BodyString(&'a str),
+    BodyBytes(&'a [u8]),
     BodyStream(Box<dyn Read + 'a>),
 }
 
@@ -969,9 +1675,26 @@ impl<'a> Body<'a> {
         BodyString("")
     }
 
+    // only text that is meant to be read as text becomes a BodyString, anything else is just bytes
+    pub fn from_bytes(bytes: &'a [u8], is_textual: bool) -> Body<'a> {
+        match str::from_utf8(bytes) {
+            Ok(str) if is_textual => BodyString(str),
+            _ => BodyBytes(bytes)
+        }
+    }
+
     pub fn is_body_string(&self) -> bool {
         match self {
             BodyString(_) => true,
+            BodyBytes(_) => false,
+            BodyStream(_) => false
+        }
+    }
+
+    pub fn is_body_bytes(&self) -> bool {
+        match self {
+            BodyString(_) => false,
+            BodyBytes(_) => true,
             BodyStream(_) => false
         }
     }
@@ -979,6 +1702,7 @@ impl<'a> Body<'a> {
     pub fn is_body_stream(&self) -> bool {
         match self {
             BodyString(_) => false,
+            BodyBytes(_) => false,
             BodyStream(_) => true
         }
     }
@@ -986,18 +1710,44 @@ impl<'a> Body<'a> {
     pub fn length(&self) -> usize {
         match self {
             BodyString(str) => str.len(),
+            BodyBytes(bytes) => bytes.len(),
             BodyStream(_) => panic!("Do not know the length of a body stream!")
         }
     }
+
+    // the whole body, if we have all of it already
+    pub fn as_bytes(&self) -> Option<&'a [u8]> {
+        match self {
+            BodyString(str) => Some(str.as_bytes()),
+            BodyBytes(bytes) => Some(bytes),
+            BodyStream(_) => None
+        }
+    }
 }
 
 pub fn body_length(body: &Body) -> u32 {
     match body {
         BodyString(str) => str.len() as u32,
+        BodyBytes(bytes) => bytes.len() as u32,
         BodyStream(_) => panic!("Cannot find length of BodyStream, please provide Content-Length header")
     }
 }
 
+/*
+ https://datatracker.ietf.org/doc/html/rfc7231#section-3.1.1.5
+ If a Content-Type header field is not present, the recipient MAY either assume a media type of
+ "application/octet-stream" or examine the data to determine its type.
+ We examine the data, ie. if it is valid utf-8 then we treat it as text.
+ */
+pub fn is_textual(headers: &Headers) -> bool {
+    headers.get("Content-Type").map(|content_type| {
+        let content_type = content_type.to_lowercase();
+        content_type.starts_with("text/")
+            || content_type.contains("charset=")
+            || ["json", "xml", "javascript", "x-www-form-urlencoded"].iter().any(|textual| content_type.contains(textual))
+    }).unwrap_or(true)
+}
+
 pub fn with_content_length(message: HttpMessage) -> HttpMessage {
     match message {
         HttpMessage::Request(request) => {
@@ -1023,7 +1773,7 @@ pub fn with_content_length(message: HttpMessage) -> HttpMessage {
     }
 }
 
-#[derive(PartialEq)]
+#[derive(PartialEq, Clone)]
 pub struct HttpVersion {
     pub major: u8,
     pub minor: u8,
@@ -1047,7 +1797,7 @@ pub struct Request<'a> {
     pub uri: Uri<'a>,
     pub method: Method,
     pub version: HttpVersion,
-    pub trailers: Headers,
+    pub trailers: Trailers,
 }
 
 pub struct Response<'a> {
@@ -1055,21 +1805,21 @@ pub struct Response<'a> {
     pub body: Body<'a>,
     pub status: Status,
     pub version: HttpVersion,
-    pub trailers: Headers,
+    pub trailers: Trailers,
 }
 
 
 impl<'a> Request<'a> {
     pub fn request(method: Method, uri: Uri, headers: Headers) -> Request {
-        Request { method, headers, body: Body::empty(), uri, version: HttpVersion { major: 1, minor: 1 }, trailers: Headers::empty() }
+        Request { method, headers, body: Body::empty(), uri, version: HttpVersion { major: 1, minor: 1 }, trailers: Trailers::empty() }
     }
 
     pub fn get(uri: Uri, headers: Headers) -> Request {
-        Request { method: GET, headers, body: Body::empty(), uri, version: HttpVersion { major: 1, minor: 1 }, trailers: Headers::empty() }
+        Request { method: GET, headers, body: Body::empty(), uri, version: HttpVersion { major: 1, minor: 1 }, trailers: Trailers::empty() }
     }
 
     pub fn post(uri: Uri<'a>, headers: Headers, body: Body<'a>) -> Request<'a> {
-        Request { method: POST, headers, body, uri, version: HttpVersion { major: 1, minor: 1 }, trailers: Headers::empty() }
+        Request { method: POST, headers, body, uri, version: HttpVersion { major: 1, minor: 1 }, trailers: Trailers::empty() }
     }
 
     pub fn with_body(self, body: Body<'a>) -> Request<'a> {
@@ -1086,9 +1836,9 @@ impl<'a> Request<'a> {
         }
     }
 
-    pub fn with_trailers(self, trailers: Headers) -> Request<'a> {
+    pub fn with_trailers<T: Into<Trailers>>(self, trailers: T) -> Request<'a> {
         Request {
-            trailers,
+            trailers: trailers.into(),
             ..self
         }
     }
@@ -1101,15 +1851,137 @@ impl<'a> Request<'a> {
     }
 }
 
-pub fn body_string(mut body: Body) -> String {
+/// Trailers come after the body, so when the body is streamed from the wire
+/// they are only known once it has been read to the end.
+/// Clones share the same trailers, so the trailers of a message can be passed on before its body has been read.
+#[derive(Clone)]
+pub struct Trailers {
+    headers: Rc<RefCell<Headers>>,
+}
+
+impl Trailers {
+    pub fn empty() -> Trailers {
+        Trailers { headers: Rc::new(RefCell::new(Headers::empty())) }
+    }
+
+    pub fn get(&self) -> Headers {
+        Headers::from_headers(&self.headers.borrow())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.headers.borrow().is_empty()
+    }
+
+    pub(crate) fn set(&self, headers: Headers) {
+        *self.headers.borrow_mut() = headers;
+    }
+}
+
+impl From<Headers> for Trailers {
+    fn from(headers: Headers) -> Self {
+        Trailers { headers: Rc::new(RefCell::new(headers)) }
+    }
+}
+
+/// A request that owns all of its parts, so it can outlive the buffers it was read into,
+/// be kept around or be sent to another thread.
+/// Use as_request to give it to a Handler.
+pub struct OwnedRequest {
+    pub headers: Headers,
+    pub body: Vec<u8>,
+    pub uri: String,
+    pub method: Method,
+    pub version: HttpVersion,
+    pub trailers: Headers,
+}
+
+/// A response that owns all of its parts, so it can outlive the buffers it was read into,
+/// be kept around or be sent to another thread.
+pub struct OwnedResponse {
+    pub headers: Headers,
+    pub body: Vec<u8>,
+    pub status: Status,
+    pub version: HttpVersion,
+    pub trailers: Headers,
+}
+
+impl<'a> Request<'a> {
+    // reads the whole body, so that we have the trailers too
+    pub fn into_owned(self) -> Result<OwnedRequest, std::io::Error> {
+        let body = read_whole_body(self.body)?;
+        Ok(OwnedRequest {
+            headers: self.headers,
+            body,
+            uri: self.uri.to_string(),
+            method: self.method,
+            version: self.version,
+            trailers: self.trailers.get(),
+        })
+    }
+}
+
+impl<'a> Response<'a> {
+    // reads the whole body, so that we have the trailers too
+    pub fn into_owned(self) -> Result<OwnedResponse, std::io::Error> {
+        let body = read_whole_body(self.body)?;
+        Ok(OwnedResponse {
+            headers: self.headers,
+            body,
+            status: self.status,
+            version: self.version,
+            trailers: self.trailers.get(),
+        })
+    }
+}
+
+impl OwnedRequest {
+    pub fn as_request(&self) -> Request<'_> {
+        Request {
+            headers: Headers::from_headers(&self.headers),
+            body: Body::from_bytes(self.body.as_slice(), is_textual(&self.headers)),
+            uri: Uri::parse(self.uri.as_str()),
+            method: self.method.clone(),
+            version: self.version.clone(),
+            trailers: Trailers::from(Headers::from_headers(&self.trailers)),
+        }
+    }
+}
+
+impl OwnedResponse {
+    pub fn as_response(&self) -> Response<'_> {
+        Response {
+            headers: Headers::from_headers(&self.headers),
+            body: Body::from_bytes(self.body.as_slice(), is_textual(&self.headers)),
+            status: self.status.clone(),
+            version: self.version.clone(),
+            trailers: Trailers::from(Headers::from_headers(&self.trailers)),
+        }
+    }
+}
+
+fn read_whole_body(body: Body) -> Result<Vec<u8>, std::io::Error> {
+    match body {
+        BodyString(str) => Ok(str.as_bytes().to_vec()),
+        BodyBytes(bytes) => Ok(bytes.to_vec()),
+        BodyStream(mut reader) => {
+            let mut whole = Vec::new();
+            reader.read_to_end(&mut whole)?;
+            Ok(whole)
+        }
+    }
+}
+
+// an Err if the body could not be read, eg the connection went away or timed out
+pub fn body_string(mut body: Body) -> Result<String, MessageError> {
     match body {
-        BodyString(str) => str.to_string(),
+        BodyString(str) => Ok(str.to_string()),
+        BodyBytes(bytes) => Ok(String::from_utf8_lossy(bytes).to_string()),
         BodyStream(ref mut reader) => {
             let big = &mut Vec::new();
-            let _read_bytes = reader.read_to_end(big).unwrap(); //todo() this blows up sometimes! unwrap_or()?
-            str::from_utf8(&big).unwrap()
+            reader.read_to_end(big).map_err(|e| MessageError::IoError(e.to_string()))?;
+            Ok(String::from_utf8_lossy(big)
                 .trim_end_matches(char::from(0))
-                .to_string()
+                .to_string())
         }
     }
 }
@@ -1122,6 +1994,11 @@ pub struct RequestOptions {
     pub content_encoding: CompressionAlgorithm,
     pub wants_trailers: bool,
     pub expected_trailers: Vec<String>,
+    pub connection: Option<String>,
+    // past this we send a compressed stream chunked, rather than holding on to all of it to find its Content-Length
+    pub max_compressed_content_length: usize,
+    // the response is to a HEAD request, so it gets the headers a GET would but no body
+    pub responding_to_head: bool,
 }
 
 #[allow(non_snake_case)]
@@ -1138,6 +2015,9 @@ impl RequestOptions {
                 .map(|t| t.to_string())
                 .collect::<Vec<String>>())
                 .unwrap_or(vec!()),
+            connection: None,
+            max_compressed_content_length: 1048576,
+            responding_to_head: false,
         }
     }
 
@@ -1160,12 +2040,15 @@ impl RequestOptions {
             compression_from_TE_header: NONE,
             wants_trailers: false,
             expected_trailers: vec!(),
+            connection: None,
+            max_compressed_content_length: 1048576,
+            responding_to_head: false,
         }
     }
 }
 
 
-#[derive(PartialEq, Debug)]
+#[derive(PartialEq, Debug, Clone)]
 pub enum Method {
     GET,
     CONNECT,
@@ -1176,11 +2059,14 @@ pub enum Method {
     PATCH,
     PUT,
     TRACE,
+    // any other method, eg. PROPFIND from WebDAV or PURGE
+    Other(String),
 }
 
 impl Method {
     pub fn value(&self) -> String {
         match self {
+            Method::Other(method) => method.clone(),
             GET => String::from("GET"),
             POST => String::from("POST"),
             PATCH => String::from("PATCH"),
@@ -1193,53 +2079,191 @@ impl Method {
         }
     }
 
-    pub fn from(str: &str) -> Method {
+    pub fn from(str: &str) -> Result<Method, MessageError> {
+        Method::parse(str)
+    }
+
+    /*
+     https://www.rfc-editor.org/rfc/rfc9110#section-9.1
+     method = token
+     The method token is case-sensitive because it might be used as a gateway to
+     object-based systems with case-sensitive method names.
+     */
+    pub fn parse(str: &str) -> Result<Method, MessageError> {
         match str {
-            "GET" => GET,
-            "POST" => POST,
-            "PATCH" => PATCH,
-            "OPTIONS" => OPTIONS,
-            "DELETE" => DELETE,
-            "CONNECT" => CONNECT,
-            "TRACE" => TRACE,
-            "HEAD" => HEAD,
-            _ => panic!("Unknown method")
+            "GET" => Ok(GET),
+            "POST" => Ok(POST),
+            "PATCH" => Ok(PATCH),
+            "OPTIONS" => Ok(OPTIONS),
+            "DELETE" => Ok(DELETE),
+            "CONNECT" => Ok(CONNECT),
+            "TRACE" => Ok(TRACE),
+            "HEAD" => Ok(HEAD),
+            "PUT" => Ok(PUT),
+            _ if is_token(str) => Ok(Method::Other(str.to_string())),
+            _ => Err(MessageError::UnknownMethod(format!("Unknown method {}", str)))
         }
     }
+
+    /*
+     https://www.rfc-editor.org/rfc/rfc9110#section-9.2.1
+     Of the request methods defined by this specification, the GET, HEAD, OPTIONS, and TRACE
+     methods are defined to be safe.
+     We do not know what other methods do, so we assume they are not safe.
+     */
+    pub fn is_safe(&self) -> bool {
+        matches!(self, GET | HEAD | OPTIONS | TRACE)
+    }
+
+    /*
+     https://www.rfc-editor.org/rfc/rfc9110#section-9.2.2
+     Of the request methods defined by this specification, PUT, DELETE, and safe request methods
+     are idempotent.
+     A client SHOULD NOT automatically retry a request with a non-idempotent method unless it has
+     some means to know that the request semantics are actually idempotent.
+     */
+    pub fn is_idempotent(&self) -> bool {
+        match self {
+            PUT | DELETE => true,
+            _ => self.is_safe()
+        }
+    }
+
+    // a body sent with a safe method has no defined meaning, and CONNECT turns the connection into a tunnel
+    pub fn can_have_body(&self) -> bool {
+        !self.is_safe() && *self != CONNECT
+    }
+}
+
+// https://www.rfc-editor.org/rfc/rfc9110#section-5.6.2
+pub(crate) fn is_token(str: &str) -> bool {
+    !str.is_empty() && str.chars().all(|c| c.is_ascii_alphanumeric() || "!#$%&'*+-.^_`|~".contains(c))
 }
 
 impl<'a> Response<'a> {
+    pub fn new(status: Status, headers: Headers, body: Body) -> Response {
+        Response { headers, body, status, version: HttpVersion { major: 1, minor: 1 }, trailers: Trailers::empty() }
+    }
+
     pub fn ok(headers: Headers, body: Body) -> Response {
-        Response { headers, body, status: OK, version: HttpVersion { major: 1, minor: 1 }, trailers: Headers::empty() }
+        Response { headers, body, status: OK, version: HttpVersion { major: 1, minor: 1 }, trailers: Trailers::empty() }
     }
 
     pub fn bad_request(headers: Headers, body: Body) -> Response {
-        Response { headers, body, status: BadRequest, version: HttpVersion { major: 1, minor: 1 }, trailers: Headers::empty() }
+        Response { headers, body, status: BadRequest, version: HttpVersion { major: 1, minor: 1 }, trailers: Trailers::empty() }
     }
 
     pub fn internal_server_error(headers: Headers, body: Body) -> Response {
-        Response { headers, body, status: InternalServerError, version: HttpVersion { major: 1, minor: 1 }, trailers: Headers::empty() }
+        Response { headers, body, status: InternalServerError, version: HttpVersion { major: 1, minor: 1 }, trailers: Trailers::empty() }
     }
 
     pub fn length_required(headers: Headers, body: Body) -> Response {
-        Response { headers, body, status: LengthRequired, version: HttpVersion { major: 1, minor: 1 }, trailers: Headers::empty() }
+        Response { headers, body, status: LengthRequired, version: HttpVersion { major: 1, minor: 1 }, trailers: Trailers::empty() }
     }
 
     pub fn not_found(headers: Headers, body: Body) -> Response {
-        Response { headers, body, status: NotFound, version: HttpVersion { major: 1, minor: 1 }, trailers: Headers::empty() }
+        Response { headers, body, status: NotFound, version: HttpVersion { major: 1, minor: 1 }, trailers: Trailers::empty() }
     }
 
     pub fn forbidden(headers: Headers, body: Body) -> Response {
-        Response { headers, body, status: Forbidden, version: HttpVersion { major: 1, minor: 1 }, trailers: Headers::empty() }
+        Response { headers, body, status: Forbidden, version: HttpVersion { major: 1, minor: 1 }, trailers: Trailers::empty() }
+    }
+
+    pub fn content_too_large(headers: Headers, body: Body) -> Response {
+        Response { headers, body, status: ContentTooLarge, version: HttpVersion { major: 1, minor: 1 }, trailers: Trailers::empty() }
+    }
+
+    pub fn uri_too_long(headers: Headers, body: Body) -> Response {
+        Response { headers, body, status: URITooLong, version: HttpVersion { major: 1, minor: 1 }, trailers: Trailers::empty() }
+    }
+
+    pub fn request_header_fields_too_large(headers: Headers, body: Body) -> Response {
+        Response { headers, body, status: RequestHeaderFieldsTooLarge, version: HttpVersion { major: 1, minor: 1 }, trailers: Trailers::empty() }
+    }
+
+    pub fn expectation_failed(headers: Headers, body: Body) -> Response {
+        Response { headers, body, status: ExpectationFailed, version: HttpVersion { major: 1, minor: 1 }, trailers: Trailers::empty() }
+    }
+
+    pub fn not_implemented(headers: Headers, body: Body) -> Response {
+        Response { headers, body, status: NotImplemented, version: HttpVersion { major: 1, minor: 1 }, trailers: Trailers::empty() }
+    }
+
+    pub fn http_version_not_supported(headers: Headers, body: Body) -> Response {
+        Response { headers, body, status: HttpVersionNotSupported, version: HttpVersion { major: 1, minor: 1 }, trailers: Trailers::empty() }
     }
 
     pub fn moved_permanently(headers: Headers, body: Body) -> Response {
-        Response { headers, body, status: MovedPermanently, version: HttpVersion { major: 1, minor: 1 }, trailers: Headers::empty() }
+        Response { headers, body, status: MovedPermanently, version: HttpVersion { major: 1, minor: 1 }, trailers: Trailers::empty() }
+    }
+
+    pub fn created(headers: Headers, body: Body) -> Response {
+        Response { headers, body, status: Created, version: HttpVersion { major: 1, minor: 1 }, trailers: Trailers::empty() }
     }
 
-    pub fn with_trailers(self, trailers: Headers) -> Response<'a> {
+    pub fn accepted(headers: Headers, body: Body) -> Response {
+        Response { headers, body, status: Accepted, version: HttpVersion { major: 1, minor: 1 }, trailers: Trailers::empty() }
+    }
+
+    pub fn no_content(headers: Headers, body: Body) -> Response {
+        Response { headers, body, status: NoContent, version: HttpVersion { major: 1, minor: 1 }, trailers: Trailers::empty() }
+    }
+
+    pub fn found(headers: Headers, body: Body) -> Response {
+        Response { headers, body, status: Found, version: HttpVersion { major: 1, minor: 1 }, trailers: Trailers::empty() }
+    }
+
+    pub fn see_other(headers: Headers, body: Body) -> Response {
+        Response { headers, body, status: SeeOther, version: HttpVersion { major: 1, minor: 1 }, trailers: Trailers::empty() }
+    }
+
+    pub fn not_modified(headers: Headers, body: Body) -> Response {
+        Response { headers, body, status: NotModified, version: HttpVersion { major: 1, minor: 1 }, trailers: Trailers::empty() }
+    }
+
+    pub fn temporary_redirect(headers: Headers, body: Body) -> Response {
+        Response { headers, body, status: TemporaryRedirect, version: HttpVersion { major: 1, minor: 1 }, trailers: Trailers::empty() }
+    }
+
+    pub fn permanent_redirect(headers: Headers, body: Body) -> Response {
+        Response { headers, body, status: PermanentRedirect, version: HttpVersion { major: 1, minor: 1 }, trailers: Trailers::empty() }
+    }
+
+    pub fn unauthorized(headers: Headers, body: Body) -> Response {
+        Response { headers, body, status: Unauthorized, version: HttpVersion { major: 1, minor: 1 }, trailers: Trailers::empty() }
+    }
+
+    pub fn method_not_allowed(headers: Headers, body: Body) -> Response {
+        Response { headers, body, status: MethodNotAllowed, version: HttpVersion { major: 1, minor: 1 }, trailers: Trailers::empty() }
+    }
+
+    pub fn request_timeout(headers: Headers, body: Body) -> Response {
+        Response { headers, body, status: RequestTimeout, version: HttpVersion { major: 1, minor: 1 }, trailers: Trailers::empty() }
+    }
+
+    pub fn conflict(headers: Headers, body: Body) -> Response {
+        Response { headers, body, status: Conflict, version: HttpVersion { major: 1, minor: 1 }, trailers: Trailers::empty() }
+    }
+
+    pub fn too_many_requests(headers: Headers, body: Body) -> Response {
+        Response { headers, body, status: TooManyRequests, version: HttpVersion { major: 1, minor: 1 }, trailers: Trailers::empty() }
+    }
+
+    pub fn bad_gateway(headers: Headers, body: Body) -> Response {
+        Response { headers, body, status: BadGateway, version: HttpVersion { major: 1, minor: 1 }, trailers: Trailers::empty() }
+    }
+
+    pub fn service_unavailable(headers: Headers, body: Body) -> Response {
+        Response { headers, body, status: ServiceUnavailable, version: HttpVersion { major: 1, minor: 1 }, trailers: Trailers::empty() }
+    }
+
+    pub fn gateway_timeout(headers: Headers, body: Body) -> Response {
+        Response { headers, body, status: GatewayTimeout, version: HttpVersion { major: 1, minor: 1 }, trailers: Trailers::empty() }
+    }
+
+    pub fn with_trailers<T: Into<Trailers>>(self, trailers: T) -> Response<'a> {
         Response {
-            trailers,
+            trailers: trailers.into(),
             ..self
         }
     }
@@ -1252,51 +2276,145 @@ impl<'a> Response<'a> {
     }
 }
 
-#[derive(PartialEq, Debug)]
-#[repr(u32)]
-pub enum Status {
-    OK = 200,
-    MovedPermanently = 301,
-    BadRequest = 400,
-    LengthRequired = 411,
-    NotFound = 404,
-    Forbidden = 403,
-    InternalServerError = 500,
-    Unknown = 0,
+// generates the Status enum, so that the code and reason phrase of each status are only written down once
+macro_rules! statuses {
+    ($($status:ident = $code:literal, $reason:literal;)*) => {
+        #[derive(PartialEq, Debug, Clone)]
+        pub enum Status {
+            $($status,)*
+            // any other 3 digit status, or a registered one with a reason phrase of its own
+            Other(u32, String),
+        }
+
+        impl Status {
+            pub fn value(&self) -> u32 {
+                match self {
+                    $(Status::$status => $code,)*
+                    Status::Other(code, _) => *code,
+                }
+            }
+
+            pub fn to_string(&self) -> String {
+                match self {
+                    $(Status::$status => $reason.to_string(),)*
+                    Status::Other(_, reason) => reason.clone(),
+                }
+            }
+
+            pub fn from_code(code: u32) -> Status {
+                match code {
+                    $($code => Status::$status,)*
+                    _ => Status::Other(code, String::new()),
+                }
+            }
+        }
+    }
+}
+
+// https://www.iana.org/assignments/http-status-codes/http-status-codes.xhtml
+statuses! {
+    Continue = 100, "Continue";
+    SwitchingProtocols = 101, "Switching Protocols";
+    Processing = 102, "Processing";
+    EarlyHints = 103, "Early Hints";
+    OK = 200, "OK";
+    Created = 201, "Created";
+    Accepted = 202, "Accepted";
+    NonAuthoritativeInformation = 203, "Non-Authoritative Information";
+    NoContent = 204, "No Content";
+    ResetContent = 205, "Reset Content";
+    PartialContent = 206, "Partial Content";
+    MultiStatus = 207, "Multi-Status";
+    AlreadyReported = 208, "Already Reported";
+    IMUsed = 226, "IM Used";
+    MultipleChoices = 300, "Multiple Choices";
+    MovedPermanently = 301, "Moved Permanently";
+    Found = 302, "Found";
+    SeeOther = 303, "See Other";
+    NotModified = 304, "Not Modified";
+    UseProxy = 305, "Use Proxy";
+    TemporaryRedirect = 307, "Temporary Redirect";
+    PermanentRedirect = 308, "Permanent Redirect";
+    BadRequest = 400, "Bad Request";
+    Unauthorized = 401, "Unauthorized";
+    PaymentRequired = 402, "Payment Required";
+    Forbidden = 403, "Forbidden";
+    NotFound = 404, "Not Found";
+    MethodNotAllowed = 405, "Method Not Allowed";
+    NotAcceptable = 406, "Not Acceptable";
+    ProxyAuthenticationRequired = 407, "Proxy Authentication Required";
+    RequestTimeout = 408, "Request Timeout";
+    Conflict = 409, "Conflict";
+    Gone = 410, "Gone";
+    LengthRequired = 411, "Length Required";
+    PreconditionFailed = 412, "Precondition Failed";
+    ContentTooLarge = 413, "Content Too Large";
+    URITooLong = 414, "URI Too Long";
+    UnsupportedMediaType = 415, "Unsupported Media Type";
+    RangeNotSatisfiable = 416, "Range Not Satisfiable";
+    ExpectationFailed = 417, "Expectation Failed";
+    MisdirectedRequest = 421, "Misdirected Request";
+    UnprocessableContent = 422, "Unprocessable Content";
+    Locked = 423, "Locked";
+    FailedDependency = 424, "Failed Dependency";
+    TooEarly = 425, "Too Early";
+    UpgradeRequired = 426, "Upgrade Required";
+    PreconditionRequired = 428, "Precondition Required";
+    TooManyRequests = 429, "Too Many Requests";
+    RequestHeaderFieldsTooLarge = 431, "Request Header Fields Too Large";
+    UnavailableForLegalReasons = 451, "Unavailable For Legal Reasons";
+    InternalServerError = 500, "Internal Server Error";
+    NotImplemented = 501, "Not Implemented";
+    BadGateway = 502, "Bad Gateway";
+    ServiceUnavailable = 503, "Service Unavailable";
+    GatewayTimeout = 504, "Gateway Timeout";
+    HttpVersionNotSupported = 505, "HTTP Version Not Supported";
+    VariantAlsoNegotiates = 506, "Variant Also Negotiates";
+    InsufficientStorage = 507, "Insufficient Storage";
+    LoopDetected = 508, "Loop Detected";
+    NotExtended = 510, "Not Extended";
+    NetworkAuthenticationRequired = 511, "Network Authentication Required";
 }
 
 impl Status {
-    pub fn to_string(&self) -> String {
-        match self {
-            OK => "OK".to_string(),
-            MovedPermanently => "Moved Permanently".to_string(),
-            NotFound => "Not Found".to_string(),
-            Forbidden => "Forbidden".to_string(),
-            BadRequest => "Bad Request".to_string(),
-            InternalServerError => "Internal Server Error".to_string(),
-            _ => "Unknown".to_string()
+    pub fn from(str: &str) -> Result<Status, MessageError> {
+        Status::parse(str, "")
+    }
+
+    /*
+     https://datatracker.ietf.org/doc/html/rfc7230#section-3.1.2
+     A client SHOULD ignore the reason-phrase content.
+     So we only keep the reason phrase of a status we do not know.
+     */
+    pub fn parse(code: &str, reason: &str) -> Result<Status, MessageError> {
+        match code.parse::<u32>() {
+            Ok(value) if code.len() == 3 && code.bytes().all(|b| b.is_ascii_digit()) => {
+                Status::custom(value, reason).map(|status| match Status::from_code(value) {
+                    Status::Other(_, _) => status,
+                    registered => registered,
+                })
+            }
+            _ => Err(MessageError::MalformedStartLine(format!("Status code must be 3 digits, got {}", code))),
         }
     }
-    pub fn value(&self) -> u32 {
-        match self {
-            OK => 200,
-            MovedPermanently => 301,
-            BadRequest => 400,
-            Forbidden => 403,
-            NotFound => 404,
-            InternalServerError => 500,
-            _ => 500
-        }
-    }
-    pub fn from(str: &str) -> Self {
-        match str.to_lowercase().as_str() {
-            "200" => OK,
-            "301" => MovedPermanently,
-            "400" => BadRequest,
-            "403" => Forbidden,
-            "404" => NotFound,
-            "500" => InternalServerError,
-            _ => Unknown
+
+    /*
+     https://www.rfc-editor.org/rfc/rfc9112#section-4
+     status-code    = 3DIGIT
+     reason-phrase  = 1*( HTAB / SP / VCHAR / obs-text )
+     A registered code with a reason phrase of its own is sent with that reason phrase,
+     but it is still that code, so eg a 204 still cannot have a body.
+     */
+    pub fn custom(code: u32, reason: &str) -> Result<Status, MessageError> {
+        if !(100..=999).contains(&code) {
+            return Err(MessageError::MalformedStartLine(format!("Status code must be 3 digits, got {}", code)));
+        }
+        if reason.chars().any(|c| c.is_control() && c != '\t') {
+            return Err(MessageError::MalformedStartLine("Reason phrase must not have control characters".to_string()));
+        }
+        match Status::from_code(code) {
+            status if reason.is_empty() || reason == status.to_string() => Ok(status),
+            _ => Ok(Status::Other(code, reason.to_string())),
         }
     }
 }
@@ -24,5 +24,15 @@ pub mod headers;
 pub mod uri;
 pub mod query;
 pub mod codex;
+pub mod connection;
+pub mod socket;
+pub mod hpack;
+pub mod http2;
+pub mod websocket;
+#[cfg(feature = "tls")]
+pub mod tls;
+mod thread_pool;
+#[cfg(unix)]
+mod event_loop;
 
 
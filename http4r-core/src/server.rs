@@ -1,112 +1,662 @@
-use std::net::{TcpListener, TcpStream};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::io::Write;
+use std::net::Shutdown;
 use std::thread;
-use std::io::{Write};
-use std::sync::{Arc};
+use std::thread::JoinHandle;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+use crate::thread_pool::ThreadPool;
 use crate::handler::Handler;
 use crate::headers::Headers;
-use crate::http_message::{HttpMessage, read_message_from_wire, MessageError, RequestOptions, Response, write_message_to_wire};
+use crate::http_message::{expects_continue, HttpMessage, one_pt_oh, read_message_from_wire, MessageError, MessageLimits, Request, RequestOptions, Response, skip_unread_body, WireState, write_message_to_wire};
 use crate::http_message::Body::{BodyString};
+use crate::http_message::Method::HEAD;
+use crate::http_message::Status::SwitchingProtocols;
+use crate::http2;
+use crate::http2::Http2Options;
+use crate::socket::{Listener, SocketAddress, Stream};
+#[cfg(unix)]
+use crate::event_loop::EventLoop;
+#[cfg(feature = "tls")]
+use crate::tls::ServerTls;
 
 pub struct Server {
     pub port: u16,
-    // options: ServerOptions,
+    // where it listens, once started this is where it ended up, eg with the port it was given
+    pub address: SocketAddress,
+    options: ServerOptions,
 }
 
+#[derive(Clone)]
 pub struct ServerOptions {
+    // how many connections are handled at once
     pub threadpool_size: usize,
+    // how many accepted connections can wait for a thread to be free
+    pub accept_queue_size: usize,
+    // what to do with a connection when the queue is full
+    pub overload_policy: OverloadPolicy,
+    // how the threads in the pool are shared out between connections
+    pub mode: ServerMode,
+    // serve https rather than http
+    #[cfg(feature = "tls")]
+    pub tls: Option<ServerTls>,
+    // serve HTTP/2 to clients that start with its preface, over ALPN h2 or knowing we speak it beforehand,
+    // off unless asked for, as each stream has a thread of its own on top of the pool's
+    pub http2: Option<Http2Options>,
+    pub request_line_size: usize,
     pub headers_size: usize,
+    pub max_headers: usize,
+    pub max_body_size: usize,
     pub trailers_size: usize,
+    pub max_decompressed_size: usize,
+    // how long to wait for the next request on an idle connection
+    pub keep_alive_timeout: Duration,
+    // how long the request line and headers can take to arrive, once the request has started
+    pub headers_timeout: Duration,
+    // how long to wait for each read of the body
+    pub body_timeout: Duration,
+    pub write_timeout: Duration,
+    pub max_requests_per_connection: usize,
+    pub max_compressed_content_length: usize,
+}
+
+impl Default for ServerOptions {
+    fn default() -> ServerOptions {
+        let limits = MessageLimits::default();
+        ServerOptions {
+            request_line_size: 16384,
+            headers_size: 16384,
+            max_headers: limits.max_headers,
+            max_body_size: limits.max_body_size,
+            trailers_size: 16384,
+            max_decompressed_size: limits.max_decompressed_size,
+            threadpool_size: 10,
+            accept_queue_size: 100,
+            overload_policy: OverloadPolicy::Queue,
+            mode: ServerMode::ThreadPerConnection,
+            #[cfg(feature = "tls")]
+            tls: None,
+            http2: None,
+            keep_alive_timeout: Duration::from_secs(5),
+            headers_timeout: Duration::from_secs(10),
+            body_timeout: Duration::from_secs(30),
+            write_timeout: Duration::from_secs(30),
+            max_requests_per_connection: 100,
+            max_compressed_content_length: 1048576,
+        }
+    }
+}
+
+impl ServerOptions {
+    // whether connections start with a TLS handshake
+    pub(crate) fn is_tls(&self) -> bool {
+        #[cfg(feature = "tls")]
+        return self.tls.is_some();
+        #[cfg(not(feature = "tls"))]
+        false
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum OverloadPolicy {
+    // stop accepting connections until there is room in the queue, so the rest wait in the listen backlog
+    Queue,
+    // tell the client we are too busy, and when to try again
+    Reject { retry_after: Duration },
+    // close the connection without a word
+    Close,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ServerMode {
+    // each connection has a thread from the pool for as long as it is open
+    ThreadPerConnection,
+    // one thread waits on every connection until the head of its next request has arrived,
+    // and only then hands it to a thread from the pool, so idle connections do not hold on to a thread each.
+    // over TLS, the handshake is done on a thread from the pool, as is a whole HTTP/2 connection
+    #[cfg(unix)]
+    EventLoop,
+}
+
+/// How connections are accepted, and waited on in between requests.
+enum Acceptor {
+    Blocking(Listener),
+    #[cfg(unix)]
+    Polling(EventLoop),
 }
 
 impl Server where {
     pub fn new(port: u16) -> Server {
+        Server::with_options(port, ServerOptions::default())
+    }
+
+    pub fn with_options(port: u16, options: ServerOptions) -> Server {
+        Server::bind(SocketAddress::any(port), options)
+    }
+
+    /// Listens on a particular address instead of every interface,
+    /// eg only on loopback, on ipv6, or on a unix socket.
+    pub fn bind(address: SocketAddress, options: ServerOptions) -> Server {
         Server {
-            port,
-            // options: options.unwrap_or(ServerOptions {
-            //     headers_size: 16384,
-            //     trailers_size: 16384,
-            //     threadpool_size: 10,
-            // })
+            port: address.port(),
+            address,
+            options,
         }
     }
 
-    pub fn start<F, H>(&mut self, fun: F, close_on_finish: bool)
+    /// Runs the server in the background if close_on_finish, otherwise blocks for as long as it runs.
+    /// The handle it returns can stop it.
+    pub fn start<F, H>(&mut self, fun: F, close_on_finish: bool) -> Result<ServerHandle, std::io::Error>
         where F: Fn() -> Result<H, String> + Send + Sync + 'static, H: Handler {
-        let listener = self.listen();
+        let listener = self.listen()?;
+        let acceptor = match self.options.mode {
+            ServerMode::ThreadPerConnection => Acceptor::Blocking(listener),
+            #[cfg(unix)]
+            ServerMode::EventLoop => Acceptor::Polling(EventLoop::new(listener)?),
+        };
+        let address = self.address.clone();
         let handler = Arc::new(fun);
+        let options = self.options.clone();
+        #[cfg(feature = "tls")]
+        let options = match options.http2 {
+            Some(_) => options,
+            None => ServerOptions { tls: options.tls.map(|tls| tls.without_alpn_protocol("h2")), ..options },
+        };
+        let connections = Arc::new(Connections::new());
+        let mut handle = ServerHandle { port: self.port, address, connections: connections.clone(), accepting: None };
 
         if close_on_finish {
-            thread::spawn(|| {
-                Self::handle_tcp_stream(listener, handler)
-            });
+            handle.accepting = Some(thread::spawn(|| {
+                Self::accept_connections(acceptor, handler, options, connections)
+            }));
         } else {
-            Self::handle_tcp_stream(listener, handler);
+            Self::accept_connections(acceptor, handler, options, connections);
         };
+        Ok(handle)
+    }
+
+    fn accept_connections<F, H>(acceptor: Acceptor, handler: Arc<F>, options: ServerOptions, connections: Arc<Connections>)
+        where F: Fn() -> Result<H, String> + Send + Sync + 'static,
+              H: Handler {
+        match acceptor {
+            Acceptor::Blocking(listener) => Self::accept_blocking(listener, handler, options, connections),
+            #[cfg(unix)]
+            Acceptor::Polling(event_loop) => event_loop.run(handler, options, connections),
+        }
     }
 
-    fn handle_tcp_stream<F, H>(listener: TcpListener, handler: Arc<F>)
+    fn accept_blocking<F, H>(listener: Listener, handler: Arc<F>, options: ServerOptions, connections: Arc<Connections>)
         where F: Fn() -> Result<H, String> + Send + Sync + 'static,
               H: Handler {
-        for stream in listener.incoming() {
-            let h = handler.clone();
-            thread::spawn(move || {
-                Self::handle_request(h.clone(), stream.unwrap());
-            });
+        let connection_options = options.clone();
+        let open_connections = connections.clone();
+        // dropping the pool at the end waits for the connections it is handling to finish
+        let pool = ThreadPool::new(options.threadpool_size, options.accept_queue_size, move |stream: Stream| {
+            Self::handle_connection(handler.clone(), stream, connection_options.clone(), &open_connections);
+        });
+        loop {
+            let stream = listener.accept();
+            // the handle wakes us up to tell us to stop
+            if connections.stopping() {
+                break;
+            }
+            // eg the client gave up before we accepted it
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(_) => continue,
+            };
+            if let Err(stream) = pool.try_send(stream) {
+                match &options.overload_policy {
+                    OverloadPolicy::Queue => { let _ = pool.send(stream); }
+                    OverloadPolicy::Reject { retry_after } => Self::reject(stream, retry_after),
+                    OverloadPolicy::Close => {}
+                }
+            }
         }
     }
 
-    fn handle_request<F, H>(handler: Arc<F>, mut stream: TcpStream)
+    /*
+     https://www.rfc-editor.org/rfc/rfc9110#section-15.6.4
+     The 503 (Service Unavailable) status code indicates that the server is currently unable to handle
+     the request due to a temporary overload or scheduled maintenance, which will likely be alleviated
+     after some delay. The server MAY send a Retry-After header field to suggest an appropriate amount
+     of time for the client to wait before retrying the request.
+     */
+    pub(crate) fn reject(mut stream: Stream, retry_after: &Duration) {
+        // this is on the thread that accepts connections, so do not let a slow client hold it up
+        let _ = stream.set_write_timeout(Some(Duration::from_secs(1)));
+        let response = Response::service_unavailable(
+            Headers::from(vec!(("Retry-After", retry_after.as_secs().to_string().as_str()))),
+            BodyString("Too busy to handle the request, try again later"));
+        let mut options = RequestOptions::default();
+        options.connection = Some("close".to_string());
+        let _ = write_message_to_wire(&mut stream, HttpMessage::Response(response), options);
+    }
+
+    // the event loop gave up waiting for the head of the request, which handle_request would have timed out too
+    pub(crate) fn time_out(mut stream: Stream) {
+        let _ = stream.set_write_timeout(Some(Duration::from_secs(1)));
+        let response = Response::request_timeout(Headers::empty(), BodyString("Timed out waiting for the message"));
+        let mut options = RequestOptions::default();
+        options.connection = Some("close".to_string());
+        let _ = write_message_to_wire(&mut stream, HttpMessage::Response(response), options);
+    }
+
+    fn handle_connection<F, H>(handler: Arc<F>, stream: Stream, options: ServerOptions, connections: &Connections)
+        where F: Fn() -> Result<H, String> + Send + Sync + 'static, H: Handler {
+        let id = match connections.open(&stream) {
+            Some(id) => id,
+            None => return,
+        };
+        // however we are done with it, even if the handler panics, otherwise the clone of it we keep stays open
+        let _open = ClosesOnDrop::new(connections, id);
+        // the handshake is done here rather than on the thread that accepts, so that a slow one only holds up this connection
+        #[cfg(feature = "tls")]
+        let stream = match &options.tls {
+            Some(tls) => match tls.accept(stream, options.headers_timeout) {
+                Ok(stream) => stream,
+                Err(_) => return,
+            },
+            None => stream,
+        };
+        Self::serve_connection(&handler, stream, id, &options, connections, 0, false);
+    }
+
+    /// Handles the requests on a connection for as long as it is kept alive.
+    /// If give_back_when_idle, then the event loop waits for the next request instead of us,
+    /// so the connection is given back to it, along with how many requests it has had so far.
+    pub(crate) fn serve_connection<F, H>(
+        handler: &Arc<F>,
+        mut stream: Stream,
+        id: usize,
+        options: &ServerOptions,
+        connections: &Connections,
+        mut requests_on_connection: usize,
+        give_back_when_idle: bool,
+    ) -> Option<(Stream, usize)>
         where F: Fn() -> Result<H, String> + Send + Sync + 'static, H: Handler {
-        let mut reader: &mut [u8] = &mut [0; 4096];
+        let mut reader = [0; 4096];
+        let mut wire = WireState::with_limits(MessageLimits {
+            max_headers: options.max_headers,
+            max_body_size: options.max_body_size,
+            max_decompressed_size: options.max_decompressed_size,
+            head_timeout: Some(options.headers_timeout),
+            read_timeout: Some(options.body_timeout),
+        });
+        let _ = stream.set_write_timeout(Some(options.write_timeout));
         let mut chunks_writer = Vec::with_capacity(1048576);
         let mut compress_writer = Vec::with_capacity(1048576);
-        let mut start_line_writer = Vec::with_capacity(16384);
-        let mut headers_writer = Vec::with_capacity(16384);
-        let mut trailers_writer = Vec::with_capacity(16384);
+        // the writers are only allowed to get as big as they start out
+        let mut start_line_writer = Vec::with_capacity(options.request_line_size);
+        let mut headers_writer = Vec::with_capacity(options.headers_size);
+        let mut trailers_writer = Vec::with_capacity(options.trailers_size);
+        // the event loop only gives us the connection once the head of a request has arrived
+        let mut request_has_arrived = give_back_when_idle;
+
+        loop {
+            chunks_writer.clear();
+            compress_writer.clear();
+            start_line_writer.clear();
+            headers_writer.clear();
+            trailers_writer.clear();
+
+            if !wire.has_leftover_bytes() && !request_has_arrived {
+                if give_back_when_idle {
+                    return Some((stream, requests_on_connection));
+                }
+                // an idle connection has not started a request, so there is nothing to time out, we just close it
+                if !connections.next_request_arrives(id, &stream, options.keep_alive_timeout) {
+                    return None;
+                }
+            }
+            request_has_arrived = false;
+            // a connection is HTTP/2 from the start or not at all
+            if requests_on_connection == 0 {
+                if let Some(http2) = &options.http2 {
+                    if http2::preface_arrives(&stream, options.headers_timeout) {
+                        http2::serve_connection(handler, stream, id, options, http2, connections);
+                        return None;
+                    }
+                }
+            }
+            requests_on_connection += 1;
+
+            let keep_alive = Self::handle_request(
+                handler,
+                &mut stream,
+                &mut reader,
+                &mut wire,
+                &mut start_line_writer,
+                &mut headers_writer,
+                &mut chunks_writer,
+                &mut compress_writer,
+                &mut trailers_writer,
+                &|| requests_on_connection < options.max_requests_per_connection && !connections.stopping(),
+                options,
+            );
+            if stream.flush().is_err() || !keep_alive || !skip_unread_body(&mut stream, &mut reader, &mut wire, &mut chunks_writer, &mut trailers_writer) {
+                return None;
+            }
+        }
+    }
 
+    // returns whether the connection can be used for another request
+    #[allow(clippy::too_many_arguments)]
+    fn handle_request<F, H>(
+        handler: &Arc<F>,
+        stream: &mut Stream,
+        reader: &mut [u8],
+        wire: &mut WireState,
+        start_line_writer: &mut Vec<u8>,
+        headers_writer: &mut Vec<u8>,
+        chunks_writer: &mut Vec<u8>,
+        compress_writer: &mut Vec<u8>,
+        trailers_writer: &mut Vec<u8>,
+        more_requests_allowed: &dyn Fn() -> bool,
+        options: &ServerOptions,
+    ) -> bool
+        where F: Fn() -> Result<H, String> + Send + Sync + 'static, H: Handler {
         let result = read_message_from_wire(
             stream.try_clone().unwrap(),
-            &mut reader,
-            &mut start_line_writer,
-            &mut headers_writer,
-            &mut chunks_writer,
-            &mut compress_writer,
-            &mut trailers_writer,
+            reader,
+            wire,
+            false,
+            start_line_writer,
+            headers_writer,
+            chunks_writer,
+            compress_writer,
+            trailers_writer,
         );
 
+        // the connection is closed after any response we make up here, so it does not matter if it could not be written
         match result {
-            Err(MessageError::HeadersTooBig(msg))
-            | Err(MessageError::TrailersTooBig(msg))
-            | Err(MessageError::InvalidContentLength(msg))
-            | Err(MessageError::StartLineTooBig(msg))
+            Err(MessageError::InvalidContentLength(msg))
             | Err(MessageError::InvalidBoundaryDigit(msg))
+            | Err(MessageError::MalformedStartLine(msg))
+            | Err(MessageError::MalformedHeader(msg))
+            | Err(MessageError::ContentLengthAndTransferEncoding(msg))
+            | Err(MessageError::TransferEncodingNotChunked(msg))
+            | Err(MessageError::WhitespaceBeforeColon(msg))
+            | Err(MessageError::ObsFold(msg))
+            | Err(MessageError::BareLineFeed(msg))
+            | Err(MessageError::ConflictingContentLength(msg))
             => {
                 let response = Response::bad_request(Headers::empty(), BodyString(msg.as_str()));
-                write_message_to_wire(&mut stream, HttpMessage::Response(response), RequestOptions::default());
+                let _ = write_message_to_wire(stream, HttpMessage::Response(response), RequestOptions::default());
+                false
+            }
+            // https://www.rfc-editor.org/rfc/rfc9110#section-15.5.15
+            // the request line is almost all request target, so it is the uri that is too long
+            Err(MessageError::StartLineTooBig(msg)) => {
+                let response = Response::uri_too_long(Headers::empty(), BodyString(msg.as_str()));
+                let _ = write_message_to_wire(stream, HttpMessage::Response(response), RequestOptions::default());
+                false
+            }
+            // https://www.rfc-editor.org/rfc/rfc6585#section-5
+            Err(MessageError::HeadersTooBig(msg))
+            | Err(MessageError::TooManyHeaders(msg))
+            | Err(MessageError::TrailersTooBig(msg)) => {
+                let response = Response::request_header_fields_too_large(Headers::empty(), BodyString(msg.as_str()));
+                let _ = write_message_to_wire(stream, HttpMessage::Response(response), RequestOptions::default());
+                false
+            }
+            // https://www.rfc-editor.org/rfc/rfc9110#section-15.5.14
+            Err(MessageError::BodyTooBig(msg)) => {
+                let response = Response::content_too_large(Headers::empty(), BodyString(msg.as_str()));
+                let _ = write_message_to_wire(stream, HttpMessage::Response(response), RequestOptions::default());
+                false
             }
             Err(MessageError::NoContentLengthOrTransferEncoding(msg)) => {
                 let response = Response::length_required(Headers::empty(), BodyString(msg.as_str()));
-                write_message_to_wire(&mut stream, HttpMessage::Response(response), RequestOptions::default());
+                let _ = write_message_to_wire(stream, HttpMessage::Response(response), RequestOptions::default());
+                false
+            }
+            Err(MessageError::UnknownMethod(msg)) => {
+                let response = Response::not_implemented(Headers::empty(), BodyString(msg.as_str()));
+                let _ = write_message_to_wire(stream, HttpMessage::Response(response), RequestOptions::default());
+                false
+            }
+            Err(MessageError::UnsupportedVersion(msg)) => {
+                let response = Response::http_version_not_supported(Headers::empty(), BodyString(msg.as_str()));
+                let _ = write_message_to_wire(stream, HttpMessage::Response(response), RequestOptions::default());
+                false
+            }
+            /*
+             https://www.rfc-editor.org/rfc/rfc9110#section-15.5.9
+             The 408 (Request Timeout) status code indicates that the server did not receive a complete
+             request message within the time that it was prepared to wait.
+             If the client has an outstanding request in transit, it MAY repeat that request.
+             If the current connection is not usable (e.g., as it would be in HTTP/1.1 because request
+             delimitation is lost), a "close" connection option is sent.
+             */
+            Err(MessageError::Timeout(msg)) => {
+                let response = Response::request_timeout(Headers::empty(), BodyString(msg.as_str()));
+                let mut options = RequestOptions::default();
+                options.connection = Some("close".to_string());
+                let _ = write_message_to_wire(stream, HttpMessage::Response(response), options);
+                false
+            }
+            // there is no one to respond to, or they have gone quiet
+            Err(MessageError::ConnectionClosed(_))
+            | Err(MessageError::IoError(_)) => false,
+            Ok(HttpMessage::Request(request)) if request.headers.has("Expect") && !expects_continue(&request.headers) => {
+                // 100-continue is the only expectation there is
+                let response = Response::expectation_failed(Headers::empty(), BodyString("Only 100-continue is supported"));
+                let _ = write_message_to_wire(stream, HttpMessage::Response(response), RequestOptions::default());
+                false
             }
             Ok(HttpMessage::Request(request)) => {
-                let options = RequestOptions::from(&(request.headers));
+                let wants_keep_alive = Self::wants_keep_alive(&request);
+                let wants_upgrade = request.headers.has("Upgrade");
+                let is_version_1_0 = request.version == one_pt_oh();
+                let max_compressed_content_length = options.max_compressed_content_length;
+                let mut options = RequestOptions::from(&(request.headers));
+                options.max_compressed_content_length = max_compressed_content_length;
+                options.responding_to_head = request.method == HEAD;
+                // if the handler never responds then the connection cannot be used again
+                let mut keep_alive = false;
                 let mut h = handler().unwrap();
                 h.handle(request, |response| {
-                    write_message_to_wire(&mut stream, HttpMessage::Response(response), options);
+                    // the connection is no longer ours, so the handler gets it to speak the protocol it switched to
+                    if wants_upgrade && response.status.value() == SwitchingProtocols.value() {
+                        let options = RequestOptions { connection: Some("Upgrade".to_string()), ..RequestOptions::default() };
+                        if write_message_to_wire(stream, HttpMessage::Response(response), options).is_ok() && stream.flush().is_ok() {
+                            UPGRADED.with(|upgraded| *upgraded.borrow_mut() = stream.try_clone().ok());
+                        }
+                        return;
+                    }
+                    // decided once the handler is done, as the server may have started stopping in the meantime
+                    keep_alive = wants_keep_alive && more_requests_allowed();
+                    options.connection = if !keep_alive {
+                        Some("close".to_string())
+                    } else if is_version_1_0 {
+                        Some("keep-alive".to_string())
+                    } else {
+                        None
+                    };
+                    // eg the client went away or was too slow to read it, so the connection is no use any more
+                    if write_message_to_wire(stream, HttpMessage::Response(response), options).is_err() {
+                        keep_alive = false;
+                    }
                 });
+                // either the handler is done with the connection it was given, or it never took it
+                UPGRADED.with(|upgraded| upgraded.borrow_mut().take());
+                keep_alive
             }
             Ok(HttpMessage::Response(response)) => {
-                write_message_to_wire(&mut stream, HttpMessage::Response(response), RequestOptions::default());
+                let _ = write_message_to_wire(stream, HttpMessage::Response(response), RequestOptions::default());
+                false
             }
-        };
+        }
+    }
 
-        stream.flush().unwrap();
+    /*
+     HTTP/1.1 connections are persistent unless the client says otherwise,
+     HTTP/1.0 connections are only persistent if the client asks for keep-alive.
+     */
+    fn wants_keep_alive(request: &Request) -> bool {
+        let connection = request.headers.get("Connection").map(|c| c.to_lowercase());
+        if request.version == one_pt_oh() {
+            connection.map(|c| c.contains("keep-alive")).unwrap_or(false)
+        } else {
+            !connection.map(|c| c.contains("close")).unwrap_or(false)
+        }
+    }
+
+    fn listen(&mut self) -> Result<Listener, std::io::Error> {
+        let listener = Listener::bind(&self.address)?;
+        self.address = listener.local_address()?;
+        self.port = self.address.port();
+        Ok(listener)
+    }
+}
+
+thread_local! {
+    // the connection of the request being handled on this thread, once its response has switched it to another protocol
+    static UPGRADED: RefCell<Option<Stream>> = const { RefCell::new(None) };
+}
+
+/// Takes over the connection of the request being handled, once the handler has responded 101 Switching Protocols to it.
+/// None if there is no such connection, eg the request came over HTTP/2, which has no upgrades.
+pub(crate) fn upgraded_connection() -> Option<Stream> {
+    UPGRADED.with(|upgraded| upgraded.borrow_mut().take())
+}
+
+pub struct ServerHandle {
+    pub port: u16,
+    address: SocketAddress,
+    connections: Arc<Connections>,
+    // None if the server ran on the thread that started it, in which case it has already stopped
+    accepting: Option<JoinHandle<()>>,
+}
+
+impl ServerHandle {
+    /// Stops accepting connections and closes the ones that are waiting for their next request.
+    /// Requests in flight are answered with Connection: close, until the deadline,
+    /// when the connections still open are closed underneath them.
+    /// Returns once every thread has finished, so a handler that never returns means this never does either.
+    pub fn stop(mut self, deadline: Duration) {
+        self.connections.stop();
+        // the accept loop is waiting for a connection, so give it one
+        let _ = Stream::connect(&self.address.reachable(), Duration::from_secs(1));
+        let give_up_at = Instant::now() + deadline;
+        if let Some(accepting) = self.accepting.take() {
+            while !accepting.is_finished() && Instant::now() < give_up_at {
+                thread::sleep(Duration::from_millis(10));
+            }
+            self.connections.close_all();
+            let _ = accepting.join();
+        }
+    }
+
+    /// Blocks until the server has stopped.
+    pub fn wait(mut self) {
+        if let Some(accepting) = self.accepting.take() {
+            let _ = accepting.join();
+        }
+    }
+}
+
+/// The connections that are open, so that they can be closed when the server stops.
+/// Each one is idle while it waits for its next request, which once we are stopping it is never going to get.
+pub(crate) struct Connections {
+    stopping: AtomicBool,
+    next_id: AtomicUsize,
+    open: Mutex<HashMap<usize, (Stream, bool)>>,
+    // the HTTP/2 streams being handled on every connection, each on a thread of its own
+    streams: AtomicUsize,
+}
+
+/// Closes the connection once it goes out of scope, unwinding included.
+pub(crate) struct ClosesOnDrop<'a> {
+    connections: &'a Connections,
+    id: usize,
+}
+
+impl<'a> ClosesOnDrop<'a> {
+    pub(crate) fn new(connections: &'a Connections, id: usize) -> ClosesOnDrop<'a> {
+        ClosesOnDrop { connections, id }
     }
 
-    fn listen(&mut self) -> TcpListener {
-        let addr = format!("0.0.0.0:{}", self.port);
-        let listener = TcpListener::bind(addr).unwrap();
-        self.port = listener.local_addr().unwrap().port();
-        listener
+    // someone else is going to close it, eg the event loop once it has been given back
+    pub(crate) fn keep_open(self) {
+        std::mem::forget(self);
+    }
+}
+
+impl Drop for ClosesOnDrop<'_> {
+    fn drop(&mut self) {
+        self.connections.close(self.id);
+    }
+}
+
+impl Connections {
+    fn new() -> Connections {
+        Connections { stopping: AtomicBool::new(false), next_id: AtomicUsize::new(0), open: Mutex::new(HashMap::new()), streams: AtomicUsize::new(0) }
+    }
+
+    pub(crate) fn stopping(&self) -> bool {
+        self.stopping.load(Ordering::SeqCst)
+    }
+
+    // None if we are already stopping, as then the connection was only ever queued
+    pub(crate) fn open(&self, stream: &Stream) -> Option<usize> {
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        let stream = stream.try_clone().ok()?;
+        let mut open = self.open.lock().unwrap();
+        if self.stopping() {
+            return None;
+        }
+        open.insert(id, (stream, false));
+        Some(id)
+    }
+
+    pub(crate) fn close(&self, id: usize) {
+        self.open.lock().unwrap().remove(&id);
+    }
+
+    // false if there are already as many streams as the server will handle at once
+    pub(crate) fn open_stream(&self, max_streams: usize) -> bool {
+        self.streams.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |streams| {
+            if streams < max_streams { Some(streams + 1) } else { None }
+        }).is_ok()
+    }
+
+    pub(crate) fn close_stream(&self) {
+        self.streams.fetch_sub(1, Ordering::SeqCst);
+    }
+
+    pub(crate) fn set_idle(&self, id: usize, idle: bool) {
+        if let Some(connection) = self.open.lock().unwrap().get_mut(&id) {
+            connection.1 = idle;
+        }
+    }
+
+    // only wait so long for the next request on an idle connection, and not at all if we are stopping
+    fn next_request_arrives(&self, id: usize, stream: &Stream, keep_alive_timeout: Duration) -> bool {
+        // marked idle before we check, so that if we start stopping after we check, it will be closed while we wait
+        self.set_idle(id, true);
+        let _ = stream.set_read_timeout(Some(keep_alive_timeout));
+        let arrived = !self.stopping() && stream.peek(&mut [0; 1]).map(|peeked| peeked > 0).unwrap_or(false);
+        self.set_idle(id, false);
+        arrived
+    }
+
+    fn stop(&self) {
+        let open = self.open.lock().unwrap();
+        self.stopping.store(true, Ordering::SeqCst);
+        for (stream, idle) in open.values() {
+            if *idle {
+                let _ = stream.shutdown(Shutdown::Both);
+            }
+        }
+    }
+
+    fn close_all(&self) {
+        for (stream, _) in self.open.lock().unwrap().values() {
+            let _ = stream.shutdown(Shutdown::Both);
+        }
     }
 }
@@ -0,0 +1,326 @@
+use std::fmt;
+use std::io::{Read, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, Shutdown, SocketAddr, TcpListener, TcpStream};
+#[cfg(unix)]
+use std::os::unix::io::{AsRawFd, RawFd};
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::PathBuf;
+use std::time::Duration;
+#[cfg(feature = "tls")]
+use crate::tls::TlsStream;
+
+/// Where a server listens, or a client connects to.
+/// An ip address can be v4 or v6, eg 127.0.0.1:8080 or [::1]:8080, and a port of 0 lets the server pick one.
+#[derive(Clone, Debug, PartialEq)]
+pub enum SocketAddress {
+    Ip(SocketAddr),
+    #[cfg(unix)]
+    Unix(PathBuf),
+}
+
+impl SocketAddress {
+    /// Parses eg 0.0.0.0:80, [::]:80 or unix:/var/run/app.sock
+    pub fn parse(str: &str) -> Result<SocketAddress, String> {
+        if let Some(path) = str.strip_prefix("unix:") {
+            return Self::unix(path);
+        }
+        str.parse::<SocketAddr>()
+            .map(SocketAddress::Ip)
+            .map_err(|_| format!("Invalid socket address {}", str))
+    }
+
+    pub fn any(port: u16) -> SocketAddress {
+        SocketAddress::Ip(SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), port))
+    }
+
+    pub fn localhost(port: u16) -> SocketAddress {
+        SocketAddress::Ip(SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), port))
+    }
+
+    #[cfg(unix)]
+    fn unix(path: &str) -> Result<SocketAddress, String> {
+        if path.is_empty() {
+            return Err("Unix socket address needs a path".to_string());
+        }
+        Ok(SocketAddress::Unix(PathBuf::from(path)))
+    }
+
+    #[cfg(not(unix))]
+    fn unix(_path: &str) -> Result<SocketAddress, String> {
+        Err("Unix sockets are not supported on this platform".to_string())
+    }
+
+    // unix sockets do not have a port
+    pub fn port(&self) -> u16 {
+        match self {
+            SocketAddress::Ip(address) => address.port(),
+            #[cfg(unix)]
+            SocketAddress::Unix(_) => 0,
+        }
+    }
+
+    // what to put in the Host header, which a unix socket has nothing to say about
+    pub fn host(&self) -> String {
+        match self {
+            SocketAddress::Ip(address) => address.to_string(),
+            #[cfg(unix)]
+            SocketAddress::Unix(_) => "localhost".to_string(),
+        }
+    }
+
+    // a server listening on every interface can be reached on loopback
+    pub(crate) fn reachable(&self) -> SocketAddress {
+        match self {
+            SocketAddress::Ip(address) if address.ip().is_unspecified() => {
+                let loopback = match address.ip() {
+                    IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::LOCALHOST),
+                    IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::LOCALHOST),
+                };
+                SocketAddress::Ip(SocketAddr::new(loopback, address.port()))
+            }
+            other => other.clone(),
+        }
+    }
+}
+
+impl fmt::Display for SocketAddress {
+    fn fmt(&self, format: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SocketAddress::Ip(address) => write!(format, "{}", address),
+            #[cfg(unix)]
+            SocketAddress::Unix(path) => write!(format, "unix:{}", path.display()),
+        }
+    }
+}
+
+impl From<SocketAddr> for SocketAddress {
+    fn from(address: SocketAddr) -> Self {
+        SocketAddress::Ip(address)
+    }
+}
+
+pub enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener, PathBuf),
+}
+
+impl Listener {
+    pub fn bind(address: &SocketAddress) -> std::io::Result<Listener> {
+        match address {
+            SocketAddress::Ip(address) => TcpListener::bind(address).map(Listener::Tcp),
+            #[cfg(unix)]
+            SocketAddress::Unix(path) => UnixListener::bind(path).map(|listener| Listener::Unix(listener, path.clone())),
+        }
+    }
+
+    // the address we ended up with, eg the port picked for us
+    pub fn local_address(&self) -> std::io::Result<SocketAddress> {
+        match self {
+            Listener::Tcp(listener) => listener.local_addr().map(SocketAddress::Ip),
+            #[cfg(unix)]
+            Listener::Unix(_, path) => Ok(SocketAddress::Unix(path.clone())),
+        }
+    }
+
+    pub fn accept(&self) -> std::io::Result<Stream> {
+        match self {
+            Listener::Tcp(listener) => listener.accept().map(|(stream, _)| Stream::Tcp(stream)),
+            #[cfg(unix)]
+            Listener::Unix(listener, _) => listener.accept().map(|(stream, _)| Stream::Unix(stream)),
+        }
+    }
+
+    pub fn set_nonblocking(&self, nonblocking: bool) -> std::io::Result<()> {
+        match self {
+            Listener::Tcp(listener) => listener.set_nonblocking(nonblocking),
+            #[cfg(unix)]
+            Listener::Unix(listener, _) => listener.set_nonblocking(nonblocking),
+        }
+    }
+}
+
+#[cfg(unix)]
+impl AsRawFd for Listener {
+    fn as_raw_fd(&self) -> RawFd {
+        match self {
+            Listener::Tcp(listener) => listener.as_raw_fd(),
+            Listener::Unix(listener, _) => listener.as_raw_fd(),
+        }
+    }
+}
+
+impl Drop for Listener {
+    // otherwise nothing can bind to the path again
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        if let Listener::Unix(_, path) = self {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+/// A connection, over whichever kind of socket it came in on.
+pub enum Stream {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+    #[cfg(feature = "tls")]
+    Tls(TlsStream),
+}
+
+impl Stream {
+    pub fn connect(address: &SocketAddress, timeout: Duration) -> std::io::Result<Stream> {
+        match address {
+            SocketAddress::Ip(address) => TcpStream::connect_timeout(address, timeout).map(Stream::Tcp),
+            // it is on this machine, so there is nothing to wait for
+            #[cfg(unix)]
+            SocketAddress::Unix(path) => UnixStream::connect(path).map(Stream::Unix),
+        }
+    }
+
+    pub fn try_clone(&self) -> std::io::Result<Stream> {
+        match self {
+            Stream::Tcp(stream) => stream.try_clone().map(Stream::Tcp),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.try_clone().map(Stream::Unix),
+            #[cfg(feature = "tls")]
+            Stream::Tls(stream) => stream.try_clone().map(Stream::Tls),
+        }
+    }
+
+    pub fn set_read_timeout(&self, timeout: Option<Duration>) -> std::io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => stream.set_read_timeout(timeout),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.set_read_timeout(timeout),
+            // the socket underneath has the timeouts, and can be shut down while a read is waiting
+            #[cfg(feature = "tls")]
+            Stream::Tls(stream) => stream.socket().set_read_timeout(timeout),
+        }
+    }
+
+    pub fn set_write_timeout(&self, timeout: Option<Duration>) -> std::io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => stream.set_write_timeout(timeout),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.set_write_timeout(timeout),
+            #[cfg(feature = "tls")]
+            Stream::Tls(stream) => stream.socket().set_write_timeout(timeout),
+        }
+    }
+
+    pub fn set_nonblocking(&self, nonblocking: bool) -> std::io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => stream.set_nonblocking(nonblocking),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.set_nonblocking(nonblocking),
+            #[cfg(feature = "tls")]
+            Stream::Tls(stream) => stream.socket().set_nonblocking(nonblocking),
+        }
+    }
+
+    pub fn shutdown(&self, how: Shutdown) -> std::io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => stream.shutdown(how),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.shutdown(how),
+            #[cfg(feature = "tls")]
+            Stream::Tls(stream) => stream.shutdown(how),
+        }
+    }
+
+    // reads without taking what was read off the stream
+    pub fn peek(&self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self {
+            Stream::Tcp(stream) => stream.peek(buf),
+            #[cfg(unix)]
+            Stream::Unix(stream) => peek_unix(stream, buf),
+            #[cfg(feature = "tls")]
+            Stream::Tls(stream) => stream.peek(buf),
+        }
+    }
+
+    // the protocol agreed on over ALPN, which only a TLS connection has
+    #[cfg(feature = "tls")]
+    pub fn alpn_protocol(&self) -> Option<Vec<u8>> {
+        match self {
+            Stream::Tls(stream) => stream.alpn_protocol(),
+            _ => None,
+        }
+    }
+}
+
+// UnixStream::peek is not stable yet, so ask for MSG_PEEK ourselves
+#[cfg(unix)]
+fn peek_unix(stream: &UnixStream, buf: &mut [u8]) -> std::io::Result<usize> {
+    use std::os::unix::io::AsRawFd;
+    let peeked = unsafe {
+        libc::recv(stream.as_raw_fd(), buf.as_mut_ptr() as *mut libc::c_void, buf.len(), libc::MSG_PEEK)
+    };
+    if peeked < 0 {
+        Err(std::io::Error::last_os_error())
+    } else {
+        Ok(peeked as usize)
+    }
+}
+
+#[cfg(unix)]
+impl AsRawFd for Stream {
+    fn as_raw_fd(&self) -> RawFd {
+        match self {
+            Stream::Tcp(stream) => stream.as_raw_fd(),
+            Stream::Unix(stream) => stream.as_raw_fd(),
+            #[cfg(feature = "tls")]
+            Stream::Tls(stream) => stream.socket().as_raw_fd(),
+        }
+    }
+}
+
+impl From<TcpStream> for Stream {
+    fn from(stream: TcpStream) -> Self {
+        Stream::Tcp(stream)
+    }
+}
+
+#[cfg(unix)]
+impl From<UnixStream> for Stream {
+    fn from(stream: UnixStream) -> Self {
+        Stream::Unix(stream)
+    }
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self {
+            Stream::Tcp(stream) => stream.read(buf),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.read(buf),
+            #[cfg(feature = "tls")]
+            Stream::Tls(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            Stream::Tcp(stream) => stream.write(buf),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.write(buf),
+            #[cfg(feature = "tls")]
+            Stream::Tls(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => stream.flush(),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.flush(),
+            #[cfg(feature = "tls")]
+            Stream::Tls(stream) => stream.flush(),
+        }
+    }
+}
@@ -0,0 +1,90 @@
+use std::panic::{AssertUnwindSafe, catch_unwind};
+use std::sync::{Arc, Condvar, Mutex};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::thread;
+use std::thread::JoinHandle;
+
+/// A fixed number of worker threads, each doing the same work on whatever it takes off a bounded queue.
+/// If the queue is full then you get what you sent back, so that you can decide what to do with it instead.
+/// Dropping the pool lets the workers finish whatever is already queued, and waits for them to.
+pub struct ThreadPool<T> {
+    sender: Option<Sender<T>>,
+    // how many more items it can take right now, ie the idle workers plus the room left in the queue
+    room: Arc<(Mutex<usize>, Condvar)>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl<T> ThreadPool<T> where T: Send + 'static {
+    pub fn new<W>(size: usize, queue_size: usize, work: W) -> ThreadPool<T>
+        where W: Fn(T) + Send + Sync + 'static {
+        Self::with_when_free(size, queue_size, work, || {})
+    }
+
+    /// when_free is called each time a worker is done with an item, once the pool has room for another
+    pub fn with_when_free<W, F>(size: usize, queue_size: usize, work: W, when_free: F) -> ThreadPool<T>
+        where W: Fn(T) + Send + Sync + 'static, F: Fn() + Send + Sync + 'static {
+        let size = size.max(1);
+        let (sender, receiver) = channel();
+        let receiver = Arc::new(Mutex::new(receiver));
+        let room = Arc::new((Mutex::new(size + queue_size), Condvar::new()));
+        let work = Arc::new(work);
+        let when_free = Arc::new(when_free);
+        let workers = (0..size).map(|_| {
+            let receiver = receiver.clone();
+            let room = room.clone();
+            let work = work.clone();
+            let when_free = when_free.clone();
+            thread::spawn(move || Self::worker(receiver, room, work, when_free))
+        }).collect();
+        ThreadPool { sender: Some(sender), room, workers }
+    }
+
+    fn worker<W, F>(receiver: Arc<Mutex<Receiver<T>>>, room: Arc<(Mutex<usize>, Condvar)>, work: Arc<W>, when_free: Arc<F>)
+        where W: Fn(T), F: Fn() {
+        loop {
+            // the lock is only held while waiting for the next item, not while working on it
+            let next = receiver.lock().unwrap().recv();
+            match next {
+                Ok(item) => {
+                    // if the work panics, the worker carries on, otherwise the pool would shrink
+                    let _ = catch_unwind(AssertUnwindSafe(|| work(item)));
+                    // there is room for another before anyone is told, so that whoever tries again gets in
+                    let (free, freed) = &*room;
+                    *free.lock().unwrap() += 1;
+                    freed.notify_one();
+                    when_free();
+                }
+                // the pool has gone away
+                Err(_) => return,
+            }
+        }
+    }
+
+    // gives the item back if every worker is busy and the queue is full
+    pub fn try_send(&self, item: T) -> Result<(), T> {
+        let mut free = self.room.0.lock().unwrap();
+        if *free == 0 {
+            return Err(item);
+        }
+        *free -= 1;
+        self.sender.as_ref().expect("the sender is only taken when the pool is dropped").send(item).map_err(|e| e.0)
+    }
+
+    // waits for room in the queue
+    pub fn send(&self, item: T) -> Result<(), T> {
+        let (free, freed) = &*self.room;
+        let mut free = freed.wait_while(free.lock().unwrap(), |free| *free == 0).unwrap();
+        *free -= 1;
+        self.sender.as_ref().expect("the sender is only taken when the pool is dropped").send(item).map_err(|e| e.0)
+    }
+}
+
+impl<T> Drop for ThreadPool<T> {
+    fn drop(&mut self) {
+        // once the queue is empty the workers find it closed, and stop
+        self.sender.take();
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
@@ -0,0 +1,411 @@
+use std::io::{Read, Write};
+use std::net::Shutdown;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use rustls::{ClientConfig, ClientConnection, RootCertStore, ServerConfig, ServerConnection};
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName};
+use rustls::pki_types::pem::PemObject;
+use crate::socket::Stream;
+
+/// What a server needs to terminate TLS, ie its certificate and private key.
+/// Cheap to clone, as every connection shares the one config.
+#[derive(Clone)]
+pub struct ServerTls {
+    config: Arc<ServerConfig>,
+}
+
+pub struct ServerTlsOptions {
+    // the protocols we will agree to over ALPN, most preferred first, eg h2 and http/1.1
+    pub alpn_protocols: Vec<String>,
+}
+
+impl Default for ServerTlsOptions {
+    fn default() -> ServerTlsOptions {
+        ServerTlsOptions {
+            // HTTP/2 if the client speaks it, as long as the server has it turned on
+            alpn_protocols: vec!("h2".to_string(), "http/1.1".to_string()),
+        }
+    }
+}
+
+impl ServerTls {
+    /// The certificate chain starts with our own certificate, followed by any intermediates.
+    pub fn from_pem(certificate_chain: &[u8], private_key: &[u8], options: Option<ServerTlsOptions>) -> Result<ServerTls, String> {
+        let options = options.unwrap_or_default();
+        let certificates = certificates(certificate_chain)?;
+        let private_key = PrivateKeyDer::from_pem_slice(private_key)
+            .map_err(|e| format!("Invalid private key: {}", e))?;
+        let mut config = ServerConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .map_err(|e| e.to_string())?
+            .with_no_client_auth()
+            .with_single_cert(certificates, private_key)
+            .map_err(|e| format!("Invalid certificate or private key: {}", e))?;
+        config.alpn_protocols = options.alpn_protocols.iter().map(|protocol| protocol.as_bytes().to_vec()).collect();
+        Ok(ServerTls::from_config(Arc::new(config)))
+    }
+
+    pub fn from_pem_files<P: AsRef<Path>>(certificate_chain: P, private_key: P, options: Option<ServerTlsOptions>) -> Result<ServerTls, String> {
+        let certificate_chain = read_file(certificate_chain.as_ref())?;
+        let private_key = read_file(private_key.as_ref())?;
+        Self::from_pem(certificate_chain.as_slice(), private_key.as_slice(), options)
+    }
+
+    /// For anything the options do not cover, eg asking clients for their certificates.
+    pub fn from_config(config: Arc<ServerConfig>) -> ServerTls {
+        ServerTls { config }
+    }
+
+    // so that a client is not told we speak a protocol, eg h2 when HTTP/2 is not turned on
+    pub(crate) fn without_alpn_protocol(&self, protocol: &str) -> ServerTls {
+        let mut config = (*self.config).clone();
+        config.alpn_protocols.retain(|agreed| agreed != protocol.as_bytes());
+        ServerTls::from_config(Arc::new(config))
+    }
+
+    /// Does the handshake, which has to be done within the timeout.
+    pub fn accept(&self, stream: Stream, timeout: Duration) -> std::io::Result<Stream> {
+        let connection = ServerConnection::new(self.config.clone())
+            .map_err(std::io::Error::other)?;
+        TlsStream::handshake(TlsConnection::Server(connection), stream, timeout)
+    }
+}
+
+/// What a client needs to check who it is talking to.
+/// Cheap to clone, as every connection shares the one config.
+#[derive(Clone)]
+pub struct ClientTls {
+    config: Arc<ClientConfig>,
+    server_name: Option<String>,
+}
+
+pub struct ClientTlsOptions {
+    // trust the certificate authorities that browsers trust
+    pub well_known_roots: bool,
+    // and these ones too, in PEM, eg our own certificate authority or a self-signed certificate
+    pub root_certificates: Vec<u8>,
+    // the name to send in SNI and to check the certificate against, if not the host we connect to
+    pub server_name: Option<String>,
+    // the protocols we would like to speak over ALPN, most preferred first
+    pub alpn_protocols: Vec<String>,
+}
+
+impl Default for ClientTlsOptions {
+    fn default() -> ClientTlsOptions {
+        ClientTlsOptions {
+            well_known_roots: true,
+            root_certificates: vec!(),
+            server_name: None,
+            alpn_protocols: vec!(),
+        }
+    }
+}
+
+impl ClientTls {
+    pub fn new(options: Option<ClientTlsOptions>) -> Result<ClientTls, String> {
+        let options = options.unwrap_or_default();
+        let mut roots = RootCertStore::empty();
+        if options.well_known_roots {
+            roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+        }
+        for certificate in certificates(options.root_certificates.as_slice())? {
+            roots.add(certificate).map_err(|e| format!("Invalid root certificate: {}", e))?;
+        }
+        let mut config = ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .map_err(|e| e.to_string())?
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        config.alpn_protocols = options.alpn_protocols.iter().map(|protocol| protocol.as_bytes().to_vec()).collect();
+        Ok(ClientTls { config: Arc::new(config), server_name: options.server_name })
+    }
+
+    /// For anything the options do not cover, eg presenting a client certificate.
+    pub fn from_config(config: Arc<ClientConfig>, server_name: Option<String>) -> ClientTls {
+        ClientTls { config, server_name }
+    }
+
+    /// Does the handshake with the host, unless we were given another name to check the certificate against.
+    pub fn connect(&self, stream: Stream, host: &str, timeout: Duration) -> std::io::Result<Stream> {
+        let name = self.server_name.as_deref().unwrap_or(host);
+        // the certificate for an ip address is for the address without brackets
+        let name = name.trim_start_matches('[').trim_end_matches(']');
+        let server_name = ServerName::try_from(name.to_string())
+            .map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("Invalid server name {}", name)))?;
+        let connection = ClientConnection::new(self.config.clone(), server_name)
+            .map_err(std::io::Error::other)?;
+        TlsStream::handshake(TlsConnection::Client(connection), stream, timeout)
+    }
+}
+
+fn certificates(pem: &[u8]) -> Result<Vec<CertificateDer<'static>>, String> {
+    CertificateDer::pem_slice_iter(pem)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("Invalid certificate: {}", e))
+}
+
+fn read_file(path: &Path) -> Result<Vec<u8>, String> {
+    std::fs::read(path).map_err(|e| format!("Could not read {}: {}", path.display(), e))
+}
+
+enum TlsConnection {
+    Server(ServerConnection),
+    Client(ClientConnection),
+}
+
+/// The TLS state, along with the plaintext that has been peeked at but not read yet,
+/// and what has been read off the socket but not decrypted yet.
+struct TlsState {
+    connection: TlsConnection,
+    transport: Stream,
+    peeked: Vec<u8>,
+    ciphertext: Vec<u8>,
+    close_notify_sent: bool,
+}
+
+impl TlsState {
+    // WouldBlock if there is no plaintext until more has been read off the socket
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match &mut self.connection {
+            TlsConnection::Server(connection) => connection.reader().read(buf),
+            TlsConnection::Client(connection) => connection.reader().read(buf),
+        }
+    }
+
+    // decrypts what has been read off the socket until there is some plaintext,
+    // as rustls will only hold on to so much of it, and keeps the rest for when that has been read
+    fn receive(&mut self, closed: bool) -> std::io::Result<()> {
+        let result = match &mut self.connection {
+            TlsConnection::Server(connection) => receive(connection, &mut self.ciphertext, closed),
+            TlsConnection::Client(connection) => receive(connection, &mut self.ciphertext, closed),
+        };
+        // anything rustls has to say back, eg an alert about what was wrong with it
+        let _ = self.flush();
+        result
+    }
+
+    // rustls only buffers so much plaintext at a time, so we send each lot before giving it the rest,
+    // as whoever writes to us may not check how much of it was written
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let mut written = 0;
+        while written < buf.len() {
+            let accepted = match &mut self.connection {
+                TlsConnection::Server(connection) => connection.writer().write(&buf[written..])?,
+                TlsConnection::Client(connection) => connection.writer().write(&buf[written..])?,
+            };
+            self.flush()?;
+            if accepted == 0 {
+                break;
+            }
+            written += accepted;
+        }
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match &mut self.connection {
+            TlsConnection::Server(connection) => {
+                while connection.wants_write() {
+                    connection.write_tls(&mut self.transport)?;
+                }
+            }
+            TlsConnection::Client(connection) => {
+                while connection.wants_write() {
+                    connection.write_tls(&mut self.transport)?;
+                }
+            }
+        }
+        self.transport.flush()
+    }
+
+    // tells the other side we have finished writing, so it knows nothing was cut short
+    fn close_notify(&mut self) {
+        if self.close_notify_sent {
+            return;
+        }
+        self.close_notify_sent = true;
+        match &mut self.connection {
+            TlsConnection::Server(connection) => connection.send_close_notify(),
+            TlsConnection::Client(connection) => connection.send_close_notify(),
+        }
+        let _ = self.flush();
+    }
+
+    fn complete_handshake(&mut self) -> std::io::Result<()> {
+        match &mut self.connection {
+            TlsConnection::Server(connection) => {
+                while connection.is_handshaking() {
+                    connection.complete_io(&mut self.transport)?;
+                }
+            }
+            TlsConnection::Client(connection) => {
+                while connection.is_handshaking() {
+                    connection.complete_io(&mut self.transport)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn alpn_protocol(&self) -> Option<Vec<u8>> {
+        match &self.connection {
+            TlsConnection::Server(connection) => connection.alpn_protocol().map(|protocol| protocol.to_vec()),
+            TlsConnection::Client(connection) => connection.alpn_protocol().map(|protocol| protocol.to_vec()),
+        }
+    }
+}
+
+// the socket having closed is passed on, so that the next read says how it ended
+fn receive<Data>(connection: &mut rustls::ConnectionCommon<Data>, ciphertext: &mut Vec<u8>, closed: bool) -> std::io::Result<()> {
+    let mut unread = ciphertext.as_slice();
+    if closed && unread.is_empty() {
+        connection.read_tls(&mut unread)?;
+        connection.process_new_packets()
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+    }
+    while !unread.is_empty() {
+        connection.read_tls(&mut unread)?;
+        let io_state = connection.process_new_packets()
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+        if io_state.plaintext_bytes_to_read() > 0 {
+            break;
+        }
+    }
+    let consumed = ciphertext.len() - unread.len();
+    ciphertext.drain(..consumed);
+    Ok(())
+}
+
+// reads from the socket underneath without a lock, so that writes can carry on while we wait
+fn read_from(transport: &Stream, buf: &mut [u8]) -> std::io::Result<usize> {
+    match transport {
+        Stream::Tcp(stream) => (&*stream).read(buf),
+        #[cfg(unix)]
+        Stream::Unix(stream) => (&*stream).read(buf),
+        // we never put one TLS connection inside another
+        Stream::Tls(_) => Err(std::io::Error::new(std::io::ErrorKind::Unsupported, "TLS over TLS")),
+    }
+}
+
+/// A connection over TLS.
+/// Clones share the TLS state, which is only locked while it encrypts or decrypts,
+/// so one thread can wait to read while others write, eg the streams of an HTTP/2 connection.
+/// Timeouts and shutting down go straight to the socket underneath,
+/// so that the server can close a connection while a read on it is waiting.
+pub struct TlsStream {
+    state: Arc<Mutex<TlsState>>,
+    transport: Box<Stream>,
+}
+
+impl TlsStream {
+    fn handshake(connection: TlsConnection, transport: Stream, timeout: Duration) -> std::io::Result<Stream> {
+        let socket = transport.try_clone()?;
+        let mut state = TlsState { connection, transport, peeked: vec!(), ciphertext: vec!(), close_notify_sent: false };
+        socket.set_read_timeout(Some(timeout))?;
+        socket.set_write_timeout(Some(timeout))?;
+        state.complete_handshake()?;
+        socket.set_read_timeout(None)?;
+        socket.set_write_timeout(None)?;
+        Ok(Stream::Tls(TlsStream { state: Arc::new(Mutex::new(state)), transport: Box::new(socket) }))
+    }
+
+    pub(crate) fn socket(&self) -> &Stream {
+        &self.transport
+    }
+
+    pub(crate) fn try_clone(&self) -> std::io::Result<TlsStream> {
+        Ok(TlsStream { state: self.state.clone(), transport: Box::new(self.transport.try_clone()?) })
+    }
+
+    // says close_notify first if nothing else is writing, as the server stopping must not wait on a slow client
+    pub(crate) fn shutdown(&self, how: Shutdown) -> std::io::Result<()> {
+        if how != Shutdown::Read {
+            if let Ok(mut state) = self.state.try_lock() {
+                state.close_notify();
+            }
+        }
+        self.transport.shutdown(how)
+    }
+
+    // the protocol we agreed on over ALPN, if any
+    pub fn alpn_protocol(&self) -> Option<Vec<u8>> {
+        self.state.lock().unwrap().alpn_protocol()
+    }
+
+    // keeps hold of the plaintext until it is read, as the socket can only peek at what is encrypted
+    pub(crate) fn peek(&self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let already = self.state.lock().unwrap().peeked.len();
+        if already < buf.len() {
+            let mut more = vec![0; buf.len() - already];
+            match self.read_plaintext(&mut more) {
+                Ok(read) => self.state.lock().unwrap().peeked.extend_from_slice(&more[..read]),
+                Err(e) if already == 0 => return Err(e),
+                // we have something to show for it already
+                Err(_) => {}
+            }
+        }
+        let state = self.state.lock().unwrap();
+        let peeked = buf.len().min(state.peeked.len());
+        buf[..peeked].copy_from_slice(&state.peeked[..peeked]);
+        Ok(peeked)
+    }
+
+    fn read_plaintext(&self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let mut ciphertext = [0; 16384];
+        loop {
+            {
+                let mut state = self.state.lock().unwrap();
+                match state.read(buf) {
+                    Err(e) if e.kind() == std::io::ErrorKind::WouldBlock && !state.ciphertext.is_empty() => {
+                        state.receive(false)?;
+                        continue;
+                    }
+                    Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {}
+                    // https://www.rfc-editor.org/rfc/rfc8446#section-6.1
+                    // the socket closing without a close_notify is UnexpectedEof, as what was sent may have been cut short
+                    other => return other,
+                }
+            }
+            let read = read_from(&self.transport, &mut ciphertext)?;
+            let mut state = self.state.lock().unwrap();
+            state.ciphertext.extend_from_slice(&ciphertext[..read]);
+            state.receive(read == 0)?;
+        }
+    }
+}
+
+impl Read for TlsStream {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        {
+            let mut state = self.state.lock().unwrap();
+            if !state.peeked.is_empty() {
+                let read = buf.len().min(state.peeked.len());
+                buf[..read].copy_from_slice(&state.peeked[..read]);
+                state.peeked.drain(..read);
+                return Ok(read);
+            }
+        }
+        self.read_plaintext(buf)
+    }
+}
+// the last clone to go says close_notify, which is how the connection is closed once we are done with it
+impl Drop for TlsStream {
+    fn drop(&mut self) {
+        if Arc::strong_count(&self.state) == 1 {
+            if let Ok(mut state) = self.state.lock() {
+                state.close_notify();
+            }
+        }
+    }
+}
+
+impl Write for TlsStream {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.state.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.state.lock().unwrap().flush()
+    }
+}
@@ -0,0 +1,650 @@
+use std::collections::hash_map::RandomState;
+use std::fmt;
+use std::fs::File;
+use std::hash::{BuildHasher, Hasher};
+use std::io::{ErrorKind, Read, Write};
+use std::net::Shutdown;
+use std::sync::OnceLock;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use crate::headers::Headers;
+use crate::http_message::{Body, Request, Response};
+use crate::http_message::Body::BodyString;
+use crate::http_message::Method::GET;
+use crate::http_message::Status::{SwitchingProtocols, UpgradeRequired};
+use crate::server;
+use crate::socket::Stream;
+
+/*
+ https://www.rfc-editor.org/rfc/rfc6455#section-1.3
+ The server would append the string "258EAFA5-E914-47DA-95CA-C5AB0DC85B11" to the value of the
+ |Sec-WebSocket-Key| header field, take the SHA-1 hash of this, and base64-encode it.
+ */
+const GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+pub const CONTINUATION: u8 = 0x0;
+pub const TEXT: u8 = 0x1;
+pub const BINARY: u8 = 0x2;
+pub const CLOSE: u8 = 0x8;
+pub const PING: u8 = 0x9;
+pub const PONG: u8 = 0xA;
+
+// https://www.rfc-editor.org/rfc/rfc6455#section-7.4.1
+pub const NORMAL_CLOSURE: u16 = 1000;
+pub const GOING_AWAY: u16 = 1001;
+pub const PROTOCOL_ERROR: u16 = 1002;
+pub const UNSUPPORTED_DATA: u16 = 1003;
+pub const INVALID_PAYLOAD: u16 = 1007;
+pub const POLICY_VIOLATION: u16 = 1008;
+pub const MESSAGE_TOO_BIG: u16 = 1009;
+pub const INTERNAL_ERROR: u16 = 1011;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct WebSocketOptions {
+    // the biggest message we will put back together from its fragments
+    pub max_message_size: usize,
+    // messages we send that are bigger than this are split into fragments
+    pub max_frame_size: usize,
+    // how long to wait for the next frame, after which read gives a Timeout and can be called again, eg after a ping.
+    // None waits for as long as the connection is open, and on the server it holds on to a thread for as long
+    pub read_timeout: Option<Duration>,
+    // how long to wait for the other end to answer our close
+    pub close_timeout: Duration,
+    // the subprotocols we speak, the one we like best first
+    pub protocols: Vec<String>,
+}
+
+impl Default for WebSocketOptions {
+    fn default() -> WebSocketOptions {
+        WebSocketOptions {
+            max_message_size: 16777216,
+            max_frame_size: 65536,
+            read_timeout: Some(Duration::from_secs(60)),
+            close_timeout: Duration::from_secs(5),
+            protocols: vec!(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Message {
+    Text(String),
+    Binary(Vec<u8>),
+    // we have already answered it with a pong
+    Ping(Vec<u8>),
+    Pong(Vec<u8>),
+    // the code and reason the other end closed with, if it gave them, and we have already answered it
+    Close(Option<(u16, String)>),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum WebSocketError {
+    // the other end broke the rules, so we closed the connection with a code that says how
+    Protocol(u16, String),
+    // we were asked to send something that is not allowed, eg a ping of more than 125 bytes
+    Invalid(String),
+    // the close handshake is done, or the connection went away without one
+    Closed,
+    Timeout(String),
+    Io(String),
+}
+
+impl fmt::Display for WebSocketError {
+    fn fmt(&self, format: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            WebSocketError::Protocol(code, msg) => write!(format, "{} {}", code, msg),
+            WebSocketError::Invalid(msg)
+            | WebSocketError::Timeout(msg)
+            | WebSocketError::Io(msg) => format.write_str(msg),
+            WebSocketError::Closed => format.write_str("The WebSocket is closed"),
+        }
+    }
+}
+
+/// A connection that has been switched over from HTTP/1.1 to the WebSocket protocol, see RFC 6455.
+/// Messages are read and sent one at a time, on the thread of the handler or client that opened it,
+/// and pings are answered as they are read.
+pub struct WebSocket {
+    stream: Stream,
+    // read off the connection but not yet made into a frame
+    buffer: Vec<u8>,
+    // clients mask what they send, servers must not
+    is_client: bool,
+    options: WebSocketOptions,
+    // the subprotocol agreed on in the handshake
+    pub protocol: Option<String>,
+    // the opcode and payload so far of a message that is arriving in fragments
+    fragments: Option<(u8, Vec<u8>)>,
+    close_sent: bool,
+    close_received: bool,
+}
+
+struct Frame {
+    fin: bool,
+    opcode: u8,
+    payload: Vec<u8>,
+}
+
+impl WebSocket {
+    pub(crate) fn new(stream: Stream, buffer: Vec<u8>, is_client: bool, options: WebSocketOptions, protocol: Option<String>) -> WebSocket {
+        WebSocket { stream, buffer, is_client, options, protocol, fragments: None, close_sent: false, close_received: false }
+    }
+
+    /// Whether the request asks to open a WebSocket, so a handler can tell them apart from plain requests to the same place.
+    pub fn is_handshake(req: &Request) -> bool {
+        has_token(&req.headers, "Upgrade", "websocket")
+    }
+
+    /// Answers the opening handshake of a request to a handler, with 101 Switching Protocols,
+    /// and takes over its connection from the server.
+    /// The WebSocket keeps the thread the handler was called on until it is dropped, so that thread is not free for other connections.
+    /// If the handshake is not valid then it is answered with why instead, and there is no WebSocket.
+    pub fn accept<F>(req: &Request, options: Option<WebSocketOptions>, fun: F) -> Option<WebSocket>
+        where F: FnOnce(Response) + Sized {
+        let options = options.unwrap_or_default();
+        let (accept, protocol) = match handshake(req, &options.protocols) {
+            Ok(agreed) => agreed,
+            Err(response) => {
+                fun(response);
+                return None;
+            }
+        };
+        let mut headers = Headers::from(vec!(("Upgrade", "websocket"), ("Sec-WebSocket-Accept", accept.as_str())));
+        if let Some(protocol) = &protocol {
+            headers = headers.add(("Sec-WebSocket-Protocol", protocol.as_str()));
+        }
+        fun(Response::new(SwitchingProtocols, headers, Body::empty()));
+        let stream = server::upgraded_connection()?;
+        let _ = stream.set_read_timeout(options.read_timeout);
+        Some(WebSocket::new(stream, vec!(), false, options, protocol))
+    }
+
+    /// Reads the next message, putting it back together if it arrives in fragments.
+    /// If the other end breaks the rules then we close the connection and say why.
+    pub fn read(&mut self) -> Result<Message, WebSocketError> {
+        loop {
+            if self.close_received {
+                return Err(WebSocketError::Closed);
+            }
+            let frame = self.read_frame()?;
+            match frame.opcode {
+                PING => {
+                    if !self.close_sent {
+                        self.write_frame(PONG, true, &frame.payload)?;
+                    }
+                    return Ok(Message::Ping(frame.payload));
+                }
+                PONG => return Ok(Message::Pong(frame.payload)),
+                CLOSE => return self.closed_by_other_end(frame.payload),
+                TEXT | BINARY if self.fragments.is_some() => {
+                    return Err(self.fail(PROTOCOL_ERROR, "Expected the next fragment of the message before a new one"));
+                }
+                TEXT | BINARY if !frame.fin => self.fragments = Some((frame.opcode, frame.payload)),
+                TEXT | BINARY => return self.message(frame.opcode, frame.payload),
+                _ => {
+                    let (opcode, mut payload) = match self.fragments.take() {
+                        Some(fragments) => fragments,
+                        None => return Err(self.fail(PROTOCOL_ERROR, "There is no message for the fragment to continue")),
+                    };
+                    payload.extend_from_slice(&frame.payload);
+                    if frame.fin {
+                        return self.message(opcode, payload);
+                    }
+                    self.fragments = Some((opcode, payload));
+                }
+            }
+        }
+    }
+
+    /// Sends a message, in fragments of at most max_frame_size if it is a text or binary one.
+    /// Sending a close does not wait for it to be answered, see close for that.
+    pub fn send(&mut self, message: Message) -> Result<(), WebSocketError> {
+        match message {
+            Message::Text(text) => self.write_message(TEXT, text.as_bytes()),
+            Message::Binary(bytes) => self.write_message(BINARY, &bytes),
+            Message::Ping(payload) => self.write_control(PING, &payload),
+            Message::Pong(payload) => self.write_control(PONG, &payload),
+            Message::Close(None) => {
+                self.close_sent = true;
+                self.write_frame(CLOSE, true, &[])
+            }
+            Message::Close(Some((code, reason))) => {
+                if !is_valid_close_code(code) {
+                    return Err(WebSocketError::Invalid(format!("{} is not a close code that can be sent", code)));
+                }
+                let payload = close_payload(code, reason.as_str());
+                self.write_control(CLOSE, &payload)?;
+                self.close_sent = true;
+                Ok(())
+            }
+        }
+    }
+
+    /// Closes the WebSocket, waiting up to the close timeout for the other end to answer,
+    /// skipping over any messages it sent in the meantime, and then closes the connection.
+    pub fn close(&mut self, code: u16, reason: &str) -> Result<(), WebSocketError> {
+        if !self.close_sent {
+            self.send(Message::Close(Some((code, reason.to_string()))))?;
+        }
+        let give_up_at = Instant::now() + self.options.close_timeout;
+        let _ = self.stream.set_read_timeout(Some(self.options.close_timeout));
+        let answered = loop {
+            if self.close_received || Instant::now() >= give_up_at {
+                break self.close_received;
+            }
+            match self.read() {
+                Ok(_) => continue,
+                Err(WebSocketError::Protocol(_, _)) => break true,
+                Err(_) => break false,
+            }
+        };
+        let _ = self.stream.shutdown(Shutdown::Both);
+        if answered {
+            Ok(())
+        } else {
+            Err(WebSocketError::Timeout(format!("Timed out after {}ms waiting for the close to be answered", self.options.close_timeout.as_millis())))
+        }
+    }
+
+    fn message(&mut self, opcode: u8, payload: Vec<u8>) -> Result<Message, WebSocketError> {
+        if opcode == BINARY {
+            return Ok(Message::Binary(payload));
+        }
+        match String::from_utf8(payload) {
+            Ok(text) => Ok(Message::Text(text)),
+            Err(_) => Err(self.fail(INVALID_PAYLOAD, "Text message is not valid utf-8")),
+        }
+    }
+
+    /*
+     https://www.rfc-editor.org/rfc/rfc6455#section-5.5.1
+     If an endpoint receives a Close frame and did not previously send a Close frame, the endpoint
+     MUST send a Close frame in response. (When sending a Close frame in response, the endpoint
+     typically echos the status code it received.)
+     https://www.rfc-editor.org/rfc/rfc6455#section-7.1.1
+     The underlying TCP connection, in most normal cases, SHOULD be closed first by the server.
+     */
+    fn closed_by_other_end(&mut self, payload: Vec<u8>) -> Result<Message, WebSocketError> {
+        let close = match payload.len() {
+            0 => None,
+            1 => return Err(self.fail(PROTOCOL_ERROR, "Close frame has a code that is only one byte")),
+            _ => {
+                let code = u16::from_be_bytes([payload[0], payload[1]]);
+                if !is_valid_close_code(code) {
+                    return Err(self.fail(PROTOCOL_ERROR, format!("Close frame has invalid code {}", code).as_str()));
+                }
+                match String::from_utf8(payload[2..].to_vec()) {
+                    Ok(reason) => Some((code, reason)),
+                    Err(_) => return Err(self.fail(INVALID_PAYLOAD, "Close reason is not valid utf-8")),
+                }
+            }
+        };
+        self.close_received = true;
+        if !self.close_sent {
+            self.close_sent = true;
+            let echo = close.as_ref().map(|(code, _)| code.to_be_bytes().to_vec()).unwrap_or(vec!());
+            let _ = self.write_frame(CLOSE, true, &echo);
+        }
+        if !self.is_client {
+            let _ = self.stream.shutdown(Shutdown::Both);
+        }
+        Ok(Message::Close(close))
+    }
+
+    // closes the connection with a code that says what went wrong, as long as we have not closed it already
+    fn fail(&mut self, code: u16, reason: &str) -> WebSocketError {
+        if !self.close_sent {
+            self.close_sent = true;
+            let _ = self.write_frame(CLOSE, true, &close_payload(code, reason));
+        }
+        self.close_received = true;
+        let _ = self.stream.shutdown(Shutdown::Both);
+        WebSocketError::Protocol(code, reason.to_string())
+    }
+
+    /*
+     https://www.rfc-editor.org/rfc/rfc6455#section-5.2
+      0                   1                   2                   3
+      0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1
+     +-+-+-+-+-------+-+-------------+-------------------------------+
+     |F|R|R|R| opcode|M| Payload len |    Extended payload length    |
+     |I|S|S|S|  (4)  |A|     (7)     |             (16/64)           |
+     |N|V|V|V|       |S|             |   (if payload len==126/127)   |
+     | |1|2|3|       |K|             |                               |
+     +-+-+-+-+-------+-+-------------+ - - - - - - - - - - - - - - - +
+     |     Extended payload length continued, if payload len == 127  |
+     + - - - - - - - - - - - - - - - +-------------------------------+
+     |                               |Masking-key, if MASK set to 1  |
+     +-------------------------------+-------------------------------+
+     | Masking-key (continued)       |          Payload Data         |
+     +-------------------------------- - - - - - - - - - - - - - - - +
+     */
+    fn read_frame(&mut self) -> Result<Frame, WebSocketError> {
+        self.fill(2)?;
+        let (first, second) = (self.buffer[0], self.buffer[1]);
+        let fin = first & 0x80 != 0;
+        let opcode = first & 0x0F;
+        let masked = second & 0x80 != 0;
+        // we agree on no extensions, so none of the reserved bits mean anything
+        if first & 0x70 != 0 {
+            return Err(self.fail(PROTOCOL_ERROR, "Reserved bits are set without an extension that uses them"));
+        }
+        if ![CONTINUATION, TEXT, BINARY, CLOSE, PING, PONG].contains(&opcode) {
+            return Err(self.fail(PROTOCOL_ERROR, format!("Unknown opcode {}", opcode).as_str()));
+        }
+        let is_control = opcode & 0x8 != 0;
+        let (length, mut header_length) = match second & 0x7F {
+            126 => {
+                self.fill(4)?;
+                (u16::from_be_bytes([self.buffer[2], self.buffer[3]]) as u64, 4)
+            }
+            127 => {
+                self.fill(10)?;
+                let mut length = [0; 8];
+                length.copy_from_slice(&self.buffer[2..10]);
+                (u64::from_be_bytes(length), 10)
+            }
+            length => (length as u64, 2),
+        };
+        /*
+         https://www.rfc-editor.org/rfc/rfc6455#section-5.5
+         All control frames MUST have a payload length of 125 bytes or less and MUST NOT be fragmented.
+         https://www.rfc-editor.org/rfc/rfc6455#section-5.1
+         A server MUST close the connection upon receiving a frame that is not masked.
+         A client MUST close a connection if it detects a masked frame.
+         */
+        if is_control && (!fin || length > 125) {
+            return Err(self.fail(PROTOCOL_ERROR, "Control frames must not be fragmented or have more than 125 bytes"));
+        }
+        if masked == self.is_client {
+            let reason = if self.is_client { "Frames from the server must not be masked" } else { "Frames from the client must be masked" };
+            return Err(self.fail(PROTOCOL_ERROR, reason));
+        }
+        if length & (1 << 63) != 0 {
+            return Err(self.fail(PROTOCOL_ERROR, "The most significant bit of the payload length must be 0"));
+        }
+        let so_far = if is_control { 0 } else { self.fragments.as_ref().map(|(_, payload)| payload.len()).unwrap_or(0) };
+        if length > (self.options.max_message_size - so_far.min(self.options.max_message_size)) as u64 {
+            let reason = format!("Message is bigger than {} bytes", self.options.max_message_size);
+            return Err(self.fail(MESSAGE_TOO_BIG, reason.as_str()));
+        }
+        let length = length as usize;
+        let mut mask = [0; 4];
+        if masked {
+            self.fill(header_length + 4)?;
+            mask.copy_from_slice(&self.buffer[header_length..header_length + 4]);
+            header_length += 4;
+        }
+        self.fill(header_length + length)?;
+        let mut payload: Vec<u8> = self.buffer.drain(..header_length + length).skip(header_length).collect();
+        if masked {
+            apply_mask(&mut payload, mask);
+        }
+        Ok(Frame { fin, opcode, payload })
+    }
+
+    // reads until there are at least this many bytes in the buffer
+    fn fill(&mut self, wanted: usize) -> Result<(), WebSocketError> {
+        let mut reader = [0; 16384];
+        while self.buffer.len() < wanted {
+            match self.stream.read(&mut reader) {
+                Ok(0) => {
+                    self.close_received = true;
+                    return Err(WebSocketError::Closed);
+                }
+                Ok(read) => self.buffer.extend_from_slice(&reader[..read]),
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) if e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::TimedOut => {
+                    return Err(WebSocketError::Timeout("Timed out waiting for the next frame".to_string()));
+                }
+                Err(e) => {
+                    self.close_received = true;
+                    return Err(WebSocketError::Io(e.to_string()));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn write_message(&mut self, opcode: u8, payload: &[u8]) -> Result<(), WebSocketError> {
+        if payload.is_empty() {
+            return self.write_frame(opcode, true, payload);
+        }
+        let fragments = payload.chunks(self.options.max_frame_size.max(1)).collect::<Vec<&[u8]>>();
+        let last = fragments.len() - 1;
+        for (i, fragment) in fragments.into_iter().enumerate() {
+            let opcode = if i == 0 { opcode } else { CONTINUATION };
+            self.write_frame(opcode, i == last, fragment)?;
+        }
+        Ok(())
+    }
+
+    fn write_control(&mut self, opcode: u8, payload: &[u8]) -> Result<(), WebSocketError> {
+        if payload.len() > 125 {
+            return Err(WebSocketError::Invalid("Control frames can have at most 125 bytes".to_string()));
+        }
+        self.write_frame(opcode, true, payload)
+    }
+
+    /*
+     https://www.rfc-editor.org/rfc/rfc6455#section-5.5.1
+     After sending a Close frame, the endpoint MUST NOT send any further data frames.
+     */
+    fn write_frame(&mut self, opcode: u8, fin: bool, payload: &[u8]) -> Result<(), WebSocketError> {
+        if self.close_sent && opcode != CLOSE {
+            return Err(WebSocketError::Closed);
+        }
+        let mut frame = Vec::with_capacity(payload.len() + 14);
+        frame.push(if fin { 0x80 } else { 0 } | opcode);
+        let mask_bit = if self.is_client { 0x80 } else { 0 };
+        match payload.len() {
+            length if length < 126 => frame.push(mask_bit | length as u8),
+            length if length <= u16::MAX as usize => {
+                frame.push(mask_bit | 126);
+                frame.extend_from_slice(&(length as u16).to_be_bytes());
+            }
+            length => {
+                frame.push(mask_bit | 127);
+                frame.extend_from_slice(&(length as u64).to_be_bytes());
+            }
+        }
+        let start = frame.len();
+        if self.is_client {
+            let mask = random_bytes();
+            let mask = [mask[0], mask[1], mask[2], mask[3]];
+            frame.extend_from_slice(&mask);
+            frame.extend_from_slice(payload);
+            apply_mask(&mut frame[start + 4..], mask);
+        } else {
+            frame.extend_from_slice(payload);
+        }
+        self.stream.write_all(&frame)
+            .and_then(|_| self.stream.flush())
+            .map_err(|e| WebSocketError::Io(e.to_string()))
+    }
+}
+
+/*
+ https://www.rfc-editor.org/rfc/rfc6455#section-4.2.1
+ The client's opening handshake consists of the following parts. If the server, while reading the
+ handshake, finds that the client did not send a handshake that matches the description below,
+ the server MUST stop processing the client's handshake and return an HTTP response with an
+ appropriate error code (such as 400 Bad Request).
+ https://www.rfc-editor.org/rfc/rfc6455#section-4.4
+ If the server doesn't support the requested version, it MUST respond with a |Sec-WebSocket-Version|
+ header field containing all versions it is willing to use.
+ */
+fn handshake(req: &Request, protocols: &[String]) -> Result<(String, Option<String>), Response<'static>> {
+    let bad_request = |reason: &'static str| Response::bad_request(Headers::empty(), BodyString(reason));
+    if req.method != GET {
+        return Err(Response::method_not_allowed(Headers::from(vec!(("Allow", "GET"))), Body::empty()));
+    }
+    if req.version.major != 1 || req.version.minor < 1 {
+        return Err(bad_request("WebSockets are opened over HTTP/1.1"));
+    }
+    if !req.headers.has("Host") {
+        return Err(bad_request("Missing Host header"));
+    }
+    if !has_token(&req.headers, "Upgrade", "websocket") || !has_token(&req.headers, "Connection", "upgrade") {
+        return Err(bad_request("Expected Upgrade: websocket and Connection: Upgrade"));
+    }
+    if req.headers.get("Sec-WebSocket-Version").map(|version| version.trim() != "13").unwrap_or(true) {
+        return Err(Response::new(UpgradeRequired, Headers::from(vec!(("Sec-WebSocket-Version", "13"))), Body::empty()));
+    }
+    let key = match req.headers.get("Sec-WebSocket-Key") {
+        Some(key) if base64_decode(key.trim()).map(|nonce| nonce.len() == 16).unwrap_or(false) => key,
+        _ => return Err(bad_request("Sec-WebSocket-Key must be 16 bytes in base64")),
+    };
+    // we pick the one we like best out of those the client offers
+    let offered = req.headers.get("Sec-WebSocket-Protocol").unwrap_or("".to_string());
+    let offered = offered.split(',').map(|protocol| protocol.trim()).collect::<Vec<&str>>();
+    let protocol = protocols.iter().find(|protocol| offered.contains(&protocol.as_str())).cloned();
+    Ok((accept_key(key.trim()), protocol))
+}
+
+// eg Connection: keep-alive, Upgrade
+pub(crate) fn has_token(headers: &Headers, name: &str, token: &str) -> bool {
+    headers.get(name)
+        .map(|value| value.split(',').any(|part| part.trim().eq_ignore_ascii_case(token)))
+        .unwrap_or(false)
+}
+
+/// What the server answers a Sec-WebSocket-Key with, to show it understood the handshake.
+pub fn accept_key(key: &str) -> String {
+    base64_encode(&sha1(format!("{}{}", key, GUID).as_bytes()))
+}
+
+/// A Sec-WebSocket-Key for a client to open a WebSocket with.
+pub(crate) fn new_key() -> String {
+    let (first, second) = (random_bytes(), random_bytes());
+    base64_encode(&[first, second].concat())
+}
+
+static URANDOM: OnceLock<Option<File>> = OnceLock::new();
+
+/*
+ https://www.rfc-editor.org/rfc/rfc6455#section-5.3
+ The masking key needs to be unpredictable; thus, the masking key MUST be derived from a strong
+ source of entropy.
+ So we read the operating system's own, and only where there is no /dev/urandom, eg on windows,
+ fall back on the std hasher, which is keyed randomly each time.
+ */
+fn random_bytes() -> [u8; 8] {
+    let mut bytes = [0; 8];
+    if let Some(mut urandom) = URANDOM.get_or_init(|| File::open("/dev/urandom").ok()).as_ref() {
+        if urandom.read_exact(&mut bytes).is_ok() {
+            return bytes;
+        }
+    }
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u128(SystemTime::now().duration_since(UNIX_EPOCH).map(|now| now.as_nanos()).unwrap_or(0));
+    hasher.finish().to_be_bytes()
+}
+
+fn apply_mask(payload: &mut [u8], mask: [u8; 4]) {
+    for (i, byte) in payload.iter_mut().enumerate() {
+        *byte ^= mask[i % 4];
+    }
+}
+
+// the reason is cut short so that the frame stays within the 125 bytes of a control frame
+fn close_payload(code: u16, reason: &str) -> Vec<u8> {
+    let mut end = reason.len().min(123);
+    while !reason.is_char_boundary(end) {
+        end -= 1;
+    }
+    [code.to_be_bytes().as_slice(), &reason.as_bytes()[..end]].concat()
+}
+
+/*
+ https://www.rfc-editor.org/rfc/rfc6455#section-7.4
+ 1004, 1005, 1006 and 1015 are reserved and MUST NOT be sent in a Close frame,
+ status codes in the range 0-999 are not used, and 1000-2999 are reserved for the protocol,
+ of which only these are defined, while 3000-4999 are for libraries, frameworks and applications.
+ */
+fn is_valid_close_code(code: u16) -> bool {
+    matches!(code, 1000..=1003 | 1007..=1011 | 3000..=4999)
+}
+
+// https://www.rfc-editor.org/rfc/rfc3174
+fn sha1(message: &[u8]) -> [u8; 20] {
+    let mut h: [u32; 5] = [0x67452301, 0xEFCDAB89, 0x98BADCFE, 0x10325476, 0xC3D2E1F0];
+    let mut padded = message.to_vec();
+    padded.push(0x80);
+    while padded.len() % 64 != 56 {
+        padded.push(0);
+    }
+    padded.extend_from_slice(&((message.len() as u64) * 8).to_be_bytes());
+
+    for block in padded.chunks(64) {
+        let mut w = [0u32; 80];
+        for i in 0..16 {
+            w[i] = u32::from_be_bytes([block[i * 4], block[i * 4 + 1], block[i * 4 + 2], block[i * 4 + 3]]);
+        }
+        for i in 16..80 {
+            w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+        }
+        let (mut a, mut b, mut c, mut d, mut e) = (h[0], h[1], h[2], h[3], h[4]);
+        for (i, word) in w.iter().enumerate() {
+            let (f, k) = match i {
+                0..=19 => ((b & c) | (!b & d), 0x5A827999),
+                20..=39 => (b ^ c ^ d, 0x6ED9EBA1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8F1BBCDC),
+                _ => (b ^ c ^ d, 0xCA62C1D6),
+            };
+            let temp = a.rotate_left(5).wrapping_add(f).wrapping_add(e).wrapping_add(k).wrapping_add(*word);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = temp;
+        }
+        h[0] = h[0].wrapping_add(a);
+        h[1] = h[1].wrapping_add(b);
+        h[2] = h[2].wrapping_add(c);
+        h[3] = h[3].wrapping_add(d);
+        h[4] = h[4].wrapping_add(e);
+    }
+    let mut digest = [0; 20];
+    for (i, word) in h.iter().enumerate() {
+        digest[i * 4..i * 4 + 4].copy_from_slice(&word.to_be_bytes());
+    }
+    digest
+}
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+// https://www.rfc-editor.org/rfc/rfc4648#section-4
+fn base64_encode(bytes: &[u8]) -> String {
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for group in bytes.chunks(3) {
+        let n = (group[0] as u32) << 16 | (*group.get(1).unwrap_or(&0) as u32) << 8 | *group.get(2).unwrap_or(&0) as u32;
+        for i in 0..4 {
+            if i <= group.len() {
+                encoded.push(BASE64[(n >> (18 - 6 * i) & 0x3F) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+fn base64_decode(str: &str) -> Option<Vec<u8>> {
+    if !str.len().is_multiple_of(4) {
+        return None;
+    }
+    let mut decoded = Vec::with_capacity(str.len() / 4 * 3);
+    for group in str.as_bytes().chunks(4) {
+        let padding = group.iter().rev().take_while(|c| **c == b'=').count();
+        if padding > 2 {
+            return None;
+        }
+        let mut n = 0u32;
+        for c in &group[..4 - padding] {
+            n = n << 6 | BASE64.iter().position(|b| b == c)? as u32;
+        }
+        n <<= 6 * padding as u32;
+        decoded.extend_from_slice(&n.to_be_bytes()[1..4 - padding]);
+    }
+    Some(decoded)
+}
@@ -6,16 +6,20 @@ mod tests {
     use http4r_core::handler::Handler;
     use http4r_core::headers::Headers;
     use http4r_core::http_message::Body::BodyString;
-    use http4r_core::http_message::Method::{CONNECT, GET, HEAD, OPTIONS, TRACE};
-    use http4r_core::http_message::{body_string, Request, Response};
+    use http4r_core::http_message::Method::{CONNECT, DELETE, GET, HEAD, OPTIONS, PUT, TRACE};
+    use http4r_core::http_message::{body_string, Method, Request, Response};
     use http4r_core::server::Server;
     use http4r_core::uri::Uri;
-    use crate::common::PassThroughHandler;
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+    use http4r_core::http_message::Status;
+    use http4r_core::http_message::Status::{NotFound, NotModified};
+    use crate::common::{PassThroughHandler, Router, StatusHandler};
 
     #[test]
     fn method_semantics_ignore_body_of_get_head_options_connect_trace() {
         let mut server = Server::new(0);
-        server.start(|| { Ok(PassThroughHandler {}) }, true);
+        server.start(|| { Ok(PassThroughHandler {}) }, true).unwrap();
 
         let mut client = WithContentLength::new(
             Client::new("127.0.0.1", server.port, None)
@@ -29,22 +33,131 @@ mod tests {
 
             client.handle(should_ignore_body, |response: Response| {
                 assert_eq!("OK", response.status.to_string());
-                assert_eq!("".to_string(), body_string(response.body));
+                assert_eq!("".to_string(), body_string(response.body).unwrap());
                 assert_eq!(format!("Content-Length: 0\r\nHost: 127.0.0.1:{}", server.port), response.headers.to_wire_string());
             });
         }
     }
 
-}
+    #[test]
+    fn put_and_extension_methods_can_have_a_body() {
+        let mut server = Server::new(0);
+        server.start(|| { Ok(PassThroughHandler {}) }, true).unwrap();
+        let mut client = Client::new("127.0.0.1", server.port, None);
+
+        let methods = vec!(PUT, DELETE, Method::Other("PROPFIND".to_string()), Method::Other("PURGE".to_string()));
+
+        for method in methods {
+            let with_body = Request::request(method, Uri::parse("/"), Headers::empty())
+                .with_body(BodyString("non empty body"));
+
+            client.handle(with_body, |response: Response| {
+                assert_eq!("OK", response.status.to_string());
+                assert_eq!("non empty body".to_string(), body_string(response.body).unwrap());
+            });
+        }
+    }
+
+    #[test]
+    fn extension_methods_without_a_length_have_no_body() {
+        let mut server = Server::new(0);
+        server.start(|| { Ok(PassThroughHandler {}) }, true).unwrap();
+        let mut client = Client::new("127.0.0.1", server.port, None);
+
+        client.handle(Request::request(Method::Other("MKCOL".to_string()), Uri::parse("/"), Headers::empty()), |response: Response| {
+            assert_eq!("OK", response.status.to_string());
+            assert_eq!("".to_string(), body_string(response.body).unwrap());
+        });
+    }
+
+
+    fn raw_response_to(port: u16, request: &str) -> String {
+        let mut stream = TcpStream::connect(format!("127.0.0.1:{}", port)).unwrap();
+        stream.write_all(request.as_bytes()).unwrap();
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).unwrap();
+        String::from_utf8_lossy(response.as_slice()).to_string()
+    }
+
+    /*
+    https://www.rfc-editor.org/rfc/rfc9110#section-9.3.2
+    The HEAD method is identical to GET except that the server MUST NOT send content in the response.
+     */
+    #[test]
+    fn head_responses_have_the_headers_of_a_get_but_no_body() {
+        let mut server = Server::new(0);
+        server.start(|| { Ok(Router {}) }, true).unwrap();
+
+        assert_eq!("HTTP/1.1 404 Not Found\r\nContent-Length: 9\r\nConnection: close\r\n\r\n",
+                   raw_response_to(server.port, "HEAD /not-found HTTP/1.1\r\nConnection: close\r\n\r\n"));
+
+        let mut client = Client::new("127.0.0.1", server.port, None);
+        client.handle(Request::request(HEAD, Uri::parse("/not-found"), Headers::empty()), |response: Response| {
+            assert_eq!(NotFound, response.status);
+            assert_eq!(Some("9".to_string()), response.headers.get("Content-Length"));
+            assert_eq!("".to_string(), body_string(response.body).unwrap());
+        });
+    }
+
+    #[test]
+    fn head_responses_have_the_content_length_of_the_compressed_body() {
+        let mut server = Server::new(0);
+        server.start(|| { Ok(Router {}) }, true).unwrap();
+
+        let get = raw_response_to(server.port, "GET /not-found HTTP/1.1\r\nAccept-Encoding: gzip\r\nConnection: close\r\n\r\n");
+        let head = raw_response_to(server.port, "HEAD /not-found HTTP/1.1\r\nAccept-Encoding: gzip\r\nConnection: close\r\n\r\n");
+
+        assert!(head.contains("Content-Encoding: gzip\r\n"));
+        assert!(head.ends_with("\r\n\r\n"));
+        assert!(get.starts_with(head.as_str()));
+    }
+
+    /*
+    https://www.rfc-editor.org/rfc/rfc9110#section-8.6
+    A server MUST NOT send a Content-Length header field in any response with a status code of
+    1xx (Informational) or 204 (No Content).
+    https://www.rfc-editor.org/rfc/rfc9110#section-6.4.1
+    All 1xx (Informational), 204 (No Content), and 304 (Not Modified) responses do not include content.
+     */
+    #[test]
+    fn no_content_and_not_modified_responses_have_no_body_or_framing() {
+        let mut server = Server::new(0);
+        server.start(|| { Ok(StatusHandler {}) }, true).unwrap();
+
+        assert_eq!("HTTP/1.1 204 No Content\r\nConnection: close\r\n\r\n",
+                   raw_response_to(server.port, "GET /204 HTTP/1.1\r\nConnection: close\r\n\r\n"));
+        assert_eq!("HTTP/1.1 204 No Content\r\nConnection: close\r\n\r\n",
+                   raw_response_to(server.port, "GET /204?stream HTTP/1.1\r\nConnection: close\r\n\r\n"));
+        assert_eq!("HTTP/1.1 304 Not Modified\r\nConnection: close\r\n\r\n",
+                   raw_response_to(server.port, "GET /304?stream HTTP/1.1\r\nConnection: close\r\n\r\n"));
 
-/*
+        let mut client = Client::new("127.0.0.1", server.port, None);
+        client.handle(Request::get(Uri::parse("/304"), Headers::empty()), |response: Response| {
+            assert_eq!(NotModified, response.status);
+            assert_eq!("".to_string(), body_string(response.body).unwrap());
+        });
+    }
 
+    // responds with the status in the path and a reason phrase of its own, eg. /204, and a body
+    struct CustomReasonHandler {}
 
+    impl Handler for CustomReasonHandler {
+        fn handle<F>(&mut self, req: Request, fun: F) -> () where F: FnOnce(Response) -> () + Sized {
+            let code = req.uri.path.trim_start_matches("/").parse().unwrap();
+            fun(Response::new(Status::custom(code, "Just So").unwrap(), Headers::empty(), BodyString("a body")));
+        }
+    }
 
-todo()
-Any response to a HEAD request and any response with a 1xx
-       (Informational), 204 (No Content), or 304 (Not Modified) status
-       code is always terminated by the first empty line after the
-       header fields, regardless of the header fields present in the
-       message, and thus cannot contain a message body.
- */
+    #[test]
+    fn registered_statuses_go_out_with_their_own_reason_phrase_and_keep_their_semantics() {
+        let mut server = Server::new(0);
+        server.start(|| { Ok(CustomReasonHandler {}) }, true).unwrap();
+
+        assert_eq!("HTTP/1.1 200 Just So\r\nContent-Length: 6\r\nConnection: close\r\n\r\na body",
+                   raw_response_to(server.port, "GET /200 HTTP/1.1\r\nConnection: close\r\n\r\n"));
+        assert_eq!("HTTP/1.1 204 Just So\r\nConnection: close\r\n\r\n",
+                   raw_response_to(server.port, "GET /204 HTTP/1.1\r\nConnection: close\r\n\r\n"));
+        assert_eq!("HTTP/1.1 304 Just So\r\nConnection: close\r\n\r\n",
+                   raw_response_to(server.port, "GET /304 HTTP/1.1\r\nConnection: close\r\n\r\n"));
+    }
+}
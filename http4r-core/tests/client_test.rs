@@ -2,12 +2,18 @@ mod common;
 
 #[cfg(test)]
 mod tests {
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::sync::mpsc::channel;
+    use std::thread;
     use http4r_core::client::{Client};
     use http4r_core::handler::Handler;
     use http4r_core::headers::Headers;
     use http4r_core::http_message::{body_string, Request};
     use http4r_core::http_message::Body::BodyString;
-    use http4r_core::http_message::Status::OK;
+    use http4r_core::http_message::Method::PUT;
+    use http4r_core::http_message::Status;
+    use http4r_core::http_message::Status::{BadRequest, Created, OK, ServiceUnavailable};
     use http4r_core::server::Server;
     use http4r_core::uri::Uri;
 
@@ -18,26 +24,26 @@ mod tests {
     #[test]
     fn setting_a_TE_header_will_ensure_it_is_set_in_Connection_header_also(){
         let mut server = Server::new(0);
-        server.start(|| { Ok(PassHeadersAsBody {}) }, true);
+        server.start(|| { Ok(PassHeadersAsBody {}) }, true).unwrap();
 
         let mut client = Client::new("127.0.0.1", server.port, None);
 
         // no Connection header
         let headers = Headers::from(vec!(("TE", "trailers"), ("Transfer-Encoding", "chunked")));
         client.handle(Request::post(Uri::parse("/"), headers, BodyString("Some body")), |res| {
-            assert_eq!(body_string(res.body), format!("TE: trailers\r\nTransfer-Encoding: chunked\r\nHost: 127.0.0.1:{}", server.port))
+            assert_eq!(body_string(res.body).unwrap(), format!("TE: trailers\r\nTransfer-Encoding: chunked\r\nHost: 127.0.0.1:{}", server.port))
         });
 
         // already set connection header but without TE
         let headers = Headers::from(vec!(("TE", "trailers"), ("Transfer-Encoding", "chunked"), ("Connection", "close")));
         client.handle(Request::post(Uri::parse("/"), headers, BodyString("Some body")), |res| {
-            assert_eq!(body_string(res.body), format!("TE: trailers\r\nTransfer-Encoding: chunked\r\nConnection: close, TE\r\nHost: 127.0.0.1:{}", server.port))
+            assert_eq!(body_string(res.body).unwrap(), format!("TE: trailers\r\nTransfer-Encoding: chunked\r\nConnection: close, TE\r\nHost: 127.0.0.1:{}", server.port))
         });
 
         // set connection header with TE keeps it intact
         let headers = Headers::from(vec!(("TE", "trailers"), ("Transfer-Encoding", "chunked"), ("Connection", "TE")));
         client.handle(Request::post(Uri::parse("/"), headers, BodyString("Some body")), |res| {
-            assert_eq!(body_string(res.body), format!("TE: trailers\r\nTransfer-Encoding: chunked\r\nConnection: TE\r\nHost: 127.0.0.1:{}", server.port))
+            assert_eq!(body_string(res.body).unwrap(), format!("TE: trailers\r\nTransfer-Encoding: chunked\r\nConnection: TE\r\nHost: 127.0.0.1:{}", server.port))
         })
     }
 
@@ -51,6 +57,123 @@ mod tests {
         });
     }
 
+    #[test]
+    fn reads_a_close_delimited_response_until_the_server_closes_the_connection() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let body = "hello from the nineties ".repeat(1000);
+        let to_send = body.clone();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let _read = stream.read(&mut [0; 4096]).unwrap();
+            stream.write_all("HTTP/1.0 200 OK\r\nContent-Type: text/plain\r\n\r\n".as_bytes()).unwrap();
+            stream.write_all(&to_send.as_bytes()[..10000]).unwrap();
+            stream.write_all(&to_send.as_bytes()[10000..]).unwrap();
+            // dropping the stream closes the connection, which ends the body
+        });
+
+        let mut client = Client::new("127.0.0.1", port, None);
+        client.handle(Request::get(Uri::parse("/"), Headers::empty()), |res| {
+            assert_eq!(res.status, OK);
+            assert_eq!(None, res.headers.get("Content-Length"));
+            assert_eq!(body, body_string(res.body).unwrap());
+        });
+    }
+
+    #[test]
+    fn does_not_wait_for_the_connection_to_close_if_the_status_cannot_have_a_body() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let (done, wait_until_done) = channel::<()>();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let _read = stream.read(&mut [0; 4096]).unwrap();
+            stream.write_all("HTTP/1.1 204 No Content\r\n\r\n".as_bytes()).unwrap();
+            // keep the connection open until the client has its response
+            let _ = wait_until_done.recv();
+        });
+
+        let mut client = Client::new("127.0.0.1", port, None);
+        client.handle(Request::get(Uri::parse("/"), Headers::empty()), |res| {
+            assert_eq!("".to_string(), body_string(res.body).unwrap());
+        });
+        done.send(()).unwrap();
+    }
+
+    #[test]
+    fn knows_the_status_of_any_response() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        thread::spawn(move || {
+            let statuses = vec!("201 Created", "503 Service Unavailable", "299 Mostly OK");
+            for (stream, status) in listener.incoming().zip(statuses) {
+                let mut stream = stream.unwrap();
+                let _read = stream.read(&mut [0; 4096]).unwrap();
+                stream.write_all(format!("HTTP/1.1 {}\r\nContent-Length: 0\r\n\r\n", status).as_bytes()).unwrap();
+            }
+        });
+
+        let mut client = Client::new("127.0.0.1", port, None);
+        client.handle(Request::get(Uri::parse("/"), Headers::empty()), |res| {
+            assert_eq!(Created, res.status);
+        });
+        client.handle(Request::get(Uri::parse("/"), Headers::empty()), |res| {
+            assert_eq!(ServiceUnavailable, res.status);
+        });
+        client.handle(Request::get(Uri::parse("/"), Headers::empty()), |res| {
+            assert_eq!(Status::Other(299, "Mostly OK".to_string()), res.status);
+        });
+    }
+
+    // closes the first connection without responding, then responds to every request after that
+    fn server_that_drops_the_first_connection() -> (u16, std::sync::mpsc::Receiver<usize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let (connections, count) = channel::<usize>();
+        thread::spawn(move || {
+            for (index, stream) in listener.incoming().enumerate() {
+                let mut stream = stream.unwrap();
+                let _read = stream.read(&mut [0; 4096]).unwrap();
+                if index > 0 {
+                    stream.write_all("HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhello".as_bytes()).unwrap();
+                }
+                connections.send(index + 1).unwrap();
+            }
+        });
+        (port, count)
+    }
+
+    /*
+    https://www.rfc-editor.org/rfc/rfc9110#section-9.2.2
+    Idempotent methods are distinguished because the request can be repeated automatically
+    if a communication failure occurs before the client is able to read the server's response.
+     */
+    #[test]
+    fn retries_an_idempotent_request_if_the_connection_closes_before_a_response() {
+        let (port, connections) = server_that_drops_the_first_connection();
+
+        let mut client = Client::new("127.0.0.1", port, None);
+        client.handle(Request::request(PUT, Uri::parse("/"), Headers::empty()).with_body(BodyString("hello")), |res| {
+            assert_eq!(OK, res.status);
+            assert_eq!("hello", body_string(res.body).unwrap());
+        });
+        assert_eq!(1, connections.recv().unwrap());
+        assert_eq!(2, connections.recv().unwrap());
+    }
+
+    #[test]
+    fn does_not_retry_a_request_that_is_not_idempotent() {
+        let (port, connections) = server_that_drops_the_first_connection();
+
+        let mut client = Client::new("127.0.0.1", port, None);
+        client.handle(Request::post(Uri::parse("/"), Headers::empty(), BodyString("hello")), |res| {
+            assert_eq!(BadRequest, res.status);
+            assert_eq!("Connection closed", body_string(res.body).unwrap());
+        });
+        assert_eq!(1, connections.recv().unwrap());
+        assert!(connections.try_recv().is_err());
+    }
+
     //todo() test that the client will do a chunked transfer encoding if we dont have content length
     // and we have a bodystream (ie we cant know content length ahead of time)
 
@@ -1,6 +1,7 @@
 
 #[cfg(test)]
 mod tests {
+    use std::io::{Read, Write};
     use http4r_core::codex::Codex;
     use http4r_core::http_message::CompressionAlgorithm::{BROTLI, DEFLATE, GZIP};
 
@@ -51,4 +52,43 @@ mod tests {
         assert_eq!(decode_writer.as_slice(), bytestring);
     }
 
-}
\ No newline at end of file
+    #[test]
+    fn encode_and_decode_as_we_read(){
+        let original_string = "hello world my baby boo".repeat(20000);
+
+        for compression in [GZIP, DEFLATE, BROTLI] {
+            let mut encoded = Vec::new();
+            Codex::encode_reader(original_string.as_bytes(), &compression).read_to_end(&mut encoded).unwrap();
+            assert!(encoded.len() < original_string.len());
+
+            let mut decoded = Vec::new();
+            Codex::decode_reader(encoded.as_slice(), &compression).read_to_end(&mut decoded).unwrap();
+            assert_eq!(original_string.as_bytes(), decoded.as_slice());
+        }
+    }
+
+    #[test]
+    fn encode_and_decode_as_we_write(){
+        let original_string = "hello world my baby boo".repeat(20000);
+
+        for compression in [GZIP, DEFLATE, BROTLI] {
+            let mut encoded = Vec::new();
+            {
+                let mut encoder = Codex::encode_writer(&mut encoded, &compression);
+                for piece in original_string.as_bytes().chunks(1000) {
+                    encoder.write_all(piece).unwrap();
+                }
+            }
+            assert!(encoded.len() < original_string.len());
+
+            let mut decoded = Vec::new();
+            {
+                let mut decoder = Codex::decode_writer(&mut decoded, &compression);
+                for piece in encoded.chunks(10) {
+                    decoder.write_all(piece).unwrap();
+                }
+            }
+            assert_eq!(original_string.as_bytes(), decoded.as_slice());
+        }
+    }
+}
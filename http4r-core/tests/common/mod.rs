@@ -1,13 +1,14 @@
 use std::collections::HashMap;
-use std::io::Write;
+use std::io::{Read, Write};
 use std::net::TcpStream;
-use std::time::Instant;
+use std::thread;
+use std::time::{Duration, Instant};
 
 use http4r_core::handler::Handler;
 use http4r_core::headers::Headers;
 use http4r_core::http_message;
-use http4r_core::http_message::{Body, read_message_from_wire, Request, Response};
-use http4r_core::http_message::Body::BodyString;
+use http4r_core::http_message::{Body, read_message_from_wire, Request, Response, Status, WireState};
+use http4r_core::http_message::Body::{BodyBytes, BodyStream, BodyString};
 
 
 pub struct Router {}
@@ -22,6 +23,33 @@ impl Handler for Router {
     }
 }
 
+// responds with the status in the path, eg. /204, and a body whether or not the status can have one
+pub struct StatusHandler {}
+
+impl Handler for StatusHandler {
+    fn handle<F>(&mut self, req: Request, fun: F) -> () where F: FnOnce(Response) -> () + Sized {
+        let status = Status::from(req.uri.path.trim_start_matches("/")).unwrap();
+        let response = if req.uri.query == Some("stream") {
+            Response::new(status, Headers::empty(), BodyStream(Box::new("a streamed body".as_bytes())))
+        } else {
+            Response::new(status, Headers::empty(), BodyString("a body"))
+        };
+        fun(response);
+    }
+}
+
+// takes its time before responding
+pub struct SlowHandler {
+    pub delay: Duration,
+}
+
+impl Handler for SlowHandler {
+    fn handle<F>(&mut self, _req: Request, fun: F) -> () where F: FnOnce(Response) -> () + Sized {
+        thread::sleep(self.delay);
+        fun(Response::ok(Headers::empty(), BodyString("finally")));
+    }
+}
+
 pub struct PassThroughHandler {}
 
 impl Handler for PassThroughHandler {
@@ -40,6 +68,42 @@ impl Handler for EchoBodyHandler {
     }
 }
 
+// echoes the body back as a stream of a known length, rather than chunked
+pub struct EchoBodyWithContentLengthHandler {}
+
+impl Handler for EchoBodyWithContentLengthHandler {
+    fn handle<F>(&mut self, req: Request, fun: F) -> () where F: FnOnce(Response) -> () + Sized {
+        let content_length = req.headers.get("Content-Length").unwrap();
+        let response = Response::ok(Headers::from(vec!(("Content-Length", content_length.as_str()))), req.body);
+        fun(response);
+    }
+}
+
+// reads all of the body before responding, so that it can tell the client if the body was bad
+pub struct ReadWholeBodyHandler {}
+
+impl Handler for ReadWholeBodyHandler {
+    fn handle<F>(&mut self, req: Request, fun: F) -> () where F: FnOnce(Response) -> () + Sized {
+        let mut whole = Vec::new();
+        let read = match req.body {
+            BodyString(str) => whole.write(str.as_bytes()),
+            BodyBytes(bytes) => whole.write(bytes),
+            BodyStream(mut reader) => reader.read_to_end(&mut whole),
+        };
+        match read {
+            Ok(_) => {
+                let body = std::str::from_utf8(whole.as_slice()).unwrap();
+                fun(Response::ok(Headers::empty(), BodyString(body)).with_trailers(req.trailers))
+            }
+            Err(e) => {
+                let message = e.to_string();
+                fun(Response::bad_request(Headers::empty(), BodyString(message.as_str())))
+            }
+        }
+    }
+}
+
+
 
 pub struct SetContentEncodingToNoneAndEchoHeaders {}
 
@@ -80,12 +144,13 @@ impl Handler for MalformedChunkedEncodingClient {
         stream.write("GET / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhello\r\nX\r\n\r\n".as_bytes()).unwrap();
 
         let mut reader: &mut [u8] = &mut [0; 4096];
+        let mut wire = WireState::new();
         let mut chunks_writer = Vec::with_capacity(1048576);
         let mut compress_writer = Vec::with_capacity(1048576);
         let mut start_line_writer = Vec::with_capacity(16384);
         let mut headers_writer = Vec::with_capacity(16384);
         let mut trailers_writer = Vec::with_capacity(16384);
-        let result = read_message_from_wire(stream.try_clone().unwrap(), &mut reader, &mut start_line_writer, &mut headers_writer, &mut chunks_writer, &mut compress_writer, &mut trailers_writer);
+        let result = read_message_from_wire(stream.try_clone().unwrap(), &mut reader, &mut wire, false, &mut start_line_writer, &mut headers_writer, &mut chunks_writer, &mut compress_writer, &mut trailers_writer);
 
         let response = match result {
             Ok(http_message::HttpMessage::Response(res)) => res,
@@ -96,6 +161,22 @@ impl Handler for MalformedChunkedEncodingClient {
     }
 }
 
+// reads the next response off a connection that is shared with other messages
+pub fn read_response<F>(stream: &TcpStream, reader: &mut [u8], wire: &mut WireState, fun: F)
+    where F: FnOnce(Response) -> () {
+    let mut chunks_writer = Vec::with_capacity(1048576);
+    let mut compress_writer = Vec::with_capacity(1048576);
+    let mut start_line_writer = Vec::with_capacity(16384);
+    let mut headers_writer = Vec::with_capacity(16384);
+    let mut trailers_writer = Vec::with_capacity(16384);
+    let result = read_message_from_wire(stream.try_clone().unwrap(), reader, wire, false, &mut start_line_writer, &mut headers_writer, &mut chunks_writer, &mut compress_writer, &mut trailers_writer);
+
+    match result {
+        Ok(http_message::HttpMessage::Response(res)) => fun(res),
+        _ => panic!("Expected a response")
+    }
+}
+
 pub struct LoggingHttpHandler<H, C, L> where H: Handler, C: Clock, L: Logger {
     pub log: Vec<String>,
     pub next_handler: H,
@@ -0,0 +1,127 @@
+mod common;
+
+#[cfg(test)]
+mod tests {
+    use std::io::{ErrorKind, Read};
+    use std::os::unix::net::UnixStream;
+    use std::thread;
+    use http4r_core::connection::Duplex;
+    use http4r_core::headers::Headers;
+    use http4r_core::http_message::{body_string, HttpMessage, read_message_from_wire, Request, RequestOptions, Response, WireState, write_message_to_wire};
+    use http4r_core::http_message::Body::{BodyStream, BodyString};
+    use http4r_core::http_message::Method::POST;
+    use http4r_core::http_message::Status::OK;
+    use http4r_core::uri::Uri;
+
+    fn read_message<'a, R: Read + 'a>(
+        connection: Duplex<R, &'a mut Vec<u8>>,
+        reader: &'a mut [u8],
+        wire: &'a mut WireState,
+        writers: &'a mut [Vec<u8>; 5],
+    ) -> HttpMessage<'a> {
+        let [start_line_writer, headers_writer, chunks_writer, compress_writer, trailers_writer] = writers;
+        read_message_from_wire(connection, reader, wire, false, start_line_writer, headers_writer, chunks_writer, compress_writer, trailers_writer).unwrap()
+    }
+
+    fn writers() -> [Vec<u8>; 5] {
+        [Vec::with_capacity(16384), Vec::with_capacity(16384), Vec::with_capacity(1048576), Vec::with_capacity(1048576), Vec::with_capacity(16384)]
+    }
+
+    #[test]
+    fn reads_a_request_from_captured_traffic() {
+        let captured = "POST /upload HTTP/1.1\r\nHost: example.com\r\nTransfer-Encoding: chunked\r\n\r\n6\r\nhello \r\n5\r\nworld\r\n0\r\nDigest: abc\r\n\r\n";
+        let mut nothing_written = Vec::new();
+        let mut writers = writers();
+        let mut wire = WireState::new();
+        let mut reader = [0; 4096];
+
+        let request = read_message(Duplex::new(captured.as_bytes(), &mut nothing_written), &mut reader, &mut wire, &mut writers).to_req();
+
+        assert_eq!(POST, request.method);
+        assert_eq!("/upload", request.uri.to_string());
+        assert_eq!(Some("example.com".to_string()), request.headers.get("Host"));
+        let trailers = request.trailers.clone();
+        assert_eq!("hello world", body_string(request.body).unwrap());
+        assert_eq!(Some("abc".to_string()), trailers.get().get("Digest"));
+    }
+
+    #[test]
+    fn a_body_that_is_cut_short_is_an_error_rather_than_all_of_it() {
+        let captured = "POST / HTTP/1.1\r\nContent-Length: 100\r\n\r\nhello";
+        let mut nothing_written = Vec::new();
+        let mut writers = writers();
+        let mut wire = WireState::new();
+        let mut reader = [0; 4096];
+
+        let request = read_message(Duplex::new(captured.as_bytes(), &mut nothing_written), &mut reader, &mut wire, &mut writers).to_req();
+
+        let mut body = Vec::new();
+        let read = match request.body {
+            BodyStream(mut reader) => reader.read_to_end(&mut body),
+            _ => panic!("expected the body to be streamed"),
+        };
+        assert_eq!(ErrorKind::UnexpectedEof, read.unwrap_err().kind());
+    }
+
+    #[test]
+    fn tells_the_writing_half_to_continue() {
+        // the client waits to be told to continue before it sends the body
+        let head = "POST / HTTP/1.1\r\nContent-Length: 5\r\nExpect: 100-continue\r\n\r\n";
+        let body = "hello";
+        let mut written = Vec::new();
+        {
+            let mut writers = writers();
+            let mut wire = WireState::new();
+            let mut reader = [0; 4096];
+            let request = read_message(Duplex::new(head.as_bytes().chain(body.as_bytes()), &mut written), &mut reader, &mut wire, &mut writers).to_req();
+            assert_eq!("hello", body_string(request.body).unwrap());
+        }
+
+        assert_eq!("HTTP/1.1 100 Continue\r\n\r\n", String::from_utf8(written).unwrap());
+    }
+
+    #[test]
+    fn writes_a_message_into_a_buffer() {
+        let mut written: Vec<u8> = Vec::new();
+
+        let response = Response::ok(Headers::from(vec!(("Content-Type", "text/plain"))), BodyString("hello"));
+        write_message_to_wire(&mut written, HttpMessage::Response(response), RequestOptions::default()).unwrap();
+
+        assert_eq!("HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\nContent-Length: 5\r\n\r\nhello", String::from_utf8(written).unwrap());
+    }
+
+    #[test]
+    fn what_is_written_can_be_read_back() {
+        let mut written: Vec<u8> = Vec::new();
+        let request = Request::post(Uri::parse("/path?query=true"), Headers::from(vec!(("Transfer-Encoding", "chunked"))), BodyString("round trip"));
+        write_message_to_wire(&mut written, HttpMessage::Request(request), RequestOptions::default()).unwrap();
+
+        let mut nothing_written = Vec::new();
+        let mut writers = writers();
+        let mut wire = WireState::new();
+        let mut reader = [0; 4096];
+        let request = read_message(Duplex::new(written.as_slice(), &mut nothing_written), &mut reader, &mut wire, &mut writers).to_req();
+
+        assert_eq!("/path?query=true", request.uri.to_string());
+        assert_eq!("round trip", body_string(request.body).unwrap());
+    }
+
+    #[test]
+    fn works_over_a_unix_stream() {
+        let (mut client, server) = UnixStream::pair().unwrap();
+        let writing = thread::spawn(move || {
+            let response = Response::ok(Headers::empty(), BodyString("over a pipe"));
+            write_message_to_wire(&mut client, HttpMessage::Response(response), RequestOptions::default()).unwrap();
+        });
+
+        let mut writers = writers();
+        let [start_line_writer, headers_writer, chunks_writer, compress_writer, trailers_writer] = &mut writers;
+        let mut reader = [0; 4096];
+        let mut wire = WireState::new();
+        let response = read_message_from_wire(server, &mut reader, &mut wire, false, start_line_writer, headers_writer, chunks_writer, compress_writer, trailers_writer)
+            .unwrap().to_res();
+        assert_eq!(OK, response.status);
+        assert_eq!("over a pipe", body_string(response.body).unwrap());
+        writing.join().unwrap();
+    }
+}
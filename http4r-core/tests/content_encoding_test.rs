@@ -2,20 +2,20 @@ mod common;
 
 #[cfg(test)]
 mod tests {
-    use http4r_core::client::Client;
+    use http4r_core::client::{Client, ClientOptions};
     use http4r_core::handler::Handler;
     use http4r_core::headers::Headers;
     use http4r_core::http_message::Body::{BodyStream, BodyString};
     use http4r_core::http_message::{body_string, Request};
     use http4r_core::http_message::Status::OK;
-    use http4r_core::server::Server;
+    use http4r_core::server::{Server, ServerOptions};
     use http4r_core::uri::Uri;
-    use crate::common::{EchoBodyHandler, PassHeadersAsBody, PassThroughHandler, SetContentEncodingToNoneAndEchoHeaders};
+    use crate::common::{EchoBodyHandler, EchoBodyWithContentLengthHandler, PassHeadersAsBody, PassThroughHandler, SetContentEncodingToNoneAndEchoHeaders};
 
     #[test]
     fn encode_body_using_accept_encoding_prefers_brotli() {
         let mut server = Server::new(0);
-        server.start(|| { Ok(PassThroughHandler {}) }, true);
+        server.start(|| { Ok(PassThroughHandler {}) }, true).unwrap();
 
         let mut client = Client::new("127.0.0.1", server.port, None);
         let headers = Headers::from(vec!(("Accept-Encoding", "gzip, deflate, br")));
@@ -28,7 +28,7 @@ mod tests {
 
         client.handle(request, |res| {
             assert_eq!(res.status, OK);
-            assert_eq!(body, body_string(res.body));
+            assert_eq!(body, body_string(res.body).unwrap());
             assert_eq!(res.headers.vec, vec!(
                 ("Accept-Encoding".to_string(), "gzip, deflate, br".to_string()),
                 ("Host".to_string(), format!("127.0.0.1:{}", server.port)),
@@ -41,7 +41,7 @@ mod tests {
     #[test]
     fn client_can_send_a_compressed_message() {
         let mut server = Server::new(0);
-        server.start(|| { Ok(PassThroughHandler {}) }, true);
+        server.start(|| { Ok(PassThroughHandler {}) }, true).unwrap();
 
         let mut client = Client::new("127.0.0.1", server.port, None);
         let headers = Headers::from(vec!(("Content-Encoding", "br")));
@@ -54,7 +54,7 @@ mod tests {
 
         client.handle(request, |res| {
             assert_eq!(res.status, OK);
-            assert_eq!(body, body_string(res.body));
+            assert_eq!(body, body_string(res.body).unwrap());
             assert_eq!(res.headers.vec, vec!(
                 ("Content-Encoding".to_string(), "br".to_string()),
                 ("Host".to_string(), format!("127.0.0.1:{}", server.port)),
@@ -67,7 +67,7 @@ mod tests {
     #[test]
     fn compressed_body_stream() {
         let mut server = Server::new(0);
-        server.start(|| { Ok(PassThroughHandler {}) }, true);
+        server.start(|| { Ok(PassThroughHandler {}) }, true).unwrap();
 
         let mut client = Client::new("127.0.0.1", server.port, None);
         let headers = Headers::from(vec!(
@@ -82,7 +82,7 @@ mod tests {
             BodyStream(Box::new(body.as_bytes())));
 
         client.handle(request, |res| {
-            assert_eq!(body, body_string(res.body));
+            assert_eq!(body, body_string(res.body).unwrap());
             assert_eq!(res.status, OK);
             assert_eq!(res.headers.vec, vec!(
                 ("Content-Encoding".to_string(), "br".to_string()),
@@ -96,7 +96,7 @@ mod tests {
     #[test]
     fn accept_encoding_wins_over_transfer_encoding_as_client_may_send_in_one_format_and_accept_in_another() {
         let mut server = Server::new(0);
-        server.start(|| { Ok(PassHeadersAsBody {}) }, true);
+        server.start(|| { Ok(PassHeadersAsBody {}) }, true).unwrap();
 
         let mut client = Client::new("127.0.0.1", server.port, None);
         let headers = Headers::from(vec!(
@@ -113,7 +113,7 @@ mod tests {
             BodyStream(Box::new(str.as_bytes())));
 
         client.handle(request, move|res| {
-            assert_eq!(expected_body.to_wire_string(), body_string(res.body));
+            assert_eq!(expected_body.to_wire_string(), body_string(res.body).unwrap());
             assert_eq!(res.status, OK);
             assert_eq!(res.headers.vec, vec!(
                 ("Content-Length".to_string(), "113".to_string()),
@@ -125,7 +125,7 @@ mod tests {
     #[test]
     fn setting_response_header_of_content_encoding_none_wins_over_everything() {
         let mut server = Server::new(0);
-        server.start(|| { Ok(SetContentEncodingToNoneAndEchoHeaders {}) }, true);
+        server.start(|| { Ok(SetContentEncodingToNoneAndEchoHeaders {}) }, true).unwrap();
 
         let mut client = Client::new("127.0.0.1", server.port, None);
         let headers = Headers::from(vec!(
@@ -152,7 +152,7 @@ mod tests {
     #[test]
     fn transfer_encoding_wins_over_content_encoding_as_client_may_send_in_one_format_and_accept_in_another() {
         let mut server = Server::new(0);
-        server.start(|| { Ok(EchoBodyHandler {}) }, true);
+        server.start(|| { Ok(EchoBodyHandler {}) }, true).unwrap();
 
         let mut client = Client::new("127.0.0.1", server.port, None);
         let headers = Headers::from(vec!(
@@ -167,7 +167,7 @@ mod tests {
             BodyStream(Box::new(str.as_bytes())));
 
         client.handle(request, |res| {
-            assert_eq!(str, body_string(res.body));
+            assert_eq!(str, body_string(res.body).unwrap());
             assert_eq!(res.status, OK);
             assert_eq!(res.headers.vec, vec!(
                 ("Transfer-Encoding".to_string(), "gzip, chunked".to_string()),
@@ -179,7 +179,7 @@ mod tests {
     #[test]
     fn TE_wins_over_content_encoding_as_client_may_send_in_one_format_and_accept_in_another() {
         let mut server = Server::new(0);
-        server.start(|| { Ok(EchoBodyHandler {}) }, true);
+        server.start(|| { Ok(EchoBodyHandler {}) }, true).unwrap();
 
         let mut client = Client::new("127.0.0.1", server.port, None);
         let headers = Headers::from(vec!(
@@ -195,7 +195,7 @@ mod tests {
             BodyStream(Box::new(str.as_bytes())));
 
         client.handle(request, |res| {
-            assert_eq!(str, body_string(res.body));
+            assert_eq!(str, body_string(res.body).unwrap());
             assert_eq!(res.status, OK);
             assert_eq!(res.headers.vec, vec!(
                 ("Transfer-Encoding".to_string(), "deflate, chunked".to_string()),
@@ -208,7 +208,7 @@ mod tests {
     #[test]
     fn if_content_encoding_on_its_way_in_then_dont_compress_on_way_back_without_accept_encoding() {
         let mut server = Server::new(0);
-        server.start(|| { Ok(EchoBodyHandler {}) }, true);
+        server.start(|| { Ok(EchoBodyHandler {}) }, true).unwrap();
 
         let mut client = Client::new("127.0.0.1", server.port, None);
 
@@ -219,11 +219,122 @@ mod tests {
             BodyString(str));
 
         client.handle(request_br_encoded_body, |res| {
-            assert_eq!(str, body_string(res.body));
+            assert_eq!(str, body_string(res.body).unwrap());
             assert_eq!(res.status, OK);
             assert_eq!(res.headers.vec, vec!(
                 ("Content-Length".to_string(), "9".to_string()),
             ));
         })
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn large_compressed_bodies_are_decompressed_as_they_stream() {
+        let mut server = Server::new(0);
+        server.start(|| { Ok(EchoBodyHandler {}) }, true).unwrap();
+
+        let mut client = Client::new("127.0.0.1", server.port, None);
+        // does not compress very well, so it is still much bigger than the reader once compressed
+        let mut seed: u64 = 42;
+        let body = (0..100000).map(|_| {
+            seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            char::from(b'a' + (seed >> 59) as u8)
+        }).collect::<String>();
+
+        let request = Request::post(
+            Uri::parse("/"),
+            Headers::from(vec!(("Content-Encoding", "gzip"))),
+            BodyString(body.as_str()));
+
+        client.handle(request, |res| {
+            assert_eq!(res.status, OK);
+            assert_eq!(body, body_string(res.body).unwrap());
+            // we do not know how long the body is until we have decompressed all of it
+            assert_eq!(res.headers.vec, vec!(
+                ("Transfer-Encoding".to_string(), "chunked".to_string()),
+            ));
+        })
+    }
+
+    #[test]
+    fn compresses_all_of_a_stream_with_a_content_length() {
+        let mut server = Server::new(0);
+        server.start(|| { Ok(EchoBodyHandler {}) }, true).unwrap();
+
+        let mut client = Client::new("127.0.0.1", server.port, None);
+        let body = "Some quite long body".repeat(1000);
+        let request = Request::post(
+            Uri::parse("/"),
+            Headers::from(vec!(
+                ("Content-Encoding", "gzip"),
+                ("Content-Length", body.len().to_string().as_str()),
+            )),
+            BodyStream(Box::new(body.as_bytes())));
+
+        client.handle(request, |res| {
+            assert_eq!(res.status, OK);
+            assert_eq!(body, body_string(res.body).unwrap());
+        })
+    }
+
+    #[test]
+    fn a_compressed_stream_is_sent_chunked_if_it_is_bigger_than_the_max_compressed_content_length() {
+        let mut server = Server::new(0);
+        server.start(|| { Ok(PassHeadersAsBody {}) }, true).unwrap();
+
+        let mut client = Client::new("127.0.0.1", server.port, Some(ClientOptions {
+            max_compressed_content_length: 10,
+            ..ClientOptions::default()
+        }));
+        let body = "Some quite long body".repeat(1000);
+        let request = Request::post(
+            Uri::parse("/"),
+            Headers::from(vec!(
+                ("Content-Encoding", "gzip"),
+                ("Content-Length", body.len().to_string().as_str()),
+            )),
+            BodyStream(Box::new(body.as_bytes())));
+
+        client.handle(request, |res| {
+            assert_eq!(res.status, OK);
+            let request_headers = body_string(res.body).unwrap();
+            assert!(request_headers.contains("Transfer-Encoding: chunked"));
+            assert!(!request_headers.contains("Content-Length"));
+        })
+    }
+
+    #[test]
+    fn server_compresses_all_of_a_response_stream_unless_it_is_too_big() {
+        let body = "Some quite long body".repeat(1000);
+
+        let mut server = Server::new(0);
+        server.start(|| { Ok(EchoBodyWithContentLengthHandler {}) }, true).unwrap();
+        let mut client = Client::new("127.0.0.1", server.port, None);
+        let request = Request::post(
+            Uri::parse("/"),
+            Headers::from(vec!(("Accept-Encoding", "gzip"))),
+            BodyString(body.as_str()));
+
+        client.handle(request, |res| {
+            assert_eq!(res.status, OK);
+            assert_eq!(Some("gzip".to_string()), res.headers.get("Content-Encoding"));
+            assert_eq!(Some(body.len().to_string()), res.headers.get("Content-Length"));
+            assert_eq!(body, body_string(res.body).unwrap());
+        });
+
+        let mut server = Server::with_options(0, ServerOptions { max_compressed_content_length: 10, ..ServerOptions::default() });
+        server.start(|| { Ok(EchoBodyWithContentLengthHandler {}) }, true).unwrap();
+        let mut client = Client::new("127.0.0.1", server.port, None);
+        let request = Request::post(
+            Uri::parse("/"),
+            Headers::from(vec!(("Accept-Encoding", "gzip"))),
+            BodyString(body.as_str()));
+
+        client.handle(request, |res| {
+            assert_eq!(res.status, OK);
+            assert_eq!(Some("gzip".to_string()), res.headers.get("Content-Encoding"));
+            assert_eq!(Some("chunked".to_string()), res.headers.get("Transfer-Encoding"));
+            assert_eq!(None, res.headers.get("Content-Length"));
+            assert_eq!(body, body_string(res.body).unwrap());
+        })
+    }
+}
@@ -0,0 +1,231 @@
+mod common;
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+    use std::thread;
+    use std::time::{Duration, Instant};
+    use http4r_core::client::Client;
+    use http4r_core::handler::Handler;
+    use http4r_core::headers::Headers;
+    use http4r_core::http_message::{body_string, Request, WireState};
+    use http4r_core::http_message::Body::BodyString;
+    use http4r_core::http_message::Status::OK;
+    use http4r_core::server::{OverloadPolicy, Server, ServerHandle, ServerMode, ServerOptions};
+    use http4r_core::socket::SocketAddress;
+    use http4r_core::uri::Uri;
+    use crate::common::{EchoBodyHandler, read_response, ReadWholeBodyHandler, SlowHandler};
+
+    fn event_loop_server(options: ServerOptions) -> (Server, ServerHandle) {
+        let mut server = Server::with_options(0, ServerOptions { mode: ServerMode::EventLoop, ..options });
+        let handle = server.start(|| { Ok(EchoBodyHandler {}) }, true).unwrap();
+        (server, handle)
+    }
+
+    fn connect(server: &Server) -> TcpStream {
+        TcpStream::connect(format!("127.0.0.1:{}", server.port)).unwrap()
+    }
+
+    fn post(mut stream: &TcpStream, body: &str) -> (String, Option<String>) {
+        stream.write_all(format!("POST / HTTP/1.1\r\nContent-Length: {}\r\n\r\n{}", body.len(), body).as_bytes()).unwrap();
+        let mut response = None;
+        read_response(stream, &mut [0; 4096], &mut WireState::new(), |res| {
+            assert_eq!(OK, res.status);
+            response = Some((body_string(res.body).unwrap(), res.headers.get("Connection")));
+        });
+        response.unwrap()
+    }
+
+    #[test]
+    fn handles_requests_one_after_another_on_the_same_connection() {
+        let (server, _handle) = event_loop_server(ServerOptions::default());
+        let mut client = Client::new("127.0.0.1", server.port, None);
+        client.handle(Request::post(Uri::parse("/"), Headers::empty(), BodyString("hello")), |res| {
+            assert_eq!("hello", body_string(res.body).unwrap());
+        });
+
+        let stream = connect(&server);
+        for i in 0..5 {
+            let body = format!("request {}", i);
+            assert_eq!(body, post(&stream, body.as_str()).0);
+        }
+    }
+
+    #[test]
+    fn handles_requests_that_are_pipelined() {
+        let (server, _handle) = event_loop_server(ServerOptions::default());
+        let mut stream = connect(&server);
+
+        stream.write_all("POST / HTTP/1.1\r\nContent-Length: 5\r\n\r\nfirstPOST / HTTP/1.1\r\nContent-Length: 6\r\n\r\nsecond".as_bytes()).unwrap();
+        let mut reader = [0; 4096];
+        let mut wire = WireState::new();
+        read_response(&stream, &mut reader, &mut wire, |res| {
+            assert_eq!("first", body_string(res.body).unwrap());
+        });
+        read_response(&stream, &mut reader, &mut wire, |res| {
+            assert_eq!("second", body_string(res.body).unwrap());
+        });
+    }
+
+    #[test]
+    fn idle_connections_do_not_hold_on_to_a_thread() {
+        let (server, _handle) = event_loop_server(ServerOptions { threadpool_size: 1, ..ServerOptions::default() });
+
+        // with a thread per connection, the first of these would have the only thread until it closed
+        let idle: Vec<TcpStream> = (0..50).map(|_| {
+            let stream = connect(&server);
+            post(&stream, "then go quiet");
+            stream
+        }).collect();
+
+        let stream = connect(&server);
+        assert_eq!("still served", post(&stream, "still served").0);
+        // and the idle ones are still open
+        assert_eq!("and so are we", post(&idle[0], "and so are we").0);
+    }
+
+    #[test]
+    fn waits_for_the_whole_head_before_handling_the_request() {
+        let (server, _handle) = event_loop_server(ServerOptions { threadpool_size: 1, ..ServerOptions::default() });
+        let mut trickling = connect(&server);
+        trickling.write_all("POST / HTTP/1.1\r\nContent-".as_bytes()).unwrap();
+        thread::sleep(Duration::from_millis(50));
+
+        // the only thread is not stuck waiting for the rest of the head
+        assert_eq!("not stuck", post(&connect(&server), "not stuck").0);
+
+        trickling.write_all("Length: 7\r\n\r\n".as_bytes()).unwrap();
+        thread::sleep(Duration::from_millis(50));
+        // the body can arrive after the head, it is up to the handler to wait for it
+        trickling.write_all("arrived".as_bytes()).unwrap();
+        read_response(&trickling, &mut [0; 4096], &mut WireState::new(), |res| {
+            assert_eq!("arrived", body_string(res.body).unwrap());
+        });
+    }
+
+    #[test]
+    fn head_that_does_not_arrive_in_time_is_a_request_timeout() {
+        let (server, _handle) = event_loop_server(ServerOptions { headers_timeout: Duration::from_millis(100), ..ServerOptions::default() });
+        let mut stream = connect(&server);
+        let started = Instant::now();
+
+        stream.write_all("GET / HTTP/1.1\r\n".as_bytes()).unwrap();
+        let mut response = String::new();
+        let _ = stream.read_to_string(&mut response);
+
+        assert!(response.starts_with("HTTP/1.1 408 Request Timeout\r\n"), "got {}", response);
+        assert!(response.contains("Connection: close"));
+        assert!(started.elapsed() < Duration::from_secs(2));
+    }
+
+    #[test]
+    fn carries_on_waiting_on_connections_while_others_wait_for_a_thread() {
+        let mut server = Server::with_options(0, ServerOptions {
+            mode: ServerMode::EventLoop,
+            threadpool_size: 1,
+            accept_queue_size: 0,
+            overload_policy: OverloadPolicy::Queue,
+            headers_timeout: Duration::from_millis(100),
+            ..ServerOptions::default()
+        });
+        let _handle = server.start(|| { Ok(SlowHandler { delay: Duration::from_millis(500) }) }, true).unwrap();
+        let mut trickling = connect(&server);
+        thread::sleep(Duration::from_millis(50));
+        let busy = connect(&server);
+        let busy = thread::spawn(move || post(&busy, "busy").0);
+        thread::sleep(Duration::from_millis(50));
+        let queued = connect(&server);
+        let queued = thread::spawn(move || post(&queued, "queued").0);
+        thread::sleep(Duration::from_millis(50));
+
+        // the only thread is busy and the next request is waiting for it, yet the head still times out in time
+        let started = Instant::now();
+        trickling.write_all("GET / HTTP/1.1\r\n".as_bytes()).unwrap();
+        let mut response = String::new();
+        let _ = trickling.read_to_string(&mut response);
+        assert!(response.starts_with("HTTP/1.1 408 Request Timeout\r\n"), "got {}", response);
+        assert!(started.elapsed() < Duration::from_millis(300));
+
+        assert_eq!("finally", busy.join().unwrap());
+        assert_eq!("finally", queued.join().unwrap());
+    }
+
+    #[test]
+    fn idle_connections_are_closed_after_the_keep_alive_timeout() {
+        let (server, _handle) = event_loop_server(ServerOptions { keep_alive_timeout: Duration::from_millis(100), ..ServerOptions::default() });
+        let mut stream = connect(&server);
+        post(&stream, "hello");
+
+        let mut rest = Vec::new();
+        stream.read_to_end(&mut rest).unwrap();
+        assert!(rest.is_empty());
+    }
+
+    #[test]
+    fn closes_the_connection_after_the_most_requests_it_can_have() {
+        let (server, _handle) = event_loop_server(ServerOptions { max_requests_per_connection: 3, ..ServerOptions::default() });
+        let stream = connect(&server);
+
+        assert_eq!(None, post(&stream, "one").1);
+        assert_eq!(None, post(&stream, "two").1);
+        assert_eq!(Some("close".to_string()), post(&stream, "three").1);
+    }
+
+    #[test]
+    fn stops_gracefully() {
+        let mut server = Server::with_options(0, ServerOptions { mode: ServerMode::EventLoop, ..ServerOptions::default() });
+        let handle = server.start(|| { Ok(SlowHandler { delay: Duration::from_millis(200) }) }, true).unwrap();
+        let mut idle = connect(&server);
+        idle.write_all("GET / HTTP/1.1\r\n\r\n".as_bytes()).unwrap();
+        read_response(&idle, &mut [0; 4096], &mut WireState::new(), |res| {
+            assert_eq!(OK, res.status);
+        });
+        let mut in_flight = connect(&server);
+        in_flight.write_all("GET / HTTP/1.1\r\n\r\n".as_bytes()).unwrap();
+        let in_flight = thread::spawn(move || {
+            let mut connection = None;
+            read_response(&in_flight, &mut [0; 4096], &mut WireState::new(), |res| {
+                connection = res.headers.get("Connection");
+            });
+            connection
+        });
+        thread::sleep(Duration::from_millis(50));
+
+        handle.stop(Duration::from_secs(5));
+
+        assert_eq!(Some("close".to_string()), in_flight.join().unwrap());
+        let mut nothing = Vec::new();
+        let _ = idle.read_to_end(&mut nothing);
+        assert!(nothing.is_empty());
+        assert!(TcpStream::connect(format!("127.0.0.1:{}", server.port)).is_err());
+    }
+
+    #[test]
+    fn works_on_a_unix_socket_too() {
+        let path = std::env::temp_dir().join(format!("http4r-event-loop-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let mut server = Server::bind(SocketAddress::Unix(path.clone()), ServerOptions { mode: ServerMode::EventLoop, ..ServerOptions::default() });
+        let handle = server.start(|| { Ok(ReadWholeBodyHandler {}) }, true).unwrap();
+
+        let mut client = Client::connect_to(SocketAddress::Unix(path), None);
+        client.handle(Request::post(Uri::parse("/"), Headers::empty(), BodyString("over a unix socket")), |res| {
+            assert_eq!(OK, res.status);
+            assert_eq!("over a unix socket", body_string(res.body).unwrap());
+        });
+        handle.stop(Duration::from_secs(1));
+    }
+
+    #[test]
+    fn closes_the_connection_when_the_handler_panics() {
+        let mut server = Server::with_options(0, ServerOptions { mode: ServerMode::EventLoop, ..ServerOptions::default() });
+        let _handle = server.start(|| -> Result<EchoBodyHandler, String> { Err("no handler for you".to_string()) }, true).unwrap();
+        let mut stream = connect(&server);
+        stream.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+        stream.write_all("GET / HTTP/1.1\r\n\r\n".as_bytes()).unwrap();
+
+        let mut response = Vec::new();
+        assert!(stream.read_to_end(&mut response).is_ok());
+        assert!(response.is_empty());
+    }
+}
@@ -3,6 +3,17 @@
 mod tests {
     use http4r_core::headers::Headers;
 
+    #[test]
+    fn parse_headers() {
+        let headers = Headers::parse_from("Host: example.com\r\nno-space:value\r\nwith-colon: a: b \t").unwrap();
+        assert_eq!(headers.vec, vec!(
+            ("Host".to_string(), "example.com".to_string()),
+            ("no-space".to_string(), "value".to_string()),
+            ("with-colon".to_string(), "a: b".to_string())));
+
+        assert!(Headers::parse_from("no colon").is_err());
+    }
+
     #[test]
     fn get_header() {
         assert_eq!(Headers::empty().get("foo"), None);
@@ -0,0 +1,104 @@
+#[cfg(test)]
+mod tests {
+    use http4r_core::hpack::{Decoder, encode, HpackError, huffman_decode};
+
+    fn hex(str: &str) -> Vec<u8> {
+        let digits: String = str.chars().filter(|c| !c.is_whitespace()).collect();
+        (0..digits.len()).step_by(2).map(|i| u8::from_str_radix(&digits[i..i + 2], 16).unwrap()).collect()
+    }
+
+    fn headers(pairs: Vec<(&str, &str)>) -> Vec<(String, String)> {
+        pairs.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect()
+    }
+
+    // https://www.rfc-editor.org/rfc/rfc7541#appendix-C.3
+    #[test]
+    fn decodes_requests_that_add_to_the_dynamic_table() {
+        let mut decoder = Decoder::new(4096);
+
+        assert_eq!(headers(vec!((":method", "GET"), (":scheme", "http"), (":path", "/"), (":authority", "www.example.com"))),
+                   decoder.decode(&hex("8286 8441 0f77 7777 2e65 7861 6d70 6c65 2e63 6f6d"), 65536).unwrap());
+        assert_eq!(headers(vec!((":method", "GET"), (":scheme", "http"), (":path", "/"), (":authority", "www.example.com"), ("cache-control", "no-cache"))),
+                   decoder.decode(&hex("8286 84be 5808 6e6f 2d63 6163 6865"), 65536).unwrap());
+        assert_eq!(headers(vec!((":method", "GET"), (":scheme", "https"), (":path", "/index.html"), (":authority", "www.example.com"), ("custom-key", "custom-value"))),
+                   decoder.decode(&hex("8287 85bf 400a 6375 7374 6f6d 2d6b 6579 0c63 7573 746f 6d2d 7661 6c75 65"), 65536).unwrap());
+    }
+
+    // https://www.rfc-editor.org/rfc/rfc7541#appendix-C.4
+    #[test]
+    fn decodes_huffman_coded_strings() {
+        let mut decoder = Decoder::new(4096);
+
+        assert_eq!(headers(vec!((":method", "GET"), (":scheme", "http"), (":path", "/"), (":authority", "www.example.com"))),
+                   decoder.decode(&hex("8286 8441 8cf1 e3c2 e5f2 3a6b a0ab 90f4 ff"), 65536).unwrap());
+        assert_eq!(headers(vec!((":method", "GET"), (":scheme", "http"), (":path", "/"), (":authority", "www.example.com"), ("cache-control", "no-cache"))),
+                   decoder.decode(&hex("8286 84be 5886 a8eb 1064 9cbf"), 65536).unwrap());
+        assert_eq!(headers(vec!((":method", "GET"), (":scheme", "https"), (":path", "/index.html"), (":authority", "www.example.com"), ("custom-key", "custom-value"))),
+                   decoder.decode(&hex("8287 85bf 4088 25a8 49e9 5ba9 7d7f 8925 a849 e95b b8e8 b4bf"), 65536).unwrap());
+    }
+
+    // https://www.rfc-editor.org/rfc/rfc7541#appendix-C.6
+    #[test]
+    fn evicts_the_oldest_entries_when_the_table_is_full() {
+        let mut decoder = Decoder::new(256);
+
+        assert_eq!(headers(vec!((":status", "302"), ("cache-control", "private"), ("date", "Mon, 21 Oct 2013 20:13:21 GMT"), ("location", "https://www.example.com"))),
+                   decoder.decode(&hex("4882 6402 5885 aec3 771a 4b61 96d0 7abe 9410 54d4 44a8 2005 9504 0b81 66e0 82a6 2d1b ff6e 919d 29ad 1718 63c7 8f0b 97c8 e9ae 82ae 43d3"), 65536).unwrap());
+        assert_eq!(headers(vec!((":status", "307"), ("cache-control", "private"), ("date", "Mon, 21 Oct 2013 20:13:21 GMT"), ("location", "https://www.example.com"))),
+                   decoder.decode(&hex("4883 640e ffc1 c0bf"), 65536).unwrap());
+        assert_eq!(headers(vec!((":status", "200"), ("cache-control", "private"), ("date", "Mon, 21 Oct 2013 20:13:22 GMT"), ("location", "https://www.example.com"),
+                                ("content-encoding", "gzip"), ("set-cookie", "foo=ASDJKHQKBZXOQWEOPIUAXQWEOIU; max-age=3600; version=1"))),
+                   decoder.decode(&hex("88c1 6196 d07a be94 1054 d444 a820 0595 040b 8166 e084 a62d 1bff c05a 839b d9ab 77ad 94e7 821d d7f2 e6c7 b335 dfdf cd5b 3960 d5af 2708 7f36 72c1 ab27 0fb5 291f 9587 3160 65c0 03ed 4ee5 b106 3d50 07"), 65536).unwrap());
+    }
+
+    #[test]
+    fn encodes_what_it_decodes() {
+        let original = headers(vec!((":method", "GET"), (":path", "/a/much/longer/path/than/thirty/one/characters/long"), ("content-type", "text/plain"),
+                                    ("x-custom", "custom"), ("cookie", "secret=1")));
+        let mut block = vec!();
+        encode(&original, &mut block);
+
+        assert_eq!(original, Decoder::new(4096).decode(&block, 65536).unwrap());
+        // :method GET is in the static table, so is a single octet
+        assert_eq!(0x82, block[0]);
+    }
+
+    #[test]
+    fn size_update_can_not_be_bigger_than_we_allow() {
+        assert!(Decoder::new(4096).decode(&hex("3fe1 1f"), 65536).is_ok());
+        assert!(Decoder::new(100).decode(&hex("3fe1 1f"), 65536).is_err());
+        // nor come after a header
+        assert!(Decoder::new(4096).decode(&hex("8220"), 65536).is_err());
+    }
+
+    #[test]
+    fn index_must_be_in_a_table() {
+        assert!(Decoder::new(4096).decode(&hex("80"), 65536).is_err());
+        assert!(Decoder::new(4096).decode(&hex("be"), 65536).is_err());
+        // or the block ends part way through
+        assert!(Decoder::new(4096).decode(&hex("410f 7777"), 65536).is_err());
+    }
+
+    #[test]
+    fn headers_can_not_decode_to_more_than_the_max_header_list_size() {
+        // x: 4000 a's, added to the table
+        let mut block = vec!(0x40, 0x01, b'x', 0x7f, 0xa1, 0x1e);
+        block.extend_from_slice("a".repeat(4000).as_bytes());
+        // then thousands of one octet references to it
+        block.extend_from_slice(&[0xbe; 5000]);
+
+        assert_eq!(Err(HpackError::HeaderListTooBig(65536)), Decoder::new(4096).decode(&block, 65536));
+        // the entry on its own is fine
+        assert_eq!(1, Decoder::new(4096).decode(&block[..4006], 65536).unwrap().len());
+    }
+
+    #[test]
+    fn huffman_padding_must_be_short_and_all_ones() {
+        // www.example.com
+        assert_eq!("www.example.com".as_bytes(), huffman_decode(&hex("f1e3 c2e5 f23a 6ba0 ab90 f4ff")).unwrap().as_slice());
+        // padding of zeroes
+        assert!(huffman_decode(&hex("f1e3 c2e5 f23a 6ba0 ab90 f400")).is_err());
+        // a whole octet of padding
+        assert!(huffman_decode(&hex("f1e3 c2e5 f23a 6ba0 ab90 f4ff ff")).is_err());
+    }
+}
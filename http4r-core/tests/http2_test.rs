@@ -0,0 +1,411 @@
+mod common;
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+    use std::thread;
+    use std::time::Duration;
+    use http4r_core::client::{Client, ClientOptions};
+    use http4r_core::handler::Handler;
+    use http4r_core::headers::Headers;
+    use http4r_core::hpack::{Decoder, encode};
+    use http4r_core::http2::{ACK, CONTINUATION, DATA, END_HEADERS, END_STREAM, ENHANCE_YOUR_CALM, Frame, GOAWAY, HEADERS, Http2Options, PING, PREFACE, PROTOCOL_ERROR, read_frame, REFUSED_STREAM, RST_STREAM, SETTINGS, WINDOW_UPDATE, write_frame};
+    use http4r_core::http_message::{body_string, Request, Response, two_pt_oh};
+    use http4r_core::http_message::Body::{BodyStream, BodyString};
+    use http4r_core::http_message::Status::{OK, RequestHeaderFieldsTooLarge};
+    use http4r_core::server::{Server, ServerHandle, ServerOptions};
+    use http4r_core::uri::Uri;
+    use crate::common::{EchoBodyHandler, PassHeadersAsBody};
+
+    // responds with the version it was asked with, after a while if the path is /slow, and with a big body if it is /big
+    struct VersionHandler {}
+
+    impl Handler for VersionHandler {
+        fn handle<F>(&mut self, req: Request, fun: F) -> () where F: FnOnce(Response) -> () + Sized {
+            let path = req.uri.path.to_string();
+            if path == "/slow" {
+                thread::sleep(Duration::from_millis(200));
+            }
+            if path == "/big" {
+                let big = "b".repeat(100000);
+                return fun(Response::ok(Headers::empty(), BodyStream(Box::new(big.as_bytes()))));
+            }
+            let version = format!("{}.{} {}", req.version.major, req.version.minor, path);
+            fun(Response::ok(Headers::empty(), BodyString(version.as_str())))
+        }
+    }
+
+    // reads the body to the end so that the trailers have arrived, and sends them back as trailers of its own
+    struct EchoTrailersHandler {}
+
+    impl Handler for EchoTrailersHandler {
+        fn handle<F>(&mut self, req: Request, fun: F) -> () where F: FnOnce(Response) -> () + Sized {
+            let trailers = req.trailers.clone();
+            let body = body_string(req.body).unwrap();
+            fun(Response::ok(Headers::empty(), BodyString(body.as_str())).with_trailers(trailers.get()))
+        }
+    }
+
+    fn server<F, H>(options: ServerOptions, handler: F) -> (Server, ServerHandle)
+        where F: Fn() -> Result<H, String> + Send + Sync + 'static, H: Handler {
+        let mut server = Server::with_options(0, options);
+        let handle = server.start(handler, true).unwrap();
+        (server, handle)
+    }
+
+    fn http2_options() -> ServerOptions {
+        ServerOptions { http2: Some(Http2Options::default()), ..ServerOptions::default() }
+    }
+
+    fn http2_client(port: u16) -> Client {
+        Client::new("127.0.0.1", port, Some(ClientOptions { http2: Some(Http2Options::default()), ..ClientOptions::default() }))
+    }
+
+    fn fields(pairs: Vec<(&str, &str)>) -> Vec<(String, String)> {
+        pairs.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect()
+    }
+
+    // starts a connection by hand, with the default settings
+    fn connect(server: &Server) -> TcpStream {
+        let mut stream = TcpStream::connect(format!("127.0.0.1:{}", server.port)).unwrap();
+        stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        stream.write_all(PREFACE).unwrap();
+        send(&mut stream, Frame::new(SETTINGS, 0, 0, vec!()));
+        stream
+    }
+
+    fn send(stream: &mut TcpStream, frame: Frame) {
+        write_frame(stream, &frame).unwrap();
+    }
+
+    fn request(stream: &mut TcpStream, stream_id: u32, method: &str, path: &str, end_stream: bool) {
+        let mut block = vec!();
+        encode(&fields(vec!((":method", method), (":scheme", "http"), (":authority", "localhost"), (":path", path))), &mut block);
+        let flags = if end_stream { END_HEADERS | END_STREAM } else { END_HEADERS };
+        send(stream, Frame::new(HEADERS, flags, stream_id, block));
+    }
+
+    // skips over the frames about the connection that we are not interested in
+    fn next_frame(stream: &mut TcpStream) -> Frame {
+        loop {
+            let frame = read_frame(stream, 16777215).unwrap();
+            if frame.kind != SETTINGS && frame.kind != WINDOW_UPDATE {
+                return frame;
+            }
+        }
+    }
+
+    #[test]
+    fn serves_and_calls_http2_with_prior_knowledge() {
+        let (server, _handle) = server(http2_options(), || Ok(EchoBodyHandler {}));
+        let mut client = http2_client(server.port);
+
+        client.handle(Request::post(Uri::parse("/"), Headers::empty(), BodyString("hello over http2")), |res| {
+            assert_eq!(OK, res.status);
+            assert!(two_pt_oh() == res.version);
+            assert_eq!("hello over http2", body_string(res.body).unwrap());
+        });
+        assert!(client.error.is_none(), "{}", client.err);
+    }
+
+    #[test]
+    fn handlers_see_the_same_request_as_over_http1() {
+        let (server, _handle) = server(http2_options(), || Ok(VersionHandler {}));
+        let mut client = http2_client(server.port);
+        client.handle(Request::get(Uri::parse("/some/path?query=1"), Headers::empty()), |res| {
+            assert_eq!("2.0 /some/path", body_string(res.body).unwrap());
+        });
+
+        // and the authority is the Host header
+        let (server, _handle) = self::server(http2_options(), || Ok(PassHeadersAsBody {}));
+        let mut client = http2_client(server.port);
+        client.handle(Request::get(Uri::parse("/"), Headers::from(vec!(("X-Custom", "custom")))), |res| {
+            let body = body_string(res.body).unwrap().to_lowercase();
+            assert!(body.contains(format!("host: 127.0.0.1:{}", server.port).as_str()), "{}", body);
+            assert!(body.contains("x-custom: custom"), "{}", body);
+        });
+    }
+
+    #[test]
+    fn the_same_server_still_speaks_http1() {
+        let (server, _handle) = server(http2_options(), || Ok(VersionHandler {}));
+        let mut client = Client::new("127.0.0.1", server.port, None);
+
+        client.handle(Request::get(Uri::parse("/"), Headers::empty()), |res| {
+            assert_eq!("1.1 /", body_string(res.body).unwrap());
+        });
+    }
+
+    #[test]
+    fn sends_big_bodies_both_ways_within_the_flow_control_windows() {
+        let (server, _handle) = server(http2_options(), || Ok(EchoBodyHandler {}));
+        let mut client = http2_client(server.port);
+        let big = "a".repeat(500000);
+
+        client.handle(Request::post(Uri::parse("/"), Headers::empty(), BodyStream(Box::new(big.as_bytes()))), |res| {
+            assert_eq!(big, body_string(res.body).unwrap());
+        });
+        assert!(client.error.is_none(), "{}", client.err);
+    }
+
+    #[test]
+    fn waits_for_the_client_to_open_its_window_before_sending_more() {
+        let (server, _handle) = server(http2_options(), || Ok(VersionHandler {}));
+        let mut stream = connect(&server);
+        request(&mut stream, 1, "GET", "/big", true);
+
+        assert_eq!(HEADERS, next_frame(&mut stream).kind);
+        let mut received = 0;
+        while received < 65535 {
+            let frame = next_frame(&mut stream);
+            assert_eq!(DATA, frame.kind);
+            received += frame.payload.len();
+        }
+        assert_eq!(65535, received);
+        // the default window is full, so nothing more comes
+        stream.set_read_timeout(Some(Duration::from_millis(200))).unwrap();
+        assert!(read_frame(&mut stream, 16777215).is_err());
+
+        stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        send(&mut stream, Frame::new(WINDOW_UPDATE, 0, 0, 100000u32.to_be_bytes().to_vec()));
+        send(&mut stream, Frame::new(WINDOW_UPDATE, 0, 1, 100000u32.to_be_bytes().to_vec()));
+        loop {
+            let frame = next_frame(&mut stream);
+            received += frame.payload.len();
+            if frame.has(END_STREAM) {
+                break;
+            }
+        }
+        assert_eq!(100000, received);
+    }
+
+    #[test]
+    fn answers_streams_on_one_connection_in_whatever_order_they_finish() {
+        let (server, _handle) = server(http2_options(), || Ok(VersionHandler {}));
+        let mut stream = connect(&server);
+        request(&mut stream, 1, "GET", "/slow", true);
+        request(&mut stream, 3, "GET", "/fast", true);
+
+        let mut decoder = Decoder::new(4096);
+        let mut finished = vec!();
+        let mut bodies = vec!((1, vec!()), (3, vec!()));
+        while finished.len() < 2 {
+            let frame = next_frame(&mut stream);
+            match frame.kind {
+                HEADERS => assert_eq!(fields(vec!((":status", "200"))), decoder.decode(&frame.payload, 65536).unwrap()),
+                DATA => bodies.iter_mut().find(|(id, _)| *id == frame.stream_id).unwrap().1.extend_from_slice(&frame.payload),
+                other => panic!("Did not expect frame {}", other),
+            }
+            if frame.has(END_STREAM) {
+                finished.push(frame.stream_id);
+            }
+        }
+
+        // the fast one did not wait for the slow one
+        assert_eq!(vec!(3, 1), finished);
+        assert_eq!("2.0 /slow".as_bytes(), bodies[0].1.as_slice());
+        assert_eq!("2.0 /fast".as_bytes(), bodies[1].1.as_slice());
+    }
+
+    #[test]
+    fn refuses_streams_beyond_the_most_it_allows_at_once() {
+        let http2 = Http2Options { max_concurrent_streams: 1, ..Http2Options::default() };
+        let (server, _handle) = server(ServerOptions { http2: Some(http2), ..ServerOptions::default() }, || Ok(VersionHandler {}));
+        let mut stream = connect(&server);
+        request(&mut stream, 1, "GET", "/slow", true);
+        request(&mut stream, 3, "GET", "/fast", true);
+
+        let refused = next_frame(&mut stream);
+        assert_eq!((RST_STREAM, 3), (refused.kind, refused.stream_id));
+        assert_eq!(REFUSED_STREAM.to_be_bytes().to_vec(), refused.payload);
+        let answered = next_frame(&mut stream);
+        assert_eq!((HEADERS, 1), (answered.kind, answered.stream_id));
+    }
+
+    #[test]
+    fn refuses_streams_beyond_the_most_the_server_allows_over_every_connection() {
+        let http2 = Http2Options { max_streams: 1, ..Http2Options::default() };
+        let (server, _handle) = server(ServerOptions { http2: Some(http2), ..ServerOptions::default() }, || Ok(VersionHandler {}));
+        let mut slow = connect(&server);
+        request(&mut slow, 1, "GET", "/slow", true);
+        thread::sleep(Duration::from_millis(50));
+        let mut fast = connect(&server);
+        request(&mut fast, 1, "GET", "/fast", true);
+
+        let refused = next_frame(&mut fast);
+        assert_eq!((RST_STREAM, 1), (refused.kind, refused.stream_id));
+        assert_eq!(REFUSED_STREAM.to_be_bytes().to_vec(), refused.payload);
+        assert_eq!(HEADERS, next_frame(&mut slow).kind);
+        // once the slow one has finished there is room again
+        thread::sleep(Duration::from_millis(50));
+        request(&mut fast, 3, "GET", "/fast", true);
+        let answered = next_frame(&mut fast);
+        assert_eq!((HEADERS, 3), (answered.kind, answered.stream_id));
+    }
+
+    #[test]
+    fn is_off_unless_asked_for() {
+        let (server, _handle) = server(ServerOptions::default(), || Ok(VersionHandler {}));
+        let mut stream = TcpStream::connect(format!("127.0.0.1:{}", server.port)).unwrap();
+        stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        stream.write_all(PREFACE).unwrap();
+
+        let mut response = vec!();
+        let _ = stream.read_to_end(&mut response);
+        assert!(String::from_utf8_lossy(&response).starts_with("HTTP/1.1 505 HTTP Version Not Supported"));
+    }
+
+    #[test]
+    fn sends_and_receives_trailers() {
+        let (server, _handle) = server(http2_options(), || Ok(EchoTrailersHandler {}));
+        let mut client = http2_client(server.port);
+        let request = Request::post(Uri::parse("/"), Headers::empty(), BodyStream(Box::new("with trailers".as_bytes())))
+            .with_trailers(Headers::from(vec!(("Digest", "sha-256=abc"))));
+
+        client.handle(request, |res| {
+            let trailers = res.trailers.clone();
+            assert_eq!("with trailers", body_string(res.body).unwrap());
+            assert_eq!(Some("sha-256=abc".to_string()), trailers.get().get("Digest"));
+        });
+    }
+
+    #[test]
+    fn compresses_responses_as_over_http1() {
+        let (server, _handle) = server(http2_options(), || Ok(EchoBodyHandler {}));
+        let mut client = http2_client(server.port);
+        let body = "compress me ".repeat(1000);
+
+        client.handle(Request::post(Uri::parse("/"), Headers::from(vec!(("Accept-Encoding", "gzip"))), BodyString(body.as_str())), |res| {
+            assert_eq!(Some("gzip".to_string()), res.headers.get("Content-Encoding"));
+            assert_eq!(body, body_string(res.body).unwrap());
+        });
+    }
+
+    #[test]
+    fn headers_that_are_too_big_get_a_431() {
+        let (server, _handle) = server(ServerOptions { headers_size: 1000, ..http2_options() }, || Ok(EchoBodyHandler {}));
+        let mut client = http2_client(server.port);
+        let big = "a".repeat(2000);
+
+        client.handle(Request::get(Uri::parse("/"), Headers::from(vec!(("X-Big", big.as_str())))), |res| {
+            assert_eq!(RequestHeaderFieldsTooLarge, res.status);
+        });
+    }
+
+    #[test]
+    fn splits_big_header_blocks_into_continuation_frames() {
+        let (server, _handle) = server(ServerOptions { headers_size: 100000, ..http2_options() }, || Ok(PassHeadersAsBody {}));
+        let mut stream = connect(&server);
+        let big = "a".repeat(20000);
+        let mut block = vec!();
+        encode(&fields(vec!((":method", "GET"), (":scheme", "http"), (":path", "/"), ("x-big", big.as_str()))), &mut block);
+        send(&mut stream, Frame::new(HEADERS, END_STREAM, 1, block[..10000].to_vec()));
+        send(&mut stream, Frame::new(CONTINUATION, END_HEADERS, 1, block[10000..].to_vec()));
+
+        assert_eq!(HEADERS, next_frame(&mut stream).kind);
+        let mut body = vec!();
+        loop {
+            let frame = next_frame(&mut stream);
+            body.extend_from_slice(&frame.payload);
+            if frame.has(END_STREAM) {
+                break;
+            }
+        }
+        assert!(String::from_utf8(body).unwrap().contains(big.as_str()));
+    }
+
+    #[test]
+    fn goes_away_if_a_small_header_block_decodes_to_too_many_headers() {
+        let (server, _handle) = server(ServerOptions { headers_size: 16384, ..http2_options() }, || Ok(EchoBodyHandler {}));
+        let mut stream = connect(&server);
+        let mut block = vec!();
+        encode(&fields(vec!((":method", "GET"), (":scheme", "http"), (":path", "/"))), &mut block);
+        // x: 4000 a's, added to the table, then thousands of one octet references to it
+        block.extend_from_slice(&[0x40, 0x01, b'x', 0x7f, 0xa1, 0x1e]);
+        block.extend_from_slice("a".repeat(4000).as_bytes());
+        block.extend_from_slice(&[0xbe; 10000]);
+        send(&mut stream, Frame::new(HEADERS, END_STREAM | END_HEADERS, 1, block));
+
+        let goaway = next_frame(&mut stream);
+        assert_eq!(GOAWAY, goaway.kind);
+        assert_eq!(ENHANCE_YOUR_CALM.to_be_bytes(), goaway.payload[4..8]);
+    }
+
+    #[test]
+    fn answers_pings() {
+        let (server, _handle) = server(http2_options(), || Ok(EchoBodyHandler {}));
+        let mut stream = connect(&server);
+        send(&mut stream, Frame::new(PING, 0, 0, b"12345678".to_vec()));
+
+        let pong = next_frame(&mut stream);
+        assert_eq!((PING, ACK), (pong.kind, pong.flags));
+        assert_eq!(b"12345678".to_vec(), pong.payload);
+    }
+
+    #[test]
+    fn goes_away_after_a_protocol_error() {
+        let (server, _handle) = server(http2_options(), || Ok(EchoBodyHandler {}));
+        let mut stream = connect(&server);
+        // clients must use odd stream ids
+        request(&mut stream, 2, "GET", "/", true);
+
+        let goaway = next_frame(&mut stream);
+        assert_eq!(GOAWAY, goaway.kind);
+        assert_eq!(PROTOCOL_ERROR.to_be_bytes(), goaway.payload[4..8]);
+        let mut rest = vec!();
+        let _ = stream.read_to_end(&mut rest);
+        assert!(rest.is_empty());
+    }
+
+    #[test]
+    fn goes_away_after_the_most_requests_it_can_have() {
+        let (server, _handle) = server(ServerOptions { max_requests_per_connection: 1, ..http2_options() }, || Ok(VersionHandler {}));
+        let mut stream = connect(&server);
+        request(&mut stream, 1, "GET", "/", true);
+
+        let goaway = next_frame(&mut stream);
+        assert_eq!(GOAWAY, goaway.kind);
+        // the last stream it will answer
+        assert_eq!(1u32.to_be_bytes(), goaway.payload[0..4]);
+        let answered = next_frame(&mut stream);
+        assert_eq!((HEADERS, 1), (answered.kind, answered.stream_id));
+        // and then it closes, once the stream it did take is done
+        let mut rest = vec!();
+        let _ = stream.read_to_end(&mut rest);
+        assert!(!rest.is_empty());
+    }
+
+    #[test]
+    fn closes_idle_connections_after_the_keep_alive_timeout() {
+        let (server, _handle) = server(ServerOptions { keep_alive_timeout: Duration::from_millis(100), ..http2_options() }, || Ok(EchoBodyHandler {}));
+        let mut stream = connect(&server);
+
+        let goaway = next_frame(&mut stream);
+        assert_eq!(GOAWAY, goaway.kind);
+        let mut rest = vec!();
+        let _ = stream.read_to_end(&mut rest);
+    }
+
+    #[cfg(feature = "tls")]
+    #[test]
+    fn agrees_on_http2_over_alpn() {
+        use http4r_core::tls::{ClientTls, ClientTlsOptions, ServerTls};
+        let certificate = rcgen::generate_simple_self_signed(vec!("localhost".to_string())).unwrap();
+        let tls = ServerTls::from_pem(certificate.cert.pem().as_bytes(), certificate.key_pair.serialize_pem().as_bytes(), None).unwrap();
+        let (server, _handle) = server(ServerOptions { tls: Some(tls), ..http2_options() }, || Ok(VersionHandler {}));
+        let client_tls = ClientTls::new(Some(ClientTlsOptions {
+            well_known_roots: false,
+            root_certificates: certificate.cert.pem().into_bytes(),
+            alpn_protocols: vec!("h2".to_string(), "http/1.1".to_string()),
+            ..ClientTlsOptions::default()
+        })).unwrap();
+        let mut client = Client::new("localhost", server.port, Some(ClientOptions { tls: Some(client_tls), ..ClientOptions::default() }));
+
+        client.handle(Request::get(Uri::parse("/over/tls"), Headers::empty()), |res| {
+            assert!(two_pt_oh() == res.version);
+            assert_eq!("2.0 /over/tls", body_string(res.body).unwrap());
+        });
+        assert!(client.error.is_none(), "{}", client.err);
+    }
+}
@@ -0,0 +1,156 @@
+mod common;
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+    use std::thread;
+    use std::time::Duration;
+    use http4r_core::http_message::{body_string, WireState};
+    use http4r_core::http_message::Status::OK;
+    use http4r_core::server::{Server, ServerOptions};
+    use crate::common::{PassThroughHandler, read_response, Router};
+
+    fn is_closed(stream: &mut TcpStream) -> bool {
+        let mut buffer = [0; 16];
+        stream.read(&mut buffer).map(|read| read == 0).unwrap_or(true)
+    }
+
+    #[test]
+    fn http_1_1_connections_are_kept_alive_by_default() {
+        let mut server = Server::new(0);
+        server.start(|| { Ok(PassThroughHandler {}) }, true).unwrap();
+        let mut stream = TcpStream::connect(format!("127.0.0.1:{}", server.port)).unwrap();
+        let mut reader = [0; 4096];
+        let mut wire = WireState::new();
+
+        stream.write("POST / HTTP/1.1\r\nContent-Length: 5\r\n\r\nhello".as_bytes()).unwrap();
+        read_response(&stream, &mut reader, &mut wire, |res| {
+            assert_eq!(OK, res.status);
+            assert_eq!(None, res.headers.get("Connection"));
+            assert_eq!("hello", body_string(res.body).unwrap());
+        });
+
+        stream.write("POST / HTTP/1.1\r\nContent-Length: 7\r\n\r\ngoodbye".as_bytes()).unwrap();
+        read_response(&stream, &mut reader, &mut wire, |res| {
+            assert_eq!(OK, res.status);
+            assert_eq!("goodbye", body_string(res.body).unwrap());
+        });
+    }
+
+    #[test]
+    fn connection_close_closes_the_connection_after_the_response() {
+        let mut server = Server::new(0);
+        server.start(|| { Ok(PassThroughHandler {}) }, true).unwrap();
+        let mut stream = TcpStream::connect(format!("127.0.0.1:{}", server.port)).unwrap();
+        let mut reader = [0; 4096];
+        let mut wire = WireState::new();
+
+        stream.write("GET / HTTP/1.1\r\nConnection: close\r\n\r\n".as_bytes()).unwrap();
+        read_response(&stream, &mut reader, &mut wire, |res| {
+            assert_eq!(OK, res.status);
+            assert_eq!(Some("close".to_string()), res.headers.get("Connection"));
+        });
+        assert!(is_closed(&mut stream));
+    }
+
+    #[test]
+    fn http_1_0_connections_are_closed_unless_keep_alive_is_asked_for() {
+        let mut server = Server::new(0);
+        server.start(|| { Ok(PassThroughHandler {}) }, true).unwrap();
+        let mut reader = [0; 4096];
+
+        let mut stream = TcpStream::connect(format!("127.0.0.1:{}", server.port)).unwrap();
+        let mut wire = WireState::new();
+        stream.write("GET / HTTP/1.0\r\n\r\n".as_bytes()).unwrap();
+        read_response(&stream, &mut reader, &mut wire, |res| {
+            assert_eq!(Some("close".to_string()), res.headers.get("Connection"));
+        });
+        assert!(is_closed(&mut stream));
+
+        let mut stream = TcpStream::connect(format!("127.0.0.1:{}", server.port)).unwrap();
+        let mut wire = WireState::new();
+        stream.write("GET / HTTP/1.0\r\nConnection: keep-alive\r\n\r\n".as_bytes()).unwrap();
+        read_response(&stream, &mut reader, &mut wire, |res| {
+            assert_eq!(Some("keep-alive".to_string()), res.headers.get("Connection"));
+        });
+        stream.write("GET / HTTP/1.0\r\nConnection: keep-alive\r\n\r\n".as_bytes()).unwrap();
+        read_response(&stream, &mut reader, &mut wire, |res| {
+            assert_eq!(OK, res.status);
+        });
+    }
+
+    #[test]
+    fn closes_the_connection_after_max_requests_per_connection() {
+        let mut server = Server::with_options(0, ServerOptions { max_requests_per_connection: 2, ..ServerOptions::default() });
+        server.start(|| { Ok(Router {}) }, true).unwrap();
+        let mut stream = TcpStream::connect(format!("127.0.0.1:{}", server.port)).unwrap();
+        let mut reader = [0; 4096];
+        let mut wire = WireState::new();
+
+        stream.write("GET / HTTP/1.1\r\n\r\n".as_bytes()).unwrap();
+        read_response(&stream, &mut reader, &mut wire, |res| {
+            assert_eq!(None, res.headers.get("Connection"));
+        });
+        stream.write("GET / HTTP/1.1\r\n\r\n".as_bytes()).unwrap();
+        read_response(&stream, &mut reader, &mut wire, |res| {
+            assert_eq!(Some("close".to_string()), res.headers.get("Connection"));
+        });
+        assert!(is_closed(&mut stream));
+    }
+
+    #[test]
+    fn closes_an_idle_connection_after_the_keep_alive_timeout() {
+        let mut server = Server::with_options(0, ServerOptions { keep_alive_timeout: Duration::from_millis(50), ..ServerOptions::default() });
+        server.start(|| { Ok(Router {}) }, true).unwrap();
+        let mut stream = TcpStream::connect(format!("127.0.0.1:{}", server.port)).unwrap();
+        let mut reader = [0; 4096];
+        let mut wire = WireState::new();
+
+        stream.write("GET / HTTP/1.1\r\n\r\n".as_bytes()).unwrap();
+        read_response(&stream, &mut reader, &mut wire, |res| {
+            assert_eq!(OK, res.status);
+        });
+        thread::sleep(Duration::from_millis(200));
+        assert!(is_closed(&mut stream));
+    }
+
+    #[test]
+    fn skips_a_request_body_the_handler_did_not_read() {
+        let mut server = Server::new(0);
+        server.start(|| { Ok(Router {}) }, true).unwrap();
+        let mut stream = TcpStream::connect(format!("127.0.0.1:{}", server.port)).unwrap();
+        let mut reader = [0; 4096];
+        let mut wire = WireState::new();
+
+        let unread_body = "t".repeat(20000);
+        stream.write(format!("POST / HTTP/1.1\r\nContent-Length: 20000\r\n\r\n{}", unread_body).as_bytes()).unwrap();
+        read_response(&stream, &mut reader, &mut wire, |res| {
+            assert_eq!(OK, res.status);
+        });
+
+        stream.write("GET /not-found HTTP/1.1\r\n\r\n".as_bytes()).unwrap();
+        read_response(&stream, &mut reader, &mut wire, |res| {
+            assert_eq!("Not found", body_string(res.body).unwrap());
+        });
+    }
+
+    #[test]
+    fn skips_a_chunked_request_body_the_handler_did_not_read() {
+        let mut server = Server::new(0);
+        server.start(|| { Ok(Router {}) }, true).unwrap();
+        let mut stream = TcpStream::connect(format!("127.0.0.1:{}", server.port)).unwrap();
+        let mut reader = [0; 4096];
+        let mut wire = WireState::new();
+
+        stream.write("POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhello\r\n".as_bytes()).unwrap();
+        read_response(&stream, &mut reader, &mut wire, |res| {
+            assert_eq!(OK, res.status);
+        });
+
+        stream.write("7\r\ngoodbye\r\n0\r\nExpires: Wed, 21 Oct 2015 07:28:00 GMT\r\n\r\nGET /not-found HTTP/1.1\r\n\r\n".as_bytes()).unwrap();
+        read_response(&stream, &mut reader, &mut wire, |res| {
+            assert_eq!("Not found", body_string(res.body).unwrap());
+        });
+    }
+}
@@ -2,21 +2,23 @@ mod common;
 
 #[cfg(test)]
 mod tests {
+    use std::io::Write;
+    use std::net::TcpStream;
     use http4r_core::client::Client;
     use http4r_core::handler::Handler;
     use http4r_core::headers::Headers;
     use http4r_core::http_message::Body::BodyString;
-    use http4r_core::http_message::{body_string, Request, Response};
-    use http4r_core::http_message::Status::{BadRequest, OK};
+    use http4r_core::http_message::{body_string, Request, Response, WireState};
+    use http4r_core::http_message::Status::{BadRequest, LengthRequired, OK};
     use http4r_core::server::Server;
     use http4r_core::uri::Uri;
-    use crate::common::PassThroughHandler;
+    use crate::common::{PassThroughHandler, read_response};
 
 
     #[test]
     fn duplicate_different_content_length_headers_result_in_bad_request() {
         let mut server = Server::new(0);
-        server.start(|| { Ok(PassThroughHandler {}) }, true);
+        server.start(|| { Ok(PassThroughHandler {}) }, true).unwrap();
 
         let mut client = Client::new("127.0.0.1", server.port, None);
 
@@ -25,14 +27,14 @@ mod tests {
             vec!(("Content-length", "5"), ("Content-length", "10"))
         ), BodyString("hello")), |response: Response| {
             assert_eq!(BadRequest, response.status);
-            assert_eq!("Content Length header couldn't be parsed, got 5, 10", body_string(response.body));
+            assert_eq!("Content Length header has conflicting values 5, 10", body_string(response.body).unwrap());
         });
     }
 
     #[test]
     fn duplicate_same_content_lengths_are_fine(){
         let mut server = Server::new(0);
-        server.start(|| { Ok(PassThroughHandler {}) }, true);
+        server.start(|| { Ok(PassThroughHandler {}) }, true).unwrap();
 
         let mut client = Client::new("127.0.0.1", server.port, None);
 
@@ -41,14 +43,14 @@ mod tests {
             vec!(("Content-length", "5"), ("Content-length", "5"))
         ), BodyString("hello")), |response: Response| {
             assert_eq!(OK, response.status);
-            assert_eq!("hello", body_string(response.body));
+            assert_eq!("hello", body_string(response.body).unwrap());
         });
     }
 
     #[test]
     fn content_length_must_be_positive(){
         let mut server = Server::new(0);
-        server.start(|| { Ok(PassThroughHandler {}) }, true);
+        server.start(|| { Ok(PassThroughHandler {}) }, true).unwrap();
 
         let mut client = Client::new("127.0.0.1", server.port, None);
 
@@ -57,14 +59,28 @@ mod tests {
             vec!(("Content-length", "-5"))
         ), BodyString("hello")), |response: Response| {
             assert_eq!(BadRequest, response.status);
-            assert_eq!("Content Length header couldn't be parsed, got -5", body_string(response.body));
+            assert_eq!("Content Length header couldn't be parsed, got -5", body_string(response.body).unwrap());
+        });
+    }
+
+    #[test]
+    fn request_without_content_length_or_transfer_encoding_is_length_required() {
+        let mut server = Server::new(0);
+        server.start(|| { Ok(PassThroughHandler {}) }, true).unwrap();
+        let mut stream = TcpStream::connect(format!("127.0.0.1:{}", server.port)).unwrap();
+
+        // unlike a response, a request body cannot be delimited by closing the connection
+        stream.write("POST /bob HTTP/1.1\r\n\r\nhello".as_bytes()).unwrap();
+        read_response(&stream, &mut [0; 4096], &mut WireState::new(), |response| {
+            assert_eq!(LengthRequired, response.status);
+            assert_eq!("Content-Length or Transfer-Encoding must be provided", body_string(response.body).unwrap());
         });
     }
 
     #[test]
     fn duplicate_invalid_lengths_are_invalid() {
         let mut server = Server::new(0);
-        server.start(|| { Ok(PassThroughHandler {}) }, true);
+        server.start(|| { Ok(PassThroughHandler {}) }, true).unwrap();
 
         let mut client = Client::new("127.0.0.1", server.port, None);
 
@@ -73,7 +89,7 @@ mod tests {
             vec!(("Content-length", "-5"), ("Content-length", "-5"))
         ), BodyString("hello")), |response: Response| {
             assert_eq!(BadRequest, response.status);
-            assert_eq!("Content Length header couldn't be parsed, got -5", body_string(response.body));
+            assert_eq!("Content Length header couldn't be parsed, got -5", body_string(response.body).unwrap());
         });
     }
 }
@@ -0,0 +1,199 @@
+mod common;
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::thread;
+    use std::time::{Duration, Instant};
+    use http4r_core::client::{Client, ClientOptions};
+    use http4r_core::handler::Handler;
+    use http4r_core::headers::Headers;
+    use http4r_core::http_message::{body_string, Request, WireState};
+    use http4r_core::http_message::Body::BodyString;
+    use http4r_core::http_message::Status::{ExpectationFailed, NotFound, OK};
+    use http4r_core::server::Server;
+    use http4r_core::uri::Uri;
+    use crate::common::{EchoBodyHandler, read_response, Router};
+
+    /*
+    https://datatracker.ietf.org/doc/html/rfc7231#section-5.1.1
+    A server that receives a 100-continue expectation in an HTTP/1.1
+    request ... MAY omit sending a 100 (Continue) response if it has
+    already received some or all of the message body for the
+    corresponding request, or if the framing indicates that there is no
+    message body.
+    A server MAY omit sending a 100 (Continue) response if it has already
+    decided to respond with a final status code.
+     */
+    #[test]
+    fn server_sends_continue_once_the_handler_reads_the_body() {
+        let mut server = Server::new(0);
+        server.start(|| { Ok(EchoBodyHandler {}) }, true).unwrap();
+        let mut stream = TcpStream::connect(format!("127.0.0.1:{}", server.port)).unwrap();
+        let mut reader = [0; 4096];
+        let mut wire = WireState::new();
+
+        stream.write("POST / HTTP/1.1\r\nContent-Length: 5\r\nExpect: 100-continue\r\n\r\n".as_bytes()).unwrap();
+        let mut interim = [0; 25];
+        stream.read_exact(&mut interim).unwrap();
+        assert_eq!("HTTP/1.1 100 Continue\r\n\r\n", String::from_utf8(interim.to_vec()).unwrap());
+
+        stream.write("hello".as_bytes()).unwrap();
+        read_response(&stream, &mut reader, &mut wire, |res| {
+            assert_eq!(OK, res.status);
+            assert_eq!("hello", body_string(res.body).unwrap());
+        });
+    }
+
+    #[test]
+    fn server_does_not_send_continue_if_the_handler_responds_without_reading_the_body() {
+        let mut server = Server::new(0);
+        server.start(|| { Ok(Router {}) }, true).unwrap();
+        let mut stream = TcpStream::connect(format!("127.0.0.1:{}", server.port)).unwrap();
+        let mut reader = [0; 4096];
+        let mut wire = WireState::new();
+
+        stream.write("POST /not-found HTTP/1.1\r\nContent-Length: 5\r\nExpect: 100-continue\r\n\r\n".as_bytes()).unwrap();
+        read_response(&stream, &mut reader, &mut wire, |res| {
+            assert_eq!(NotFound, res.status);
+            assert_eq!("Not found", body_string(res.body).unwrap());
+        });
+        // we never sent the body, so the server cannot tell where the next request starts
+        let mut buffer = [0; 16];
+        assert_eq!(0, stream.read(&mut buffer).unwrap_or(0));
+    }
+
+    /*
+    https://datatracker.ietf.org/doc/html/rfc7231#section-5.1.1
+    A server that receives an Expect field-value other than 100-continue
+    MAY respond with a 417 (Expectation Failed) status code to indicate
+    that the unexpected expectation cannot be met.
+     */
+    #[test]
+    fn server_responds_expectation_failed_to_anything_but_continue() {
+        let mut server = Server::new(0);
+        server.start(|| { Ok(EchoBodyHandler {}) }, true).unwrap();
+        let mut stream = TcpStream::connect(format!("127.0.0.1:{}", server.port)).unwrap();
+        let mut reader = [0; 4096];
+        let mut wire = WireState::new();
+
+        stream.write("POST / HTTP/1.1\r\nContent-Length: 5\r\nExpect: something-else\r\n\r\nhello".as_bytes()).unwrap();
+        read_response(&stream, &mut reader, &mut wire, |res| {
+            assert_eq!(ExpectationFailed, res.status);
+            assert_eq!("Only 100-continue is supported", body_string(res.body).unwrap());
+        });
+    }
+
+    #[test]
+    fn client_waits_for_continue_before_sending_a_large_body() {
+        let mut server = Server::new(0);
+        server.start(|| { Ok(EchoBodyHandler {}) }, true).unwrap();
+        let mut client = Client::new("127.0.0.1", server.port, Some(ClientOptions {
+            expect_continue_threshold: 10,
+            ..ClientOptions::default()
+        }));
+        let large_body = "t".repeat(100000);
+
+        client.handle(Request::post(Uri::parse("/"), Headers::empty(), BodyString(large_body.as_str())), |res| {
+            assert_eq!(OK, res.status);
+            assert_eq!(large_body, body_string(res.body).unwrap());
+        });
+    }
+
+    #[test]
+    fn client_does_not_send_the_body_if_the_server_responds_without_continue() {
+        let mut server = Server::new(0);
+        server.start(|| { Ok(Router {}) }, true).unwrap();
+        let mut client = Client::new("127.0.0.1", server.port, None);
+
+        client.handle(Request::post(Uri::parse("/not-found"), Headers::from(vec!(("Expect", "100-continue"))), BodyString("hello")), |res| {
+            assert_eq!(NotFound, res.status);
+            assert_eq!("Not found", body_string(res.body).unwrap());
+        });
+    }
+
+    // a server that answers the one request however the test says to, once it has read the head
+    fn answer_by_hand<F>(answer: F) -> (u16, thread::JoinHandle<Vec<u8>>) where F: FnOnce(&mut TcpStream) -> Vec<u8> + Send + 'static {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let answered = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut head = vec!();
+            let mut octet = [0; 1];
+            while !head.ends_with(b"\r\n\r\n") && stream.read(&mut octet).unwrap() == 1 {
+                head.push(octet[0]);
+            }
+            answer(&mut stream)
+        });
+        (port, answered)
+    }
+
+    fn read_body(stream: &mut TcpStream) -> Vec<u8> {
+        let mut body = vec![0; 5];
+        stream.read_exact(&mut body).unwrap();
+        body
+    }
+
+    #[test]
+    fn client_waits_for_the_whole_status_line_without_giving_up_on_it() {
+        let (port, answered) = answer_by_hand(|stream| {
+            stream.write_all(b"HTTP/1.1 1").unwrap();
+            thread::sleep(Duration::from_millis(100));
+            stream.write_all(b"00 Continue\r\n\r\n").unwrap();
+            let body = read_body(stream);
+            stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok").unwrap();
+            body
+        });
+        let mut client = Client::new("127.0.0.1", port, None);
+
+        client.handle(Request::post(Uri::parse("/"), Headers::from(vec!(("Expect", "100-continue"))), BodyString("hello")), |res| {
+            assert_eq!(OK, res.status);
+            assert_eq!("ok", body_string(res.body).unwrap());
+        });
+        assert_eq!(b"hello".to_vec(), answered.join().unwrap());
+    }
+
+    #[test]
+    fn client_reads_a_final_status_that_arrives_in_pieces_as_the_response() {
+        let (port, answered) = answer_by_hand(|stream| {
+            stream.write_all(b"HTTP/1.1 4").unwrap();
+            thread::sleep(Duration::from_millis(100));
+            stream.write_all(b"17 Expectation Failed\r\nContent-Length: 0\r\n\r\n").unwrap();
+            stream.set_read_timeout(Some(Duration::from_millis(200))).unwrap();
+            let mut body = vec!();
+            let _ = stream.read_to_end(&mut body);
+            body
+        });
+        let mut client = Client::new("127.0.0.1", port, None);
+
+        client.handle(Request::post(Uri::parse("/"), Headers::from(vec!(("Expect", "100-continue"))), BodyString("hello")), |res| {
+            assert_eq!(ExpectationFailed, res.status);
+        });
+        // and the body was never sent
+        assert!(answered.join().unwrap().is_empty());
+    }
+
+    #[test]
+    fn client_only_waits_for_continue_until_the_timeout_however_the_status_line_arrives() {
+        let (port, answered) = answer_by_hand(|stream| {
+            // a status line that starts but does not finish until the body has arrived
+            stream.write_all(b"HTTP/1.1 2").unwrap();
+            let body = read_body(stream);
+            stream.write_all(b"00 OK\r\nContent-Length: 2\r\n\r\nok").unwrap();
+            body
+        });
+        let mut client = Client::new("127.0.0.1", port, Some(ClientOptions {
+            expect_continue_timeout: Duration::from_millis(200),
+            ..ClientOptions::default()
+        }));
+        let started = Instant::now();
+
+        client.handle(Request::post(Uri::parse("/"), Headers::from(vec!(("Expect", "100-continue"))), BodyString("hello")), |res| {
+            assert_eq!(OK, res.status);
+            assert_eq!("ok", body_string(res.body).unwrap());
+        });
+        assert!(started.elapsed() < Duration::from_secs(1));
+        assert_eq!(b"hello".to_vec(), answered.join().unwrap());
+    }
+}
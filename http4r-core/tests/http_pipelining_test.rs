@@ -0,0 +1,90 @@
+mod common;
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+    use std::net::TcpStream;
+    use http4r_core::http_message::{body_string, WireState};
+    use http4r_core::http_message::Status::OK;
+    use http4r_core::server::Server;
+    use crate::common::{PassThroughHandler, read_response};
+
+    /*
+    https://datatracker.ietf.org/doc/html/rfc7230#section-6.3.2
+
+    A client that supports persistent connections MAY "pipeline" its
+       requests (i.e., send multiple requests without waiting for each
+       response).  A server MAY process a sequence of pipelined requests in
+       parallel if they all have safe methods (Section 4.2.1 of [RFC7231]),
+       but it MUST send the corresponding responses in the same order that
+       the requests were received.
+     */
+    #[test]
+    fn responds_to_pipelined_requests_in_order() {
+        let mut server = Server::new(0);
+        server.start(|| { Ok(PassThroughHandler {}) }, true).unwrap();
+        let mut stream = TcpStream::connect(format!("127.0.0.1:{}", server.port)).unwrap();
+        let mut reader = [0; 4096];
+        let mut wire = WireState::new();
+
+        stream.write(("POST / HTTP/1.1\r\nContent-Length: 3\r\n\r\none".to_owned()
+            + "POST / HTTP/1.1\r\nContent-Length: 3\r\n\r\ntwo"
+            + "POST / HTTP/1.1\r\nContent-Length: 5\r\n\r\nthree").as_bytes()).unwrap();
+
+        for expected in vec!("one", "two", "three") {
+            read_response(&stream, &mut reader, &mut wire, |res| {
+                assert_eq!(OK, res.status);
+                assert_eq!(expected, body_string(res.body).unwrap());
+            });
+        }
+    }
+
+    #[test]
+    fn pipelined_chunked_requests_keep_their_own_bodies_and_trailers() {
+        let mut server = Server::new(0);
+        server.start(|| { Ok(PassThroughHandler {}) }, true).unwrap();
+        let mut stream = TcpStream::connect(format!("127.0.0.1:{}", server.port)).unwrap();
+        let mut reader = [0; 4096];
+        let mut wire = WireState::new();
+
+        stream.write(("POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\nTrailer: Expires\r\n\r\n5\r\nhello\r\n0\r\nExpires: tomorrow\r\n\r\n".to_owned()
+            + "POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n3\r\nbye\r\n0\r\n\r\n"
+            + "POST / HTTP/1.1\r\nContent-Length: 4\r\n\r\nlast").as_bytes()).unwrap();
+
+        read_response(&stream, &mut reader, &mut wire, |res| {
+            assert_eq!("hello", body_string(res.body).unwrap());
+            assert_eq!(Some("tomorrow".to_string()), res.headers.get("Expires"));
+        });
+        read_response(&stream, &mut reader, &mut wire, |res| {
+            assert_eq!("bye", body_string(res.body).unwrap());
+            assert_eq!(None, res.headers.get("Expires"));
+        });
+        read_response(&stream, &mut reader, &mut wire, |res| {
+            assert_eq!("last", body_string(res.body).unwrap());
+        });
+    }
+
+    #[test]
+    fn pipelined_requests_can_be_split_anywhere_across_reads() {
+        let mut server = Server::new(0);
+        server.start(|| { Ok(PassThroughHandler {}) }, true).unwrap();
+        let mut stream = TcpStream::connect(format!("127.0.0.1:{}", server.port)).unwrap();
+        stream.set_nodelay(true).unwrap();
+        let mut reader = [0; 4096];
+        let mut wire = WireState::new();
+
+        let requests = "POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\nTrailer: Expires\r\n\r\nA\r\nhello\r\n\r\n\r\n0\r\nExpires: tomorrow\r\n\r\n".to_owned()
+            + "POST / HTTP/1.1\r\nContent-Length: 5\r\n\r\nworld";
+        for byte in requests.as_bytes() {
+            stream.write(&[*byte]).unwrap();
+        }
+
+        read_response(&stream, &mut reader, &mut wire, |res| {
+            assert_eq!("hello\r\n\r\n\r", body_string(res.body).unwrap());
+            assert_eq!(Some("tomorrow".to_string()), res.headers.get("Expires"));
+        });
+        read_response(&stream, &mut reader, &mut wire, |res| {
+            assert_eq!("world", body_string(res.body).unwrap());
+        });
+    }
+}
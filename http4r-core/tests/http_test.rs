@@ -8,8 +8,8 @@ mod tests {
     use std::collections::HashMap;
     use http4r_core::client::{Client};
     use http4r_core::headers::Headers;
-    use http4r_core::http_message::{body_string, Request, Response};
-    use http4r_core::http_message::Body::{BodyStream, BodyString};
+    use http4r_core::http_message::{body_string, MessageError, Request, Response};
+    use http4r_core::http_message::Body::{BodyBytes, BodyStream, BodyString};
     use http4r_core::server::Server;
     use http4r_core::uri::Uri;
     use crate::common::{LoggingHttpHandler, PassThroughHandler, RedirectToHttpsHandler, Router, RustLogger, WasmClock};
@@ -18,14 +18,14 @@ mod tests {
     #[test]
     fn client_over_http_get() {
         let mut server = Server::new(0);
-        server.start(|| { Ok(PassThroughHandler {}) }, true);
+        server.start(|| { Ok(PassThroughHandler {}) }, true).unwrap();
         let mut client = Client::new("127.0.0.1", server.port, None);
         let request = Request::get(Uri::parse("/"), Headers::empty());
 
         client.handle(request, |response: Response| {
             assert_eq!("OK", response.status.to_string());
             assert_eq!(format!("Host: 127.0.0.1:{}\r\nContent-Length: 0", server.port), response.headers.to_wire_string());
-            assert_eq!("".to_string(), body_string(response.body));
+            assert_eq!("".to_string(), body_string(response.body).unwrap());
         });
     }
 
@@ -34,7 +34,7 @@ mod tests {
         let long_string = "t".repeat(20000);
 
         let mut server = Server::new(0);
-        server.start(|| { Ok(PassThroughHandler {}) }, true);
+        server.start(|| { Ok(PassThroughHandler {}) }, true).unwrap();
         let mut client = Client::new("127.0.0.1", server.port, None);
         let post_with_stream_body = Request::post(
             Uri::parse("/"),
@@ -44,8 +44,9 @@ mod tests {
         client.handle(post_with_stream_body, |response| {
             match response.body {
                 BodyString(_) => panic!("Should not be BodyString"),
+                BodyBytes(_) => panic!("Should not be BodyBytes"),
                 BodyStream(s) => {
-                    let string = body_string(BodyStream(s));
+                    let string = body_string(BodyStream(s)).unwrap();
                     assert_eq!(20000, string.len());
                     assert_eq!(string, long_string.to_string());
                     assert_eq!("OK", response.status.to_string());
@@ -55,17 +56,77 @@ mod tests {
         });
     }
 
+    #[test]
+    fn binary_bodies_are_given_to_you_as_bytes() {
+        let mut server = Server::new(0);
+        server.start(|| { Ok(PassThroughHandler {}) }, true).unwrap();
+        let mut client = Client::new("127.0.0.1", server.port, None);
+        // the start of a png, which is not valid utf-8
+        let png: [u8; 8] = [0x89, 0x50, 0x4e, 0x47, 0x0d, 0x0a, 0x1a, 0x0a];
+        let request = Request::post(
+            Uri::parse("/"),
+            Headers::from(vec!(("Content-Type", "image/png"))),
+            BodyBytes(&png));
+
+        client.handle(request, |response| {
+            assert_eq!(OK, response.status);
+            match response.body {
+                BodyBytes(bytes) => assert_eq!(png, bytes),
+                _ => panic!("Should be BodyBytes"),
+            }
+        });
+    }
+
+    #[test]
+    fn only_textual_content_types_are_given_to_you_as_a_string() {
+        let mut server = Server::new(0);
+        server.start(|| { Ok(PassThroughHandler {}) }, true).unwrap();
+        let mut client = Client::new("127.0.0.1", server.port, None);
+
+        let octets = Request::post(
+            Uri::parse("/"),
+            Headers::from(vec!(("Content-Type", "application/octet-stream"))),
+            BodyString("valid utf-8"));
+        client.handle(octets, |response| {
+            assert!(response.body.is_body_bytes());
+            assert_eq!("valid utf-8", body_string(response.body).unwrap());
+        });
+
+        let json = Request::post(
+            Uri::parse("/"),
+            Headers::from(vec!(("Content-Type", "application/json; charset=utf-8"))),
+            BodyString("{}"));
+        client.handle(json, |response| {
+            assert!(response.body.is_body_string());
+            assert_eq!("{}", body_string(response.body).unwrap());
+        });
+    }
+
+    #[test]
+    fn body_string_of_a_stream_is_lossy_but_says_if_it_could_not_be_read() {
+        let not_utf8: &[u8] = &[b'a', 0xff, b'b'];
+        assert_eq!("a\u{fffd}b", body_string(BodyStream(Box::new(not_utf8))).unwrap());
+
+        struct Broken {}
+        impl std::io::Read for Broken {
+            fn read(&mut self, _buf: &mut [u8]) -> std::io::Result<usize> {
+                Err(std::io::Error::new(std::io::ErrorKind::ConnectionReset, "connection went away"))
+            }
+        }
+        assert!(matches!(body_string(BodyStream(Box::new(Broken {}))), Err(MessageError::IoError(_))));
+    }
+
     #[test]
     fn can_handle_no_headers() {
         let mut server = Server::new(0);
-        server.start(|| { Ok(PassThroughHandler {}) }, true);
+        server.start(|| { Ok(PassThroughHandler {}) }, true).unwrap();
         let mut client = Client::new("127.0.0.1", server.port, None);
         let no_headers = Request::get(Uri::parse("/"), Headers::empty());
 
         client.handle(no_headers, |response: Response| {
             assert_eq!("OK", response.status.to_string());
             assert_eq!(format!("Host: 127.0.0.1:{}\r\nContent-Length: 0", server.port), response.headers.to_wire_string());
-            assert_eq!("".to_string(), body_string(response.body));
+            assert_eq!("".to_string(), body_string(response.body).unwrap());
         });
     }
 
@@ -88,14 +149,14 @@ mod tests {
 
         //http
         let mut server = Server::new(0);
-        server.start(|| Ok(RedirectToHttpsHandler::new(LoggingHttpHandler::new(RustLogger {}, WasmClock {}, Router {}), HashMap::new())), true);
+        server.start(|| Ok(RedirectToHttpsHandler::new(LoggingHttpHandler::new(RustLogger {}, WasmClock {}, Router {}), HashMap::new())), true).unwrap();
         let mut client = Client::new("127.0.0.1", server.port, None);
         let request = Request::get(Uri::parse("/"), Headers::empty());
 
         client.handle(request, |response: Response| {
             assert_eq!("OK", response.status.to_string());
             assert_eq!("Content-Length: 0", response.headers.to_wire_string());
-            assert_eq!("".to_string(), body_string(response.body));
+            assert_eq!("".to_string(), body_string(response.body).unwrap());
         });
     }
 }
@@ -2,7 +2,7 @@ mod common;
 
 #[cfg(test)]
 mod tests {
-    use std::io::{Cursor};
+    use std::io::{Cursor, Read, Write};
     use std::net::TcpStream;
     use http4r_core::client::Client;
     use http4r_core::handler::Handler;
@@ -13,7 +13,18 @@ mod tests {
     use http4r_core::server::Server;
     use http4r_core::uri::Uri;
 
-    use crate::common::{MalformedChunkedEncodingClient, PassThroughHandler};
+    use crate::common::{MalformedChunkedEncodingClient, PassThroughHandler, ReadWholeBodyHandler};
+
+    fn read_until(stream: &mut TcpStream, ending: &str) -> String {
+        let mut read = Vec::new();
+        let mut buffer = [0; 1024];
+        while !String::from_utf8_lossy(read.as_slice()).ends_with(ending) {
+            let bytes_read = stream.read(&mut buffer).unwrap();
+            assert!(bytes_read > 0, "Connection closed before we read {}", ending);
+            read.extend_from_slice(&buffer[..bytes_read]);
+        }
+        String::from_utf8(read).unwrap()
+    }
 
     /*
         If a message is received with both a Transfer-Encoding and a
@@ -27,7 +38,7 @@ mod tests {
     #[test]
     fn transfer_encoding_wins_over_content_length() {
         let mut server = Server::new(0);
-        server.start(|| { Ok(PassThroughHandler {}) }, true);
+        server.start(|| { Ok(PassThroughHandler {}) }, true).unwrap();
 
         let mut client = Client::new("127.0.0.1", server.port, None);
 
@@ -39,7 +50,7 @@ mod tests {
             BodyString(little_string));
 
         client.handle(big_chunked_request, |response: Response| {
-            assert_eq!(little_string, body_string(response.body));
+            assert_eq!(little_string, body_string(response.body).unwrap());
             assert_eq!(OK, response.status);
             assert_eq!(vec!(
                 ("Transfer-Encoding".to_string(), "chunked".to_string()),
@@ -51,7 +62,7 @@ mod tests {
     #[test]
     fn large_chunked_request() {
         let mut server = Server::new(0);
-        server.start(|| { Ok(PassThroughHandler {}) }, true);
+        server.start(|| { Ok(PassThroughHandler {}) }, true).unwrap();
 
         let mut client = Client::new("127.0.0.1", server.port, None);
 
@@ -63,7 +74,7 @@ mod tests {
             BodyString(long_string.as_str()));
 
         client.handle(big_chunked_request, |response: Response| {
-            assert_eq!(long_string, body_string(response.body));
+            assert_eq!(long_string, body_string(response.body).unwrap());
             assert_eq!(OK, response.status);
             assert_eq!(vec!(
                 ("Transfer-Encoding".to_string(), "chunked".to_string()),
@@ -75,7 +86,7 @@ mod tests {
     #[test]
     fn can_include_boundary_characters_in_chunk() {
         let mut server = Server::new(0);
-        server.start(|| { Ok(PassThroughHandler {}) }, true);
+        server.start(|| { Ok(PassThroughHandler {}) }, true).unwrap();
 
         let mut client = Client::new("127.0.0.1", server.port, None);
         let with_encoding = "hello\r\n";
@@ -87,7 +98,7 @@ mod tests {
 
         client.handle(big_chunked_request, |response: Response| {
             assert_eq!(OK, response.status);
-            assert_eq!(with_encoding, body_string(response.body));
+            assert_eq!(with_encoding, body_string(response.body).unwrap());
             assert_eq!(vec!(
                 ("Transfer-Encoding".to_string(), "chunked".to_string()),
                 ("Host".to_string(), format!("127.0.0.1:{}", server.port)),
@@ -110,7 +121,7 @@ mod tests {
     #[test]
     fn supports_trailers_parsing_into_a_body() {
         let mut server = Server::new(0);
-        server.start(|| { Ok(PassThroughHandler {}) }, true);
+        server.start(|| { Ok(PassThroughHandler {}) }, true).unwrap();
 
         let mut client = Client::new("127.0.0.1", server.port, None);
 
@@ -130,7 +141,7 @@ mod tests {
 
         client.handle(with_trailer, |response: Response| {
             assert_eq!(OK, response.status);
-            assert_eq!(little_string, body_string(response.body));
+            assert_eq!(little_string, body_string(response.body).unwrap());
             assert_eq!(vec!(
                 ("Expires".to_string(), "Wed, 21 Oct 2015 07:28:00 GMT".to_string()),
                 ("Integrity".to_string(), "Some hash".to_string()),
@@ -163,7 +174,7 @@ When a chunked message containing a non-empty trailer is received,
     #[test]
     fn cannot_set_certain_trailers_as_headers() {
         let mut server = Server::new(0);
-        server.start(|| { Ok(PassThroughHandler {}) }, true);
+        server.start(|| { Ok(PassThroughHandler {}) }, true).unwrap();
 
         let mut client = Client::new("127.0.0.1", server.port, None);
 
@@ -193,8 +204,8 @@ When a chunked message containing a non-empty trailer is received,
 
         client.handle(with_illegal_trailers, |response: Response| {
             assert_eq!(OK, response.status);
-            assert_eq!(little_string, body_string(response.body));
-            assert!(response.trailers.vec.is_empty()); // trailers are empty
+            assert_eq!(little_string, body_string(response.body).unwrap());
+            assert!(response.trailers.get().vec.is_empty()); // trailers are empty
             assert_eq!(vec!(
                 ("Expires".to_string(), "Wed, 21 Oct 2015 07:28:00 GMT".to_string()), // valid trailer gets added to header
                 ("Transfer-Encoding".to_string(), "chunked".to_string()),
@@ -218,7 +229,7 @@ When a chunked message containing a non-empty trailer is received,
     #[test]
     fn dont_get_trailers_unless_TE_specified() {
         let mut server = Server::new(0);
-        server.start(|| { Ok(PassThroughHandler {}) }, true);
+        server.start(|| { Ok(PassThroughHandler {}) }, true).unwrap();
         let mut client = Client::new("127.0.0.1", server.port, None);
 
         let body = "hello";
@@ -236,11 +247,11 @@ When a chunked message containing a non-empty trailer is received,
 
         client.handle(do_not_want_trailers, |response: Response| {
             assert_eq!(OK, response.status);
-            assert_eq!(body, body_string(response.body));
+            assert_eq!(body, body_string(response.body).unwrap());
             let vec1: Vec<HeaderType> = vec!(
                 // => no Expires trailer in trailers
             );
-            assert_eq!(vec1, response.trailers.vec);
+            assert_eq!(vec1, response.trailers.get().vec);
             assert_eq!(vec!(
                 //Expires is in headers not trailers now
                 ("Expires".to_string(), "Wed, 21 Oct 2015 07:28:00 GMT".to_string()),
@@ -264,10 +275,10 @@ When a chunked message containing a non-empty trailer is received,
 
         client.handle(asks_for_trailers, |response: Response| {
             assert_eq!(OK, response.status);
-            assert_eq!(body, body_string(response.body));
+            assert_eq!(body, body_string(response.body).unwrap());
             assert_eq!(vec!(
                 ("Expires".to_string(), "Wed, 21 Oct 2015 07:28:00 GMT".to_string()),
-            ), response.trailers.vec);
+            ), response.trailers.get().vec);
             assert_eq!(vec!(
                 //Expires should be in trailers
                 ("Transfer-Encoding".to_string(), "chunked".to_string()),
@@ -336,7 +347,7 @@ When a chunked message containing a non-empty trailer is received,
     #[test]
     fn TE_with_ranked_compression() {
         let mut server = Server::new(0);
-        server.start(|| { Ok(PassThroughHandler {}) }, true);
+        server.start(|| { Ok(PassThroughHandler {}) }, true).unwrap();
         let mut client = Client::new("127.0.0.1", server.port, None);
 
         let body = "hello".repeat(10000);
@@ -354,7 +365,7 @@ When a chunked message containing a non-empty trailer is received,
         )));
 
         client.handle(chunked_with_TE_gzip, |response: Response| {
-            assert_eq!(body_string(response.body), body);
+            assert_eq!(body_string(response.body).unwrap(), body);
             assert_eq!(OK, response.status);
             assert_eq!(vec!(
                 ("Transfer-Encoding".to_string(), "gzip, chunked".to_string()),
@@ -362,7 +373,7 @@ When a chunked message containing a non-empty trailer is received,
                 ("Host".to_string(), format!("127.0.0.1:{}", server.port)),
             ), response.headers.vec);
             assert_eq!(vec!(("Expires".to_string(), "Wed, 21 Oct 2015 07:28:00 GMT".to_string())),
-                       response.trailers.vec);
+                       response.trailers.get().vec);
         });
 
         let chunked_with_TE_deflate = Request::post(
@@ -379,7 +390,7 @@ When a chunked message containing a non-empty trailer is received,
         )));
 
         client.handle(chunked_with_TE_deflate, |response: Response| {
-            assert_eq!(body_string(response.body), body);
+            assert_eq!(body_string(response.body).unwrap(), body);
             assert_eq!(OK, response.status);
             assert_eq!(vec!(
                 ("Transfer-Encoding".to_string(), "deflate, chunked".to_string()),
@@ -387,7 +398,7 @@ When a chunked message containing a non-empty trailer is received,
                 ("Host".to_string(), format!("127.0.0.1:{}", server.port)),
             ), response.headers.vec);
             assert_eq!(vec!(("Expires".to_string(), "Wed, 21 Oct 2015 07:28:00 GMT".to_string())),
-                       response.trailers.vec);
+                       response.trailers.get().vec);
         });
 
         let chunked_with_TE_brotli = Request::post(
@@ -404,7 +415,7 @@ When a chunked message containing a non-empty trailer is received,
         )));
 
         client.handle(chunked_with_TE_brotli, |response: Response| {
-            assert_eq!(body_string(response.body), body);
+            assert_eq!(body_string(response.body).unwrap(), body);
             assert_eq!(OK, response.status);
             assert_eq!(vec!(
                 ("Transfer-Encoding".to_string(), "brotli, chunked".to_string()),
@@ -413,14 +424,15 @@ When a chunked message containing a non-empty trailer is received,
                 // TE header does not get echoed back in response
             ), response.headers.vec);
             assert_eq!(vec!(("Expires".to_string(), "Wed, 21 Oct 2015 07:28:00 GMT".to_string())),
-                       response.trailers.vec);
+                       response.trailers.get().vec);
         });
     }
 
     #[test]
     fn if_trailers_are_too_long_we_get_an_error() {
         let mut server = Server::new(0);
-        server.start(|| { Ok(PassThroughHandler {}) }, true);
+        // the trailers are only read once the handler reads to the end of the body
+        server.start(|| { Ok(ReadWholeBodyHandler {}) }, true).unwrap();
         let mut client = Client::new("127.0.0.1", server.port, None);
 
         let very_long_trailer = "A very long trailer. ".repeat(1000);
@@ -438,26 +450,44 @@ When a chunked message containing a non-empty trailer is received,
         )));
 
         client.handle(asks_for_trailers, |response: Response| {
-            assert_eq!("Trailers must be less than 16384", body_string(response.body));
+            assert_eq!("Trailers must be less than 16384", body_string(response.body).unwrap());
             assert_eq!(BadRequest, response.status);
             assert_eq!(vec!(
                 ("Content-Length".to_string(), "32".to_string()),
+                ("Content-Encoding".to_string(), "deflate".to_string()),
             ), response.headers.vec);
             let vec1: Vec<HeaderType> = vec!();
-            assert_eq!(vec1, response.trailers.vec);
+            assert_eq!(vec1, response.trailers.get().vec);
         });
     }
 
+    #[test]
+    fn chunked_bodies_are_streamed_to_the_handler_as_they_arrive() {
+        let mut server = Server::new(0);
+        server.start(|| { Ok(PassThroughHandler {}) }, true).unwrap();
+        let mut stream = TcpStream::connect(format!("127.0.0.1:{}", server.port)).unwrap();
+
+        stream.write("POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\nTrailer: Expires\r\nTE: trailers\r\n\r\n5\r\nhello\r\n".as_bytes()).unwrap();
+        // the response starts before we have sent the rest of the body
+        let start_of_response = read_until(&mut stream, "5\r\nhello\r\n");
+        assert!(start_of_response.starts_with("HTTP/1.1 200 OK\r\n"));
+
+        stream.write("7\r\ngoodbye\r\n0\r\nExpires: Wed, 21 Oct 2015 07:28:00 GMT\r\n\r\n".as_bytes()).unwrap();
+        // and the trailers are passed on once the handler gets to the end of the body
+        let rest_of_response = read_until(&mut stream, "\r\n\r\n");
+        assert_eq!("7\r\ngoodbye\r\n0\r\nExpires: Wed, 21 Oct 2015 07:28:00 GMT\r\n\r\n", rest_of_response);
+    }
+
     #[test]
     fn best_request_if_invalid_boundary_digit() {
         let mut server = Server::new(0);
-        server.start(|| { Ok(PassThroughHandler {}) }, true);
+        server.start(|| { Ok(PassThroughHandler {}) }, true).unwrap();
         TcpStream::connect(format!("127.0.0.1:{}", server.port)).unwrap();
 
         let mut client = MalformedChunkedEncodingClient { port: server.port };
 
         client.handle(Request::get(Uri::parse("/"), Headers::from(vec!(("Transfer-encoding" , "chunked")))), |res| {
-            assert_eq!(body_string(res.body), "Could not parse boundary character X in chunked encoding".to_string());
+            assert_eq!(body_string(res.body).unwrap(), "Could not parse boundary character X in chunked encoding".to_string());
             assert_eq!(res.status, Status::BadRequest);
         })
     }
@@ -465,7 +495,7 @@ When a chunked message containing a non-empty trailer is received,
     #[test]
     fn writing_a_chunked_body_stream() {
         let mut server = Server::new(0);
-        server.start(|| { Ok(PassThroughHandler {}) }, true);
+        server.start(|| { Ok(PassThroughHandler {}) }, true).unwrap();
 
         let mut client = Client::new("127.0.0.1", server.port, None);
 
@@ -478,7 +508,7 @@ When a chunked message containing a non-empty trailer is received,
             BodyStream(Box::new(Cursor::new(little_string))));
 
         client.handle(big_chunked_request, |response: Response| {
-            assert_eq!(string1, body_string(response.body));
+            assert_eq!(string1, body_string(response.body).unwrap());
             assert_eq!(OK, response.status);
             assert_eq!(vec!(
                 ("Transfer-Encoding".to_string(), "chunked".to_string()),
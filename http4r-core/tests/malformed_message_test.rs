@@ -0,0 +1,88 @@
+mod common;
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+    use std::net::TcpStream;
+    use http4r_core::http_message::{body_string, Status, WireState};
+    use http4r_core::http_message::Status::{BadRequest, HttpVersionNotSupported, NotImplemented, OK};
+    use http4r_core::server::Server;
+    use crate::common::{PassThroughHandler, read_response};
+
+    fn respond_to(port: u16, message: &[u8], expected: Status, expected_body: &str) {
+        let mut stream = TcpStream::connect(format!("127.0.0.1:{}", port)).unwrap();
+        let mut reader = [0; 4096];
+        let mut wire = WireState::new();
+
+        stream.write(message).unwrap();
+        read_response(&stream, &mut reader, &mut wire, |res| {
+            assert_eq!(expected, res.status);
+            assert_eq!(expected_body, body_string(res.body).unwrap());
+        });
+    }
+
+    /*
+    https://datatracker.ietf.org/doc/html/rfc7230#section-3.1.1
+    Recipients of an invalid request-line SHOULD respond with either a
+    400 (Bad Request) error or a 301 (Moved Permanently) redirect with
+    the request-target properly encoded.
+     */
+    #[test]
+    fn malformed_start_lines_are_a_bad_request() {
+        let mut server = Server::new(0);
+        server.start(|| { Ok(PassThroughHandler {}) }, true).unwrap();
+
+        respond_to(server.port, "hello\r\n\r\n".as_bytes(), BadRequest, "Malformed start line hello");
+        respond_to(server.port, "GET /\r\n\r\n".as_bytes(), BadRequest, "Malformed start line GET /");
+        respond_to(server.port, "GET  / HTTP/1.1\r\n\r\n".as_bytes(), BadRequest, "Malformed start line GET  / HTTP/1.1");
+        respond_to(server.port, "GET / HTTP/one\r\n\r\n".as_bytes(), BadRequest, "Invalid http version HTTP/one");
+        respond_to(server.port, &[b'G', b'E', b'T', b' ', 0xff, b' ', b'H', b'\r', b'\n', b'\r', b'\n'], BadRequest, "Start line is not valid utf-8");
+
+        // and the server is still up
+        respond_to(server.port, "GET / HTTP/1.1\r\n\r\n".as_bytes(), OK, "");
+    }
+
+    /*
+    https://datatracker.ietf.org/doc/html/rfc7230#section-3.2.4
+    A server MUST respond with a 400 (Bad Request) to any received request message
+    that is not a valid header field.
+     */
+    #[test]
+    fn malformed_headers_are_a_bad_request() {
+        let mut server = Server::new(0);
+        server.start(|| { Ok(PassThroughHandler {}) }, true).unwrap();
+
+        respond_to(server.port, "GET / HTTP/1.1\r\nno colon\r\n\r\n".as_bytes(), BadRequest, "Malformed header no colon");
+        respond_to(server.port, "GET / HTTP/1.1\r\n: no name\r\n\r\n".as_bytes(), BadRequest, "Malformed header : no name");
+        respond_to(server.port, &[b'G', b'E', b'T', b' ', b'/', b' ', b'H', b'T', b'T', b'P', b'/', b'1', b'.', b'1', b'\r', b'\n', b'a', b':', 0xff, b'\r', b'\n', b'\r', b'\n'], BadRequest, "Headers are not valid utf-8");
+    }
+
+    /*
+    https://datatracker.ietf.org/doc/html/rfc7231#section-4.1
+    An origin server that receives a request method that is unrecognized
+    or not implemented SHOULD respond with the 501 (Not Implemented)
+    status code.
+     */
+    #[test]
+    fn unknown_methods_are_not_implemented() {
+        let mut server = Server::new(0);
+        server.start(|| { Ok(PassThroughHandler {}) }, true).unwrap();
+
+        respond_to(server.port, "BR(W / HTTP/1.1\r\n\r\n".as_bytes(), NotImplemented, "Unknown method BR(W");
+    }
+
+    /*
+    https://datatracker.ietf.org/doc/html/rfc7231#section-6.6.6
+    The 505 (HTTP Version Not Supported) status code indicates that the
+    server does not support, or refuses to support, the major version of
+    HTTP that was used in the request message.
+     */
+    #[test]
+    fn other_major_versions_are_not_supported() {
+        let mut server = Server::new(0);
+        server.start(|| { Ok(PassThroughHandler {}) }, true).unwrap();
+
+        respond_to(server.port, "GET / HTTP/2.0\r\n\r\n".as_bytes(), HttpVersionNotSupported, "Only HTTP/1.x is supported, got HTTP/2.0");
+        respond_to(server.port, "GET / HTTP/1.9\r\n\r\n".as_bytes(), OK, "");
+    }
+}
@@ -0,0 +1,158 @@
+mod common;
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+    use std::net::TcpStream;
+    use http4r_core::client::{Client, ClientOptions};
+    use http4r_core::handler::Handler;
+    use http4r_core::headers::Headers;
+    use http4r_core::http_message::{body_string, Request, WireState};
+    use http4r_core::http_message::Body::BodyString;
+    use http4r_core::http_message::Status::{BadRequest, OK, ContentTooLarge, RequestHeaderFieldsTooLarge, URITooLong};
+    use http4r_core::server::{Server, ServerOptions};
+    use http4r_core::uri::Uri;
+    use crate::common::{PassThroughHandler, read_response, ReadWholeBodyHandler};
+
+    fn server_with(options: ServerOptions) -> Server {
+        let mut server = Server::with_options(0, options);
+        server.start(|| { Ok(ReadWholeBodyHandler {}) }, true).unwrap();
+        server
+    }
+
+    /*
+    https://www.rfc-editor.org/rfc/rfc9112#section-3
+    A server that receives a request-target longer than any URI it wishes to parse
+    MUST respond with a 414 (URI Too Long) status code.
+     */
+    #[test]
+    fn request_line_that_is_too_long_is_uri_too_long() {
+        let server = server_with(ServerOptions { request_line_size: 64, ..ServerOptions::default() });
+        let mut client = Client::new("127.0.0.1", server.port, None);
+
+        let long_path = format!("/{}", "a".repeat(64));
+        client.handle(Request::get(Uri::parse(long_path.as_str()), Headers::empty()), |res| {
+            assert_eq!(URITooLong, res.status);
+            assert_eq!("Start line must be less than 64", body_string(res.body).unwrap());
+        });
+        client.handle(Request::get(Uri::parse("/short"), Headers::empty()), |res| {
+            assert_eq!(OK, res.status);
+        });
+    }
+
+    /*
+    https://www.rfc-editor.org/rfc/rfc6585#section-5
+    The 431 status code indicates that the server is unwilling to process
+    the request because its header fields are too large.
+     */
+    #[test]
+    fn headers_that_are_too_big_are_request_header_fields_too_large() {
+        let server = server_with(ServerOptions { headers_size: 128, ..ServerOptions::default() });
+        let mut client = Client::new("127.0.0.1", server.port, None);
+
+        let big_header = "a".repeat(128);
+        client.handle(Request::get(Uri::parse("/"), Headers::from(vec!(("Big", big_header.as_str())))), |res| {
+            assert_eq!(RequestHeaderFieldsTooLarge, res.status);
+            assert_eq!("Headers must be less than 128", body_string(res.body).unwrap());
+        });
+    }
+
+    #[test]
+    fn too_many_headers_are_request_header_fields_too_large() {
+        let server = server_with(ServerOptions { max_headers: 3, ..ServerOptions::default() });
+        let mut stream = TcpStream::connect(format!("127.0.0.1:{}", server.port)).unwrap();
+        let mut reader = [0; 4096];
+        let mut wire = WireState::new();
+
+        stream.write_all("GET / HTTP/1.1\r\nOne: 1\r\nTwo: 2\r\nThree: 3\r\n\r\n".as_bytes()).unwrap();
+        read_response(&stream, &mut reader, &mut wire, |res| {
+            assert_eq!(OK, res.status);
+        });
+        stream.write_all("GET / HTTP/1.1\r\nOne: 1\r\nTwo: 2\r\nThree: 3\r\nFour: 4\r\n\r\n".as_bytes()).unwrap();
+        read_response(&stream, &mut reader, &mut wire, |res| {
+            assert_eq!(RequestHeaderFieldsTooLarge, res.status);
+            assert_eq!("There must be no more than 3 headers", body_string(res.body).unwrap());
+        });
+    }
+
+    /*
+    https://www.rfc-editor.org/rfc/rfc9110#section-15.5.14
+    The 413 (Content Too Large) status code indicates that the server is refusing to process
+    a request because the request content is larger than the server is willing or able to process.
+     */
+    #[test]
+    fn content_length_that_is_too_big_is_content_too_large() {
+        let server = server_with(ServerOptions { max_body_size: 10, ..ServerOptions::default() });
+        let mut client = Client::new("127.0.0.1", server.port, None);
+
+        client.handle(Request::post(Uri::parse("/"), Headers::empty(), BodyString("ten bytes!")), |res| {
+            assert_eq!(OK, res.status);
+            assert_eq!("ten bytes!", body_string(res.body).unwrap());
+        });
+        client.handle(Request::post(Uri::parse("/"), Headers::empty(), BodyString("eleven byte")), |res| {
+            assert_eq!(ContentTooLarge, res.status);
+            assert_eq!("Body must be no more than 10 bytes", body_string(res.body).unwrap());
+        });
+    }
+
+    #[test]
+    fn we_reject_a_body_that_is_too_big_before_telling_the_client_to_continue() {
+        let server = server_with(ServerOptions { max_body_size: 10, ..ServerOptions::default() });
+        let mut stream = TcpStream::connect(format!("127.0.0.1:{}", server.port)).unwrap();
+
+        // the body is never sent, so the only way the server can answer is straight away
+        stream.write_all("POST / HTTP/1.1\r\nContent-Length: 11\r\nExpect: 100-continue\r\n\r\n".as_bytes()).unwrap();
+        read_response(&stream, &mut [0; 4096], &mut WireState::new(), |res| {
+            assert_eq!(ContentTooLarge, res.status);
+        });
+    }
+
+    #[test]
+    fn chunked_body_that_is_too_big_cannot_be_read() {
+        let server = server_with(ServerOptions { max_body_size: 10, ..ServerOptions::default() });
+        let mut stream = TcpStream::connect(format!("127.0.0.1:{}", server.port)).unwrap();
+
+        // we only find out once the handler has started reading it
+        stream.write_all("POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n6\r\nhello \r\n5\r\nworld\r\n0\r\n\r\n".as_bytes()).unwrap();
+        read_response(&stream, &mut [0; 4096], &mut WireState::new(), |res| {
+            assert_eq!(BadRequest, res.status);
+            assert_eq!("Body must be no more than 10 bytes", body_string(res.body).unwrap());
+        });
+
+        // unless we have to read all of it up front, to give an http/1.0 handler its length
+        let mut stream = TcpStream::connect(format!("127.0.0.1:{}", server.port)).unwrap();
+        stream.write_all("POST / HTTP/1.0\r\nTransfer-Encoding: chunked\r\n\r\n6\r\nhello \r\n5\r\nworld\r\n0\r\n\r\n".as_bytes()).unwrap();
+        read_response(&stream, &mut [0; 4096], &mut WireState::new(), |res| {
+            assert_eq!(ContentTooLarge, res.status);
+        });
+    }
+
+    #[test]
+    fn compressed_body_that_decompresses_to_too_big_cannot_be_read() {
+        let server = server_with(ServerOptions { max_decompressed_size: 1000, ..ServerOptions::default() });
+        let mut client = Client::new("127.0.0.1", server.port, None);
+
+        // compresses down to a few bytes, well within the body size
+        let body = "a".repeat(1001);
+        let request = Request::post(Uri::parse("/"), Headers::from(vec!(("Content-Encoding", "gzip"))), BodyString(body.as_str()));
+        client.handle(request, |res| {
+            assert_eq!(BadRequest, res.status);
+            assert_eq!("Decompressed body must be no more than 1000 bytes", body_string(res.body).unwrap());
+        });
+    }
+
+    #[test]
+    fn client_has_limits_too() {
+        let mut server = Server::new(0);
+        server.start(|| { Ok(PassThroughHandler {}) }, true).unwrap();
+        let mut client = Client::new("127.0.0.1", server.port, Some(ClientOptions {
+            max_body_size: 10,
+            ..ClientOptions::default()
+        }));
+
+        client.handle(Request::post(Uri::parse("/"), Headers::empty(), BodyString("eleven byte")), |res| {
+            assert_eq!(BadRequest, res.status);
+            assert_eq!("Body too big", body_string(res.body).unwrap());
+        });
+    }
+}
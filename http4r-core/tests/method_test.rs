@@ -0,0 +1,40 @@
+#[cfg(test)]
+mod tests {
+    use http4r_core::http_message::Method;
+    use http4r_core::http_message::Method::{CONNECT, DELETE, GET, HEAD, OPTIONS, PATCH, POST, PUT, TRACE};
+
+    #[test]
+    fn methods_round_trip() {
+        for method in vec!("GET", "HEAD", "POST", "PUT", "DELETE", "CONNECT", "OPTIONS", "TRACE", "PATCH", "PROPFIND", "MKCOL", "PURGE") {
+            assert_eq!(method, Method::parse(method).unwrap().value());
+        }
+        assert_eq!(PUT, Method::parse("PUT").unwrap());
+        assert_eq!(Method::Other("PROPFIND".to_string()), Method::parse("PROPFIND").unwrap());
+    }
+
+    /*
+    https://www.rfc-editor.org/rfc/rfc9110#section-9.1
+    The method token is case-sensitive
+    method = token
+     */
+    #[test]
+    fn extension_methods_must_be_tokens() {
+        assert_eq!(Method::Other("get".to_string()), Method::parse("get").unwrap());
+        assert!(Method::parse("").is_err());
+        assert!(Method::parse("BR(W").is_err());
+        assert!(Method::parse("GET\t").is_err());
+        assert!(Method::from("BR(W").is_err());
+        assert_eq!(GET, Method::from("GET").unwrap());
+    }
+
+    #[test]
+    fn safe_and_idempotent_methods() {
+        let safe = vec!(GET, HEAD, OPTIONS, TRACE);
+        let idempotent_but_not_safe = vec!(PUT, DELETE);
+        let neither = vec!(POST, PATCH, CONNECT, Method::Other("PURGE".to_string()));
+
+        assert!(safe.iter().all(|method| method.is_safe() && method.is_idempotent()));
+        assert!(idempotent_but_not_safe.iter().all(|method| !method.is_safe() && method.is_idempotent()));
+        assert!(neither.iter().all(|method| !method.is_safe() && !method.is_idempotent()));
+    }
+}
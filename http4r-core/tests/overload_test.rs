@@ -0,0 +1,86 @@
+mod common;
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+    use std::time::Duration;
+    use http4r_core::http_message::{body_string, WireState};
+    use http4r_core::http_message::Status::{OK, ServiceUnavailable};
+    use http4r_core::server::{OverloadPolicy, Server, ServerOptions};
+    use crate::common::{read_response, Router};
+
+    fn server_with_one_thread_and_room_for_one_more(overload_policy: OverloadPolicy) -> Server {
+        let mut server = Server::with_options(0, ServerOptions {
+            threadpool_size: 1,
+            accept_queue_size: 1,
+            overload_policy,
+            ..ServerOptions::default()
+        });
+        server.start(|| { Ok(Router {}) }, true).unwrap();
+        server
+    }
+
+    // once it has had a response, the connection holds on to the only thread until it is closed
+    fn busy_connection(port: u16) -> TcpStream {
+        let mut stream = TcpStream::connect(format!("127.0.0.1:{}", port)).unwrap();
+        stream.write_all("GET / HTTP/1.1\r\n\r\n".as_bytes()).unwrap();
+        read_response(&stream, &mut [0; 4096], &mut WireState::new(), |res| {
+            assert_eq!(OK, res.status);
+        });
+        stream
+    }
+
+    fn get(mut stream: &TcpStream) {
+        stream.write_all("GET / HTTP/1.1\r\nConnection: close\r\n\r\n".as_bytes()).unwrap();
+        read_response(&stream, &mut [0; 4096], &mut WireState::new(), |res| {
+            assert_eq!(OK, res.status);
+        });
+    }
+
+    #[test]
+    fn rejects_connections_when_it_is_too_busy() {
+        let server = server_with_one_thread_and_room_for_one_more(OverloadPolicy::Reject { retry_after: Duration::from_secs(2) });
+        let busy = busy_connection(server.port);
+        let queued = TcpStream::connect(format!("127.0.0.1:{}", server.port)).unwrap();
+
+        let rejected = TcpStream::connect(format!("127.0.0.1:{}", server.port)).unwrap();
+        read_response(&rejected, &mut [0; 4096], &mut WireState::new(), |res| {
+            assert_eq!(ServiceUnavailable, res.status);
+            assert_eq!(Some("2".to_string()), res.headers.get("Retry-After"));
+            assert_eq!(Some("close".to_string()), res.headers.get("Connection"));
+            assert_eq!("Too busy to handle the request, try again later", body_string(res.body).unwrap());
+        });
+
+        // the queued connection gets the thread once the busy one is done with it
+        drop(busy);
+        get(&queued);
+    }
+
+    #[test]
+    fn closes_connections_when_it_is_too_busy() {
+        let server = server_with_one_thread_and_room_for_one_more(OverloadPolicy::Close);
+        let busy = busy_connection(server.port);
+        let queued = TcpStream::connect(format!("127.0.0.1:{}", server.port)).unwrap();
+
+        let mut closed = TcpStream::connect(format!("127.0.0.1:{}", server.port)).unwrap();
+        let mut nothing = Vec::new();
+        let _ = closed.read_to_end(&mut nothing);
+        assert!(nothing.is_empty());
+
+        drop(busy);
+        get(&queued);
+    }
+
+    #[test]
+    fn can_leave_connections_waiting_until_it_is_not_so_busy() {
+        let server = server_with_one_thread_and_room_for_one_more(OverloadPolicy::Queue);
+        let busy = busy_connection(server.port);
+        let queued = TcpStream::connect(format!("127.0.0.1:{}", server.port)).unwrap();
+        let waiting = TcpStream::connect(format!("127.0.0.1:{}", server.port)).unwrap();
+
+        drop(busy);
+        get(&queued);
+        get(&waiting);
+    }
+}
@@ -0,0 +1,58 @@
+mod common;
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc::channel;
+    use std::thread;
+    use http4r_core::client::Client;
+    use http4r_core::handler::Handler;
+    use http4r_core::headers::Headers;
+    use http4r_core::http_message::{body_string, OwnedRequest, OwnedResponse, Request};
+    use http4r_core::http_message::Body::BodyString;
+    use http4r_core::http_message::Status::{NotFound, OK};
+    use http4r_core::server::Server;
+    use http4r_core::uri::Uri;
+    use crate::common::{PassThroughHandler, Router};
+
+    #[test]
+    fn can_keep_a_client_response_after_the_callback() {
+        let mut server = Server::new(0);
+        server.start(|| { Ok(PassThroughHandler {}) }, true).unwrap();
+        let mut client = Client::new("127.0.0.1", server.port, None);
+        let mut kept: Option<OwnedResponse> = None;
+
+        client.handle(Request::post(Uri::parse("/"), Headers::empty(), BodyString("hello")), |res| {
+            kept = Some(res.into_owned().unwrap());
+        });
+
+        let response = kept.unwrap();
+        assert_eq!(OK, response.status);
+        assert_eq!("hello".as_bytes(), response.body.as_slice());
+        assert_eq!("hello", body_string(response.as_response().body).unwrap());
+    }
+
+    #[test]
+    fn can_move_a_request_to_another_thread_and_handle_it_there() {
+        let (sender, receiver) = channel::<OwnedRequest>();
+        let uri = "/not-found?query=value".to_string();
+        let request = Request::post(Uri::parse(uri.as_str()), Headers::from(vec!(("Content-Type", "text/plain"))), BodyString("hello"))
+            .with_trailers(Headers::from(vec!(("Expires", "Wed, 21 Oct 2015 07:28:00 GMT"))));
+        sender.send(request.into_owned().unwrap()).unwrap();
+
+        let handled = thread::spawn(move || {
+            let owned = receiver.recv().unwrap();
+            assert_eq!("/not-found?query=value", owned.uri);
+            assert_eq!(Some("Wed, 21 Oct 2015 07:28:00 GMT".to_string()), owned.trailers.get("Expires"));
+            let request = owned.as_request();
+            assert!(request.body.is_body_string());
+
+            let mut status = OK;
+            Router {}.handle(request, |res| {
+                status = res.status;
+            });
+            status
+        });
+
+        assert_eq!(NotFound, handled.join().unwrap());
+    }
+}
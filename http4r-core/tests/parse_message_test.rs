@@ -0,0 +1,137 @@
+#[cfg(test)]
+mod tests {
+    use http4r_core::headers::Headers;
+    use http4r_core::http_message::{body_string, MessageBuffers, MessageError, parse_message, Request, RequestOptions, Response, serialize_message};
+    use http4r_core::http_message::HttpMessage;
+    use http4r_core::http_message::Body::BodyString;
+    use http4r_core::http_message::Method::{GET, POST};
+    use http4r_core::http_message::Status::NotFound;
+    use http4r_core::uri::Uri;
+
+    #[test]
+    fn parses_a_whole_request() {
+        let bytes = "POST /path?query=true HTTP/1.1\r\nHost: example.com\r\nContent-Length: 5\r\n\r\nhello".as_bytes();
+        let mut buffers = MessageBuffers::new();
+
+        let parsed = parse_message(bytes, false, &mut buffers).unwrap().unwrap();
+
+        assert_eq!(bytes.len(), parsed.length);
+        let request = parsed.message.to_req();
+        assert_eq!(POST, request.method);
+        assert_eq!("/path?query=true", request.uri.to_string());
+        assert_eq!(Some("example.com".to_string()), request.headers.get("Host"));
+        assert_eq!("hello", body_string(request.body).unwrap());
+    }
+
+    #[test]
+    fn there_is_no_message_until_all_of_it_is_there() {
+        let whole = "POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhello\r\n0\r\nDigest: abc\r\n\r\n".as_bytes();
+        let mut buffers = MessageBuffers::new();
+
+        // part way through the start line, the headers, the chunks and the trailers
+        for up_to in [10, 30, 50, 60, whole.len() - 1] {
+            assert!(parse_message(&whole[..up_to], false, &mut buffers).unwrap().is_none(), "parsed with {} bytes", up_to);
+        }
+
+        let parsed = parse_message(whole, false, &mut buffers).unwrap().unwrap();
+        let request = parsed.message.to_req();
+        assert_eq!("hello", body_string(request.body).unwrap());
+        assert_eq!(Some("abc".to_string()), request.trailers.get().get("Digest"));
+    }
+
+    #[test]
+    fn content_length_body_that_is_not_all_there() {
+        let bytes = "POST / HTTP/1.1\r\nContent-Length: 10\r\n\r\nhello".as_bytes();
+
+        assert!(parse_message(bytes, false, &mut MessageBuffers::new()).unwrap().is_none());
+    }
+
+    #[test]
+    fn tells_you_where_the_next_message_starts() {
+        let first = "GET /first HTTP/1.1\r\n\r\n";
+        let second = "POST /second HTTP/1.1\r\nContent-Length: 6\r\n\r\nsecond";
+        let bytes = format!("{}{}", first, second);
+        let mut buffers = MessageBuffers::new();
+
+        // the message borrows the buffers, so it has to be done with before they parse the next one
+        {
+            let parsed = parse_message(bytes.as_bytes(), false, &mut buffers).unwrap().unwrap();
+            assert_eq!(first.len(), parsed.length);
+            assert_eq!("/first", parsed.message.to_req().uri.to_string());
+        }
+
+        let rest = &bytes.as_bytes()[first.len()..];
+        let parsed = parse_message(rest, false, &mut buffers).unwrap().unwrap();
+        assert_eq!(second.len(), parsed.length);
+        assert_eq!("second", body_string(parsed.message.to_req().body).unwrap());
+    }
+
+    #[test]
+    fn a_body_we_ignore_is_still_part_of_the_message() {
+        let bytes = "GET / HTTP/1.1\r\nContent-Length: 5\r\n\r\nhelloGET /next HTTP/1.1\r\n\r\n".as_bytes();
+
+        let mut buffers = MessageBuffers::new();
+        let parsed = parse_message(bytes, false, &mut buffers).unwrap().unwrap();
+
+        assert_eq!(bytes.len() - "GET /next HTTP/1.1\r\n\r\n".len(), parsed.length);
+        let request = parsed.message.to_req();
+        assert_eq!(GET, request.method);
+        assert_eq!("", body_string(request.body).unwrap());
+    }
+
+    #[test]
+    fn response_without_a_length_is_the_rest_of_the_bytes() {
+        let bytes = "HTTP/1.1 404 Not Found\r\n\r\nnot here".as_bytes();
+
+        let mut buffers = MessageBuffers::new();
+        let parsed = parse_message(bytes, false, &mut buffers).unwrap().unwrap();
+
+        assert_eq!(bytes.len(), parsed.length);
+        let response = parsed.message.to_res();
+        assert_eq!(NotFound, response.status);
+        assert_eq!("not here", body_string(response.body).unwrap());
+    }
+
+    #[test]
+    fn response_to_head_has_no_body() {
+        let bytes = "HTTP/1.1 200 OK\r\nContent-Length: 100\r\n\r\n".as_bytes();
+
+        let mut buffers = MessageBuffers::new();
+        let parsed = parse_message(bytes, true, &mut buffers).unwrap().unwrap();
+
+        assert_eq!(bytes.len(), parsed.length);
+        assert_eq!(Some("100".to_string()), parsed.message.to_res().headers.get("Content-Length"));
+    }
+
+    #[test]
+    fn malformed_messages_are_errors() {
+        let bytes = "GET / HTTP/1.1\r\nContent-Length: 5\r\nTransfer-Encoding: chunked\r\n\r\n".as_bytes();
+
+        assert!(matches!(parse_message(bytes, false, &mut MessageBuffers::new()), Err(MessageError::ContentLengthAndTransferEncoding(_))));
+    }
+
+    #[test]
+    fn serializes_a_message() {
+        let response = Response::ok(Headers::from(vec!(("Content-Type", "text/plain"))), BodyString("hello"));
+
+        let bytes = serialize_message(HttpMessage::Response(response), RequestOptions::default());
+
+        assert_eq!("HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\nContent-Length: 5\r\n\r\nhello", String::from_utf8(bytes).unwrap());
+    }
+
+    #[test]
+    fn what_is_serialized_parses_back() {
+        let request = Request::post(Uri::parse("/upload"), Headers::from(vec!(("Transfer-Encoding", "chunked"))), BodyString("round trip"))
+            .with_trailers(Headers::from(vec!(("Digest", "abc"))));
+        let bytes = serialize_message(HttpMessage::Request(request), RequestOptions::default());
+
+        let mut buffers = MessageBuffers::new();
+        let parsed = parse_message(bytes.as_slice(), false, &mut buffers).unwrap().unwrap();
+
+        assert_eq!(bytes.len(), parsed.length);
+        let request = parsed.message.to_req();
+        assert_eq!("/upload", request.uri.to_string());
+        assert_eq!(Some("abc".to_string()), request.trailers.get().get("Digest"));
+        assert_eq!("round trip", body_string(request.body).unwrap());
+    }
+}
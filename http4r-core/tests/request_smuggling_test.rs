@@ -0,0 +1,114 @@
+mod common;
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+    use http4r_core::server::Server;
+    use crate::common::PassThroughHandler;
+
+    // the server should respond once with a 400 and then close the connection,
+    // so that whatever was smuggled in after the first request is never read as a request of its own
+    fn assert_rejected(payload: &str, reason: &str) {
+        let mut server = Server::new(0);
+        server.start(|| { Ok(PassThroughHandler {}) }, true).unwrap();
+        let mut stream = TcpStream::connect(format!("127.0.0.1:{}", server.port)).unwrap();
+        stream.write_all(payload.as_bytes()).unwrap();
+
+        let mut response = Vec::new();
+        let _ = stream.read_to_end(&mut response);
+        let response = String::from_utf8_lossy(response.as_slice()).to_string();
+
+        assert!(response.starts_with("HTTP/1.1 400 Bad Request\r\n"), "{} was not rejected, got {}", payload, response);
+        assert!(response.ends_with(reason), "expected {} but got {}", reason, response);
+        assert_eq!(1, response.matches("HTTP/1.1").count(), "the smuggled request was answered: {}", response);
+    }
+
+    /*
+    https://www.rfc-editor.org/rfc/rfc9112#section-6.1
+    A server MAY reject a request that contains both Content-Length and Transfer-Encoding or process
+    such a request in accordance with the Transfer-Encoding alone.
+     */
+    #[test]
+    fn rejects_content_length_with_transfer_encoding() {
+        let reason = "Content-Length and Transfer-Encoding must not both be provided";
+        // CL.TE
+        assert_rejected("POST / HTTP/1.1\r\nContent-Length: 13\r\nTransfer-Encoding: chunked\r\n\r\n0\r\n\r\nSMUGGLED", reason);
+        // TE.CL
+        assert_rejected("POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\nContent-Length: 3\r\n\r\n8\r\nSMUGGLED\r\n0\r\n\r\n", reason);
+    }
+
+    /*
+    https://www.rfc-editor.org/rfc/rfc9112#section-6.1
+    If a Transfer-Encoding header field is present in a request and the chunked transfer coding is not
+    the final encoding, the message body length cannot be determined reliably; the server MUST respond
+    with the 400 (Bad Request) status code and then close the connection.
+     */
+    #[test]
+    fn rejects_transfer_encoding_that_does_not_end_in_chunked() {
+        assert_rejected("POST / HTTP/1.1\r\nTransfer-Encoding: chunked, identity\r\n\r\n0\r\n\r\nGET /smuggled HTTP/1.1\r\n\r\n",
+                        "Transfer-Encoding must end in chunked, got chunked, identity");
+        assert_rejected("POST / HTTP/1.1\r\nTransfer-Encoding: xchunked\r\n\r\n0\r\n\r\nGET /smuggled HTTP/1.1\r\n\r\n",
+                        "Transfer-Encoding must end in chunked, got xchunked");
+        assert_rejected("POST / HTTP/1.1\r\nTransfer-Encoding: chunked, chunked\r\n\r\n0\r\n\r\nGET /smuggled HTTP/1.1\r\n\r\n",
+                        "Transfer-Encoding must end in chunked, got chunked, chunked");
+        assert_rejected("POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\nTransfer-Encoding: identity\r\n\r\n0\r\n\r\nGET /smuggled HTTP/1.1\r\n\r\n",
+                        "Transfer-Encoding must end in chunked, got chunked, identity");
+    }
+
+    /*
+    https://www.rfc-editor.org/rfc/rfc9112#section-5.1
+    A server MUST reject, with a response status code of 400 (Bad Request), any received request
+    message that contains whitespace between a header field name and colon.
+     */
+    #[test]
+    fn rejects_whitespace_before_the_colon() {
+        assert_rejected("POST / HTTP/1.1\r\nTransfer-Encoding : chunked\r\nContent-Length: 4\r\n\r\n0\r\n\r\n",
+                        "Whitespace before the colon in header Transfer-Encoding : chunked");
+        assert_rejected("POST / HTTP/1.1\r\nContent-Length\t: 4\r\n\r\nbody",
+                        "Whitespace before the colon in header Content-Length\t: 4");
+    }
+
+    /*
+    https://www.rfc-editor.org/rfc/rfc9112#section-5.2
+    A server that receives an obs-fold in a request message that is not within a "message/http"
+    container MUST either reject the message by sending a 400 (Bad Request)...
+     */
+    #[test]
+    fn rejects_obs_fold() {
+        assert_rejected("POST / HTTP/1.1\r\nTransfer-Encoding: identity\r\n chunked\r\nContent-Length: 4\r\n\r\n0\r\n\r\n",
+                        "Header folded onto more than one line  chunked");
+    }
+
+    /*
+    https://www.rfc-editor.org/rfc/rfc9112#section-2.2
+    Although the line terminator for the start-line and fields is the sequence CRLF,
+    a recipient MAY recognize a single LF as a line terminator and ignore any preceding CR.
+    We do not, as a proxy in front of us might not either.
+     */
+    #[test]
+    fn rejects_bare_line_feeds() {
+        assert_rejected("POST / HTTP/1.1\r\nContent-Length: 4\nTransfer-Encoding: chunked\r\n\r\n0\r\n\r\n",
+                        "Line feed without a carriage return in header section");
+        assert_rejected("POST / HTTP/1.1\nContent-Length: 4\r\n\r\nbody",
+                        "Line feed without a carriage return in start line");
+    }
+
+    /*
+    https://www.rfc-editor.org/rfc/rfc9112#section-6.3
+    If a message is received without Transfer-Encoding and with an invalid Content-Length header field,
+    then the message framing is invalid and the recipient MUST treat it as an unrecoverable error.
+     */
+    #[test]
+    fn rejects_conflicting_or_invalid_content_lengths() {
+        assert_rejected("POST / HTTP/1.1\r\nContent-Length: 4\r\nContent-Length: 30\r\n\r\nbodyGET /smuggled HTTP/1.1\r\n\r\n",
+                        "Content Length header has conflicting values 4, 30");
+        assert_rejected("POST / HTTP/1.1\r\nContent-Length: 4, 30\r\n\r\nbodyGET /smuggled HTTP/1.1\r\n\r\n",
+                        "Content Length header has conflicting values 4, 30");
+        assert_rejected("POST / HTTP/1.1\r\nContent-Length: +4\r\n\r\nbody",
+                        "Content Length header couldn't be parsed, got +4");
+        // we would ignore the body of a GET, but we still have to know where it ends
+        assert_rejected("GET / HTTP/1.1\r\nContent-Length: 4\r\nContent-Length: 30\r\n\r\nbodyGET /smuggled HTTP/1.1\r\n\r\n",
+                        "Content Length header has conflicting values 4, 30");
+    }
+}
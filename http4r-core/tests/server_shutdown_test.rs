@@ -0,0 +1,128 @@
+mod common;
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::thread;
+    use std::time::{Duration, Instant};
+    use http4r_core::client::Client;
+    use http4r_core::handler::Handler;
+    use http4r_core::headers::Headers;
+    use http4r_core::http_message::{body_string, Request, WireState};
+    use http4r_core::http_message::Status::OK;
+    use http4r_core::server::Server;
+    use http4r_core::uri::Uri;
+    use crate::common::{read_response, Router, SlowHandler};
+
+    #[test]
+    fn starting_on_a_port_that_is_taken_is_an_error() {
+        let taken = TcpListener::bind("0.0.0.0:0").unwrap();
+        let mut server = Server::new(taken.local_addr().unwrap().port());
+
+        assert!(server.start(|| { Ok(Router {}) }, true).is_err());
+    }
+
+    #[test]
+    fn the_handle_knows_the_port() {
+        let mut server = Server::new(0);
+        let handle = server.start(|| { Ok(Router {}) }, true).unwrap();
+
+        assert_eq!(server.port, handle.port);
+        handle.stop(Duration::from_secs(1));
+    }
+
+    #[test]
+    fn stops_accepting_connections() {
+        let mut server = Server::new(0);
+        let handle = server.start(|| { Ok(Router {}) }, true).unwrap();
+        let mut client = Client::new("127.0.0.1", handle.port, None);
+        client.handle(Request::get(Uri::parse("/"), Headers::empty()), |res| {
+            assert_eq!(OK, res.status);
+        });
+
+        handle.stop(Duration::from_secs(1));
+
+        assert!(TcpStream::connect(format!("127.0.0.1:{}", server.port)).is_err());
+    }
+
+    #[test]
+    fn finishes_requests_in_flight_and_tells_them_to_close() {
+        let mut server = Server::new(0);
+        let handle = server.start(|| { Ok(SlowHandler { delay: Duration::from_millis(200) }) }, true).unwrap();
+        let mut stream = TcpStream::connect(format!("127.0.0.1:{}", handle.port)).unwrap();
+        stream.write_all("GET / HTTP/1.1\r\n\r\n".as_bytes()).unwrap();
+
+        let in_flight = thread::spawn(move || {
+            let mut response = None;
+            read_response(&stream, &mut [0; 4096], &mut WireState::new(), |res| {
+                response = Some((res.status, res.headers.get("Connection"), body_string(res.body).unwrap()));
+            });
+            response.unwrap()
+        });
+        thread::sleep(Duration::from_millis(50));
+        handle.stop(Duration::from_secs(5));
+
+        let (status, connection, body) = in_flight.join().unwrap();
+        assert_eq!(OK, status);
+        assert_eq!(Some("close".to_string()), connection);
+        assert_eq!("finally", body);
+    }
+
+    #[test]
+    fn does_not_wait_for_idle_connections() {
+        let mut server = Server::new(0);
+        let handle = server.start(|| { Ok(Router {}) }, true).unwrap();
+        let mut idle = TcpStream::connect(format!("127.0.0.1:{}", handle.port)).unwrap();
+        idle.write_all("GET / HTTP/1.1\r\n\r\n".as_bytes()).unwrap();
+        read_response(&idle, &mut [0; 4096], &mut WireState::new(), |res| {
+            assert_eq!(OK, res.status);
+        });
+
+        // otherwise it would wait for the keep-alive timeout
+        let started = Instant::now();
+        handle.stop(Duration::from_secs(5));
+        assert!(started.elapsed() < Duration::from_secs(1));
+
+        let mut nothing = Vec::new();
+        let _ = idle.read_to_end(&mut nothing);
+        assert!(nothing.is_empty());
+    }
+
+    #[test]
+    fn closes_connections_still_open_at_the_deadline() {
+        let mut server = Server::new(0);
+        let handle = server.start(|| { Ok(SlowHandler { delay: Duration::from_millis(1000) }) }, true).unwrap();
+        let mut stream = TcpStream::connect(format!("127.0.0.1:{}", handle.port)).unwrap();
+        stream.write_all("GET / HTTP/1.1\r\n\r\n".as_bytes()).unwrap();
+
+        let in_flight = thread::spawn(move || {
+            let started = Instant::now();
+            let mut response = Vec::new();
+            let _ = stream.read_to_end(&mut response);
+            (started.elapsed(), response)
+        });
+        thread::sleep(Duration::from_millis(50));
+        handle.stop(Duration::from_millis(100));
+
+        let (waited, response) = in_flight.join().unwrap();
+        assert!(waited < Duration::from_millis(900));
+        assert!(response.is_empty());
+    }
+
+    #[test]
+    fn closes_the_connection_when_the_handler_panics() {
+        let mut server = Server::new(0);
+        let handle = server.start(|| -> Result<Router, String> { Err("no handler for you".to_string()) }, true).unwrap();
+        let mut stream = TcpStream::connect(format!("127.0.0.1:{}", handle.port)).unwrap();
+        stream.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+        stream.write_all("GET / HTTP/1.1\r\n\r\n".as_bytes()).unwrap();
+
+        let mut response = Vec::new();
+        assert!(stream.read_to_end(&mut response).is_ok());
+        assert!(response.is_empty());
+        let started = Instant::now();
+        handle.stop(Duration::from_secs(1));
+        assert!(started.elapsed() < Duration::from_millis(500));
+    }
+}
@@ -0,0 +1,104 @@
+mod common;
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use std::time::Duration;
+    use http4r_core::client::Client;
+    use http4r_core::handler::Handler;
+    use http4r_core::headers::Headers;
+    use http4r_core::http_message::{body_string, Request};
+    use http4r_core::http_message::Body::BodyString;
+    use http4r_core::http_message::Status::OK;
+    use http4r_core::server::{Server, ServerOptions};
+    use http4r_core::socket::SocketAddress;
+    use http4r_core::uri::Uri;
+    use crate::common::{PassHeadersAsBody, PassThroughHandler, Router};
+
+    fn socket_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("http4r-{}-{}.sock", name, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn parses_socket_addresses() {
+        assert_eq!(SocketAddress::localhost(8080), SocketAddress::parse("127.0.0.1:8080").unwrap());
+        assert_eq!(8080, SocketAddress::parse("[::1]:8080").unwrap().port());
+        assert_eq!(SocketAddress::Unix(PathBuf::from("/tmp/app.sock")), SocketAddress::parse("unix:/tmp/app.sock").unwrap());
+        assert_eq!("unix:/tmp/app.sock", SocketAddress::parse("unix:/tmp/app.sock").unwrap().to_string());
+        assert_eq!(Err("Invalid socket address localhost".to_string()), SocketAddress::parse("localhost"));
+        assert!(SocketAddress::parse("unix:").is_err());
+    }
+
+    #[test]
+    fn can_listen_on_loopback_only() {
+        let mut server = Server::bind(SocketAddress::localhost(0), ServerOptions::default());
+        let handle = server.start(|| { Ok(Router {}) }, true).unwrap();
+        assert_ne!(0, server.port);
+        assert_eq!(SocketAddress::localhost(server.port), server.address);
+
+        let mut client = Client::new("127.0.0.1", server.port, None);
+        client.handle(Request::get(Uri::parse("/"), Headers::empty()), |res| {
+            assert_eq!(OK, res.status);
+        });
+
+        handle.stop(Duration::from_secs(1));
+    }
+
+    #[test]
+    fn can_listen_on_ipv6() {
+        let mut server = Server::bind(SocketAddress::parse("[::1]:0").unwrap(), ServerOptions::default());
+        // not every machine has ipv6
+        let handle = match server.start(|| { Ok(PassHeadersAsBody {}) }, true) {
+            Ok(handle) => handle,
+            Err(_) => return,
+        };
+
+        let mut client = Client::new("::1", server.port, None);
+        client.handle(Request::get(Uri::parse("/"), Headers::empty()), |res| {
+            assert_eq!(OK, res.status);
+            assert!(body_string(res.body).unwrap().contains(format!("Host: [::1]:{}", handle.port).as_str()));
+        });
+        let mut client = Client::connect_to(server.address.clone(), None);
+        client.handle(Request::post(Uri::parse("/"), Headers::empty(), BodyString("over ipv6")), |res| {
+            assert_eq!(OK, res.status);
+        });
+        handle.stop(Duration::from_secs(1));
+    }
+
+    #[test]
+    fn can_listen_on_a_unix_socket() {
+        let path = socket_path("listen");
+        let mut server = Server::bind(SocketAddress::Unix(path.clone()), ServerOptions::default());
+        let handle = server.start(|| { Ok(PassThroughHandler {}) }, true).unwrap();
+        assert_eq!(0, server.port);
+
+        let mut client = Client::connect_to(SocketAddress::Unix(path.clone()), None);
+        client.handle(Request::post(Uri::parse("/"), Headers::empty(), BodyString("over a unix socket")), |res| {
+            assert_eq!(OK, res.status);
+            assert_eq!(Some("localhost".to_string()), res.headers.get("Host"));
+            assert_eq!("over a unix socket", body_string(res.body).unwrap());
+        });
+        let chunked = Headers::from(vec!(("Transfer-Encoding", "chunked")));
+        client.handle(Request::post(Uri::parse("/"), chunked, BodyString("and chunked")), |res| {
+            assert_eq!(OK, res.status);
+            assert_eq!("and chunked", body_string(res.body).unwrap());
+        });
+
+        handle.stop(Duration::from_secs(1));
+        // so that the path can be bound again
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn binding_to_a_unix_socket_that_is_in_use_is_an_error() {
+        let path = socket_path("in-use");
+        let mut server = Server::bind(SocketAddress::Unix(path.clone()), ServerOptions::default());
+        let handle = server.start(|| { Ok(Router {}) }, true).unwrap();
+
+        let mut other = Server::bind(SocketAddress::Unix(path.clone()), ServerOptions::default());
+        assert!(other.start(|| { Ok(Router {}) }, true).is_err());
+        handle.stop(Duration::from_secs(1));
+    }
+}
@@ -0,0 +1,62 @@
+#[cfg(test)]
+mod tests {
+    use http4r_core::http_message::Status;
+    use http4r_core::http_message::Status::{ContentTooLarge, Created, LengthRequired, NoContent, OK, ServiceUnavailable};
+
+    #[test]
+    fn every_status_code_round_trips() {
+        for code in 100..600 {
+            assert_eq!(code, Status::from_code(code).value());
+            assert_eq!(Status::from_code(code), Status::from(code.to_string().as_str()).unwrap());
+        }
+        assert_eq!(411, LengthRequired.value());
+        assert_eq!("Length Required", LengthRequired.to_string());
+        assert_eq!(Created, Status::from("201").unwrap());
+        assert_eq!(NoContent, Status::from("204").unwrap());
+        assert_eq!(ServiceUnavailable, Status::from("503").unwrap());
+        assert_eq!("Service Unavailable", ServiceUnavailable.to_string());
+        assert_eq!("Content Too Large", ContentTooLarge.to_string());
+    }
+
+    #[test]
+    fn unregistered_statuses_keep_their_reason_phrase() {
+        let status = Status::parse("299", "Mostly OK").unwrap();
+        assert_eq!(Status::Other(299, "Mostly OK".to_string()), status);
+        assert_eq!(299, status.value());
+        assert_eq!("Mostly OK", status.to_string());
+
+        // a registered status we receive is known by its code, whatever its reason phrase
+        assert_eq!(Created, Status::parse("201", "Made It").unwrap());
+        assert_eq!("I'm a teapot", Status::custom(418, "I'm a teapot").unwrap().to_string());
+    }
+
+    #[test]
+    fn a_registered_status_can_have_a_reason_phrase_of_its_own() {
+        let status = Status::custom(204, "Nothing Here").unwrap();
+        assert_eq!(Status::Other(204, "Nothing Here".to_string()), status);
+        assert_eq!(204, status.value());
+        assert_eq!("Nothing Here", status.to_string());
+
+        assert_eq!(NoContent, Status::custom(204, "No Content").unwrap());
+        assert_eq!(OK, Status::custom(200, "").unwrap());
+    }
+
+    #[test]
+    fn a_status_code_must_be_three_digits() {
+        assert!(Status::from("abc").is_err());
+        assert!(Status::from("20").is_err());
+        assert!(Status::from("2000").is_err());
+        assert!(Status::from("+20").is_err());
+        assert!(Status::from("").is_err());
+        assert!(Status::custom(99, "Too Small").is_err());
+        assert!(Status::custom(1000, "Too Big").is_err());
+        assert_eq!(Status::Other(999, "Odd".to_string()), Status::custom(999, "Odd").unwrap());
+    }
+
+    #[test]
+    fn a_reason_phrase_cannot_have_control_characters() {
+        assert!(Status::custom(299, "OK\r\nSet-Cookie: a=b").is_err());
+        assert!(Status::custom(299, "OK\0").is_err());
+        assert_eq!("Mostly\tOK", Status::custom(299, "Mostly\tOK").unwrap().to_string());
+    }
+}
@@ -0,0 +1,218 @@
+mod common;
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::thread;
+    use std::time::{Duration, Instant};
+    use http4r_core::client::{Client, ClientError, ClientOptions};
+    use http4r_core::handler::Handler;
+    use http4r_core::headers::Headers;
+    use http4r_core::http_message::{body_string, Request, Response, WireState};
+    use http4r_core::http_message::Body::{BodyBytes, BodyStream};
+    use http4r_core::http_message::Status::{BadRequest, OK};
+    use http4r_core::server::{Server, ServerOptions};
+    use http4r_core::uri::Uri;
+    use crate::common::{read_response, ReadWholeBodyHandler, SlowHandler};
+
+    fn server_with(options: ServerOptions) -> Server {
+        let mut server = Server::with_options(0, options);
+        server.start(|| { Ok(ReadWholeBodyHandler {}) }, true).unwrap();
+        server
+    }
+
+    /*
+    https://www.rfc-editor.org/rfc/rfc9110#section-15.5.9
+    The 408 (Request Timeout) status code indicates that the server did not receive a complete
+    request message within the time that it was prepared to wait.
+     */
+    #[test]
+    fn headers_trickled_in_a_byte_at_a_time_time_out() {
+        let server = server_with(ServerOptions { headers_timeout: Duration::from_millis(200), ..ServerOptions::default() });
+        let mut stream = TcpStream::connect(format!("127.0.0.1:{}", server.port)).unwrap();
+        let started = Instant::now();
+
+        // each byte comes well within the time we wait for any one read, but the headers never finish
+        stream.write_all("GET / HTTP/1.1\r\n".as_bytes()).unwrap();
+        for _ in 0..10 {
+            thread::sleep(Duration::from_millis(50));
+            if stream.write_all("a".as_bytes()).is_err() {
+                break;
+            }
+        }
+
+        let mut response = String::new();
+        let _ = stream.read_to_string(&mut response);
+        assert!(response.starts_with("HTTP/1.1 408 Request Timeout\r\n"), "got {}", response);
+        assert!(response.contains("Connection: close"), "got {}", response);
+        assert!(response.ends_with("Timed out waiting for the message"));
+        assert!(started.elapsed() < Duration::from_secs(2));
+    }
+
+    #[test]
+    fn idle_connections_are_closed_without_a_response() {
+        let server = server_with(ServerOptions { keep_alive_timeout: Duration::from_millis(100), ..ServerOptions::default() });
+        let mut stream = TcpStream::connect(format!("127.0.0.1:{}", server.port)).unwrap();
+        let mut reader = [0; 4096];
+        let mut wire = WireState::new();
+
+        stream.write_all("GET / HTTP/1.1\r\n\r\n".as_bytes()).unwrap();
+        read_response(&stream, &mut reader, &mut wire, |res| {
+            assert_eq!(OK, res.status);
+        });
+
+        // no request was started, so there is nothing to tell the client
+        let mut rest = Vec::new();
+        stream.read_to_end(&mut rest).unwrap();
+        assert!(rest.is_empty());
+    }
+
+    #[test]
+    fn body_that_stops_arriving_times_out() {
+        let server = server_with(ServerOptions { body_timeout: Duration::from_millis(100), ..ServerOptions::default() });
+        let mut stream = TcpStream::connect(format!("127.0.0.1:{}", server.port)).unwrap();
+
+        stream.write_all("POST / HTTP/1.1\r\nContent-Length: 10\r\n\r\nhello".as_bytes()).unwrap();
+        read_response(&stream, &mut [0; 4096], &mut WireState::new(), |res| {
+            // the handler was reading the body when it timed out, so it is the handler that responds
+            assert_eq!(BadRequest, res.status);
+            assert_eq!("Timed out waiting for the message", body_string(res.body).unwrap());
+        });
+    }
+
+    #[test]
+    fn a_client_too_slow_to_read_the_response_is_closed_on() {
+        struct BigBody {}
+        impl Handler for BigBody {
+            fn handle<F>(&mut self, _req: Request, fun: F) -> () where F: FnOnce(Response) -> () + Sized {
+                let big = vec![b'a'; 64 * 1048576];
+                fun(Response::ok(Headers::empty(), BodyBytes(big.as_slice())))
+            }
+        }
+        let mut server = Server::with_options(0, ServerOptions { write_timeout: Duration::from_millis(100), ..ServerOptions::default() });
+        server.start(|| { Ok(BigBody {}) }, true).unwrap();
+        let mut stream = TcpStream::connect(format!("127.0.0.1:{}", server.port)).unwrap();
+        stream.write_all("GET / HTTP/1.1\r\n\r\n".as_bytes()).unwrap();
+
+        // not reading, so the server cannot finish writing
+        thread::sleep(Duration::from_millis(500));
+        stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let started = Instant::now();
+        let mut response = Vec::new();
+        // the server gave up on us and closed the connection, rather than leaving it open
+        stream.read_to_end(&mut response).unwrap();
+        assert!(response.len() < 64 * 1048576);
+        assert!(started.elapsed() < Duration::from_secs(2), "took {:?}", started.elapsed());
+    }
+
+    #[test]
+    fn client_read_timeout() {
+        let mut server = Server::new(0);
+        server.start(|| { Ok(SlowHandler { delay: Duration::from_millis(500) }) }, true).unwrap();
+        let mut client = Client::new("127.0.0.1", server.port, Some(ClientOptions {
+            read_timeout: Duration::from_millis(100),
+            ..ClientOptions::default()
+        }));
+
+        client.handle(Request::get(Uri::parse("/"), Headers::empty()), |res| {
+            assert_eq!(BadRequest, res.status);
+            assert_eq!("Timed out after 100ms reading the response", body_string(res.body).unwrap());
+        });
+        assert!(matches!(client.error, Some(ClientError::ReadTimeout(_))));
+    }
+
+    #[test]
+    fn client_total_timeout() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        // a server that responds, just very slowly
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            for octet in "HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n".as_bytes() {
+                thread::sleep(Duration::from_millis(20));
+                if stream.write_all(&[*octet]).is_err() {
+                    break;
+                }
+            }
+        });
+        let mut client = Client::new("127.0.0.1", port, Some(ClientOptions {
+            total_timeout: Some(Duration::from_millis(200)),
+            ..ClientOptions::default()
+        }));
+
+        client.handle(Request::get(Uri::parse("/"), Headers::empty()), |res| {
+            assert_eq!(BadRequest, res.status);
+            assert_eq!("Timed out after 200ms waiting for the response", body_string(res.body).unwrap());
+        });
+        assert!(matches!(client.error, Some(ClientError::TotalTimeout(_))));
+    }
+
+    #[test]
+    fn client_total_timeout_includes_reading_the_body() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        // the head comes straight away, but the body never finishes
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            stream.write_all("HTTP/1.1 200 OK\r\nContent-Length: 100\r\n\r\n".as_bytes()).unwrap();
+            for _ in 0..100 {
+                thread::sleep(Duration::from_millis(20));
+                if stream.write_all("a".as_bytes()).is_err() {
+                    break;
+                }
+            }
+        });
+        let mut client = Client::new("127.0.0.1", port, Some(ClientOptions {
+            total_timeout: Some(Duration::from_millis(200)),
+            ..ClientOptions::default()
+        }));
+        let started = Instant::now();
+
+        client.handle(Request::get(Uri::parse("/"), Headers::empty()), |res| {
+            assert_eq!(OK, res.status);
+            let mut body = Vec::new();
+            let read = match res.body {
+                BodyStream(mut reader) => reader.read_to_end(&mut body),
+                _ => panic!("expected the body to be streamed"),
+            };
+            assert!(read.is_err());
+        });
+        assert!(started.elapsed() < Duration::from_secs(1), "took {:?}", started.elapsed());
+    }
+
+    #[test]
+    fn client_total_timeout_includes_writing_the_request() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        // a server that never reads the request, so we cannot finish writing it
+        thread::spawn(move || {
+            let (_stream, _) = listener.accept().unwrap();
+            thread::sleep(Duration::from_secs(5));
+        });
+        let mut client = Client::new("127.0.0.1", port, Some(ClientOptions {
+            total_timeout: Some(Duration::from_millis(300)),
+            ..ClientOptions::default()
+        }));
+        let started = Instant::now();
+        let body = vec![b'a'; 64 * 1048576];
+
+        client.handle(Request::post(Uri::parse("/"), Headers::empty(), BodyBytes(body.as_slice())), |res| {
+            assert_eq!(BadRequest, res.status);
+        });
+        assert!(matches!(client.error, Some(ClientError::TotalTimeout(_))), "got {:?}", client.error);
+        assert!(started.elapsed() < Duration::from_secs(2), "took {:?}", started.elapsed());
+    }
+
+    #[test]
+    fn client_tells_you_when_it_could_not_connect() {
+        // nothing is listening on the port once the listener is dropped
+        let port = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let mut client = Client::new("127.0.0.1", port, None);
+
+        client.handle(Request::get(Uri::parse("/"), Headers::empty()), |res| {
+            assert_eq!(BadRequest, res.status);
+        });
+        assert!(matches!(client.error, Some(ClientError::CouldNotConnect(_))));
+    }
+}
@@ -0,0 +1,305 @@
+mod common;
+
+#[cfg(all(test, feature = "tls"))]
+mod tests {
+    use std::io::{ErrorKind, Read, Write};
+    use std::net::{Shutdown, TcpListener, TcpStream};
+    use std::thread;
+    use std::time::{Duration, Instant};
+    use rcgen::{BasicConstraints, CertificateParams, CertifiedKey, IsCa, KeyPair};
+    use http4r_core::client::{Client, ClientError, ClientOptions};
+    use http4r_core::handler::Handler;
+    use http4r_core::headers::Headers;
+    use http4r_core::http_message::{body_string, read_message_from_wire, Request, Response, WireState};
+    use http4r_core::http_message::Body::{BodyStream, BodyString};
+    use http4r_core::http_message::Status::{BadRequest, OK};
+    use http4r_core::server::{Server, ServerHandle, ServerMode, ServerOptions};
+    use http4r_core::socket::{SocketAddress, Stream};
+    use http4r_core::tls::{ClientTls, ClientTlsOptions, ServerTls, ServerTlsOptions};
+    use http4r_core::uri::Uri;
+    use crate::common::{EchoBodyHandler, PassHeadersAsBody};
+
+    fn self_signed(names: Vec<&str>) -> CertifiedKey {
+        rcgen::generate_simple_self_signed(names.iter().map(|name| name.to_string()).collect::<Vec<String>>()).unwrap()
+    }
+
+    fn https_server(certificate: &CertifiedKey, options: Option<ServerTlsOptions>) -> (Server, ServerHandle) {
+        let tls = ServerTls::from_pem(certificate.cert.pem().as_bytes(), certificate.key_pair.serialize_pem().as_bytes(), options).unwrap();
+        let mut server = Server::with_options(0, ServerOptions { tls: Some(tls), ..ServerOptions::default() });
+        let handle = server.start(|| { Ok(EchoBodyHandler {}) }, true).unwrap();
+        (server, handle)
+    }
+
+    fn trusting(certificate: &str, server_name: Option<&str>) -> ClientTls {
+        ClientTls::new(Some(ClientTlsOptions {
+            well_known_roots: false,
+            root_certificates: certificate.as_bytes().to_vec(),
+            server_name: server_name.map(|name| name.to_string()),
+            ..ClientTlsOptions::default()
+        })).unwrap()
+    }
+
+    fn https_client(port: u16, tls: ClientTls) -> Client {
+        Client::new("localhost", port, Some(ClientOptions { tls: Some(tls), ..ClientOptions::default() }))
+    }
+
+    #[test]
+    fn serves_and_calls_https() {
+        let certificate = self_signed(vec!("localhost"));
+        let (server, _handle) = https_server(&certificate, None);
+        let mut client = https_client(server.port, trusting(certificate.cert.pem().as_str(), None));
+
+        client.handle(Request::post(Uri::parse("/"), Headers::empty(), BodyString("hello over tls")), |res| {
+            assert_eq!(OK, res.status);
+            assert_eq!("hello over tls", body_string(res.body).unwrap());
+        });
+
+        // and a chunked body that is bigger than a tls record
+        let big = "a".repeat(100000);
+        client.handle(Request::post(Uri::parse("/"), Headers::from(vec!(("Transfer-Encoding", "chunked"))), BodyStream(Box::new(big.as_bytes()))), |res| {
+            assert_eq!(OK, res.status);
+            assert_eq!(big, body_string(res.body).unwrap());
+        });
+    }
+
+    #[test]
+    fn sends_a_body_bigger_than_rustls_buffers_at_a_time_in_full() {
+        let certificate = self_signed(vec!("localhost"));
+        let (server, _handle) = https_server(&certificate, None);
+        let mut client = https_client(server.port, trusting(certificate.cert.pem().as_str(), None));
+
+        // written in one go with a Content-Length, rather than a chunk at a time
+        let big = "a".repeat(300000);
+        client.handle(Request::post(Uri::parse("/"), Headers::empty(), BodyString(big.as_str())), |res| {
+            assert_eq!(OK, res.status);
+            assert_eq!(big.len(), body_string(res.body).unwrap().len());
+        });
+
+        // and the same for a response
+        struct BigBody {}
+        impl Handler for BigBody {
+            fn handle<F>(&mut self, _req: Request, fun: F) -> () where F: FnOnce(Response) -> () + Sized {
+                let big = "a".repeat(300000);
+                fun(Response::ok(Headers::empty(), BodyString(big.as_str())))
+            }
+        }
+        let tls = ServerTls::from_pem(certificate.cert.pem().as_bytes(), certificate.key_pair.serialize_pem().as_bytes(), None).unwrap();
+        let mut server = Server::with_options(0, ServerOptions { tls: Some(tls), ..ServerOptions::default() });
+        let _handle = server.start(|| { Ok(BigBody {}) }, true).unwrap();
+        let mut client = https_client(server.port, trusting(certificate.cert.pem().as_str(), None));
+        client.handle(Request::get(Uri::parse("/"), Headers::empty()), |res| {
+            assert_eq!(OK, res.status);
+            assert_eq!(Some("300000".to_string()), res.headers.get("Content-Length"));
+            assert_eq!(big.len(), body_string(res.body).unwrap().len());
+        });
+    }
+
+    #[test]
+    fn keeps_the_connection_alive_between_requests() {
+        let certificate = self_signed(vec!("localhost"));
+        let (server, _handle) = https_server(&certificate, None);
+        let tcp = Stream::connect(&SocketAddress::localhost(server.port), Duration::from_secs(1)).unwrap();
+        let mut stream = trusting(certificate.cert.pem().as_str(), None).connect(tcp, "localhost", Duration::from_secs(1)).unwrap();
+
+        stream.write_all("POST / HTTP/1.1\r\nContent-Length: 5\r\n\r\nfirstPOST / HTTP/1.1\r\nContent-Length: 6\r\n\r\nsecond".as_bytes()).unwrap();
+        let mut reader = [0; 4096];
+        let mut wire = WireState::new();
+        for expected in ["first", "second"] {
+            let (mut start_line_writer, mut headers_writer, mut trailers_writer) = (Vec::with_capacity(16384), Vec::with_capacity(16384), Vec::with_capacity(16384));
+            let (mut chunks_writer, mut compress_writer) = (Vec::with_capacity(1048576), Vec::with_capacity(1048576));
+            let response = read_message_from_wire(&mut stream, &mut reader, &mut wire, false, &mut start_line_writer, &mut headers_writer, &mut chunks_writer, &mut compress_writer, &mut trailers_writer)
+                .unwrap().to_res();
+            assert_eq!(expected, body_string(response.body).unwrap());
+        }
+    }
+
+    #[test]
+    fn does_not_trust_a_certificate_it_has_not_been_told_to() {
+        let certificate = self_signed(vec!("localhost"));
+        let (server, _handle) = https_server(&certificate, None);
+        let mut client = https_client(server.port, ClientTls::new(None).unwrap());
+
+        client.handle(Request::get(Uri::parse("/"), Headers::empty()), |res| {
+            assert_eq!(BadRequest, res.status);
+        });
+        assert!(matches!(client.error, Some(ClientError::Tls(_))), "{}", client.err);
+        assert!(client.err.contains("UnknownIssuer"), "{}", client.err);
+    }
+
+    #[test]
+    fn trusts_certificates_signed_by_a_certificate_authority_it_trusts() {
+        let mut authority_params = CertificateParams::new(vec!()).unwrap();
+        authority_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        let authority_key = KeyPair::generate().unwrap();
+        let authority = authority_params.self_signed(&authority_key).unwrap();
+        let key_pair = KeyPair::generate().unwrap();
+        let cert = CertificateParams::new(vec!("localhost".to_string())).unwrap()
+            .signed_by(&key_pair, &authority, &authority_key).unwrap();
+        let (server, _handle) = https_server(&CertifiedKey { cert, key_pair }, None);
+
+        let mut client = https_client(server.port, trusting(authority.pem().as_str(), None));
+        client.handle(Request::post(Uri::parse("/"), Headers::empty(), BodyString("signed")), |res| {
+            assert_eq!("signed", body_string(res.body).unwrap());
+        });
+    }
+
+    #[test]
+    fn checks_the_certificate_is_for_the_server_name() {
+        let certificate = self_signed(vec!("api.example.com"));
+        let (server, _handle) = https_server(&certificate, None);
+
+        let mut wrong_name = https_client(server.port, trusting(certificate.cert.pem().as_str(), None));
+        wrong_name.handle(Request::get(Uri::parse("/"), Headers::empty()), |res| {
+            assert_eq!(BadRequest, res.status);
+        });
+        assert!(wrong_name.err.contains("not valid for name"), "{}", wrong_name.err);
+
+        // connecting to localhost, but sending and checking the name the certificate is for
+        let mut right_name = https_client(server.port, trusting(certificate.cert.pem().as_str(), Some("api.example.com")));
+        right_name.handle(Request::post(Uri::parse("/"), Headers::empty(), BodyString("by name")), |res| {
+            assert_eq!("by name", body_string(res.body).unwrap());
+        });
+    }
+
+    #[test]
+    fn certificate_can_be_for_an_ip_address() {
+        let certificate = self_signed(vec!("127.0.0.1"));
+        let (server, _handle) = https_server(&certificate, None);
+        let tls = trusting(certificate.cert.pem().as_str(), None);
+        let mut client = Client::connect_to(SocketAddress::localhost(server.port), Some(ClientOptions { tls: Some(tls), ..ClientOptions::default() }));
+
+        client.handle(Request::post(Uri::parse("/"), Headers::empty(), BodyString("by address")), |res| {
+            assert_eq!("by address", body_string(res.body).unwrap());
+        });
+    }
+
+    #[test]
+    fn agrees_on_a_protocol_over_alpn() {
+        let certificate = self_signed(vec!("localhost"));
+        let (server, _handle) = https_server(&certificate, Some(ServerTlsOptions { alpn_protocols: vec!("h2".to_string(), "http/1.1".to_string()) }));
+        let client_tls = ClientTls::new(Some(ClientTlsOptions {
+            well_known_roots: false,
+            root_certificates: certificate.cert.pem().into_bytes(),
+            alpn_protocols: vec!("http/1.1".to_string()),
+            ..ClientTlsOptions::default()
+        })).unwrap();
+
+        let tcp = Stream::connect(&SocketAddress::localhost(server.port), Duration::from_secs(1)).unwrap();
+        let stream = client_tls.connect(tcp, "localhost", Duration::from_secs(1)).unwrap();
+
+        assert_eq!(Some(b"http/1.1".to_vec()), stream.alpn_protocol());
+    }
+
+    #[test]
+    fn sends_the_host_header_as_usual() {
+        let certificate = self_signed(vec!("localhost"));
+        let tls = ServerTls::from_pem(certificate.cert.pem().as_bytes(), certificate.key_pair.serialize_pem().as_bytes(), None).unwrap();
+        let mut server = Server::with_options(0, ServerOptions { tls: Some(tls), ..ServerOptions::default() });
+        let _handle = server.start(|| { Ok(PassHeadersAsBody {}) }, true).unwrap();
+
+        let mut client = https_client(server.port, trusting(certificate.cert.pem().as_str(), None));
+        client.handle(Request::get(Uri::parse("/"), Headers::empty()), |res| {
+            assert!(body_string(res.body).unwrap().contains(format!("Host: localhost:{}", server.port).as_str()));
+        });
+    }
+
+    #[test]
+    fn closes_a_connection_that_does_not_do_the_handshake_in_time() {
+        let certificate = self_signed(vec!("localhost"));
+        let tls = ServerTls::from_pem(certificate.cert.pem().as_bytes(), certificate.key_pair.serialize_pem().as_bytes(), None).unwrap();
+        let mut server = Server::with_options(0, ServerOptions { tls: Some(tls), headers_timeout: Duration::from_millis(100), ..ServerOptions::default() });
+        let _handle = server.start(|| { Ok(EchoBodyHandler {}) }, true).unwrap();
+        let started = Instant::now();
+
+        let mut stream = TcpStream::connect(format!("127.0.0.1:{}", server.port)).unwrap();
+        let mut nothing = vec!();
+        let _ = stream.read_to_end(&mut nothing);
+        assert!(started.elapsed() < Duration::from_secs(2));
+
+        // and plain http gets nowhere either
+        let mut stream = TcpStream::connect(format!("127.0.0.1:{}", server.port)).unwrap();
+        stream.write_all("GET / HTTP/1.1\r\n\r\n".as_bytes()).unwrap();
+        let mut response = vec!();
+        let _ = stream.read_to_end(&mut response);
+        assert!(!String::from_utf8_lossy(&response).contains("HTTP/1.1"));
+    }
+
+    // writes a response that is only framed by the connection closing, with or without saying close_notify first
+    fn close_after_response(certificate: &CertifiedKey, close_notify: bool) -> u16 {
+        let tls = ServerTls::from_pem(certificate.cert.pem().as_bytes(), certificate.key_pair.serialize_pem().as_bytes(), None).unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        thread::spawn(move || {
+            let (tcp, _) = listener.accept().unwrap();
+            let socket = tcp.try_clone().unwrap();
+            let mut stream = tls.accept(Stream::from(tcp), Duration::from_secs(1)).unwrap();
+            stream.write_all("HTTP/1.1 200 OK\r\n\r\nall of it".as_bytes()).unwrap();
+            if close_notify {
+                stream.shutdown(Shutdown::Both).unwrap();
+            } else {
+                socket.shutdown(Shutdown::Both).unwrap();
+            }
+        });
+        port
+    }
+
+    #[test]
+    fn a_connection_that_closes_without_close_notify_may_have_been_cut_short() {
+        let certificate = self_signed(vec!("localhost"));
+        for close_notify in [true, false] {
+            let port = close_after_response(&certificate, close_notify);
+            let tcp = Stream::connect(&SocketAddress::localhost(port), Duration::from_secs(1)).unwrap();
+            let mut stream = trusting(certificate.cert.pem().as_str(), None).connect(tcp, "localhost", Duration::from_secs(1)).unwrap();
+
+            let mut response = vec!();
+            let read = stream.read_to_end(&mut response);
+            if close_notify {
+                assert!(read.is_ok());
+                assert!(String::from_utf8(response).unwrap().ends_with("all of it"));
+            } else {
+                assert_eq!(ErrorKind::UnexpectedEof, read.unwrap_err().kind());
+            }
+        }
+    }
+
+    #[test]
+    fn invalid_pem_is_an_error() {
+        let certificate = self_signed(vec!("localhost"));
+
+        assert!(ServerTls::from_pem("not a certificate".as_bytes(), certificate.key_pair.serialize_pem().as_bytes(), None).is_err());
+        assert!(ServerTls::from_pem(certificate.cert.pem().as_bytes(), "not a key".as_bytes(), None).is_err());
+        assert!(matches!(ServerTls::from_pem_files("/does/not/exist.pem", "/does/not/exist.key", None), Err(e) if e.contains("Could not read")));
+    }
+
+    #[test]
+    fn event_loop_mode_waits_for_the_head_of_the_request_over_tls_too() {
+        let certificate = self_signed(vec!("localhost"));
+        let tls = ServerTls::from_pem(certificate.cert.pem().as_bytes(), certificate.key_pair.serialize_pem().as_bytes(), None).unwrap();
+        let mut server = Server::with_options(0, ServerOptions { tls: Some(tls), mode: ServerMode::EventLoop, threadpool_size: 1, ..ServerOptions::default() });
+        let _handle = server.start(|| { Ok(EchoBodyHandler {}) }, true).unwrap();
+        let connect = || {
+            let tcp = Stream::connect(&SocketAddress::localhost(server.port), Duration::from_secs(1)).unwrap();
+            trusting(certificate.cert.pem().as_str(), None).connect(tcp, "localhost", Duration::from_secs(1)).unwrap()
+        };
+        let mut trickling = connect();
+        trickling.write_all("POST / HTTP/1.1\r\nContent-".as_bytes()).unwrap();
+
+        // the only thread is not stuck waiting for the rest of the head
+        let mut client = https_client(server.port, trusting(certificate.cert.pem().as_str(), None));
+        client.handle(Request::post(Uri::parse("/"), Headers::empty(), BodyString("not stuck")), |res| {
+            assert_eq!("not stuck", body_string(res.body).unwrap());
+        });
+
+        // and requests that arrive together are both handled, though they were decrypted together
+        trickling.write_all("Length: 5\r\n\r\nfirstPOST / HTTP/1.1\r\nContent-Length: 6\r\n\r\nsecond".as_bytes()).unwrap();
+        let mut reader = [0; 4096];
+        let mut wire = WireState::new();
+        for expected in ["first", "second"] {
+            let (mut start_line_writer, mut headers_writer, mut trailers_writer) = (Vec::with_capacity(16384), Vec::with_capacity(16384), Vec::with_capacity(16384));
+            let (mut chunks_writer, mut compress_writer) = (Vec::with_capacity(1048576), Vec::with_capacity(1048576));
+            let response = read_message_from_wire(&mut trickling, &mut reader, &mut wire, false, &mut start_line_writer, &mut headers_writer, &mut chunks_writer, &mut compress_writer, &mut trailers_writer)
+                .unwrap().to_res();
+            assert_eq!(expected, body_string(response.body).unwrap());
+        }
+    }
+}
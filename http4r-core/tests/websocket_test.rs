@@ -0,0 +1,342 @@
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+    use std::time::Duration;
+    use http4r_core::client::{Client, ClientError};
+    use http4r_core::handler::Handler;
+    use http4r_core::headers::Headers;
+    use http4r_core::http_message::{body_string, Request, Response};
+    use http4r_core::http_message::Body::BodyString;
+    use http4r_core::http_message::Status::{BadRequest, MethodNotAllowed, OK, UpgradeRequired};
+    use http4r_core::server::{Server, ServerHandle, ServerOptions};
+    use http4r_core::uri::Uri;
+    use http4r_core::websocket::{accept_key, BINARY, CLOSE, CONTINUATION, INVALID_PAYLOAD, Message, MESSAGE_TOO_BIG, PING, PONG, PROTOCOL_ERROR, TEXT, WebSocket, WebSocketError, WebSocketOptions};
+
+    // echoes messages back until it is closed, greeting the client first if it asks to be
+    struct EchoWebSocket {}
+
+    impl Handler for EchoWebSocket {
+        fn handle<F>(&mut self, req: Request, fun: F) -> () where F: FnOnce(Response) -> () + Sized {
+            if !WebSocket::is_handshake(&req) {
+                return fun(Response::ok(Headers::empty(), BodyString("not a websocket")));
+            }
+            let greet = req.uri.path == "/greet";
+            let options = WebSocketOptions {
+                protocols: vec!("chat".to_string(), "superchat".to_string()),
+                max_message_size: 1000,
+                max_frame_size: 100,
+                ..WebSocketOptions::default()
+            };
+            let mut socket = match WebSocket::accept(&req, Some(options), fun) {
+                Some(socket) => socket,
+                None => return,
+            };
+            if greet {
+                socket.send(Message::Text("welcome".to_string())).unwrap();
+            }
+            loop {
+                let echo = match socket.read() {
+                    Ok(Message::Text(text)) => Message::Text(text),
+                    Ok(Message::Binary(bytes)) => Message::Binary(bytes),
+                    Ok(Message::Ping(_)) | Ok(Message::Pong(_)) => continue,
+                    Ok(Message::Close(_)) | Err(_) => return,
+                };
+                if socket.send(echo).is_err() {
+                    return;
+                }
+            }
+        }
+    }
+
+    fn server() -> (Server, ServerHandle) {
+        let mut server = Server::new(0);
+        let handle = server.start(|| Ok(EchoWebSocket {}), true).unwrap();
+        (server, handle)
+    }
+
+    fn handshake_headers(key: &str) -> Headers {
+        Headers::from(vec!(
+            ("Upgrade", "websocket"),
+            ("Connection", "Upgrade"),
+            ("Sec-WebSocket-Key", key),
+            ("Sec-WebSocket-Version", "13"),
+        ))
+    }
+
+    // opens a websocket by hand, and returns the head of the response along with the connection
+    fn raw_handshake(server: &Server, path: &str) -> (String, TcpStream) {
+        let mut stream = TcpStream::connect(format!("127.0.0.1:{}", server.port)).unwrap();
+        stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let request = format!("GET {} HTTP/1.1\r\nHost: localhost\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 13\r\n\r\n", path);
+        stream.write_all(request.as_bytes()).unwrap();
+        let mut head = vec!();
+        let mut octet = [0; 1];
+        while !head.ends_with(b"\r\n\r\n") {
+            stream.read_exact(&mut octet).unwrap();
+            head.push(octet[0]);
+        }
+        (String::from_utf8(head).unwrap(), stream)
+    }
+
+    // frames from a client have to be masked, unless we are seeing what happens when they are not
+    fn send_frame(stream: &mut TcpStream, first: u8, payload: &[u8], masked: bool) {
+        let mut frame = vec!(first);
+        let mask_bit = if masked { 0x80 } else { 0 };
+        if payload.len() < 126 {
+            frame.push(mask_bit | payload.len() as u8);
+        } else {
+            frame.push(mask_bit | 126);
+            frame.extend_from_slice(&(payload.len() as u16).to_be_bytes());
+        }
+        if masked {
+            let mask = [1, 2, 3, 4];
+            frame.extend_from_slice(&mask);
+            frame.extend(payload.iter().enumerate().map(|(i, byte)| byte ^ mask[i % 4]));
+        } else {
+            frame.extend_from_slice(payload);
+        }
+        stream.write_all(&frame).unwrap();
+    }
+
+    // returns the first byte, ie fin and opcode, and the payload of the next frame from the server
+    fn read_frame(stream: &mut TcpStream) -> (u8, Vec<u8>) {
+        let mut head = [0; 2];
+        stream.read_exact(&mut head).unwrap();
+        assert_eq!(0, head[1] & 0x80, "frames from the server are not masked");
+        let length = match head[1] & 0x7F {
+            126 => {
+                let mut length = [0; 2];
+                stream.read_exact(&mut length).unwrap();
+                u16::from_be_bytes(length) as usize
+            }
+            length => length as usize,
+        };
+        let mut payload = vec![0; length];
+        stream.read_exact(&mut payload).unwrap();
+        (head[0], payload)
+    }
+
+    fn close_code(payload: &[u8]) -> u16 {
+        u16::from_be_bytes([payload[0], payload[1]])
+    }
+
+    // https://www.rfc-editor.org/rfc/rfc6455#section-1.3
+    #[test]
+    fn accept_key_is_the_example_in_the_rfc() {
+        assert_eq!("s3pPLMBiTxaQ9kYGzzhZRbK+xOo=", accept_key("dGhlIHNhbXBsZSBub25jZQ=="));
+    }
+
+    #[test]
+    fn server_switches_protocols_for_a_valid_handshake() {
+        let (server, _handle) = server();
+        let (head, _stream) = raw_handshake(&server, "/");
+
+        assert!(head.starts_with("HTTP/1.1 101 Switching Protocols\r\n"), "{}", head);
+        assert!(head.contains("Upgrade: websocket\r\n"), "{}", head);
+        assert!(head.contains("Connection: Upgrade\r\n"), "{}", head);
+        assert!(head.contains("Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n"), "{}", head);
+        assert!(!head.contains("Content-Length"), "{}", head);
+    }
+
+    #[test]
+    fn server_turns_down_handshakes_that_are_not_valid() {
+        let (server, _handle) = server();
+        let mut client = Client::new("127.0.0.1", server.port, None);
+
+        let no_key = handshake_headers("").remove("Sec-WebSocket-Key");
+        client.handle(Request::get(Uri::parse("/"), no_key), |res| {
+            assert_eq!(BadRequest, res.status);
+        });
+        let short_key = handshake_headers("c2hvcnQ=");
+        client.handle(Request::get(Uri::parse("/"), short_key), |res| {
+            assert_eq!(BadRequest, res.status);
+        });
+        let old_version = handshake_headers("dGhlIHNhbXBsZSBub25jZQ==").replace(("Sec-WebSocket-Version", "8"));
+        client.handle(Request::get(Uri::parse("/"), old_version), |res| {
+            assert_eq!(UpgradeRequired, res.status);
+            assert_eq!(Some("13".to_string()), res.headers.get("Sec-WebSocket-Version"));
+        });
+        let post = Request::post(Uri::parse("/"), handshake_headers("dGhlIHNhbXBsZSBub25jZQ=="), BodyString(""));
+        client.handle(post, |res| {
+            assert_eq!(MethodNotAllowed, res.status);
+        });
+        // and a handler can still answer plain requests
+        client.handle(Request::get(Uri::parse("/"), Headers::empty()), |res| {
+            assert_eq!(OK, res.status);
+            assert_eq!("not a websocket", body_string(res.body).unwrap());
+        });
+    }
+
+    #[test]
+    fn client_and_server_send_messages_both_ways() {
+        let (server, _handle) = server();
+        let mut client = Client::new("127.0.0.1", server.port, None);
+        let mut socket = client.websocket(Request::get(Uri::parse("/"), Headers::empty()), None).unwrap();
+
+        socket.send(Message::Text("hello".to_string())).unwrap();
+        assert_eq!(Message::Text("hello".to_string()), socket.read().unwrap());
+        socket.send(Message::Binary(vec!(0, 1, 2, 255))).unwrap();
+        assert_eq!(Message::Binary(vec!(0, 1, 2, 255)), socket.read().unwrap());
+        socket.send(Message::Ping(b"are you there".to_vec())).unwrap();
+        assert_eq!(Message::Pong(b"are you there".to_vec()), socket.read().unwrap());
+        // the server sends it back in fragments of 100 bytes, which are put back together
+        let long = "a".repeat(950);
+        socket.send(Message::Text(long.clone())).unwrap();
+        assert_eq!(Message::Text(long), socket.read().unwrap());
+
+        assert_eq!(Ok(()), socket.close(1000, "bye"));
+        assert_eq!(Err(WebSocketError::Closed), socket.read());
+    }
+
+    #[test]
+    fn client_gets_messages_sent_straight_after_the_handshake() {
+        let (server, _handle) = server();
+        let mut client = Client::new("127.0.0.1", server.port, None);
+        let mut socket = client.websocket(Request::get(Uri::parse("/greet"), Headers::empty()), None).unwrap();
+
+        assert_eq!(Message::Text("welcome".to_string()), socket.read().unwrap());
+    }
+
+    #[test]
+    fn a_read_that_times_out_can_be_tried_again() {
+        // so that an idle WebSocket does not hold on to a server thread for good
+        assert_eq!(Some(Duration::from_secs(60)), WebSocketOptions::default().read_timeout);
+        let (server, _handle) = server();
+        let mut client = Client::new("127.0.0.1", server.port, None);
+        let options = WebSocketOptions { read_timeout: Some(Duration::from_millis(100)), ..WebSocketOptions::default() };
+        let mut socket = client.websocket(Request::get(Uri::parse("/"), Headers::empty()), Some(options)).unwrap();
+
+        assert!(matches!(socket.read(), Err(WebSocketError::Timeout(_))));
+        socket.send(Message::Text("still here".to_string())).unwrap();
+        assert_eq!(Message::Text("still here".to_string()), socket.read().unwrap());
+    }
+
+    #[test]
+    fn server_picks_the_subprotocol_it_likes_best_out_of_those_offered() {
+        let (server, _handle) = server();
+        let mut client = Client::new("127.0.0.1", server.port, None);
+        let options = WebSocketOptions { protocols: vec!("superchat".to_string(), "chat".to_string()), ..WebSocketOptions::default() };
+        let socket = client.websocket(Request::get(Uri::parse("/"), Headers::empty()), Some(options)).unwrap();
+        assert_eq!(Some("chat".to_string()), socket.protocol);
+
+        let options = WebSocketOptions { protocols: vec!("mqtt".to_string()), ..WebSocketOptions::default() };
+        let socket = client.websocket(Request::get(Uri::parse("/"), Headers::empty()), Some(options)).unwrap();
+        assert_eq!(None, socket.protocol);
+    }
+
+    #[test]
+    fn client_fails_if_the_server_does_not_switch_protocols() {
+        let mut server = Server::with_options(0, ServerOptions::default());
+        let _handle = server.start(|| Ok(OkHandler {}), true).unwrap();
+        let mut client = Client::new("127.0.0.1", server.port, None);
+
+        match client.websocket(Request::get(Uri::parse("/"), Headers::empty()), None) {
+            Err(ClientError::WebSocket(msg)) => assert_eq!("The server responded 200 OK rather than switching protocols", msg),
+            _ => panic!("Expected the handshake to fail"),
+        }
+    }
+
+    struct OkHandler {}
+
+    impl Handler for OkHandler {
+        fn handle<F>(&mut self, _req: Request, fun: F) -> () where F: FnOnce(Response) -> () + Sized {
+            fun(Response::ok(Headers::empty(), BodyString("no websockets here")))
+        }
+    }
+
+    #[test]
+    fn server_puts_fragments_back_together_and_answers_pings_in_between() {
+        let (server, _handle) = server();
+        let (_, mut stream) = raw_handshake(&server, "/");
+
+        send_frame(&mut stream, TEXT, b"Hel", true);
+        send_frame(&mut stream, 0x80 | PING, b"ping", true);
+        send_frame(&mut stream, CONTINUATION, b"lo ", true);
+        send_frame(&mut stream, 0x80 | CONTINUATION, b"world", true);
+
+        assert_eq!((0x80 | PONG, b"ping".to_vec()), read_frame(&mut stream));
+        assert_eq!((0x80 | TEXT, b"Hello world".to_vec()), read_frame(&mut stream));
+    }
+
+    #[test]
+    fn server_sends_messages_bigger_than_its_max_frame_size_in_fragments() {
+        let (server, _handle) = server();
+        let (_, mut stream) = raw_handshake(&server, "/");
+
+        send_frame(&mut stream, 0x80 | BINARY, &[7; 250], true);
+
+        assert_eq!((BINARY, vec![7; 100]), read_frame(&mut stream));
+        assert_eq!((CONTINUATION, vec![7; 100]), read_frame(&mut stream));
+        assert_eq!((0x80 | CONTINUATION, vec![7; 50]), read_frame(&mut stream));
+    }
+
+    #[test]
+    fn server_echoes_the_close_code_and_closes_the_connection() {
+        let (server, _handle) = server();
+        let (_, mut stream) = raw_handshake(&server, "/");
+
+        send_frame(&mut stream, 0x80 | CLOSE, &[&4000u16.to_be_bytes()[..], b"done"].concat(), true);
+
+        let (first, payload) = read_frame(&mut stream);
+        assert_eq!(0x80 | CLOSE, first);
+        assert_eq!(4000, close_code(&payload));
+        let mut rest = vec!();
+        let _ = stream.read_to_end(&mut rest);
+        assert!(rest.is_empty());
+    }
+
+    // like the autobahn test suite, each of these breaks a rule and is closed with the code for why
+    #[test]
+    fn server_closes_the_connection_with_the_code_for_the_rule_that_was_broken() {
+        let (server, _handle) = server();
+        let cases: Vec<(&str, u8, Vec<u8>, bool, u16)> = vec!(
+            ("not masked", 0x80 | TEXT, b"hello".to_vec(), false, PROTOCOL_ERROR),
+            ("reserved bit set", 0x80 | 0x40 | TEXT, b"hello".to_vec(), true, PROTOCOL_ERROR),
+            ("unknown opcode", 0x80 | 0x3, b"hello".to_vec(), true, PROTOCOL_ERROR),
+            ("fragmented ping", PING, b"ping".to_vec(), true, PROTOCOL_ERROR),
+            ("ping too long", 0x80 | PING, vec![0; 126], true, PROTOCOL_ERROR),
+            ("continuation of nothing", 0x80 | CONTINUATION, b"hello".to_vec(), true, PROTOCOL_ERROR),
+            ("text not utf-8", 0x80 | TEXT, vec!(0xce, 0xba, 0xe1, 0xbd, 0xb9, 0xcf, 0x83, 0xce, 0xbc, 0xce, 0xb5, 0xed, 0xa0, 0x80), true, INVALID_PAYLOAD),
+            ("close code of one byte", 0x80 | CLOSE, vec!(3), true, PROTOCOL_ERROR),
+            ("reserved close code", 0x80 | CLOSE, 1005u16.to_be_bytes().to_vec(), true, PROTOCOL_ERROR),
+            ("close reason not utf-8", 0x80 | CLOSE, vec!(0x03, 0xe8, 0xff), true, INVALID_PAYLOAD),
+            ("message too big", 0x80 | BINARY, vec![0; 1001], true, MESSAGE_TOO_BIG),
+        );
+
+        for (case, first, payload, masked, code) in cases {
+            let (_, mut stream) = raw_handshake(&server, "/");
+            send_frame(&mut stream, first, &payload, masked);
+
+            let (first, payload) = read_frame(&mut stream);
+            assert_eq!(0x80 | CLOSE, first, "{}", case);
+            assert_eq!(code, close_code(&payload), "{}", case);
+        }
+    }
+
+    #[test]
+    fn a_new_message_cannot_start_before_the_last_one_has_finished() {
+        let (server, _handle) = server();
+        let (_, mut stream) = raw_handshake(&server, "/");
+
+        send_frame(&mut stream, TEXT, b"first", true);
+        send_frame(&mut stream, 0x80 | TEXT, b"second", true);
+
+        let (first, payload) = read_frame(&mut stream);
+        assert_eq!(0x80 | CLOSE, first);
+        assert_eq!(PROTOCOL_ERROR, close_code(&payload));
+    }
+
+    #[test]
+    fn control_frames_and_close_codes_that_are_not_allowed_are_not_sent() {
+        let (server, _handle) = server();
+        let mut client = Client::new("127.0.0.1", server.port, None);
+        let mut socket = client.websocket(Request::get(Uri::parse("/"), Headers::empty()), None).unwrap();
+
+        assert!(matches!(socket.send(Message::Ping(vec![0; 126])), Err(WebSocketError::Invalid(_))));
+        assert!(matches!(socket.send(Message::Close(Some((1006, "".to_string())))), Err(WebSocketError::Invalid(_))));
+        // and the connection is still fine
+        socket.send(Message::Text("still here".to_string())).unwrap();
+        assert_eq!(Message::Text("still here".to_string()), socket.read().unwrap());
+    }
+}
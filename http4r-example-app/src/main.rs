@@ -12,7 +12,7 @@ fn main() {
 
     server.start(move || {
         Ok(App::production(env.copy()))
-    }, false);
+    }, false).unwrap();
 }
 
 
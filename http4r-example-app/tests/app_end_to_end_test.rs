@@ -19,22 +19,22 @@ mod tests {
     #[test]
     fn static_file_handler_home_page() {
         let mut server = Server::new(0);
-        server.start(|| { Ok(App::in_memory(Environment::empty())) }, true);
+        server.start(|| { Ok(App::in_memory(Environment::empty())) }, true).unwrap();
 
         let mut client = Client::new("127.0.0.1", server.port, None);
         client.handle(Request::get(Uri::parse("/"), Headers::empty()), |res| {
-            approve(body_string(res.body), "./resources/html/index.html");
+            approve(body_string(res.body).unwrap(), "./resources/html/index.html");
         })
     }
 
     #[test]
     fn file_not_found_results_in_404() {
         let mut server = Server::new(0);
-        server.start(|| { Ok(App::in_memory(Environment::empty())) }, true);
+        server.start(|| { Ok(App::in_memory(Environment::empty())) }, true).unwrap();
 
         let mut client = Client::new("127.0.0.1", server.port, None);
         client.handle(Request::get(Uri::parse("/some/unknown/file.html"), Headers::empty()), |res| {
-            approve(body_string(res.body), "./resources/html/not-found.html");
+            approve(body_string(res.body).unwrap(), "./resources/html/not-found.html");
         })
     }
 
@@ -43,14 +43,14 @@ mod tests {
         let mut handler = StaticFileHandler::new("/resources/html", "test".to_string());
         let request_going_outside_root = Request::get(Uri::parse("/../not-allowed-to-view"), Headers::empty());
         handler.handle(request_going_outside_root, |res| {
-            assert_eq!(body_string(res.body), format!("Attempted to access a file outside of root: {}/resources/not-allowed-to-view.html", env::current_dir().unwrap().to_str().unwrap()));
+            assert_eq!(body_string(res.body).unwrap(), format!("Attempted to access a file outside of root: {}/resources/not-allowed-to-view.html", env::current_dir().unwrap().to_str().unwrap()));
             assert_eq!(res.status, Forbidden);
         });
 
         let request_going_outside_root = Request::get(Uri::parse("/../not-allowed-to-view"), Headers::empty());
         let mut app = App::in_memory(Environment::empty());
         app.handle(request_going_outside_root, |res| {
-            approve(body_string(res.body), "./resources/html/not-found.html");
+            approve(body_string(res.body).unwrap(), "./resources/html/not-found.html");
         })
     }
 }
@@ -18,7 +18,7 @@ mod tests {
 
         not_found_handler.handle(unknown_route, |res| {
             assert_eq!(res.status, NotFound);
-            approve(body_string(res.body), "./resources/html/not-found.html");
+            approve(body_string(res.body).unwrap(), "./resources/html/not-found.html");
         });
     }
 }
\ No newline at end of file
@@ -13,7 +13,7 @@ mod tests {
     fn not_found() {
         let mut server = Server::new(0);
         // start listening on next available port, close on finish = true
-        server.start(|| { Ok(OkHandler) }, true);
+        server.start(|| { Ok(OkHandler) }, true).unwrap();
 
         // request to some url we do not serve
         let not_found_req = Request::get(Uri::parse("/not-found"), Headers::empty());
@@ -22,14 +22,14 @@ mod tests {
         // make an http request to our server and assert response status is Not Found.
         client.handle(not_found_req, |res| {
             assert_eq!(res.status, NotFound);
-            assert_eq!(body_string(res.body), "Not found.");
+            assert_eq!(body_string(res.body).unwrap(), "Not found.");
         });
 
         let ok_request = Request::get(Uri::parse("/"), Headers::empty());
         // make a request to root that we expect to be ok
         client.handle(ok_request, |res| {
             assert_eq!(res.status, OK);
-            assert_eq!(body_string(res.body), "Hello, world!");
+            assert_eq!(body_string(res.body).unwrap(), "Hello, world!");
         })
     }
 }
\ No newline at end of file
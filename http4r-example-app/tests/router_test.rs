@@ -20,7 +20,7 @@ mod tests {
         router.handle(request, |res| {
             assert_eq!(res.status, OK);
             // assimilate the info given to us in the response body
-            assert_eq!(body_string(res.body), "Uncle->Bob: Vic, Ulrika".to_string())
+            assert_eq!(body_string(res.body).unwrap(), "Uncle->Bob: Vic, Ulrika".to_string())
         });
     }
 
@@ -33,7 +33,7 @@ mod tests {
         let mut router = Router::new(ProfileRouter);
         router.handle(request, |res| {
             assert_eq!(res.status, BadRequest);
-            assert_eq!(body_string(res.body), "Expected query parameter \"org\".".to_string())
+            assert_eq!(body_string(res.body).unwrap(), "Expected query parameter \"org\".".to_string())
         });
     }
 
@@ -46,7 +46,7 @@ mod tests {
         let mut router = Router::new(ProfileRouter);
         router.handle(request, |res| {
             assert_eq!(res.status, BadRequest);
-            assert_eq!(body_string(res.body), "Expected header \"friend\".".to_string())
+            assert_eq!(body_string(res.body).unwrap(), "Expected header \"friend\".".to_string())
         });
     }
 
@@ -57,7 +57,7 @@ mod tests {
         let mut router = Router::new(ProfileRouter);
         router.handle(request, |res| {
             assert_eq!(res.status, OK);
-            assert_eq!(body_string(res.body), "Home page.".to_string())
+            assert_eq!(body_string(res.body).unwrap(), "Home page.".to_string())
         });
     }
 }
\ No newline at end of file
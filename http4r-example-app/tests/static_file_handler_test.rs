@@ -30,6 +30,7 @@ mod tests {
             assert_eq!(response.status, OK);
             match response.body {
                 Body::BodyString(_) => panic!("body string for image"),
+                Body::BodyBytes(bytes) => assert_eq!(bytes.len(), 3758),
                 Body::BodyStream(mut read) => {
                     let mut vec: Vec<u8> = Vec::new();
                     read.read_to_end(&mut vec).unwrap();
@@ -43,7 +44,7 @@ mod tests {
     fn static_file_handler_loads_icons_over_http() {
         let mut server = Server::new(0);
 
-        server.start(|| { Ok(StaticFileHandler::new("/resources/html", "test".to_string()))}, true);
+        server.start(|| { Ok(StaticFileHandler::new("/resources/html", "test".to_string()))}, true).unwrap();
 
         let mut client = Client::new("127.0.0.1", server.port, None);
 
@@ -51,6 +52,7 @@ mod tests {
             assert_eq!(response.status, OK);
             match response.body {
                 Body::BodyString(_) => panic!("body string for image"),
+                Body::BodyBytes(bytes) => assert_eq!(bytes.len(), 3758),
                 Body::BodyStream(mut read) => {
                     let mut vec: Vec<u8> = Vec::new();
                     read.read_to_end(&mut vec).unwrap();